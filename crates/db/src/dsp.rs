@@ -0,0 +1,299 @@
+use crate::Database;
+use hogehoge_types::PluginId;
+
+/// An EQ band as it was last saved.
+#[derive(Debug, Clone)]
+pub struct SavedEqBand {
+    pub kind: String,
+    pub frequency: f32,
+    pub gain_db: f32,
+    pub q: f32,
+}
+
+/// An effect plugin in the chain of a profile.
+#[derive(Debug, Clone)]
+pub struct SavedEffect {
+    pub plugin_id: PluginId,
+    pub bypassed: bool,
+}
+
+/// EQ and the rest of the DSP settings of an output device as they were last saved.
+#[derive(Debug, Clone)]
+pub struct SavedDspProfile {
+    pub eq_enabled: bool,
+    pub preamp_db: f32,
+    pub bands: Vec<SavedEqBand>,
+    pub balance: f32,
+    pub mono: bool,
+    pub limiter: bool,
+    pub effects: Vec<SavedEffect>,
+}
+
+/// A user defined EQ preset.
+#[derive(Debug, Clone)]
+pub struct SavedEqPreset {
+    pub name: String,
+    pub preamp_db: f32,
+    pub bands: Vec<SavedEqBand>,
+}
+
+impl Database {
+    /// Loads the profile of `device`, `None` loads the profile used by devices without their own.
+    #[tracing::instrument(skip(self))]
+    pub async fn load_dsp_profile(
+        &self,
+        device: Option<&str>,
+    ) -> sqlx::Result<Option<SavedDspProfile>> {
+        let Some(profile) = sqlx::query!(
+            "SELECT dsp_profile_id, eq_enabled, preamp_db, balance, mono, limiter
+            FROM dsp_profiles WHERE device IS ?",
+            device
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        let bands = sqlx::query!(
+            "SELECT kind, frequency, gain_db, q FROM dsp_profile_bands
+            WHERE dsp_profile_id = ? ORDER BY position",
+            profile.dsp_profile_id
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|band| SavedEqBand {
+            kind: band.kind,
+            frequency: band.frequency as f32,
+            gain_db: band.gain_db as f32,
+            q: band.q as f32,
+        })
+        .collect();
+
+        let effects = sqlx::query!(
+            "SELECT plugin_id, bypassed FROM dsp_profile_effects
+            WHERE dsp_profile_id = ? ORDER BY position",
+            profile.dsp_profile_id
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|effect| SavedEffect {
+            plugin_id: PluginId(effect.plugin_id),
+            bypassed: effect.bypassed != 0,
+        })
+        .collect();
+
+        Ok(Some(SavedDspProfile {
+            eq_enabled: profile.eq_enabled != 0,
+            preamp_db: profile.preamp_db as f32,
+            bands,
+            balance: profile.balance as f32,
+            mono: profile.mono != 0,
+            limiter: profile.limiter != 0,
+            effects,
+        }))
+    }
+
+    #[tracing::instrument(skip(self, profile))]
+    pub async fn save_dsp_profile(
+        &self,
+        device: Option<&str>,
+        profile: &SavedDspProfile,
+    ) -> sqlx::Result<()> {
+        let mut transaction = self.pool.begin().await?;
+
+        let preamp_db = profile.preamp_db as f64;
+        let balance = profile.balance as f64;
+
+        // the profile without a device can't be found through the unique constraint, NULLs are
+        // all distinct
+        let existing = sqlx::query_scalar!(
+            "SELECT dsp_profile_id FROM dsp_profiles WHERE device IS ?",
+            device
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+
+        let dsp_profile_id = match existing {
+            Some(dsp_profile_id) => {
+                sqlx::query!(
+                    "UPDATE dsp_profiles SET eq_enabled = ?, preamp_db = ?, balance = ?, mono = ?,
+                    limiter = ? WHERE dsp_profile_id = ?",
+                    profile.eq_enabled,
+                    preamp_db,
+                    balance,
+                    profile.mono,
+                    profile.limiter,
+                    dsp_profile_id
+                )
+                .execute(&mut *transaction)
+                .await?;
+
+                sqlx::query!(
+                    "DELETE FROM dsp_profile_bands WHERE dsp_profile_id = ?",
+                    dsp_profile_id
+                )
+                .execute(&mut *transaction)
+                .await?;
+
+                sqlx::query!(
+                    "DELETE FROM dsp_profile_effects WHERE dsp_profile_id = ?",
+                    dsp_profile_id
+                )
+                .execute(&mut *transaction)
+                .await?;
+
+                dsp_profile_id
+            }
+            None => {
+                sqlx::query_scalar!(
+                    "INSERT INTO dsp_profiles (device, eq_enabled, preamp_db, balance, mono,
+                    limiter) VALUES (?, ?, ?, ?, ?, ?) RETURNING dsp_profile_id",
+                    device,
+                    profile.eq_enabled,
+                    preamp_db,
+                    balance,
+                    profile.mono,
+                    profile.limiter
+                )
+                .fetch_one(&mut *transaction)
+                .await?
+            }
+        };
+
+        for (position, band) in profile.bands.iter().enumerate() {
+            let position = position as i64;
+            let frequency = band.frequency as f64;
+            let gain_db = band.gain_db as f64;
+            let q = band.q as f64;
+
+            sqlx::query!(
+                "INSERT INTO dsp_profile_bands (dsp_profile_id, position, kind, frequency, gain_db, q)
+                VALUES (?, ?, ?, ?, ?, ?)",
+                dsp_profile_id,
+                position,
+                band.kind,
+                frequency,
+                gain_db,
+                q
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        for (position, effect) in profile.effects.iter().enumerate() {
+            let position = position as i64;
+
+            sqlx::query!(
+                "INSERT INTO dsp_profile_effects (dsp_profile_id, position, plugin_id, bypassed)
+                VALUES (?, ?, ?, ?)",
+                dsp_profile_id,
+                position,
+                effect.plugin_id.0,
+                effect.bypassed
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await
+    }
+
+    /// Removes the profile of `device`, so it uses the shared profile again.
+    #[tracing::instrument(skip(self))]
+    pub async fn delete_dsp_profile(&self, device: &str) -> sqlx::Result<()> {
+        sqlx::query!("DELETE FROM dsp_profiles WHERE device = ?", device)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn load_eq_presets(&self) -> sqlx::Result<Vec<SavedEqPreset>> {
+        let presets =
+            sqlx::query!("SELECT eq_preset_id, name, preamp_db FROM eq_presets ORDER BY name")
+                .fetch_all(&self.pool)
+                .await?;
+
+        let mut saved = Vec::with_capacity(presets.len());
+        for preset in presets {
+            let bands = sqlx::query!(
+                "SELECT kind, frequency, gain_db, q FROM eq_preset_bands
+                WHERE eq_preset_id = ? ORDER BY position",
+                preset.eq_preset_id
+            )
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|band| SavedEqBand {
+                kind: band.kind,
+                frequency: band.frequency as f32,
+                gain_db: band.gain_db as f32,
+                q: band.q as f32,
+            })
+            .collect();
+
+            saved.push(SavedEqPreset {
+                name: preset.name,
+                preamp_db: preset.preamp_db as f32,
+                bands,
+            });
+        }
+
+        Ok(saved)
+    }
+
+    /// Saves `preset`, replacing an existing preset with the same name.
+    #[tracing::instrument(skip_all)]
+    pub async fn save_eq_preset(&self, preset: &SavedEqPreset) -> sqlx::Result<()> {
+        let mut transaction = self.pool.begin().await?;
+
+        let preamp_db = preset.preamp_db as f64;
+
+        sqlx::query!("DELETE FROM eq_presets WHERE name = ?", preset.name)
+            .execute(&mut *transaction)
+            .await?;
+
+        let eq_preset_id = sqlx::query_scalar!(
+            "INSERT INTO eq_presets (name, preamp_db) VALUES (?, ?) RETURNING eq_preset_id",
+            preset.name,
+            preamp_db
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+
+        for (position, band) in preset.bands.iter().enumerate() {
+            let position = position as i64;
+            let frequency = band.frequency as f64;
+            let gain_db = band.gain_db as f64;
+            let q = band.q as f64;
+
+            sqlx::query!(
+                "INSERT INTO eq_preset_bands (eq_preset_id, position, kind, frequency, gain_db, q)
+                VALUES (?, ?, ?, ?, ?, ?)",
+                eq_preset_id,
+                position,
+                band.kind,
+                frequency,
+                gain_db,
+                q
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn delete_eq_preset(&self, name: &str) -> sqlx::Result<()> {
+        sqlx::query!("DELETE FROM eq_presets WHERE name = ?", name)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
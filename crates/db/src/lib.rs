@@ -3,19 +3,40 @@ use futures_util::stream::BoxStream;
 use hogehoge_types::{
     AlbumId, ArtistId, TrackGroupId, TrackId, UniqueTrackIdentifier,
     library::{Tags, Track},
-    plugin::{PluginId, Uuid},
+    plugin::{PluginId, PluginTrackIdentifier, Uuid},
 };
 use sqlx::{
     QueryBuilder, SqlitePool,
     sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous},
 };
-use std::{path::Path, str::FromStr};
+use std::{collections::HashMap, path::Path, str::FromStr};
 use tracing::*;
 
+mod dsp;
+pub use dsp::{SavedDspProfile, SavedEffect, SavedEqBand, SavedEqPreset};
+mod loudness;
+pub use loudness::{Loudness, TrackLoudness, TrackToAnalyze};
+mod plugin;
+pub use plugin::{PluginMount, PluginMountId, SavedPluginLimits};
+mod player;
+pub use player::{SavedOutput, SavedQueue, SavedReplayGain, SavedTransitions};
+mod progress;
+pub use progress::TrackProgress;
+mod speed;
+
+pub use sqlx::Error as DbError;
+
 #[derive(Debug, Clone)]
 pub struct Database {
     pool: SqlitePool,
-    stats: Signal<DbStats, SyncStorage>,
+    stats: Option<Signal<DbStats, SyncStorage>>,
+}
+
+/// What a track belongs to, used to group tracks when shuffling.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrackGrouping {
+    pub album_id: Option<AlbumId>,
+    pub artist_id: Option<ArtistId>,
 }
 
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
@@ -115,6 +136,25 @@ impl Database {
         db_path: P,
         stats: Signal<DbStats, SyncStorage>,
     ) -> sqlx::Result<Self> {
+        let pool = Self::open_pool(db_path).await?;
+
+        let mut db = Self {
+            pool,
+            stats: Some(stats),
+        };
+        db.update_stats().await?;
+
+        Ok(db)
+    }
+
+    /// Connects to the database without a UI runtime to publish stats to, e.g. for CLI usage.
+    pub async fn connect_headless<P: AsRef<Path>>(db_path: P) -> sqlx::Result<Self> {
+        let pool = Self::open_pool(db_path).await?;
+
+        Ok(Self { pool, stats: None })
+    }
+
+    async fn open_pool<P: AsRef<Path>>(db_path: P) -> sqlx::Result<SqlitePool> {
         let db_path = db_path
             .as_ref()
             .to_str()
@@ -127,17 +167,19 @@ impl Database {
         let pool = SqlitePool::connect_with(opts).await?;
         sqlx::migrate!("../../migrations").run(&pool).await?;
 
-        let mut db = Self { pool, stats };
-        db.update_stats().await?;
-
-        Ok(db)
+        Ok(pool)
     }
 
-    pub fn stats(&self) -> Signal<DbStats, SyncStorage> {
+    /// Only available when connected from the UI.
+    pub fn stats(&self) -> Option<Signal<DbStats, SyncStorage>> {
         self.stats
     }
 
     pub async fn update_stats(&mut self) -> sqlx::Result<()> {
+        let Some(mut stats) = self.stats else {
+            return Ok(());
+        };
+
         let result = sqlx::query!(
             "
             SELECT 
@@ -150,7 +192,7 @@ impl Database {
         .fetch_one(&self.pool)
         .await?;
 
-        let mut stats = self.stats.write();
+        let mut stats = stats.write();
         stats.num_tracks = result.num_tracks as usize;
         stats.num_track_groups = result.num_track_groups as usize;
         stats.num_albums = result.num_albums as usize;
@@ -204,6 +246,65 @@ impl Database {
         query.fetch_all(&self.pool).await
     }
 
+    #[tracing::instrument(skip(self))]
+    pub async fn get_track_by_identifier(
+        &self,
+        track: &UniqueTrackIdentifier,
+    ) -> sqlx::Result<Option<Track>> {
+        sqlx::query_as("SELECT * FROM tracks WHERE plugin_id = ? AND plugin_data = ?")
+            .bind(track.plugin_id)
+            .bind(track.plugin_data.clone())
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn get_track_groupings(
+        &self,
+        tracks: &[UniqueTrackIdentifier],
+    ) -> sqlx::Result<HashMap<UniqueTrackIdentifier, TrackGrouping>> {
+        let mut groupings = HashMap::with_capacity(tracks.len());
+
+        // sqlite has a limit on the number of bound parameters per query
+        for chunk in tracks.chunks(1000) {
+            let mut query = QueryBuilder::new(
+                "SELECT plugin_id, plugin_data, album_id, COALESCE(artist_id, album_artist_id)
+                FROM tracks WHERE (plugin_id, plugin_data) IN (",
+            );
+
+            query.push_values(chunk, |mut b, track| {
+                b.push_bind(track.plugin_id)
+                    .push_bind(track.plugin_data.clone());
+            });
+
+            query.push(")");
+
+            let rows: Vec<(
+                PluginId,
+                PluginTrackIdentifier,
+                Option<AlbumId>,
+                Option<ArtistId>,
+            )> = query.build_query_as().fetch_all(&self.pool).await?;
+
+            groupings.extend(rows.into_iter().map(
+                |(plugin_id, plugin_data, album_id, artist_id)| {
+                    (
+                        UniqueTrackIdentifier {
+                            plugin_id,
+                            plugin_data,
+                        },
+                        TrackGrouping {
+                            album_id,
+                            artist_id,
+                        },
+                    )
+                },
+            ));
+        }
+
+        Ok(groupings)
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn get_track_listing(&self) -> BoxStream<sqlx::Result<TrackId>> {
         sqlx::query_scalar(
@@ -213,6 +314,47 @@ impl Database {
         .fetch(&self.pool)
     }
 
+    /// Albums whose ID or title matches `query`, ignoring case.
+    #[tracing::instrument(skip(self))]
+    pub async fn find_albums(&self, query: &str) -> sqlx::Result<Vec<(AlbumId, String)>> {
+        let rows = sqlx::query!(
+            "SELECT album_id, title FROM albums
+            WHERE CAST(album_id AS TEXT) = ?1 OR title = ?1 COLLATE NOCASE
+            ORDER BY album_id",
+            query
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (AlbumId(row.album_id), row.title))
+            .collect())
+    }
+
+    /// The tracks of an album in disc and track order.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_album_tracks(
+        &self,
+        album_id: AlbumId,
+    ) -> sqlx::Result<Vec<UniqueTrackIdentifier>> {
+        let rows = sqlx::query!(
+            "SELECT plugin_id, plugin_data FROM tracks WHERE album_id = ?
+            ORDER BY CAST(disc_number AS INTEGER), CAST(track_number AS INTEGER), track_title",
+            album_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| UniqueTrackIdentifier {
+                plugin_id: PluginId(row.plugin_id),
+                plugin_data: PluginTrackIdentifier(row.plugin_data),
+            })
+            .collect())
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn find_or_create_track(
         &mut self,
@@ -0,0 +1,151 @@
+use crate::Database;
+use hogehoge_types::{AlbumId, PluginId, PluginTrackIdentifier, TrackId, UniqueTrackIdentifier};
+
+/// Result of an EBU R128 measurement.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS.
+    pub integrated_lufs: f32,
+    /// Linear, `1.0` is full scale.
+    pub true_peak: f32,
+}
+
+impl Loudness {
+    /// ReplayGain 2.0 targets -18 LUFS.
+    pub fn replay_gain_db(&self) -> f32 {
+        -18.0 - self.integrated_lufs
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TrackToAnalyze {
+    pub track_id: TrackId,
+    pub identifier: UniqueTrackIdentifier,
+    pub album_id: Option<AlbumId>,
+    /// Whether the track already has ReplayGain tags.
+    pub tagged: bool,
+}
+
+/// Measured loudness of a track and of the album it belongs to.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrackLoudness {
+    pub track: Option<Loudness>,
+    pub album: Option<Loudness>,
+}
+
+impl Database {
+    /// Tracks without ReplayGain tags that haven't been analyzed yet, ordered by album.
+    ///
+    /// Album loudness can only be measured over the whole album, so every track of an album that
+    /// contains such a track is included.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_tracks_to_analyze(&self) -> sqlx::Result<Vec<TrackToAnalyze>> {
+        let rows: Vec<(
+            TrackId,
+            PluginId,
+            PluginTrackIdentifier,
+            Option<AlbumId>,
+            bool,
+        )> = sqlx::query_as(
+            "SELECT track_id, plugin_id, plugin_data, album_id,
+                replay_gain_track_gain IS NOT NULL FROM tracks
+                WHERE album_id IN (
+                    SELECT album_id FROM tracks
+                    WHERE loudness_lufs IS NULL AND replay_gain_track_gain IS NULL
+                )
+                OR (album_id IS NULL AND loudness_lufs IS NULL AND replay_gain_track_gain IS NULL)
+                ORDER BY album_id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(
+                |(track_id, plugin_id, plugin_data, album_id, tagged)| TrackToAnalyze {
+                    track_id,
+                    identifier: UniqueTrackIdentifier {
+                        plugin_id,
+                        plugin_data,
+                    },
+                    album_id,
+                    tagged,
+                },
+            )
+            .collect())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn save_track_loudness(
+        &self,
+        track_id: TrackId,
+        loudness: Loudness,
+    ) -> sqlx::Result<()> {
+        let integrated_lufs = loudness.integrated_lufs as f64;
+        let true_peak = loudness.true_peak as f64;
+
+        sqlx::query!(
+            "UPDATE tracks SET loudness_lufs = ?, true_peak = ? WHERE track_id = ?",
+            integrated_lufs,
+            true_peak,
+            track_id.0
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn save_album_loudness(
+        &self,
+        album_id: AlbumId,
+        loudness: Loudness,
+    ) -> sqlx::Result<()> {
+        let integrated_lufs = loudness.integrated_lufs as f64;
+        let true_peak = loudness.true_peak as f64;
+
+        sqlx::query!(
+            "UPDATE albums SET loudness_lufs = ?, true_peak = ? WHERE album_id = ?",
+            integrated_lufs,
+            true_peak,
+            album_id.0
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get_track_loudness(
+        &self,
+        track: &UniqueTrackIdentifier,
+    ) -> sqlx::Result<TrackLoudness> {
+        let row: Option<(Option<f64>, Option<f64>, Option<f64>, Option<f64>)> = sqlx::query_as(
+            "SELECT tracks.loudness_lufs, tracks.true_peak, albums.loudness_lufs, albums.true_peak
+            FROM tracks LEFT JOIN albums ON tracks.album_id = albums.album_id
+            WHERE tracks.plugin_id = ? AND tracks.plugin_data = ?",
+        )
+        .bind(track.plugin_id)
+        .bind(track.plugin_data.clone())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let loudness = |integrated_lufs: Option<f64>, true_peak: Option<f64>| {
+            Some(Loudness {
+                integrated_lufs: integrated_lufs? as f32,
+                true_peak: true_peak? as f32,
+            })
+        };
+
+        Ok(row
+            .map(
+                |(track_lufs, track_peak, album_lufs, album_peak)| TrackLoudness {
+                    track: loudness(track_lufs, track_peak),
+                    album: loudness(album_lufs, album_peak),
+                },
+            )
+            .unwrap_or_default())
+    }
+}
@@ -0,0 +1,300 @@
+use crate::Database;
+use hogehoge_types::{PluginId, PluginTrackIdentifier, UniqueTrackIdentifier};
+use std::time::Duration;
+use tracing::*;
+
+/// The play queue as it was last saved.
+#[derive(Debug, Clone, Default)]
+pub struct SavedQueue {
+    pub past: Vec<UniqueTrackIdentifier>,
+    pub current: Option<UniqueTrackIdentifier>,
+    pub future: Vec<UniqueTrackIdentifier>,
+    /// Order of `future` from before it was shuffled.
+    pub unshuffled: Vec<UniqueTrackIdentifier>,
+
+    pub repeat_mode: String,
+    pub shuffle_mode: String,
+}
+
+/// ReplayGain settings as they were last saved.
+#[derive(Debug, Clone)]
+pub struct SavedReplayGain {
+    pub mode: String,
+    pub preamp_db: f32,
+    pub fallback_db: f32,
+    pub prevent_clipping: bool,
+}
+
+/// Crossfade and fade durations as they were last saved.
+#[derive(Debug, Clone)]
+pub struct SavedTransitions {
+    pub crossfade: Duration,
+    pub skip_fade: Duration,
+    pub pause_fade: Duration,
+}
+
+/// Output format settings as they were last saved.
+#[derive(Debug, Clone)]
+pub struct SavedOutput {
+    pub sample_rate: Option<u32>,
+    pub resampler_quality: String,
+}
+
+impl Database {
+    #[tracing::instrument(skip(self))]
+    pub async fn load_queue(&self) -> sqlx::Result<SavedQueue> {
+        let mut queue = SavedQueue::default();
+
+        let entries = sqlx::query!(
+            "SELECT section, plugin_id, plugin_data FROM queue_entries ORDER BY queue_entry_id"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        for entry in entries {
+            let track = UniqueTrackIdentifier {
+                plugin_id: PluginId(entry.plugin_id),
+                plugin_data: PluginTrackIdentifier(entry.plugin_data),
+            };
+
+            match entry.section.as_str() {
+                "past" => queue.past.push(track),
+                "current" => queue.current = Some(track),
+                "future" => queue.future.push(track),
+                "unshuffled" => queue.unshuffled.push(track),
+                section => warn!("Ignoring queue entry in unknown section '{}'", section),
+            }
+        }
+
+        let state = sqlx::query!("SELECT repeat_mode, shuffle_mode FROM player_state")
+            .fetch_one(&self.pool)
+            .await?;
+
+        queue.repeat_mode = state.repeat_mode;
+        queue.shuffle_mode = state.shuffle_mode;
+
+        Ok(queue)
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn save_queue(&self, queue: &SavedQueue) -> sqlx::Result<()> {
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query!("DELETE FROM queue_entries")
+            .execute(&mut *transaction)
+            .await?;
+
+        let sections = [
+            ("past", queue.past.as_slice()),
+            ("current", queue.current.as_slice()),
+            ("future", queue.future.as_slice()),
+            ("unshuffled", queue.unshuffled.as_slice()),
+        ];
+
+        for (section, tracks) in sections {
+            for track in tracks {
+                sqlx::query!(
+                    "INSERT INTO queue_entries (section, plugin_id, plugin_data) VALUES (?, ?, ?)",
+                    section,
+                    track.plugin_id.0,
+                    track.plugin_data.0
+                )
+                .execute(&mut *transaction)
+                .await?;
+            }
+        }
+
+        sqlx::query!(
+            "UPDATE player_state SET repeat_mode = ?, shuffle_mode = ?",
+            queue.repeat_mode,
+            queue.shuffle_mode
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn load_playback_position(&self) -> sqlx::Result<Duration> {
+        let position_ms = sqlx::query_scalar!("SELECT position_ms FROM player_state")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(Duration::from_millis(position_ms.max(0) as u64))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn save_playback_position(&self, position: Duration) -> sqlx::Result<()> {
+        let position_ms = position.as_millis() as i64;
+
+        sqlx::query!("UPDATE player_state SET position_ms = ?", position_ms)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Tracks longer than this have their position remembered.
+    #[tracing::instrument(skip(self))]
+    pub async fn load_resume_threshold(&self) -> sqlx::Result<Duration> {
+        let threshold_ms = sqlx::query_scalar!("SELECT resume_threshold_ms FROM player_state")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(Duration::from_millis(threshold_ms.max(0) as u64))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn save_resume_threshold(&self, threshold: Duration) -> sqlx::Result<()> {
+        let threshold_ms = threshold.as_millis() as i64;
+
+        sqlx::query!(
+            "UPDATE player_state SET resume_threshold_ms = ?",
+            threshold_ms
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The saved volume level and whether it was muted.
+    #[tracing::instrument(skip(self))]
+    pub async fn load_volume(&self) -> sqlx::Result<(f32, bool)> {
+        let state = sqlx::query!("SELECT volume, muted FROM player_state")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok((state.volume as f32, state.muted != 0))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn save_volume(&self, level: f32, muted: bool) -> sqlx::Result<()> {
+        let level = level as f64;
+
+        sqlx::query!(
+            "UPDATE player_state SET volume = ?, muted = ?",
+            level,
+            muted
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn load_replay_gain(&self) -> sqlx::Result<SavedReplayGain> {
+        let state = sqlx::query!(
+            "SELECT replay_gain_mode, replay_gain_preamp_db, replay_gain_fallback_db,
+            replay_gain_prevent_clipping FROM player_state"
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(SavedReplayGain {
+            mode: state.replay_gain_mode,
+            preamp_db: state.replay_gain_preamp_db as f32,
+            fallback_db: state.replay_gain_fallback_db as f32,
+            prevent_clipping: state.replay_gain_prevent_clipping != 0,
+        })
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn save_replay_gain(&self, replay_gain: &SavedReplayGain) -> sqlx::Result<()> {
+        let preamp_db = replay_gain.preamp_db as f64;
+        let fallback_db = replay_gain.fallback_db as f64;
+
+        sqlx::query!(
+            "UPDATE player_state SET replay_gain_mode = ?, replay_gain_preamp_db = ?,
+            replay_gain_fallback_db = ?, replay_gain_prevent_clipping = ?",
+            replay_gain.mode,
+            preamp_db,
+            fallback_db,
+            replay_gain.prevent_clipping
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn load_transitions(&self) -> sqlx::Result<SavedTransitions> {
+        let state =
+            sqlx::query!("SELECT crossfade_ms, skip_fade_ms, pause_fade_ms FROM player_state")
+                .fetch_one(&self.pool)
+                .await?;
+
+        let duration = |ms: i64| Duration::from_millis(ms.max(0) as u64);
+
+        Ok(SavedTransitions {
+            crossfade: duration(state.crossfade_ms),
+            skip_fade: duration(state.skip_fade_ms),
+            pause_fade: duration(state.pause_fade_ms),
+        })
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn save_transitions(&self, transitions: &SavedTransitions) -> sqlx::Result<()> {
+        let crossfade_ms = transitions.crossfade.as_millis() as i64;
+        let skip_fade_ms = transitions.skip_fade.as_millis() as i64;
+        let pause_fade_ms = transitions.pause_fade.as_millis() as i64;
+
+        sqlx::query!(
+            "UPDATE player_state SET crossfade_ms = ?, skip_fade_ms = ?, pause_fade_ms = ?",
+            crossfade_ms,
+            skip_fade_ms,
+            pause_fade_ms
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn load_output(&self) -> sqlx::Result<SavedOutput> {
+        let state = sqlx::query!("SELECT output_sample_rate, resampler_quality FROM player_state")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(SavedOutput {
+            sample_rate: state
+                .output_sample_rate
+                .and_then(|sample_rate| u32::try_from(sample_rate).ok()),
+            resampler_quality: state.resampler_quality,
+        })
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn save_output(&self, output: &SavedOutput) -> sqlx::Result<()> {
+        sqlx::query!(
+            "UPDATE player_state SET output_sample_rate = ?, resampler_quality = ?",
+            output.sample_rate,
+            output.resampler_quality
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Name of the selected output device, `None` for the default device.
+    #[tracing::instrument(skip(self))]
+    pub async fn load_output_device(&self) -> sqlx::Result<Option<String>> {
+        sqlx::query_scalar!("SELECT output_device FROM player_state")
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn save_output_device(&self, device: Option<&str>) -> sqlx::Result<()> {
+        sqlx::query!("UPDATE player_state SET output_device = ?", device)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
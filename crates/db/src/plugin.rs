@@ -0,0 +1,243 @@
+use crate::Database;
+use hogehoge_types::PluginId;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PluginMountId(pub i64);
+
+/// A host directory that is mounted into a plugin for one of its declared `FsMount`s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginMount {
+    pub id: PluginMountId,
+    pub plugin_id: PluginId,
+    pub internal_path: String,
+    pub host_path: PathBuf,
+}
+
+/// Resource limits of a plugin that were changed from the defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SavedPluginLimits {
+    pub memory_pages: u32,
+    pub timeout: Duration,
+}
+
+impl PluginMount {
+    /// Path the host directory shows up at inside the plugin.
+    ///
+    /// Since multiple host directories can back the same `FsMount`, each one gets its own
+    /// subdirectory of the declared internal path. It is named after the host path, so paths the
+    /// plugin builds its identifiers from stay the same when a directory is removed and added
+    /// again.
+    pub fn guest_path(&self) -> String {
+        let host_path = self.host_path.to_string_lossy();
+        let subdirectory = host_path
+            .strip_prefix(r"\\?\")
+            .unwrap_or(&host_path)
+            .replace('\\', "/")
+            .replace(':', "");
+
+        format!(
+            "{}/{}",
+            self.internal_path.trim_end_matches('/'),
+            subdirectory.trim_matches('/')
+        )
+    }
+}
+
+impl Database {
+    #[tracing::instrument(skip(self))]
+    pub async fn get_plugin_mounts(&self, plugin_id: PluginId) -> sqlx::Result<Vec<PluginMount>> {
+        let mounts = sqlx::query!(
+            "SELECT plugin_mount_id, internal_path, host_path FROM plugin_mounts WHERE plugin_id = ? ORDER BY plugin_mount_id",
+            plugin_id.0
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| PluginMount {
+            id: PluginMountId(row.plugin_mount_id),
+            plugin_id,
+            internal_path: row.internal_path,
+            host_path: PathBuf::from(row.host_path),
+        })
+        .collect();
+
+        Ok(mounts)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn add_plugin_mount(
+        &self,
+        plugin_id: PluginId,
+        internal_path: &str,
+        host_path: &Path,
+    ) -> sqlx::Result<PluginMountId> {
+        let host_path = host_path
+            .to_str()
+            .ok_or_else(|| sqlx::Error::Encode("Host path is not valid UTF-8".into()))?;
+
+        let plugin_mount_id = sqlx::query!(
+            "INSERT INTO plugin_mounts (plugin_id, internal_path, host_path) VALUES (?, ?, ?) RETURNING plugin_mount_id",
+            plugin_id.0,
+            internal_path,
+            host_path
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .plugin_mount_id;
+
+        trace!("Created new plugin mount with ID: {}", plugin_mount_id);
+
+        Ok(PluginMountId(plugin_mount_id))
+    }
+
+    /// Removes a mount, returning the plugin it belonged to.
+    #[tracing::instrument(skip(self))]
+    pub async fn remove_plugin_mount(
+        &self,
+        mount_id: PluginMountId,
+    ) -> sqlx::Result<Option<PluginId>> {
+        Ok(sqlx::query_scalar!(
+            "DELETE FROM plugin_mounts WHERE plugin_mount_id = ? RETURNING plugin_id",
+            mount_id.0
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .map(PluginId))
+    }
+
+    /// The saved value of every setting of the plugin that was changed from its default.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_plugin_settings(
+        &self,
+        plugin_id: PluginId,
+    ) -> sqlx::Result<HashMap<String, String>> {
+        let settings = sqlx::query!(
+            "SELECT key, value FROM plugin_settings WHERE plugin_id = ?",
+            plugin_id.0
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| (row.key, row.value))
+        .collect();
+
+        Ok(settings)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn set_plugin_setting(
+        &self,
+        plugin_id: PluginId,
+        key: &str,
+        value: &str,
+    ) -> sqlx::Result<()> {
+        sqlx::query!(
+            "INSERT INTO plugin_settings (plugin_id, key, value) VALUES (?, ?, ?)
+            ON CONFLICT (plugin_id, key) DO UPDATE SET value = excluded.value",
+            plugin_id.0,
+            key,
+            value
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get_plugin_limits(
+        &self,
+        plugin_id: PluginId,
+    ) -> sqlx::Result<Option<SavedPluginLimits>> {
+        let limits = sqlx::query!(
+            "SELECT memory_pages, timeout_ms FROM plugin_limits WHERE plugin_id = ?",
+            plugin_id.0
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|row| SavedPluginLimits {
+            memory_pages: row.memory_pages.clamp(0, u32::MAX as i64) as u32,
+            timeout: Duration::from_millis(row.timeout_ms.max(0) as u64),
+        });
+
+        Ok(limits)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn set_plugin_limits(
+        &self,
+        plugin_id: PluginId,
+        limits: &SavedPluginLimits,
+    ) -> sqlx::Result<()> {
+        let timeout_ms = limits.timeout.as_millis() as i64;
+
+        sqlx::query!(
+            "INSERT INTO plugin_limits (plugin_id, memory_pages, timeout_ms) VALUES (?, ?, ?)
+            ON CONFLICT (plugin_id) DO UPDATE
+            SET memory_pages = excluded.memory_pages, timeout_ms = excluded.timeout_ms",
+            plugin_id.0,
+            limits.memory_pages,
+            timeout_ms
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// A value from the key value store plugins get through the host functions.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_plugin_value(
+        &self,
+        plugin_id: PluginId,
+        key: &str,
+    ) -> sqlx::Result<Option<Vec<u8>>> {
+        sqlx::query_scalar!(
+            "SELECT value FROM plugin_storage WHERE plugin_id = ? AND key = ?",
+            plugin_id.0,
+            key
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Stores `value` under `key`, `None` removes the key.
+    #[tracing::instrument(skip(self, value))]
+    pub async fn set_plugin_value(
+        &self,
+        plugin_id: PluginId,
+        key: &str,
+        value: Option<&[u8]>,
+    ) -> sqlx::Result<()> {
+        match value {
+            Some(value) => {
+                sqlx::query!(
+                    "INSERT INTO plugin_storage (plugin_id, key, value) VALUES (?, ?, ?)
+                    ON CONFLICT (plugin_id, key) DO UPDATE SET value = excluded.value",
+                    plugin_id.0,
+                    key,
+                    value
+                )
+                .execute(&self.pool)
+                .await?;
+            }
+            None => {
+                sqlx::query!(
+                    "DELETE FROM plugin_storage WHERE plugin_id = ? AND key = ?",
+                    plugin_id.0,
+                    key
+                )
+                .execute(&self.pool)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+}
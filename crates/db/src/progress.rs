@@ -0,0 +1,57 @@
+use crate::Database;
+use hogehoge_types::UniqueTrackIdentifier;
+use std::time::Duration;
+
+/// How far a track that is resumed was played the last time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackProgress {
+    pub position: Duration,
+    /// Played up to the end, so it starts over the next time.
+    pub finished: bool,
+}
+
+impl Database {
+    #[tracing::instrument(skip(self))]
+    pub async fn load_track_progress(
+        &self,
+        track: &UniqueTrackIdentifier,
+    ) -> sqlx::Result<Option<TrackProgress>> {
+        let progress = sqlx::query!(
+            "SELECT position_ms, finished FROM track_progress
+            WHERE plugin_id = ? AND plugin_data = ?",
+            track.plugin_id.0,
+            track.plugin_data.0
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(progress.map(|progress| TrackProgress {
+            position: Duration::from_millis(progress.position_ms.max(0) as u64),
+            finished: progress.finished != 0,
+        }))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn save_track_progress(
+        &self,
+        track: &UniqueTrackIdentifier,
+        progress: TrackProgress,
+    ) -> sqlx::Result<()> {
+        let position_ms = progress.position.as_millis() as i64;
+
+        sqlx::query!(
+            "INSERT INTO track_progress (plugin_id, plugin_data, position_ms, finished)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (plugin_id, plugin_data)
+            DO UPDATE SET position_ms = excluded.position_ms, finished = excluded.finished",
+            track.plugin_id.0,
+            track.plugin_data.0,
+            position_ms,
+            progress.finished
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use crate::Database;
+use hogehoge_types::UniqueTrackIdentifier;
+
+impl Database {
+    #[tracing::instrument(skip(self))]
+    pub async fn load_track_speed(
+        &self,
+        track: &UniqueTrackIdentifier,
+    ) -> sqlx::Result<Option<f32>> {
+        let speed = sqlx::query_scalar!(
+            "SELECT speed FROM track_playback_speeds WHERE plugin_id = ? AND plugin_data = ?",
+            track.plugin_id.0,
+            track.plugin_data.0
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(speed.map(|speed| speed as f32))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn save_track_speed(
+        &self,
+        track: &UniqueTrackIdentifier,
+        speed: f32,
+    ) -> sqlx::Result<()> {
+        let speed = speed as f64;
+
+        sqlx::query!(
+            "INSERT INTO track_playback_speeds (plugin_id, plugin_data, speed) VALUES (?, ?, ?)
+            ON CONFLICT (plugin_id, plugin_data) DO UPDATE SET speed = excluded.speed",
+            track.plugin_id.0,
+            track.plugin_data.0,
+            speed
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn load_show_speed(&self, show: &str) -> sqlx::Result<Option<f32>> {
+        let speed = sqlx::query_scalar!(
+            "SELECT speed FROM show_playback_speeds WHERE show = ?",
+            show
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(speed.map(|speed| speed as f32))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn save_show_speed(&self, show: &str, speed: f32) -> sqlx::Result<()> {
+        let speed = speed as f64;
+
+        sqlx::query!(
+            "INSERT INTO show_playback_speeds (show, speed) VALUES (?, ?)
+            ON CONFLICT (show) DO UPDATE SET speed = excluded.speed",
+            show,
+            speed
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+/// Generates the functions the host calls into, for the plugin and each capability it provides.
+/// This also reports the plugin API version the plugin is built against. The functions are named
+/// after [`exports`](crate::types::exports), which the host looks them up by.
+///
+/// The first entry names the type implementing [`Plugin`](crate::Plugin), the others are any of:
+/// - `track_provider`: a [`TrackProvider`](crate::TrackProvider)
+/// - `replay_gain_writer`: a [`ReplayGainWriter`](crate::ReplayGainWriter)
+/// - `decoder`: a [`Decoder`](crate::Decoder)
+/// - `seekable_decoder`: a [`Seek`](crate::Seek)able decoder, instead of `decoder`
+/// - `effect`: an [`Effect`](crate::Effect)
+/// - `visualizer`: a [`Visualizer`](crate::Visualizer)
+#[macro_export]
+macro_rules! export {
+    (plugin: $plugin:ty $(, $capability:ident: $implementor:ty)* $(,)?) => {
+        #[$crate::extism_pdk::plugin_fn]
+        pub fn get_api_version() -> $crate::FnResult<$crate::types::ApiVersion> {
+            Ok($crate::types::ApiVersion($crate::types::PLUGIN_API_VERSION))
+        }
+
+        #[$crate::extism_pdk::plugin_fn]
+        pub fn get_metadata() -> $crate::FnResult<$crate::types::PluginMetadata> {
+            Ok(<$plugin as $crate::Plugin>::metadata())
+        }
+
+        #[$crate::extism_pdk::plugin_fn]
+        pub fn configure(settings: $crate::types::PluginSettings) -> $crate::FnResult<()> {
+            <$plugin as $crate::Plugin>::configure(settings)
+        }
+
+        $($crate::__export_capability!($capability: $implementor);)*
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __export_capability {
+    (track_provider: $provider:ty) => {
+        #[$crate::extism_pdk::plugin_fn]
+        pub fn prepare_scan() -> $crate::FnResult<$crate::types::PreparedScan> {
+            <$provider as $crate::TrackProvider>::prepare_scan()
+        }
+
+        #[$crate::extism_pdk::plugin_fn]
+        pub fn scan(
+            track: $crate::types::PluginTrackIdentifier,
+        ) -> $crate::FnResult<$crate::types::ScanResult> {
+            <$provider as $crate::TrackProvider>::scan(track)
+        }
+
+        #[$crate::extism_pdk::plugin_fn]
+        pub fn get_audio_file(
+            track: $crate::types::PluginTrackIdentifier,
+        ) -> $crate::FnResult<$crate::types::AudioFile> {
+            <$provider as $crate::TrackProvider>::get_audio_file(track)
+        }
+    };
+
+    (replay_gain_writer: $writer:ty) => {
+        #[$crate::extism_pdk::plugin_fn]
+        pub fn write_replay_gain(
+            args: $crate::types::WriteReplayGainArgs,
+        ) -> $crate::FnResult<()> {
+            <$writer as $crate::ReplayGainWriter>::write_replay_gain(args)
+        }
+    };
+
+    (decoder: $decoder:ty) => {
+        static __HOGEHOGE_DECODERS: $crate::Instances<$crate::types::PlaybackId, $decoder> =
+            $crate::Instances::new();
+
+        #[$crate::extism_pdk::plugin_fn]
+        pub fn init_decoding(
+            args: $crate::types::InitDecodingArgs,
+        ) -> $crate::FnResult<$crate::types::InitDecodingResult> {
+            let decoder = <$decoder as $crate::Decoder>::init(args.file, args.gapless)?;
+            let duration = $crate::Decoder::duration(&decoder);
+            __HOGEHOGE_DECODERS.insert(args.playback_id, decoder);
+
+            Ok($crate::types::InitDecodingResult { duration })
+        }
+
+        #[$crate::extism_pdk::plugin_fn]
+        pub fn decode_block(
+            playback_id: $crate::types::PlaybackId,
+        ) -> $crate::FnResult<Option<$crate::types::AudioBlock>> {
+            __HOGEHOGE_DECODERS.with(&playback_id, <$decoder as $crate::Decoder>::decode_block)
+        }
+
+        #[$crate::extism_pdk::plugin_fn]
+        pub fn finish_decoding(playback_id: $crate::types::PlaybackId) -> $crate::FnResult<()> {
+            __HOGEHOGE_DECODERS.remove(&playback_id);
+            Ok(())
+        }
+    };
+
+    (seekable_decoder: $decoder:ty) => {
+        $crate::__export_capability!(decoder: $decoder);
+
+        #[$crate::extism_pdk::plugin_fn]
+        pub fn seek(
+            args: $crate::types::SeekArgs,
+        ) -> $crate::FnResult<$crate::types::SeekResult> {
+            let position = __HOGEHOGE_DECODERS.with(&args.playback_id, |decoder| {
+                $crate::Seek::seek(decoder, args.position)
+            })?;
+
+            Ok($crate::types::SeekResult { position })
+        }
+    };
+
+    (effect: $effect:ty) => {
+        static __HOGEHOGE_EFFECTS: $crate::Instances<$crate::types::EffectId, $effect> =
+            $crate::Instances::new();
+
+        #[$crate::extism_pdk::plugin_fn]
+        pub fn init_effect(args: $crate::types::InitEffectArgs) -> $crate::FnResult<()> {
+            let effect = <$effect as $crate::Effect>::init(args.sample_rate, args.channel_count)?;
+            __HOGEHOGE_EFFECTS.insert(args.effect_id, effect);
+
+            Ok(())
+        }
+
+        #[$crate::extism_pdk::plugin_fn]
+        pub fn process_block(
+            args: $crate::types::ProcessBlockArgs,
+        ) -> $crate::FnResult<$crate::types::AudioBlock> {
+            __HOGEHOGE_EFFECTS.with(&args.effect_id, |effect| {
+                $crate::Effect::process_block(effect, args.block)
+            })
+        }
+
+        #[$crate::extism_pdk::plugin_fn]
+        pub fn finish_effect(effect_id: $crate::types::EffectId) -> $crate::FnResult<()> {
+            __HOGEHOGE_EFFECTS.remove(&effect_id);
+            Ok(())
+        }
+    };
+
+    (visualizer: $visualizer:ty) => {
+        #[$crate::extism_pdk::plugin_fn]
+        pub fn visualize(frame: $crate::types::VisualizerFrame) -> $crate::FnResult<()> {
+            <$visualizer as $crate::Visualizer>::visualize(frame)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use hogehoge_types::exports;
+
+    // `plugin_fn` takes the export name from the function name, so the constants can't be used
+    #[test]
+    fn every_export_is_generated() {
+        let source = include_str!("export.rs");
+
+        for name in exports::ALL {
+            assert!(
+                source.contains(&format!("pub fn {}(", name)),
+                "export! doesn't generate '{}'",
+                name
+            );
+        }
+    }
+}
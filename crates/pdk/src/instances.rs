@@ -0,0 +1,52 @@
+use extism_pdk::FnResult;
+use std::{collections::HashMap, hash::Hash, sync::Mutex};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum InstanceError {
+    #[error("No instance was initialized for the given ID")]
+    NotInitialized,
+}
+
+/// Per playback or per effect state, kept between calls from the host.
+pub struct Instances<K, T> {
+    // created on first use, `HashMap::new` can't be used in a static
+    instances: Mutex<Option<HashMap<K, T>>>,
+}
+
+impl<K: Eq + Hash, T> Instances<K, T> {
+    pub const fn new() -> Self {
+        Instances {
+            instances: Mutex::new(None),
+        }
+    }
+
+    pub fn insert(&self, id: K, instance: T) {
+        self.instances
+            .lock()
+            .unwrap()
+            .get_or_insert_with(HashMap::new)
+            .insert(id, instance);
+    }
+
+    /// Runs `f` with the instance for `id`, failing if there is none.
+    pub fn with<R>(&self, id: &K, f: impl FnOnce(&mut T) -> FnResult<R>) -> FnResult<R> {
+        let mut instances = self.instances.lock().unwrap();
+        let instance = instances
+            .as_mut()
+            .and_then(|instances| instances.get_mut(id))
+            .ok_or(InstanceError::NotInitialized)?;
+
+        f(instance)
+    }
+
+    pub fn remove(&self, id: &K) -> Option<T> {
+        self.instances.lock().unwrap().as_mut()?.remove(id)
+    }
+}
+
+impl<K: Eq + Hash, T> Default for Instances<K, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,87 @@
+//! Everything needed to write a hogehoge plugin.
+//!
+//! A plugin implements [`Plugin`] and the traits of whatever it provides, then hands them to
+//! [`export!`], which generates the functions the host looks for:
+//!
+//! ```ignore
+//! struct Crossfeed { /* ... */ }
+//!
+//! impl Plugin for Crossfeed { /* ... */ }
+//! impl Effect for Crossfeed { /* ... */ }
+//!
+//! hogehoge_pdk::export! {
+//!     plugin: Crossfeed,
+//!     effect: Crossfeed,
+//! }
+//! ```
+//!
+//! The generated functions use `#[plugin_fn]`, so plugins still need to depend on `extism-pdk`.
+
+use hogehoge_types::*;
+use std::time::Duration;
+
+mod export;
+mod instances;
+pub use instances::{InstanceError, Instances};
+
+pub use extism_pdk::{self, FnResult};
+pub use hogehoge_types::{self as types, guest as host};
+
+pub trait Plugin {
+    fn metadata() -> PluginMetadata;
+
+    /// Called with the values of the declared settings before the plugin is used, and again
+    /// whenever they change.
+    fn configure(settings: PluginSettings) -> FnResult<()> {
+        let _ = settings;
+        Ok(())
+    }
+}
+
+/// Finds tracks and hands out their files.
+pub trait TrackProvider {
+    /// Lists every track the plugin provides.
+    fn prepare_scan() -> FnResult<PreparedScan>;
+
+    fn scan(track: PluginTrackIdentifier) -> FnResult<ScanResult>;
+
+    fn get_audio_file(track: PluginTrackIdentifier) -> FnResult<AudioFile>;
+}
+
+/// Stores the results of the loudness analysis in the files of provided tracks.
+pub trait ReplayGainWriter {
+    fn write_replay_gain(args: WriteReplayGainArgs) -> FnResult<()>;
+}
+
+/// Decodes a single file, one instance is created for every playback.
+pub trait Decoder: Sized + Send {
+    fn init(file: AudioFile, gapless: bool) -> FnResult<Self>;
+
+    fn duration(&self) -> Option<Duration> {
+        None
+    }
+
+    /// The next block of audio, `None` once the file ended.
+    fn decode_block(&mut self) -> FnResult<Option<AudioBlock>>;
+}
+
+/// A decoder that can jump to any position.
+pub trait Seek: Decoder {
+    /// Returns the position decoding actually continues from.
+    fn seek(&mut self, position: Duration) -> FnResult<Duration>;
+}
+
+/// Processes the audio before it is played, one instance is created for every time the effect
+/// is added to a chain.
+pub trait Effect: Sized + Send {
+    fn init(sample_rate: SampleRate, channel_count: ChannelCount) -> FnResult<Self>;
+
+    /// Must return exactly as many samples as it was given.
+    fn process_block(&mut self, block: AudioBlock) -> FnResult<AudioBlock>;
+}
+
+/// Follows what is playing, e.g. to drive lights or an external display. Frames that come in
+/// while the plugin is still busy with the previous one are skipped.
+pub trait Visualizer {
+    fn visualize(frame: VisualizerFrame) -> FnResult<()>;
+}
@@ -20,6 +20,25 @@ impl Default for PlaybackId {
     }
 }
 
+/// Identifies one running instance of an effect, so a plugin can keep state for several at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ToBytes, FromBytes, Serialize, Deserialize)]
+#[encoding(Msgpack)]
+pub struct EffectId(Uuid);
+
+impl EffectId {
+    #[cfg(feature = "internal")]
+    pub fn new() -> Self {
+        EffectId(Uuid::new_v4())
+    }
+}
+
+#[cfg(feature = "internal")]
+impl Default for EffectId {
+    fn default() -> Self {
+        EffectId::new()
+    }
+}
+
 #[derive(Debug, Clone, ToBytes, FromBytes, Serialize, Deserialize)]
 #[encoding(Msgpack)]
 pub struct AudioFile {
@@ -39,3 +58,17 @@ pub struct AudioBlock {
     pub sample_rate: u32,
     pub channel_count: u16,
 }
+
+/// A snapshot of what is playing right now, for drawing spectrum analyzers and oscilloscopes.
+///
+/// Both are taken after the DSP chain, but before the volume is applied.
+#[derive(Debug, Clone, Default, PartialEq, ToBytes, FromBytes, Serialize, Deserialize)]
+#[encoding(Msgpack)]
+pub struct VisualizerFrame {
+    /// The most recent audio mixed down to mono and decimated, oldest first.
+    pub waveform: Vec<Sample>,
+    /// Magnitude of each FFT bin in dBFS, from 0 Hz up to half the sample rate.
+    pub spectrum: Vec<f32>,
+    /// Frequency range each bin of `spectrum` covers, in Hz.
+    pub bin_width: f32,
+}
@@ -0,0 +1,87 @@
+//! Typed wrappers around the host functions, for use from within plugins.
+
+use crate::{
+    host::*,
+    library::{AlbumId, Tags},
+    plugin::PluginTrackIdentifier,
+};
+use extism_pdk::{Error, Msgpack, host_fn};
+use std::time::{Duration, SystemTime};
+
+mod imports {
+    use super::*;
+
+    #[host_fn]
+    extern "ExtismHost" {
+        pub fn hogehoge_log(record: LogRecord);
+        pub fn hogehoge_storage_get(key: String) -> StoredValue;
+        pub fn hogehoge_storage_set(args: StorageSetArgs);
+        pub fn hogehoge_now() -> Msgpack<u64>;
+        pub fn hogehoge_notify(notification: HostNotification);
+        pub fn hogehoge_find_albums(query: String) -> Msgpack<Vec<LibraryAlbum>>;
+        pub fn hogehoge_album_tracks(album_id: AlbumId) -> Msgpack<Vec<Tags>>;
+        pub fn hogehoge_track_tags(track: PluginTrackIdentifier) -> Msgpack<Option<Tags>>;
+    }
+}
+
+/// Logs through the host. Logging never fails the plugin, records the host can't take are lost.
+pub fn log(level: LogLevel, message: impl Into<String>) {
+    log_record(LogRecord::new(level, message));
+}
+
+pub fn log_record(record: LogRecord) {
+    let _ = unsafe { imports::hogehoge_log(record) };
+}
+
+/// A value the plugin stored earlier, the store is kept across restarts.
+pub fn storage_get(key: impl Into<String>) -> Result<Option<Vec<u8>>, Error> {
+    Ok(unsafe { imports::hogehoge_storage_get(key.into()) }?.0)
+}
+
+pub fn storage_set(key: impl Into<String>, value: impl Into<Vec<u8>>) -> Result<(), Error> {
+    unsafe {
+        imports::hogehoge_storage_set(StorageSetArgs {
+            key: key.into(),
+            value: Some(value.into()),
+        })
+    }
+}
+
+pub fn storage_remove(key: impl Into<String>) -> Result<(), Error> {
+    unsafe {
+        imports::hogehoge_storage_set(StorageSetArgs {
+            key: key.into(),
+            value: None,
+        })
+    }
+}
+
+/// The current time of the host, WASI clocks aren't available to every plugin.
+pub fn now() -> Result<SystemTime, Error> {
+    let millis = unsafe { imports::hogehoge_now() }?.0;
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_millis(millis))
+}
+
+pub fn notify(title: impl Into<String>, message: impl Into<String>) -> Result<(), Error> {
+    unsafe {
+        imports::hogehoge_notify(HostNotification {
+            title: title.into(),
+            message: message.into(),
+        })
+    }
+}
+
+/// Albums in the library whose ID or title matches `query`, ignoring case.
+pub fn find_albums(query: impl Into<String>) -> Result<Vec<LibraryAlbum>, Error> {
+    Ok(unsafe { imports::hogehoge_find_albums(query.into()) }?.0)
+}
+
+/// Tags of the tracks of an album in disc and track order, no matter which plugin provides them.
+pub fn album_tracks(album_id: AlbumId) -> Result<Vec<Tags>, Error> {
+    Ok(unsafe { imports::hogehoge_album_tracks(album_id) }?.0)
+}
+
+/// Tags of a track provided by this plugin, as they were imported into the library.
+pub fn track_tags(track: &PluginTrackIdentifier) -> Result<Option<Tags>, Error> {
+    Ok(unsafe { imports::hogehoge_track_tags(track.clone()) }?.0)
+}
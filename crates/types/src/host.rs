@@ -0,0 +1,81 @@
+//! Types passed to and from the host functions plugins can call. Plugins built with the `pdk`
+//! feature get typed wrappers for them in [`crate::guest`].
+
+use crate::library::AlbumId;
+use extism_convert::{FromBytes, Msgpack, ToBytes};
+use serde::{Deserialize, Serialize};
+
+/// Names the host functions are registered under.
+pub mod functions {
+    pub const LOG: &str = "hogehoge_log";
+    pub const STORAGE_GET: &str = "hogehoge_storage_get";
+    pub const STORAGE_SET: &str = "hogehoge_storage_set";
+    pub const NOW: &str = "hogehoge_now";
+    pub const NOTIFY: &str = "hogehoge_notify";
+    pub const FIND_ALBUMS: &str = "hogehoge_find_albums";
+    pub const ALBUM_TRACKS: &str = "hogehoge_album_tracks";
+    pub const TRACK_TAGS: &str = "hogehoge_track_tags";
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// A log message, which ends up in the span of whatever the host called the plugin for.
+#[derive(Debug, Clone, ToBytes, FromBytes, Serialize, Deserialize)]
+#[encoding(Msgpack)]
+pub struct LogRecord {
+    pub level: LogLevel,
+    pub message: String,
+    /// Extra key value pairs logged along with the message.
+    pub fields: Vec<(String, String)>,
+}
+
+/// Changes a value in the key value store of the plugin.
+#[derive(Debug, Clone, ToBytes, FromBytes, Serialize, Deserialize)]
+#[encoding(Msgpack)]
+pub struct StorageSetArgs {
+    pub key: String,
+    /// `None` removes the key.
+    #[serde(with = "serde_bytes")]
+    pub value: Option<Vec<u8>>,
+}
+
+/// A value from the key value store of the plugin.
+#[derive(Debug, Clone, ToBytes, FromBytes, Serialize, Deserialize)]
+#[encoding(Msgpack)]
+pub struct StoredValue(#[serde(with = "serde_bytes")] pub Option<Vec<u8>>);
+
+/// Shown to the user like any other notification, with the name of the plugin in the title.
+#[derive(Debug, Clone, ToBytes, FromBytes, Serialize, Deserialize)]
+#[encoding(Msgpack)]
+pub struct HostNotification {
+    pub title: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LibraryAlbum {
+    pub id: AlbumId,
+    pub title: String,
+}
+
+impl LogRecord {
+    pub fn new(level: LogLevel, message: impl Into<String>) -> Self {
+        LogRecord {
+            level,
+            message: message.into(),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn with_field(mut self, key: impl Into<String>, value: impl std::fmt::Display) -> Self {
+        self.fields.push((key.into(), value.to_string()));
+        self
+    }
+}
@@ -8,4 +8,10 @@ pub mod theme;
 pub use theme::*;
 
 pub mod audio;
-pub use audio::*;
\ No newline at end of file
+pub use audio::*;
+
+pub mod host;
+pub use host::*;
+
+#[cfg(feature = "pdk")]
+pub mod guest;
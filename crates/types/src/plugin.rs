@@ -1,7 +1,7 @@
-use crate::{AudioFile, PlaybackId, Tags};
+use crate::{AudioBlock, AudioFile, ChannelCount, EffectId, PlaybackId, SampleRate, Tags};
 use extism_convert::{FromBytes, Msgpack, ToBytes};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 pub use uuid::{Uuid, uuid};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ToBytes, FromBytes, Serialize, Deserialize)]
@@ -23,6 +23,53 @@ pub struct UniqueTrackIdentifier {
     pub plugin_data: PluginTrackIdentifier,
 }
 
+/// Version of the interface between the host and plugins. Has to be bumped whenever a type that
+/// crosses it changes in a way the other side can no longer read, `tests/wire_format.rs` fails
+/// when that happens.
+pub const PLUGIN_API_VERSION: u32 = 1;
+
+/// Names of the functions plugins export for the host to call, generated by `export!` in the pdk.
+pub mod exports {
+    pub const GET_API_VERSION: &str = "get_api_version";
+    pub const GET_METADATA: &str = "get_metadata";
+    pub const CONFIGURE: &str = "configure";
+    pub const PREPARE_SCAN: &str = "prepare_scan";
+    pub const SCAN: &str = "scan";
+    pub const GET_AUDIO_FILE: &str = "get_audio_file";
+    pub const WRITE_REPLAY_GAIN: &str = "write_replay_gain";
+    pub const INIT_DECODING: &str = "init_decoding";
+    pub const DECODE_BLOCK: &str = "decode_block";
+    pub const FINISH_DECODING: &str = "finish_decoding";
+    pub const SEEK: &str = "seek";
+    pub const INIT_EFFECT: &str = "init_effect";
+    pub const PROCESS_BLOCK: &str = "process_block";
+    pub const FINISH_EFFECT: &str = "finish_effect";
+    pub const VISUALIZE: &str = "visualize";
+
+    pub const ALL: &[&str] = &[
+        GET_API_VERSION,
+        GET_METADATA,
+        CONFIGURE,
+        PREPARE_SCAN,
+        SCAN,
+        GET_AUDIO_FILE,
+        WRITE_REPLAY_GAIN,
+        INIT_DECODING,
+        DECODE_BLOCK,
+        FINISH_DECODING,
+        SEEK,
+        INIT_EFFECT,
+        PROCESS_BLOCK,
+        FINISH_EFFECT,
+        VISUALIZE,
+    ];
+}
+
+/// The plugin API version a plugin was built against, returned by its `get_api_version` export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToBytes, FromBytes, Serialize, Deserialize)]
+#[encoding(Msgpack)]
+pub struct ApiVersion(pub u32);
+
 #[derive(Debug, Clone, ToBytes, FromBytes, Serialize, Deserialize)]
 #[encoding(Msgpack)]
 pub struct PluginMetadata {
@@ -32,6 +79,12 @@ pub struct PluginMetadata {
     pub author: Option<String>,
 
     pub fs_mounts: Vec<FsMount>,
+    /// Plugins from before settings existed don't send any.
+    #[serde(default)]
+    pub settings: Vec<SettingDeclaration>,
+    /// Version of the plugin itself, only shown to the user.
+    #[serde(default)]
+    pub version: String,
 }
 
 #[derive(Debug, Clone, ToBytes, FromBytes, Serialize, Deserialize)]
@@ -41,6 +94,150 @@ pub struct FsMount {
     pub description: String,
 }
 
+impl FsMount {
+    /// Config key under which the host passes the directories mounted for the `FsMount` at
+    /// `internal_path`, one guest path per line.
+    pub fn config_key(internal_path: &str) -> String {
+        format!("fs_mount:{}", internal_path)
+    }
+}
+
+/// A setting the user can configure for the plugin. The values are stored by the host and passed
+/// to the `configure` export of every instance, and again whenever they change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SettingDeclaration {
+    /// Identifies the setting in [`PluginSettings`].
+    pub key: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub kind: SettingKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SettingKind {
+    String {
+        default: String,
+    },
+    Bool {
+        default: bool,
+    },
+    Number {
+        default: f64,
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    /// One of `options`.
+    Enum {
+        options: Vec<String>,
+        default: String,
+    },
+    /// A path on the host, which the plugin can only access if it lies within one of its mounts.
+    Path {
+        default: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SettingValue {
+    String(String),
+    Bool(bool),
+    Number(f64),
+    Enum(String),
+    Path(String),
+}
+
+/// The value of every declared setting, as passed to the `configure` export.
+#[derive(Debug, Clone, Default, PartialEq, ToBytes, FromBytes, Serialize, Deserialize)]
+#[encoding(Msgpack)]
+pub struct PluginSettings {
+    pub values: HashMap<String, SettingValue>,
+}
+
+impl SettingKind {
+    pub fn default_value(&self) -> SettingValue {
+        match self {
+            SettingKind::String { default } => SettingValue::String(default.clone()),
+            SettingKind::Bool { default } => SettingValue::Bool(*default),
+            SettingKind::Number { default, .. } => SettingValue::Number(*default),
+            SettingKind::Enum { default, .. } => SettingValue::Enum(default.clone()),
+            SettingKind::Path { default } => SettingValue::Path(default.clone()),
+        }
+    }
+
+    /// Whether `value` is of this kind and within its range or options.
+    pub fn accepts(&self, value: &SettingValue) -> bool {
+        match (self, value) {
+            (SettingKind::String { .. }, SettingValue::String(_))
+            | (SettingKind::Bool { .. }, SettingValue::Bool(_))
+            | (SettingKind::Path { .. }, SettingValue::Path(_)) => true,
+            (SettingKind::Number { min, max, .. }, SettingValue::Number(number)) => {
+                number.is_finite()
+                    && min.is_none_or(|min| *number >= min)
+                    && max.is_none_or(|max| *number <= max)
+            }
+            (SettingKind::Enum { options, .. }, SettingValue::Enum(option)) => {
+                options.contains(option)
+            }
+            _ => false,
+        }
+    }
+
+    /// Reads a value of this kind back from how [`SettingValue`] displays it.
+    pub fn parse(&self, text: &str) -> Option<SettingValue> {
+        let value = match self {
+            SettingKind::String { .. } => SettingValue::String(text.to_string()),
+            SettingKind::Bool { .. } => SettingValue::Bool(text.parse().ok()?),
+            SettingKind::Number { .. } => SettingValue::Number(text.parse().ok()?),
+            SettingKind::Enum { .. } => SettingValue::Enum(text.to_string()),
+            SettingKind::Path { .. } => SettingValue::Path(text.to_string()),
+        };
+
+        Some(value).filter(|value| self.accepts(value))
+    }
+}
+
+impl std::fmt::Display for SettingValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingValue::String(text) | SettingValue::Enum(text) | SettingValue::Path(text) => {
+                write!(f, "{}", text)
+            }
+            SettingValue::Bool(value) => write!(f, "{}", value),
+            SettingValue::Number(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl PluginSettings {
+    pub fn get(&self, key: &str) -> Option<&SettingValue> {
+        self.values.get(key)
+    }
+
+    /// The value of a string, enum or path setting.
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            SettingValue::String(text) | SettingValue::Enum(text) | SettingValue::Path(text) => {
+                Some(text)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)? {
+            SettingValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_number(&self, key: &str) -> Option<f64> {
+        match self.get(key)? {
+            SettingValue::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, ToBytes, FromBytes, Serialize, Deserialize)]
 #[encoding(Msgpack)]
 pub struct PreparedScan {
@@ -66,3 +263,44 @@ pub struct InitDecodingArgs {
 pub struct InitDecodingResult {
     pub duration: Option<Duration>,
 }
+
+#[derive(Clone, Debug, ToBytes, FromBytes, Serialize, Deserialize)]
+#[encoding(Msgpack)]
+pub struct SeekArgs {
+    pub playback_id: PlaybackId,
+    pub position: Duration,
+}
+
+#[derive(Debug, Clone, ToBytes, FromBytes, Serialize, Deserialize)]
+#[encoding(Msgpack)]
+pub struct SeekResult {
+    /// Position decoding actually continues from.
+    pub position: Duration,
+}
+
+#[derive(Clone, Debug, ToBytes, FromBytes, Serialize, Deserialize)]
+#[encoding(Msgpack)]
+pub struct WriteReplayGainArgs {
+    pub track: PluginTrackIdentifier,
+    pub track_gain_db: f32,
+    pub track_peak: f32,
+    pub album_gain_db: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+#[derive(Clone, Debug, ToBytes, FromBytes, Serialize, Deserialize)]
+#[encoding(Msgpack)]
+pub struct InitEffectArgs {
+    pub effect_id: EffectId,
+    pub sample_rate: SampleRate,
+    pub channel_count: ChannelCount,
+}
+
+/// A block of interleaved audio for an effect, in the format it was initialized with. The
+/// processed block has to have the same length.
+#[derive(Clone, Debug, ToBytes, FromBytes, Serialize, Deserialize)]
+#[encoding(Msgpack)]
+pub struct ProcessBlockArgs {
+    pub effect_id: EffectId,
+    pub block: AudioBlock,
+}
@@ -168,16 +168,22 @@ make_theme! {
     ],
 
     icons: [
-        // play,
-        // pause,
-        // stop,
-        // next,
-        // previous,
-        // volume_up,
+        play,
+        pause,
+        stop,
+        next,
+        previous,
+        shuffle,
+        repeat,
+        repeat_one,
+        volume_up,
         // volume_down,
-        // volume_mute,
+        volume_mute,
 
         background_task_running,
+        library,
+        queue,
+        settings,
     ]
 }
 
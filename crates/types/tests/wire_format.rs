@@ -0,0 +1,536 @@
+//! Pins the encoding of everything that crosses the boundary between the host and plugins.
+//!
+//! Each type is mirrored here as it looked at the current `PLUGIN_API_VERSION`. If one of these
+//! tests fails, plugins built against the old layout can no longer talk to the host, so either
+//! keep the change compatible or bump `PLUGIN_API_VERSION` and update the mirrors.
+
+use extism_convert::{FromBytesOwned, Msgpack, ToBytes};
+use hogehoge_types as current;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{collections::HashMap, fmt::Debug, time::Duration};
+use uuid::Uuid;
+
+/// Sends `frozen` through the current type `T` and back, which only gives the same value if both
+/// are encoded the same way.
+fn assert_round_trip<T, F>(frozen: F)
+where
+    T: for<'a> ToBytes<'a> + FromBytesOwned,
+    F: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let bytes = Msgpack(&frozen).to_bytes().unwrap();
+    let decoded = T::from_bytes_owned(&bytes).expect("frozen layout to decode as current type");
+
+    let bytes = decoded.to_bytes().unwrap();
+    let Msgpack(round_tripped) =
+        Msgpack::<F>::from_bytes_owned(bytes.as_ref()).expect("current type to decode as frozen");
+
+    assert_eq!(round_tripped, frozen);
+}
+
+#[test]
+fn api_version() {
+    assert_eq!(current::PLUGIN_API_VERSION, 1);
+    assert_round_trip::<current::ApiVersion, _>(ApiVersion(current::PLUGIN_API_VERSION));
+}
+
+#[test]
+fn metadata() {
+    assert_round_trip::<current::PluginMetadata, _>(PluginMetadata {
+        name: "Plugin".to_string(),
+        uuid: Uuid::from_u128(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef),
+        description: Some("Does things".to_string()),
+        author: None,
+        fs_mounts: vec![FsMount {
+            internal_path: "/music".to_string(),
+            description: "Music library".to_string(),
+        }],
+        settings: vec![
+            SettingDeclaration {
+                key: "strength".to_string(),
+                name: "Strength".to_string(),
+                description: None,
+                kind: SettingKind::Number {
+                    default: 0.5,
+                    min: Some(0.0),
+                    max: None,
+                },
+            },
+            SettingDeclaration {
+                key: "mode".to_string(),
+                name: "Mode".to_string(),
+                description: Some("How to do things".to_string()),
+                kind: SettingKind::Enum {
+                    options: vec!["fast".to_string(), "slow".to_string()],
+                    default: "fast".to_string(),
+                },
+            },
+        ],
+        version: "1.2.3".to_string(),
+    });
+}
+
+#[test]
+fn settings() {
+    let values = HashMap::from([
+        (
+            "name".to_string(),
+            SettingValue::String("value".to_string()),
+        ),
+        ("enabled".to_string(), SettingValue::Bool(true)),
+        ("strength".to_string(), SettingValue::Number(0.25)),
+        ("mode".to_string(), SettingValue::Enum("slow".to_string())),
+        ("dir".to_string(), SettingValue::Path("/music".to_string())),
+    ]);
+
+    assert_round_trip::<current::PluginSettings, _>(PluginSettings { values });
+}
+
+#[test]
+fn scanning() {
+    assert_round_trip::<current::PreparedScan, _>(PreparedScan {
+        tracks: vec![
+            PluginTrackIdentifier("a.flac".to_string()),
+            PluginTrackIdentifier("b.mp3".to_string()),
+        ],
+    });
+
+    assert_round_trip::<current::ScanResult, _>(ScanResult {
+        tags: Tags {
+            track_title: "Title".to_string(),
+            musicbrainz_track_id: Some(Uuid::from_u128(1)),
+            track_number: Some("3".to_string()),
+            bpm: Some(128.5),
+            ..Tags::default()
+        },
+    });
+
+    assert_round_trip::<current::AudioFile, _>(AudioFile {
+        data: vec![0, 1, 2, 255],
+        format_hint: Some("flac".to_string()),
+    });
+
+    assert_round_trip::<current::WriteReplayGainArgs, _>(WriteReplayGainArgs {
+        track: PluginTrackIdentifier("a.flac".to_string()),
+        track_gain_db: -7.25,
+        track_peak: 0.984375,
+        album_gain_db: Some(-6.0),
+        album_peak: None,
+    });
+}
+
+#[test]
+fn decoding() {
+    let playback_id = PlaybackId(Uuid::from_u128(2));
+
+    assert_round_trip::<current::InitDecodingArgs, _>(InitDecodingArgs {
+        playback_id,
+        file: AudioFile {
+            data: vec![42; 16],
+            format_hint: None,
+        },
+        gapless: true,
+    });
+    assert_round_trip::<current::InitDecodingResult, _>(InitDecodingResult {
+        duration: Some(Duration::from_millis(183_250)),
+    });
+    assert_round_trip::<current::PlaybackId, _>(playback_id);
+    // the end of the track is signalled by extism itself, only the blocks are ours
+    assert_round_trip::<Option<current::AudioBlock>, _>(AudioBlock {
+        samples: vec![0.0, 0.5, -0.5, 1.0],
+        sample_rate: 44100,
+        channel_count: 2,
+    });
+
+    assert_round_trip::<current::SeekArgs, _>(SeekArgs {
+        playback_id,
+        position: Duration::from_secs(90),
+    });
+    assert_round_trip::<current::SeekResult, _>(SeekResult {
+        position: Duration::from_millis(89_987),
+    });
+}
+
+#[test]
+fn effects() {
+    let effect_id = EffectId(Uuid::from_u128(3));
+
+    assert_round_trip::<current::InitEffectArgs, _>(InitEffectArgs {
+        effect_id,
+        sample_rate: 48000,
+        channel_count: 2,
+    });
+    assert_round_trip::<current::ProcessBlockArgs, _>(ProcessBlockArgs {
+        effect_id,
+        block: AudioBlock {
+            samples: vec![0.25, -0.25],
+            sample_rate: 48000,
+            channel_count: 2,
+        },
+    });
+    assert_round_trip::<current::EffectId, _>(effect_id);
+}
+
+#[test]
+fn visualizer() {
+    assert_round_trip::<current::VisualizerFrame, _>(VisualizerFrame {
+        waveform: vec![0.5, -0.5],
+        spectrum: vec![-12.0, -120.0],
+        bin_width: 23.4375,
+    });
+}
+
+#[test]
+fn host_functions() {
+    assert_round_trip::<current::LogRecord, _>(LogRecord {
+        level: LogLevel::Warn,
+        message: "Something happened".to_string(),
+        fields: vec![("file".to_string(), "a.flac".to_string())],
+    });
+    assert_round_trip::<current::StorageSetArgs, _>(StorageSetArgs {
+        key: "token".to_string(),
+        value: Some(vec![1, 2, 3]),
+    });
+    assert_round_trip::<current::StorageSetArgs, _>(StorageSetArgs {
+        key: "token".to_string(),
+        value: None,
+    });
+    assert_round_trip::<current::StoredValue, _>(StoredValue(Some(vec![4, 5, 6])));
+    assert_round_trip::<current::HostNotification, _>(HostNotification {
+        title: "Done".to_string(),
+        message: "Finished syncing".to_string(),
+    });
+    assert_round_trip::<Msgpack<Vec<current::LibraryAlbum>>, _>(vec![LibraryAlbum {
+        id: AlbumId(7),
+        title: "Album".to_string(),
+    }]);
+    assert_round_trip::<current::AlbumId, _>(AlbumId(7));
+}
+
+// the types as of plugin API version 1
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ApiVersion(u32);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct PluginMetadata {
+    name: String,
+    uuid: Uuid,
+    description: Option<String>,
+    author: Option<String>,
+    fs_mounts: Vec<FsMount>,
+    settings: Vec<SettingDeclaration>,
+    version: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct FsMount {
+    internal_path: String,
+    description: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SettingDeclaration {
+    key: String,
+    name: String,
+    description: Option<String>,
+    kind: SettingKind,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum SettingKind {
+    String {
+        default: String,
+    },
+    Bool {
+        default: bool,
+    },
+    Number {
+        default: f64,
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    Enum {
+        options: Vec<String>,
+        default: String,
+    },
+    Path {
+        default: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum SettingValue {
+    String(String),
+    Bool(bool),
+    Number(f64),
+    Enum(String),
+    Path(String),
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct PluginSettings {
+    values: HashMap<String, SettingValue>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct PluginTrackIdentifier(String);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct PreparedScan {
+    tracks: Vec<PluginTrackIdentifier>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ScanResult {
+    tags: Tags,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Tags {
+    // general
+    track_title: String,
+    musicbrainz_work_id: Option<Uuid>,
+    musicbrainz_track_id: Option<Uuid>,
+    musicbrainz_recording_id: Option<Uuid>,
+    track_subtitle: Option<String>,
+    track_title_sort_order: Option<String>,
+    comment: Option<String>,
+    description: Option<String>,
+    language: Option<String>,
+    script: Option<String>,
+    lyrics: Option<String>,
+
+    // album
+    album_title: Option<String>,
+    set_subtitle: Option<String>,
+    musicbrainz_release_id: Option<Uuid>,
+    original_album_title: Option<String>,
+    album_title_sort_order: Option<String>,
+    album_artist: Option<String>,
+    musicbrainz_release_artist_id: Option<Uuid>,
+    content_group: Option<String>,
+    musicbrainz_release_group_id: Option<Uuid>,
+
+    // artist
+    track_artist: Option<String>,
+    track_artists: Option<String>,
+    musicbrainz_artist_id: Option<Uuid>,
+    original_artist: Option<String>,
+    album_artist_sort_order: Option<String>,
+    track_artist_sort_order: Option<String>,
+
+    // show
+    show_name: Option<String>,
+    show_name_sort_order: Option<String>,
+
+    // style
+    genre: Option<String>,
+    initial_key: Option<String>,
+    color: Option<String>,
+    mood: Option<String>,
+    bpm: Option<f32>,
+
+    // urls
+    audio_file_url: Option<String>,
+    audio_source_url: Option<String>,
+    commercial_information_url: Option<String>,
+    copyright_url: Option<String>,
+    track_artist_url: Option<String>,
+    radio_station_url: Option<String>,
+    payment_url: Option<String>,
+    publisher_url: Option<String>,
+
+    // numbering
+    disc_number: Option<String>,
+    disc_total: Option<String>,
+    track_number: Option<String>,
+    track_total: Option<String>,
+    movement: Option<String>,
+    movement_number: Option<String>,
+    movement_total: Option<String>,
+
+    // dates
+    year: Option<String>,
+    recording_date: Option<String>,
+    release_date: Option<String>,
+    original_release_date: Option<String>,
+
+    // file
+    file_type: Option<String>,
+    file_owner: Option<String>,
+    tagging_time: Option<String>,
+    length: Option<String>,
+    original_file_name: Option<String>,
+    original_media_type: Option<String>,
+
+    // encoding
+    encoded_by: Option<String>,
+    encoder_software: Option<String>,
+    encoder_settings: Option<String>,
+    encoding_time: Option<String>,
+
+    // replaygain
+    replay_gain_album_gain: Option<String>,
+    replay_gain_album_peak: Option<String>,
+    replay_gain_track_gain: Option<String>,
+    replay_gain_track_peak: Option<String>,
+
+    // identification
+    isrc: Option<String>,
+    barcode: Option<String>,
+    catalog_number: Option<String>,
+    work: Option<String>,
+
+    // flags
+    flag_compilation: Option<String>,
+    flag_podcast: Option<String>,
+
+    // legal
+    copyright_message: Option<String>,
+    license: Option<String>,
+
+    // misc
+    popularimeter: Option<String>,
+    parental_advisory: Option<String>,
+
+    // people
+    arranger: Option<String>,
+    writer: Option<String>,
+    composer: Option<String>,
+    composer_sort_order: Option<String>,
+    conductor: Option<String>,
+    director: Option<String>,
+    engineer: Option<String>,
+    lyricist: Option<String>,
+    original_lyricist: Option<String>,
+    mix_dj: Option<String>,
+    mix_engineer: Option<String>,
+    musician_credits: Option<String>,
+    performer: Option<String>,
+    producer: Option<String>,
+    publisher: Option<String>,
+    label: Option<String>,
+    internet_radio_station_name: Option<String>,
+    internet_radio_station_owner: Option<String>,
+    remixer: Option<String>,
+
+    // podcast
+    podcast_description: Option<String>,
+    podcast_series_category: Option<String>,
+    podcast_url: Option<String>,
+    podcast_global_unique_id: Option<String>,
+    podcast_keywords: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct AudioFile {
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+    format_hint: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct WriteReplayGainArgs {
+    track: PluginTrackIdentifier,
+    track_gain_db: f32,
+    track_peak: f32,
+    album_gain_db: Option<f32>,
+    album_peak: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct PlaybackId(Uuid);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct InitDecodingArgs {
+    playback_id: PlaybackId,
+    file: AudioFile,
+    gapless: bool,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct InitDecodingResult {
+    duration: Option<Duration>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct AudioBlock {
+    samples: Vec<f32>,
+    sample_rate: u32,
+    channel_count: u16,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SeekArgs {
+    playback_id: PlaybackId,
+    position: Duration,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SeekResult {
+    position: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct EffectId(Uuid);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct InitEffectArgs {
+    effect_id: EffectId,
+    sample_rate: u32,
+    channel_count: u16,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ProcessBlockArgs {
+    effect_id: EffectId,
+    block: AudioBlock,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct VisualizerFrame {
+    waveform: Vec<f32>,
+    spectrum: Vec<f32>,
+    bin_width: f32,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct LogRecord {
+    level: LogLevel,
+    message: String,
+    fields: Vec<(String, String)>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct StorageSetArgs {
+    key: String,
+    #[serde(with = "serde_bytes")]
+    value: Option<Vec<u8>>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct StoredValue(#[serde(with = "serde_bytes")] Option<Vec<u8>>);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct HostNotification {
+    title: String,
+    message: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct AlbumId(i64);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct LibraryAlbum {
+    id: AlbumId,
+    title: String,
+}
@@ -0,0 +1,10 @@
+CREATE TABLE plugin_mounts(
+    plugin_mount_id INTEGER NOT NULL PRIMARY KEY,
+    plugin_id INTEGER NOT NULL,
+
+    internal_path TEXT NOT NULL,
+    host_path TEXT NOT NULL,
+
+    FOREIGN KEY (plugin_id) REFERENCES plugins(plugin_id),
+    UNIQUE (plugin_id, internal_path, host_path)
+);
@@ -0,0 +1,22 @@
+CREATE TABLE queue_entries(
+    queue_entry_id INTEGER NOT NULL PRIMARY KEY,
+    -- 'past', 'current', 'future' or 'unshuffled'
+    section TEXT NOT NULL,
+
+    plugin_id INTEGER NOT NULL,
+    plugin_data TEXT NOT NULL,
+
+    FOREIGN KEY (plugin_id) REFERENCES plugins(plugin_id)
+);
+
+-- only ever has a single row
+CREATE TABLE player_state(
+    player_state_id INTEGER NOT NULL PRIMARY KEY CHECK (player_state_id = 0),
+
+    repeat_mode TEXT NOT NULL,
+    shuffle_mode TEXT NOT NULL,
+    position_ms INTEGER NOT NULL
+);
+
+INSERT INTO player_state (player_state_id, repeat_mode, shuffle_mode, position_ms)
+VALUES (0, 'off', 'off', 0);
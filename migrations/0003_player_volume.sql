@@ -0,0 +1,2 @@
+ALTER TABLE player_state ADD COLUMN volume REAL NOT NULL DEFAULT 1.0;
+ALTER TABLE player_state ADD COLUMN muted INTEGER NOT NULL DEFAULT 0;
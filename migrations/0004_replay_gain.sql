@@ -0,0 +1,4 @@
+ALTER TABLE player_state ADD COLUMN replay_gain_mode TEXT NOT NULL DEFAULT 'track';
+ALTER TABLE player_state ADD COLUMN replay_gain_preamp_db REAL NOT NULL DEFAULT 0.0;
+ALTER TABLE player_state ADD COLUMN replay_gain_fallback_db REAL NOT NULL DEFAULT 0.0;
+ALTER TABLE player_state ADD COLUMN replay_gain_prevent_clipping INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,6 @@
+-- measured by the loudness analysis, NULL until a track (or every track of an album) is analyzed
+ALTER TABLE tracks ADD COLUMN loudness_lufs REAL;
+ALTER TABLE tracks ADD COLUMN true_peak REAL;
+
+ALTER TABLE albums ADD COLUMN loudness_lufs REAL;
+ALTER TABLE albums ADD COLUMN true_peak REAL;
@@ -0,0 +1,3 @@
+ALTER TABLE player_state ADD COLUMN crossfade_ms INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE player_state ADD COLUMN skip_fade_ms INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE player_state ADD COLUMN pause_fade_ms INTEGER NOT NULL DEFAULT 0;
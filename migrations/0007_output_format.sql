@@ -0,0 +1,2 @@
+ALTER TABLE player_state ADD COLUMN output_sample_rate INTEGER;
+ALTER TABLE player_state ADD COLUMN resampler_quality TEXT NOT NULL DEFAULT 'high';
@@ -0,0 +1 @@
+ALTER TABLE player_state ADD COLUMN output_device TEXT;
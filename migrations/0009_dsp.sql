@@ -0,0 +1,43 @@
+CREATE TABLE dsp_profiles(
+    dsp_profile_id INTEGER NOT NULL PRIMARY KEY,
+    -- output device the profile is used for, NULL for every device without its own profile
+    device TEXT UNIQUE,
+
+    eq_enabled INTEGER NOT NULL,
+    preamp_db REAL NOT NULL,
+    balance REAL NOT NULL,
+    mono INTEGER NOT NULL,
+    limiter INTEGER NOT NULL
+);
+
+CREATE TABLE dsp_profile_bands(
+    dsp_profile_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+
+    kind TEXT NOT NULL,
+    frequency REAL NOT NULL,
+    gain_db REAL NOT NULL,
+    q REAL NOT NULL,
+
+    PRIMARY KEY (dsp_profile_id, position),
+    FOREIGN KEY (dsp_profile_id) REFERENCES dsp_profiles(dsp_profile_id) ON DELETE CASCADE
+);
+
+CREATE TABLE eq_presets(
+    eq_preset_id INTEGER NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    preamp_db REAL NOT NULL
+);
+
+CREATE TABLE eq_preset_bands(
+    eq_preset_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+
+    kind TEXT NOT NULL,
+    frequency REAL NOT NULL,
+    gain_db REAL NOT NULL,
+    q REAL NOT NULL,
+
+    PRIMARY KEY (eq_preset_id, position),
+    FOREIGN KEY (eq_preset_id) REFERENCES eq_presets(eq_preset_id) ON DELETE CASCADE
+);
@@ -0,0 +1,11 @@
+CREATE TABLE dsp_profile_effects(
+    dsp_profile_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+
+    -- not a foreign key, the effect stays in the profile while its plugin isn't loaded
+    plugin_id INTEGER NOT NULL,
+    bypassed INTEGER NOT NULL,
+
+    PRIMARY KEY (dsp_profile_id, position),
+    FOREIGN KEY (dsp_profile_id) REFERENCES dsp_profiles(dsp_profile_id) ON DELETE CASCADE
+);
@@ -0,0 +1,13 @@
+-- tracks of a show (like a podcast) share their speed, everything else remembers it per track
+CREATE TABLE track_playback_speeds(
+    plugin_id INTEGER NOT NULL,
+    plugin_data TEXT NOT NULL,
+    speed REAL NOT NULL,
+
+    PRIMARY KEY (plugin_id, plugin_data)
+);
+
+CREATE TABLE show_playback_speeds(
+    show TEXT NOT NULL PRIMARY KEY,
+    speed REAL NOT NULL
+);
@@ -0,0 +1,12 @@
+-- where podcasts and long tracks were left off, so they can be resumed from there
+CREATE TABLE track_progress(
+    plugin_id INTEGER NOT NULL,
+    plugin_data TEXT NOT NULL,
+    position_ms INTEGER NOT NULL,
+    finished INTEGER NOT NULL,
+
+    PRIMARY KEY (plugin_id, plugin_data)
+);
+
+-- 20 minutes
+ALTER TABLE player_state ADD COLUMN resume_threshold_ms INTEGER NOT NULL DEFAULT 1200000;
@@ -0,0 +1,10 @@
+CREATE TABLE plugin_settings(
+    plugin_id INTEGER NOT NULL,
+    key TEXT NOT NULL,
+
+    -- as displayed by `SettingValue`, parsed again using the declared kind
+    value TEXT NOT NULL,
+
+    PRIMARY KEY (plugin_id, key),
+    FOREIGN KEY (plugin_id) REFERENCES plugins(plugin_id)
+);
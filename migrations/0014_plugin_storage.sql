@@ -0,0 +1,8 @@
+CREATE TABLE plugin_storage(
+    plugin_id INTEGER NOT NULL,
+    key TEXT NOT NULL,
+    value BLOB NOT NULL,
+
+    PRIMARY KEY (plugin_id, key),
+    FOREIGN KEY (plugin_id) REFERENCES plugins(plugin_id)
+);
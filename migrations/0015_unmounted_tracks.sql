@@ -0,0 +1,15 @@
+-- before mounts were configurable the filesystem plugin had a single directory hardcoded at /music
+-- and its identifiers were relative to that. nothing says which host directory that was, so the
+-- tracks are removed and come back with the next scan once a directory is mounted.
+CREATE TEMP TABLE unmounted_plugins AS
+SELECT plugin_id
+FROM plugins
+WHERE uuid = X'c29408638121447eae25499a809c361e'
+    AND NOT EXISTS (SELECT 1 FROM plugin_mounts WHERE plugin_mounts.plugin_id = plugins.plugin_id);
+
+DELETE FROM tracks WHERE plugin_id IN (SELECT plugin_id FROM unmounted_plugins);
+DELETE FROM queue_entries WHERE plugin_id IN (SELECT plugin_id FROM unmounted_plugins);
+DELETE FROM track_progress WHERE plugin_id IN (SELECT plugin_id FROM unmounted_plugins);
+DELETE FROM track_playback_speeds WHERE plugin_id IN (SELECT plugin_id FROM unmounted_plugins);
+
+DROP TABLE unmounted_plugins;
@@ -0,0 +1,9 @@
+-- only plugins whose limits were changed from the defaults have a row
+CREATE TABLE plugin_limits(
+    plugin_id INTEGER NOT NULL PRIMARY KEY,
+
+    memory_pages INTEGER NOT NULL,
+    timeout_ms INTEGER NOT NULL,
+
+    FOREIGN KEY (plugin_id) REFERENCES plugins(plugin_id)
+);
@@ -1,35 +1,40 @@
-use extism_pdk::{FnResult, plugin_fn};
-use hogehoge_types::{
-    AudioBlock, InitDecodingArgs, InitDecodingResult, PlaybackId, PluginMetadata, Sample, uuid,
-};
-use std::{
-    collections::HashMap,
-    io::Cursor,
-    sync::{LazyLock, Mutex},
-};
+use hogehoge_pdk::{Decoder, FnResult, Plugin, Seek};
+use hogehoge_types::{AudioBlock, AudioFile, PluginMetadata, Sample, uuid};
+use std::{io::Cursor, time::Duration};
 use symphonia::core::{
     audio::{AudioBuffer, AudioBufferRef, Signal},
-    codecs::{Decoder, DecoderOptions},
+    codecs::{self, DecoderOptions},
     conv::IntoSample,
     errors::Error as SymphoniaError,
-    formats::{FormatOptions, FormatReader},
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
     io::MediaSourceStream,
     meta::MetadataOptions,
     probe::Hint,
     sample::Sample as SymphoniaSample,
+    units::{Time, TimeStamp},
 };
 use thiserror::Error;
 
-#[plugin_fn]
-pub fn get_metadata() -> FnResult<PluginMetadata> {
-    Ok(PluginMetadata {
-        name: "Base Formats".to_string(),
-        uuid: uuid!("6968fce9-2521-410c-b933-32c6e5800f93"),
-        description: Some("Playback support for commonly used audio formats".to_string()),
-        author: None,
+struct BaseFormats;
+
+hogehoge_pdk::export! {
+    plugin: BaseFormats,
+    seekable_decoder: SymphoniaDecoder,
+}
 
-        fs_mounts: vec![],
-    })
+impl Plugin for BaseFormats {
+    fn metadata() -> PluginMetadata {
+        PluginMetadata {
+            name: "Base Formats".to_string(),
+            uuid: uuid!("6968fce9-2521-410c-b933-32c6e5800f93"),
+            description: Some("Playback support for commonly used audio formats".to_string()),
+            author: None,
+
+            fs_mounts: vec![],
+            settings: vec![],
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -37,121 +42,140 @@ pub enum DecodeError {
     #[error("File doesn't contain a valid audio track")]
     InvalidAudioTrack,
 
-    #[error("No encoding initialized for given playback ID")]
-    EncodingNotInitialized,
+    #[error("Track has no time base to seek with")]
+    MissingTimeBase,
 }
 
-struct DecoderState {
+struct SymphoniaDecoder {
     format: Box<dyn FormatReader>,
-    decoder: Box<dyn Decoder>,
+    decoder: Box<dyn codecs::Decoder>,
     track_id: u32,
+    duration: Option<Duration>,
+    // after an accurate seek, frames before this timestamp still need to be decoded but dropped
+    skip_until: Option<TimeStamp>,
 }
 
-static DECODER_STATES: LazyLock<Mutex<HashMap<PlaybackId, DecoderState>>> =
-    LazyLock::new(|| Mutex::new(HashMap::new()));
+impl Decoder for SymphoniaDecoder {
+    fn init(file: AudioFile, gapless: bool) -> FnResult<Self> {
+        let mss = MediaSourceStream::new(Box::new(Cursor::new(file.data)), Default::default());
 
-#[plugin_fn]
-pub fn init_decoding(
-    InitDecodingArgs {
-        playback_id,
-        file,
-        gapless,
-    }: InitDecodingArgs,
-) -> FnResult<InitDecodingResult> {
-    let mut state = DECODER_STATES.lock().unwrap();
-
-    let mss = MediaSourceStream::new(Box::new(Cursor::new(file.data)), Default::default());
-
-    let mut hint = Hint::new();
-    if let Some(format) = file.format_hint {
-        hint.with_extension(&format);
-    }
+        let mut hint = Hint::new();
+        if let Some(format) = file.format_hint {
+            hint.with_extension(&format);
+        }
 
-    let meta_options: MetadataOptions = Default::default();
-    let format_options = FormatOptions {
-        enable_gapless: gapless,
-        ..Default::default()
-    };
-    let probed =
-        symphonia::default::get_probe().format(&hint, mss, &format_options, &meta_options)?;
+        let meta_options: MetadataOptions = Default::default();
+        let format_options = FormatOptions {
+            enable_gapless: gapless,
+            ..Default::default()
+        };
+        let probed =
+            symphonia::default::get_probe().format(&hint, mss, &format_options, &meta_options)?;
 
-    let format = probed.format;
+        let format = probed.format;
 
-    let track = format
-        .default_track()
-        .ok_or(DecodeError::InvalidAudioTrack)?;
+        let track = format
+            .default_track()
+            .ok_or(DecodeError::InvalidAudioTrack)?;
 
-    let decoder_options: DecoderOptions = Default::default();
-    let decoder = symphonia::default::get_codecs().make(&track.codec_params, &decoder_options)?;
+        let decoder_options: DecoderOptions = Default::default();
+        let decoder =
+            symphonia::default::get_codecs().make(&track.codec_params, &decoder_options)?;
 
-    let duration = track
-        .codec_params
-        .time_base
-        .zip(track.codec_params.n_frames)
-        .map(|(time_base, n_frames)| time_base.calc_time(n_frames).into());
+        let duration = track
+            .codec_params
+            .time_base
+            .zip(track.codec_params.n_frames)
+            .map(|(time_base, n_frames)| time_base.calc_time(n_frames).into());
 
-    let track_id = track.id;
+        let track_id = track.id;
 
-    state.insert(
-        playback_id,
-        DecoderState {
+        Ok(SymphoniaDecoder {
             format,
             decoder,
             track_id,
-        },
-    );
-
-    Ok(InitDecodingResult { duration })
-}
+            duration,
+            skip_until: None,
+        })
+    }
 
-#[plugin_fn]
-pub fn decode_block(playback_id: PlaybackId) -> FnResult<Option<AudioBlock>> {
-    let mut state = DECODER_STATES.lock().unwrap();
-    let state = state
-        .get_mut(&playback_id)
-        .ok_or(DecodeError::EncodingNotInitialized)?;
+    fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
 
-    loop {
-        let packet = state.format.next_packet()?;
+    fn decode_block(&mut self) -> FnResult<Option<AudioBlock>> {
+        loop {
+            let packet = self.format.next_packet()?;
 
-        while !state.format.metadata().is_latest() {
-            state.format.metadata().pop();
-        }
+            while !self.format.metadata().is_latest() {
+                self.format.metadata().pop();
+            }
 
-        if packet.track_id() != state.track_id {
-            continue;
-        }
+            if packet.track_id() != self.track_id {
+                continue;
+            }
 
-        match state.decoder.decode(&packet) {
-            Ok(decoded) => {
-                if decoded.frames() == 0 {
-                    continue;
+            match self.decoder.decode(&packet) {
+                Ok(decoded) => {
+                    if decoded.frames() == 0 {
+                        continue;
+                    }
+
+                    let sample_rate = decoded.spec().rate;
+                    let channel_count = decoded.spec().channels.count() as u16;
+
+                    let skip_frames = match self.skip_until.take() {
+                        Some(required_ts) if packet.ts() + packet.dur() <= required_ts => {
+                            self.skip_until = Some(required_ts);
+                            continue;
+                        }
+                        Some(required_ts) => required_ts.saturating_sub(packet.ts()) as usize,
+                        None => 0,
+                    };
+
+                    let mut samples = copy_decoded_samples(decoded);
+                    samples
+                        .drain(..usize::min(skip_frames * channel_count as usize, samples.len()));
+
+                    return Ok(Some(AudioBlock {
+                        samples,
+                        sample_rate,
+                        channel_count,
+                    }));
                 }
 
-                let sample_rate = decoded.spec().rate;
-                let channel_count = decoded.spec().channels.count() as u16;
-                let samples = copy_decoded_samples(decoded);
+                Err(SymphoniaError::IoError(_)) => continue,
+                Err(SymphoniaError::DecodeError(_)) => continue,
 
-                return Ok(Some(AudioBlock {
-                    samples,
-                    sample_rate,
-                    channel_count,
-                }));
+                Err(_) => return Ok(None),
             }
-
-            Err(SymphoniaError::IoError(_)) => continue,
-            Err(SymphoniaError::DecodeError(_)) => continue,
-
-            Err(_) => return Ok(None),
         }
     }
 }
 
-#[plugin_fn]
-pub fn finish_decoding(playback_id: PlaybackId) -> FnResult<()> {
-    let mut state = DECODER_STATES.lock().unwrap();
-    state.remove(&playback_id);
-    Ok(())
+impl Seek for SymphoniaDecoder {
+    fn seek(&mut self, position: Duration) -> FnResult<Duration> {
+        let seeked_to = self.format.seek(
+            SeekMode::Accurate,
+            SeekTo::Time {
+                time: Time::from(position),
+                track_id: Some(self.track_id),
+            },
+        )?;
+
+        self.decoder.reset();
+        self.skip_until = Some(seeked_to.required_ts);
+
+        let time_base = self
+            .format
+            .tracks()
+            .iter()
+            .find(|track| track.id == self.track_id)
+            .and_then(|track| track.codec_params.time_base)
+            .ok_or(DecodeError::MissingTimeBase)?;
+
+        Ok(time_base.calc_time(seeked_to.required_ts).into())
+    }
 }
 
 fn copy_decoded_samples(src: AudioBufferRef) -> Vec<Sample> {
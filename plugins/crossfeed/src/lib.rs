@@ -0,0 +1,158 @@
+use hogehoge_pdk::{Effect, FnResult, Plugin};
+use hogehoge_types::{
+    AudioBlock, ChannelCount, PluginMetadata, PluginSettings, Sample, SampleRate,
+    SettingDeclaration, SettingKind, uuid,
+};
+use std::{f32::consts::PI, sync::Mutex};
+
+// below this the other channel is fed over, above it the own channel is boosted instead
+const DEFAULT_CUTOFF_HZ: f32 = 700.0;
+// about -6 dB
+const DEFAULT_FEED: f32 = 0.5;
+
+struct CrossfeedPlugin;
+
+hogehoge_pdk::export! {
+    plugin: CrossfeedPlugin,
+    effect: Crossfeed,
+}
+
+impl Plugin for CrossfeedPlugin {
+    fn metadata() -> PluginMetadata {
+        PluginMetadata {
+            name: "Crossfeed".to_string(),
+            uuid: uuid!("c140397b-669b-401c-9c87-faf03444d98d"),
+            description: Some(
+                "Mixes some of the other channel into each ear, for less tiring stereo on headphones"
+                    .to_string(),
+            ),
+            author: None,
+
+            fs_mounts: vec![],
+            settings: vec![
+                SettingDeclaration {
+                    key: "feed".to_string(),
+                    name: "Strength".to_string(),
+                    description: Some("How much of the other channel is mixed in".to_string()),
+                    kind: SettingKind::Number {
+                        default: DEFAULT_FEED as f64,
+                        min: Some(0.0),
+                        max: Some(1.0),
+                    },
+                },
+                SettingDeclaration {
+                    key: "cutoff".to_string(),
+                    name: "Cutoff".to_string(),
+                    description: Some(
+                        "Frequency in Hz below which the other channel is mixed in".to_string(),
+                    ),
+                    kind: SettingKind::Number {
+                        default: DEFAULT_CUTOFF_HZ as f64,
+                        min: Some(300.0),
+                        max: Some(1200.0),
+                    },
+                },
+            ],
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    fn configure(settings: PluginSettings) -> FnResult<()> {
+        let mut current = SETTINGS.lock().unwrap();
+        current.feed = settings.get_number("feed").unwrap_or(DEFAULT_FEED as f64) as f32;
+        current.cutoff_hz = settings
+            .get_number("cutoff")
+            .unwrap_or(DEFAULT_CUTOFF_HZ as f64) as f32;
+        current.version += 1;
+
+        Ok(())
+    }
+}
+
+struct Settings {
+    feed: f32,
+    cutoff_hz: f32,
+    // bumped by every configure call, so effects only recompute their filter after one
+    version: u64,
+}
+
+static SETTINGS: Mutex<Settings> = Mutex::new(Settings {
+    feed: DEFAULT_FEED,
+    cutoff_hz: DEFAULT_CUTOFF_HZ,
+    version: 0,
+});
+
+struct Crossfeed {
+    channels: usize,
+    sample_rate: f32,
+    feed: f32,
+    // one pole lowpass coefficient
+    alpha: f32,
+    // of the settings `feed` and `alpha` were computed from
+    settings_version: u64,
+    // lowpassed left and right channel
+    lowpassed: [f32; 2],
+}
+
+impl Effect for Crossfeed {
+    fn init(sample_rate: SampleRate, channel_count: ChannelCount) -> FnResult<Self> {
+        let mut effect = Crossfeed {
+            channels: channel_count as usize,
+            sample_rate: sample_rate as f32,
+            feed: DEFAULT_FEED,
+            alpha: 0.0,
+            settings_version: 0,
+            lowpassed: [0.0; 2],
+        };
+        effect.configure(&SETTINGS.lock().unwrap());
+
+        Ok(effect)
+    }
+
+    fn process_block(&mut self, mut block: AudioBlock) -> FnResult<AudioBlock> {
+        // the settings may have changed since the last block
+        let settings = SETTINGS.lock().unwrap();
+        if settings.version != self.settings_version {
+            self.configure(&settings);
+        }
+        drop(settings);
+
+        // only the front left and right channel are crossfed, mono stays as it is
+        if self.channels < 2 {
+            return Ok(block);
+        }
+
+        for frame in block.samples.chunks_exact_mut(self.channels) {
+            let [left, right] = self.process(frame[0], frame[1]);
+            frame[0] = left;
+            frame[1] = right;
+        }
+
+        Ok(block)
+    }
+}
+
+impl Crossfeed {
+    fn configure(&mut self, settings: &Settings) {
+        self.feed = settings.feed;
+        self.settings_version = settings.version;
+        self.alpha = 1.0 - (-2.0 * PI * settings.cutoff_hz / self.sample_rate).exp();
+    }
+
+    /// Each ear gets the lows of the other channel and a matching boost of its own highs, so
+    /// anything that is the same on both channels comes out unchanged.
+    fn process(&mut self, left: Sample, right: Sample) -> [Sample; 2] {
+        self.lowpassed[0] += (left - self.lowpassed[0]) * self.alpha;
+        self.lowpassed[1] += (right - self.lowpassed[1]) * self.alpha;
+
+        let [left_low, right_low] = self.lowpassed;
+        let left_high = left - left_low;
+        let right_high = right - right_low;
+
+        let feed = self.feed;
+        [
+            (left + feed * (left_high + right_low)) / (1.0 + feed),
+            (right + feed * (right_high + left_low)) / (1.0 + feed),
+        ]
+    }
+}
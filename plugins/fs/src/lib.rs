@@ -1,36 +1,106 @@
 use std::path::Path;
 use thiserror::Error;
 
-use extism_pdk::{FnResult, plugin_fn};
+use extism_pdk::config;
+use hogehoge_pdk::{FnResult, Plugin, ReplayGainWriter, TrackProvider};
 use hogehoge_types::{
-    AudioFile, FsMount, PluginMetadata, PluginTrackIdentifier, PreparedScan, ScanResult, uuid,
+    AudioFile, FsMount, PluginMetadata, PluginTrackIdentifier, PreparedScan, ScanResult,
+    WriteReplayGainArgs, uuid,
 };
 use std::fs;
 
 mod tags;
 
-#[plugin_fn]
-pub fn get_metadata() -> FnResult<PluginMetadata> {
-    Ok(PluginMetadata {
-        name: "Filesystem".to_string(),
-        uuid: uuid!("c2940863-8121-447e-ae25-499a809c361e"),
-        description: Some("Load, import and manage tracks from the local filesystem".to_string()),
-        author: None,
-
-        fs_mounts: vec![FsMount {
-            internal_path: "/music".to_string(),
-            description: "Music files".to_string(),
-        }],
-    })
+struct Filesystem;
+
+hogehoge_pdk::export! {
+    plugin: Filesystem,
+    track_provider: Filesystem,
+    replay_gain_writer: Filesystem,
+}
+
+impl Plugin for Filesystem {
+    fn metadata() -> PluginMetadata {
+        PluginMetadata {
+            name: "Filesystem".to_string(),
+            uuid: uuid!("c2940863-8121-447e-ae25-499a809c361e"),
+            description: Some(
+                "Load, import and manage tracks from the local filesystem".to_string(),
+            ),
+            author: None,
+
+            fs_mounts: vec![FsMount {
+                internal_path: "/music".to_string(),
+                description: "Music files".to_string(),
+            }],
+            settings: vec![],
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+impl TrackProvider for Filesystem {
+    fn prepare_scan() -> FnResult<PreparedScan> {
+        let mut tracks = Vec::new();
+
+        // every host directory configured for the mount gets its own subdirectory in /music
+        let roots = config::get(FsMount::config_key("/music"))?.unwrap_or_default();
+        for root in roots.lines() {
+            scan_recurse(&mut tracks, root)?;
+        }
+
+        Ok(PreparedScan { tracks })
+    }
+
+    fn scan(ident: PluginTrackIdentifier) -> FnResult<ScanResult> {
+        use lofty::file::TaggedFileExt;
+
+        let path = Path::new("/music").join(ident.0);
+
+        let tagged_file = lofty::read_from_path(path)?;
+        let tag = tagged_file.primary_tag().ok_or(ScanError::NoTags)?;
+
+        let tags = tags::map_lofty_to_internal(tag)?;
+
+        Ok(ScanResult { tags })
+    }
+
+    fn get_audio_file(ident: PluginTrackIdentifier) -> FnResult<AudioFile> {
+        let path = Path::new("/music").join(ident.0);
+
+        let data = fs::read(&path).map_err(GetAudioFileError::ReadError)?;
+
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        let format_hint = extension.map(|ext| ext.to_string());
+
+        Ok(AudioFile { data, format_hint })
+    }
 }
 
-#[plugin_fn]
-pub fn prepare_scan() -> FnResult<PreparedScan> {
-    let mut tracks = Vec::new();
-    scan_recurse(&mut tracks, "/music")?;
+impl ReplayGainWriter for Filesystem {
+    fn write_replay_gain(args: WriteReplayGainArgs) -> FnResult<()> {
+        use lofty::{
+            config::WriteOptions,
+            file::TaggedFileExt,
+            tag::{Tag, TagExt},
+        };
+
+        let path = Path::new("/music").join(&args.track.0);
+
+        let mut tagged_file = lofty::read_from_path(&path)?;
+        if tagged_file.primary_tag().is_none() {
+            tagged_file.insert_tag(Tag::new(tagged_file.primary_tag_type()));
+        }
+        let tag = tagged_file.primary_tag_mut().ok_or(ScanError::NoTags)?;
+
+        tags::insert_replay_gain(tag, &args);
+
+        tag.save_to_path(&path, WriteOptions::default())?;
 
-    Ok(PreparedScan { tracks })
+        Ok(())
+    }
 }
+
 fn scan_recurse<P: AsRef<Path>>(tracks: &mut Vec<PluginTrackIdentifier>, path: P) -> FnResult<()> {
     let path = path.as_ref();
 
@@ -65,34 +135,8 @@ enum ScanError {
     NoTags,
 }
 
-#[plugin_fn]
-pub fn scan(ident: PluginTrackIdentifier) -> FnResult<ScanResult> {
-    use lofty::file::TaggedFileExt;
-
-    let path = Path::new("/music").join(ident.0);
-
-    let tagged_file = lofty::read_from_path(path)?;
-    let tag = tagged_file.primary_tag().ok_or(ScanError::NoTags)?;
-
-    let tags = tags::map_lofty_to_internal(tag)?;
-
-    Ok(ScanResult { tags })
-}
-
 #[derive(Debug, Error)]
 enum GetAudioFileError {
     #[error("Failed to read file: {0}")]
     ReadError(#[from] std::io::Error),
 }
-
-#[plugin_fn]
-pub fn get_audio_file(ident: PluginTrackIdentifier) -> FnResult<AudioFile> {
-    let path = Path::new("/music").join(ident.0);
-
-    let data = fs::read(&path).map_err(GetAudioFileError::ReadError)?;
-
-    let extension = path.extension().and_then(|ext| ext.to_str());
-    let format_hint = extension.map(|ext| ext.to_string());
-
-    Ok(AudioFile { data, format_hint })
-}
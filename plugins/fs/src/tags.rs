@@ -1,4 +1,4 @@
-use hogehoge_types::{Tags, Uuid};
+use hogehoge_types::{Tags, Uuid, WriteReplayGainArgs};
 use lofty::tag::{ItemKey, ItemValue};
 use thiserror::Error;
 
@@ -30,6 +30,22 @@ pub fn map_lofty_to_internal(input: &lofty::tag::Tag) -> Result<Tags, TagsError>
     Ok(tags)
 }
 
+/// Album values are only written if they are known, existing ones are kept otherwise.
+pub fn insert_replay_gain(tag: &mut lofty::tag::Tag, args: &WriteReplayGainArgs) {
+    let format_gain = |gain_db: f32| format!("{:+.2} dB", gain_db);
+    let format_peak = |peak: f32| format!("{:.6}", peak);
+
+    tag.insert_text(ItemKey::ReplayGainTrackGain, format_gain(args.track_gain_db));
+    tag.insert_text(ItemKey::ReplayGainTrackPeak, format_peak(args.track_peak));
+
+    if let Some(album_gain_db) = args.album_gain_db {
+        tag.insert_text(ItemKey::ReplayGainAlbumGain, format_gain(album_gain_db));
+    }
+    if let Some(album_peak) = args.album_peak {
+        tag.insert_text(ItemKey::ReplayGainAlbumPeak, format_peak(album_peak));
+    }
+}
+
 //TODO: log error
 pub fn parse_uuid(value: &str) -> Option<Uuid> {
     Uuid::parse_str(value).ok()
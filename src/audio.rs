@@ -1,91 +1,701 @@
 use crate::plugin::{PluginError, PluginHandle, PluginSystem};
 use crate::queue::{Queue, QueueUpdate, QueueUpdateRx};
+use hogehoge_db::{Database, DbError};
 use hogehoge_types::{
-    AudioFile, ChannelCount, PlaybackId, PluginId, Sample, SampleRate, UniqueTrackIdentifier,
+    AlbumId, AudioBlock, AudioFile, ChannelCount, PlaybackId, PluginId, Sample, SampleRate,
+    UniqueTrackIdentifier,
+};
+use rodio::{Source, source::Zero};
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::{
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
 };
-use rodio::source::TrackPosition;
-use rodio::{OutputStream, OutputStreamBuilder, Source, source::Zero};
-use std::{sync::Arc, time::Duration};
 use thiserror::Error;
 use tokio::{
     runtime,
-    sync::{oneshot, watch},
+    sync::{broadcast::error::TryRecvError, mpsc, oneshot, watch},
+    task,
 };
 use tracing::*;
 
+mod decoder;
+use decoder::DecodedSource;
+mod dsp;
+use dsp::DspChain;
+pub use dsp::{DspSettings, EffectSettings, EqBand, EqBandKind, EqPreset};
+mod output;
+use output::{DeviceLostCallback, Output, SharedSource};
+pub use output::{OutputBackend, OutputError, OutputFormat, OutputSettings};
+mod persistence;
+mod render;
+pub use render::{RenderError, RenderSummary, render};
+mod replay_gain;
+use replay_gain::ReplayGainInfo;
+pub use replay_gain::{ReplayGainMode, ReplayGainSettings};
+mod resampler;
+pub use resampler::ResamplerQuality;
+mod resume;
+use resume::DEFAULT_RESUME_THRESHOLD;
+mod sleep;
+use sleep::SleepDeadline;
+pub use sleep::{SleepState, SleepTimer};
+mod stretch;
+use stretch::StretchedSource;
+pub use stretch::{MAX_SPEED, MIN_SPEED};
+mod transition;
+pub use transition::TransitionSettings;
+use transition::{Crossfade, Ramp};
+mod visualizer;
+pub use visualizer::VisualizerFrame;
+use visualizer::VisualizerTap;
+
+// in frames
 const SILENCE_LENGTH: usize = 512;
+const VOLUME_RAMP: Duration = Duration::from_millis(20);
+// they're collected with every change, so there are hardly ever more than one or two waiting
+const RETIRED_DSP_CAPACITY: usize = 4;
 
 #[derive(Clone)]
 pub struct AudioPlayer {
     pub queue: Arc<Queue>,
     playback_state: watch::Receiver<PlaybackState>,
+    command_tx: mpsc::UnboundedSender<PlayerCommand>,
+    replay_gain: Arc<Mutex<ReplayGainSettings>>,
+    transitions: Arc<Mutex<TransitionSettings>>,
+    resume_threshold: Arc<Mutex<Duration>>,
+    output: Arc<Mutex<OutputSettings>>,
+    output_format: OutputFormat,
+    output_device: Arc<Mutex<Option<String>>>,
+    dsp: Arc<watch::Sender<DspProfile>>,
+    visualizer: Arc<watch::Sender<VisualizerFrame>>,
+    db: Database,
 
-    #[allow(dead_code)]
     inner: Arc<AudioPlayerInner>,
 }
 
 pub struct AudioPlayerInner {
-    #[allow(dead_code)]
-    output_stream: OutputStream,
+    // only `None` while switching, so the old output stops before the new one starts
+    output: Mutex<Option<Output>>,
+    backend: OutputBackend,
+    source: Arc<Mutex<QueueSource>>,
+    format: OutputFormat,
+    device_state: watch::Sender<OutputDeviceState>,
+
+    device_lost_tx: mpsc::UnboundedSender<u64>,
+    // counts up with every opened output, so only the loss of the current device is handled
+    generation: AtomicU64,
+    // DSP settings the audio thread is done with, so they aren't freed there
+    retired_dsp: Mutex<Consumer<DspSettings>>,
+}
+
+/// The device audio is currently played on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputDeviceState {
+    /// `None` if there is no device and the audio isn't played anywhere.
+    pub device: Option<String>,
+    /// A device that disappeared or couldn't be opened, so the default device is used instead.
+    pub missing: Option<String>,
+}
+
+/// The DSP settings in use and where they come from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DspProfile {
+    pub settings: DspSettings,
+    /// Device the settings belong to, `None` for the profile shared by devices without their own.
+    pub device: Option<String>,
 }
 
-#[derive(Default, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub enum PlaybackState {
-    #[default]
-    Stopped,
+    Stopped {
+        volume: Volume,
+    },
     Playing {
+        track: Arc<UniqueTrackIdentifier>,
         duration: Option<Duration>,
         position: Duration,
         paused: bool,
+        seekable: bool,
+        /// How often the decoder couldn't keep up with playback for the current track.
+        underruns: u64,
+        volume: Volume,
+        /// Playback speed, the position and duration are in track time regardless.
+        speed: f32,
+        /// The position is saved, so the track continues from there the next time it's played.
+        remembers_position: bool,
+        /// `None` without a sleep timer.
+        sleep: Option<SleepState>,
     },
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Volume {
+    /// Between `0.0` and `1.0`.
+    pub level: f32,
+    pub muted: bool,
+}
+
+/// Commands are picked up by the [`QueueSource`] on the audio thread at its next state update.
+#[derive(Debug, Clone)]
+enum PlayerCommand {
+    Play,
+    Pause,
+    TogglePause,
+    Stop,
+    Seek(Duration),
+    /// Offset in seconds, negative values seek backwards.
+    SeekRelative(f64),
+    SetVolume(f32),
+    SetMuted(bool),
+    ToggleMute,
+    SetReplayGain(ReplayGainSettings),
+    SetTransitions(TransitionSettings),
+    SetResamplerQuality(ResamplerQuality),
+    SetDsp(DspSettings),
+    SetSpeed(f32),
+    SetResumeThreshold(Duration),
+    SetSleepTimer(Option<SleepDeadline>),
+}
+
+type PendingSource = oneshot::Receiver<Result<LoadedTrack, PluginAudioSourceError>>;
+type PendingResult =
+    Result<Result<LoadedTrack, PluginAudioSourceError>, oneshot::error::TryRecvError>;
+
+/// A track that is ready to be played.
+struct LoadedTrack {
+    // shared with the playback state, so updating it doesn't allocate
+    track: Arc<UniqueTrackIdentifier>,
+    source: StretchedSource,
+    replay_gain: ReplayGainInfo,
+    album_id: Option<AlbumId>,
+    // podcast the track is an episode of, all of its episodes share their speed
+    show: Option<String>,
+    remembers_position: bool,
+}
+
+/// What is known about a track from the library before it's played.
+struct TrackDetails {
+    replay_gain: ReplayGainInfo,
+    album_id: Option<AlbumId>,
+    show: Option<String>,
+    speed: f32,
+    remembers_position: bool,
+    resume_position: Option<Duration>,
+}
+
 pub struct QueueSource {
     current_playing: QueueCurrentSource,
-    cache: Option<(
-        UniqueTrackIdentifier,
-        oneshot::Receiver<Result<PluginAudioSource, PluginAudioSourceError>>,
-    )>,
+    cache: Option<(UniqueTrackIdentifier, PendingSource)>,
 
     rt: runtime::Handle,
     queue: Arc<Queue>,
+    db: Database,
     update_rx: QueueUpdateRx,
+    command_rx: mpsc::UnboundedReceiver<PlayerCommand>,
+
+    // every track is converted to this, so the output never changes its format
+    format: OutputFormat,
+    resampler_quality: ResamplerQuality,
+    // rendering to a file as fast as possible instead of playing in real time
+    offline: bool,
+
+    paused: bool,
+    // set by an explicit stop, keeps the queue from advancing until playback is started again
+    stopped: bool,
+    // seek that was requested while the current track was still loading
+    pending_seek: Option<Duration>,
+
+    volume: Volume,
+    // ramps towards the gain for `volume` to avoid clicks
+    volume_gain: Ramp,
+    replay_gain: ReplayGainSettings,
+    // ReplayGain of the current track as a linear factor
+    track_gain: Ramp,
+
+    transitions: TransitionSettings,
+    // podcasts and tracks longer than this remember their position
+    resume_threshold: Duration,
+    // fades out before a skip or pause, which then happens once it's silent
+    fade: Ramp,
+    after_fade: Option<AfterFade>,
+    crossfade: Option<Crossfade>,
+
+    sleep: Option<SleepDeadline>,
+    // samples played since the sleep timer was set, not counting the time spent paused
+    sleep_samples: u64,
+    // fades out towards the end of the sleep timer
+    sleep_gain: Ramp,
+
+    // speed that was set for a show while playing, prefetched episodes still have the old one
+    show_speed: Option<(String, f32)>,
+
+    dsp: DspChain,
+    // the DSP works on whole frames, which are then handed out sample by sample
+    frame: Vec<Sample>,
+    frame_position: usize,
+    // only set up for playback, rendering to a file has nobody to show it to
+    visualizer: Option<VisualizerTap>,
+    // hands back applied DSP settings, `None` when nobody collects them
+    retired_dsp: Option<Producer<DspSettings>>,
 
     state_tx: watch::Sender<PlaybackState>,
     samples_to_state_update: usize,
 }
 
-#[allow(clippy::large_enum_variant)]
+enum AfterFade {
+    Pause,
+    Switch(Option<UniqueTrackIdentifier>),
+}
+
 enum QueueCurrentSource {
-    Source(TrackPosition<PluginAudioSource>),
+    Source(UniqueTrackIdentifier, LoadedTrack),
+    // plays silence until the decoder for the track is ready
+    Loading(UniqueTrackIdentifier, PendingSource, Zero),
     Nothing(Zero),
 }
 
 impl AudioPlayer {
-    pub fn new(plugins: PluginSystem) -> AudioPlayer {
-        let output_stream = OutputStreamBuilder::open_default_stream()
-            .expect("Failed to open default audio output stream");
-
-        let queue = Queue::new(plugins);
-
-        let queue_src = QueueSource::new(queue.clone());
+    /// The output settings and device have to be known up front, the output is opened with them.
+    pub fn new(
+        plugins: PluginSystem,
+        db: Database,
+        backend: &OutputBackend,
+        settings: OutputSettings,
+        device: Option<String>,
+    ) -> Result<AudioPlayer, OutputError> {
+        let (device_lost_tx, device_lost_rx) = mpsc::unbounded_channel();
+        let on_lost = device_lost_callback(&device_lost_tx, 0);
+
+        let output = Output::open(backend, &settings, device.as_deref(), &on_lost)?;
+        let output_format = output.format();
+
+        let visualizing_plugins = plugins
+            .plugins
+            .values()
+            .any(|pool| pool.capabilities.visualize);
+        let plugin_system = plugins.clone();
+        let queue = Queue::new(plugins, db.clone());
+
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let mut queue_src = QueueSource::new(
+            queue.clone(),
+            db.clone(),
+            command_rx,
+            output_format,
+            settings.resampler_quality,
+        );
 
         let playback_state = queue_src.subscribe_state();
 
-        output_stream.mixer().add(queue_src);
+        let visualizer = Arc::new(watch::Sender::new(VisualizerFrame::default()));
+        queue_src.visualizer = Some(VisualizerTap::spawn(
+            output_format,
+            Arc::downgrade(&visualizer),
+        ));
+        // plugins keep the visualizer running for as long as the player exists
+        if visualizing_plugins {
+            tokio::spawn(visualizer::forward_to_plugins(
+                plugin_system,
+                visualizer.subscribe(),
+            ));
+        }
 
-        let inner = AudioPlayerInner { output_stream };
+        let (retired_dsp_tx, retired_dsp_rx) = RingBuffer::new(RETIRED_DSP_CAPACITY);
+        queue_src.retired_dsp = Some(retired_dsp_tx);
+
+        let source = Arc::new(Mutex::new(queue_src));
+        output
+            .mixer()
+            .add(SharedSource::new(source.clone(), output_format));
+
+        let device_state = OutputDeviceState {
+            device: output.device().map(str::to_string),
+            missing: device.clone().filter(|_| output.fell_back()),
+        };
+
+        let inner = Arc::new(AudioPlayerInner {
+            output: Mutex::new(Some(output)),
+            backend: backend.clone(),
+            source,
+            format: output_format,
+            device_state: watch::Sender::new(device_state),
+            device_lost_tx,
+            generation: AtomicU64::new(0),
+            retired_dsp: Mutex::new(retired_dsp_rx),
+        });
 
-        AudioPlayer {
+        tokio::spawn(fall_back_on_device_loss(
+            Arc::downgrade(&inner),
+            device_lost_rx,
+        ));
+
+        Ok(AudioPlayer {
             queue,
             playback_state,
-            inner: Arc::new(inner),
-        }
+            command_tx,
+            replay_gain: Arc::new(Mutex::new(ReplayGainSettings::default())),
+            transitions: Arc::new(Mutex::new(TransitionSettings::default())),
+            resume_threshold: Arc::new(Mutex::new(DEFAULT_RESUME_THRESHOLD)),
+            output: Arc::new(Mutex::new(settings)),
+            output_format,
+            output_device: Arc::new(Mutex::new(device)),
+            dsp: Arc::new(watch::Sender::new(DspProfile::default())),
+            visualizer,
+            db,
+            inner,
+        })
     }
 
     pub fn subscribe_state(&self) -> watch::Receiver<PlaybackState> {
         self.playback_state.clone()
     }
+
+    /// Waveform and spectrum of what is playing, updated about 60 times a second for as long as
+    /// anyone is subscribed.
+    pub fn subscribe_visualizer(&self) -> watch::Receiver<VisualizerFrame> {
+        self.visualizer.subscribe()
+    }
+
+    /// Resumes playback, or restarts the current track if playback was stopped.
+    pub fn play(&self) {
+        self.send_command(PlayerCommand::Play);
+    }
+
+    pub fn pause(&self) {
+        self.send_command(PlayerCommand::Pause);
+    }
+
+    pub fn toggle_pause(&self) {
+        self.send_command(PlayerCommand::TogglePause);
+    }
+
+    /// Stops the current track. The queue stays as it is.
+    pub fn stop(&self) {
+        self.send_command(PlayerCommand::Stop);
+    }
+
+    pub fn seek(&self, position: Duration) {
+        self.send_command(PlayerCommand::Seek(position));
+    }
+
+    /// Seeks relative to the current position, negative offsets seek backwards.
+    pub fn seek_relative(&self, offset_secs: f64) {
+        self.send_command(PlayerCommand::SeekRelative(offset_secs));
+    }
+
+    /// Sets the volume between `0.0` and `1.0`.
+    pub fn set_volume(&self, level: f32) {
+        self.send_command(PlayerCommand::SetVolume(level));
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.send_command(PlayerCommand::SetMuted(muted));
+    }
+
+    pub fn toggle_mute(&self) {
+        self.send_command(PlayerCommand::ToggleMute);
+    }
+
+    /// Changes the speed of the current track between [`MIN_SPEED`] and [`MAX_SPEED`] without
+    /// changing its pitch. The speed is remembered for the show the track belongs to, or for the
+    /// track itself if it isn't part of one.
+    pub fn set_speed(&self, speed: f32) {
+        let speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+
+        self.send_command(PlayerCommand::SetSpeed(speed));
+        if let Some(track) = self.queue.get_current_track() {
+            self.save_playback_speed(track, speed);
+        }
+    }
+
+    pub fn replay_gain(&self) -> ReplayGainSettings {
+        *self.replay_gain.lock().unwrap()
+    }
+
+    /// Applies new ReplayGain settings and saves them.
+    pub fn set_replay_gain(&self, settings: ReplayGainSettings) {
+        self.apply_replay_gain(settings);
+        self.save_replay_gain(settings);
+    }
+
+    fn apply_replay_gain(&self, settings: ReplayGainSettings) {
+        *self.replay_gain.lock().unwrap() = settings;
+        self.send_command(PlayerCommand::SetReplayGain(settings));
+    }
+
+    pub fn transitions(&self) -> TransitionSettings {
+        *self.transitions.lock().unwrap()
+    }
+
+    /// Applies new crossfade and fade durations and saves them.
+    pub fn set_transitions(&self, settings: TransitionSettings) {
+        self.apply_transitions(settings);
+        self.save_transitions(settings);
+    }
+
+    fn apply_transitions(&self, settings: TransitionSettings) {
+        *self.transitions.lock().unwrap() = settings;
+        self.send_command(PlayerCommand::SetTransitions(settings));
+    }
+
+    pub fn resume_threshold(&self) -> Duration {
+        *self.resume_threshold.lock().unwrap()
+    }
+
+    /// Sets and saves how long a track has to be to remember where it was left off. Podcasts
+    /// always remember it, zero turns it off for everything else.
+    pub fn set_resume_threshold(&self, threshold: Duration) {
+        self.apply_resume_threshold(threshold);
+        self.save_resume_threshold(threshold);
+    }
+
+    fn apply_resume_threshold(&self, threshold: Duration) {
+        *self.resume_threshold.lock().unwrap() = threshold;
+        self.send_command(PlayerCommand::SetResumeThreshold(threshold));
+    }
+
+    pub fn output_settings(&self) -> OutputSettings {
+        *self.output.lock().unwrap()
+    }
+
+    /// The format the output actually runs at, which might differ from the configured one.
+    pub fn output_format(&self) -> OutputFormat {
+        self.output_format
+    }
+
+    /// Saves new output settings. The resampler quality applies to tracks that start loading from
+    /// now on, a new sample rate only once the output is reopened on the next start.
+    pub fn set_output_settings(&self, settings: OutputSettings) {
+        *self.output.lock().unwrap() = settings;
+        self.send_command(PlayerCommand::SetResamplerQuality(
+            settings.resampler_quality,
+        ));
+        self.save_output_settings(settings);
+    }
+
+    /// Names of the output devices that can be selected.
+    pub async fn output_devices(&self) -> Vec<String> {
+        task::spawn_blocking(output::output_devices)
+            .await
+            .unwrap_or_default()
+    }
+
+    /// The selected output device, `None` for the default device.
+    pub fn output_device(&self) -> Option<String> {
+        self.output_device.lock().unwrap().clone()
+    }
+
+    /// Selects and saves the output device. Playback moves over to it right away and carries on
+    /// where it was.
+    pub fn set_output_device(&self, device: Option<String>) {
+        *self.output_device.lock().unwrap() = device.clone();
+        self.save_output_device(device.clone());
+
+        if self.inner.backend != OutputBackend::Device {
+            info!(
+                "Not switching to {:?}, audio is played into {:?}",
+                device, self.inner.backend
+            );
+            return;
+        }
+
+        let inner = self.inner.clone();
+        task::spawn_blocking(move || inner.switch_output(device.as_deref(), None));
+    }
+
+    /// Changes whenever the output switches to another device.
+    pub fn subscribe_output_device(&self) -> watch::Receiver<OutputDeviceState> {
+        self.inner.device_state.subscribe()
+    }
+
+    pub fn dsp_profile(&self) -> DspProfile {
+        self.dsp.borrow().clone()
+    }
+
+    /// Changes whenever the DSP settings change, also when another device brings its own profile.
+    pub fn subscribe_dsp_profile(&self) -> watch::Receiver<DspProfile> {
+        self.dsp.subscribe()
+    }
+
+    /// Applies new DSP settings and saves them to the profile that is in use.
+    pub fn set_dsp_settings(&self, settings: DspSettings) {
+        let device = self.dsp.borrow().device.clone();
+        self.save_dsp_profile(device.clone(), settings.clone());
+        self.apply_dsp(DspProfile { settings, device });
+    }
+
+    fn apply_dsp(&self, profile: DspProfile) {
+        // free the settings the audio thread is done with
+        while self.inner.retired_dsp.lock().unwrap().pop().is_ok() {}
+
+        self.send_command(PlayerCommand::SetDsp(profile.settings.clone()));
+        self.dsp.send_replace(profile);
+    }
+
+    /// Gives the current device a profile of its own, starting out with the current settings, or
+    /// goes back to the shared profile and forgets the one of the device.
+    pub fn set_device_profile(&self, enabled: bool) {
+        let current = self.dsp_profile();
+
+        if enabled {
+            let Some(device) = self.inner.device_state.borrow().device.clone() else {
+                return;
+            };
+
+            self.save_dsp_profile(Some(device.clone()), current.settings.clone());
+            self.apply_dsp(DspProfile {
+                settings: current.settings,
+                device: Some(device),
+            });
+        } else if let Some(device) = current.device {
+            let player = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = player.db.delete_dsp_profile(&device).await {
+                    warn!("Failed to delete DSP profile of {}: {}", device, e);
+                }
+
+                player.load_dsp_profile(None).await;
+            });
+        }
+    }
+
+    fn send_command(&self, command: PlayerCommand) {
+        if let Err(e) = self.command_tx.send(command) {
+            error!("Audio thread is gone, dropping {:?}", e.0);
+        }
+    }
+}
+
+impl AudioPlayerInner {
+    /// Replaces the output with one on `device`, the queue continues on it where it was.
+    ///
+    /// `lost` is the device that disappeared, if that's why the output is switched.
+    fn switch_output(&self, device: Option<&str>, lost: Option<String>) {
+        let mut output = self.output.lock().unwrap();
+        drop(output.take());
+
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+        let on_lost = device_lost_callback(&self.device_lost_tx, generation);
+
+        // ask for the format everything is already converted to, so the mixer doesn't have to
+        let settings = OutputSettings {
+            sample_rate: Some(self.format.sample_rate),
+            ..OutputSettings::default()
+        };
+
+        let new_output = match Output::open(&self.backend, &settings, device, &on_lost) {
+            Ok(new_output) => new_output,
+            Err(e) => {
+                error!("Failed to switch the output: {}", e);
+                return;
+            }
+        };
+
+        if new_output.format() != self.format {
+            info!(
+                "{:?} runs at {:?}, converting from {:?}",
+                new_output.device(),
+                new_output.format(),
+                self.format
+            );
+        }
+
+        new_output
+            .mixer()
+            .add(SharedSource::new(self.source.clone(), self.format));
+
+        let missing = device
+            .filter(|_| new_output.fell_back())
+            .map(str::to_string)
+            .or(lost);
+        self.device_state.send_replace(OutputDeviceState {
+            device: new_output.device().map(str::to_string),
+            missing,
+        });
+
+        *output = Some(new_output);
+    }
+}
+
+fn device_lost_callback(tx: &mpsc::UnboundedSender<u64>, generation: u64) -> DeviceLostCallback {
+    let tx = tx.clone();
+    Arc::new(move || {
+        let _ = tx.send(generation);
+    })
+}
+
+/// Moves playback to the default device whenever the current device disappears.
+async fn fall_back_on_device_loss(
+    inner: Weak<AudioPlayerInner>,
+    mut device_lost_rx: mpsc::UnboundedReceiver<u64>,
+) {
+    while let Some(generation) = device_lost_rx.recv().await {
+        let Some(inner) = inner.upgrade() else {
+            break;
+        };
+
+        // the stream keeps reporting errors until it's dropped
+        if generation != inner.generation.load(Ordering::Relaxed) {
+            continue;
+        }
+
+        let lost = inner.device_state.borrow().device.clone();
+        warn!(
+            "Output device {:?} disappeared, switching to the default device",
+            lost
+        );
+
+        let _ = task::spawn_blocking(move || inner.switch_output(None, lost)).await;
+    }
+}
+
+impl Default for TrackDetails {
+    fn default() -> Self {
+        TrackDetails {
+            replay_gain: ReplayGainInfo::default(),
+            album_id: None,
+            show: None,
+            speed: 1.0,
+            remembers_position: false,
+            resume_position: None,
+        }
+    }
+}
+
+impl Default for PlaybackState {
+    fn default() -> Self {
+        PlaybackState::Stopped {
+            volume: Volume::default(),
+        }
+    }
+}
+
+impl PlaybackState {
+    pub fn volume(&self) -> Volume {
+        match self {
+            PlaybackState::Stopped { volume } | PlaybackState::Playing { volume, .. } => *volume,
+        }
+    }
+}
+
+impl Volume {
+    /// Loudness is perceived logarithmically, so a linear slider needs a curve to feel even.
+    fn gain(&self) -> f32 {
+        if self.muted { 0.0 } else { self.level.powi(3) }
+    }
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Volume {
+            level: 1.0,
+            muted: false,
+        }
+    }
 }
 
 impl std::fmt::Debug for AudioPlayer {
@@ -98,22 +708,57 @@ impl std::fmt::Debug for AudioPlayer {
 }
 
 impl QueueSource {
-    pub fn new(queue: Arc<Queue>) -> Self {
-        let current_playing = QueueCurrentSource::new_silence();
+    fn new(
+        queue: Arc<Queue>,
+        db: Database,
+        command_rx: mpsc::UnboundedReceiver<PlayerCommand>,
+        format: OutputFormat,
+        resampler_quality: ResamplerQuality,
+    ) -> Self {
+        let current_playing = QueueCurrentSource::new_silence(format);
         let update_rx = queue.subscribe_updates();
 
         let rt = runtime::Handle::current();
 
         let state_tx = watch::Sender::new(PlaybackState::default());
 
+        let dsp = DspChain::new(format, queue.plugin_system.clone(), &DspSettings::default());
+
         QueueSource {
             current_playing,
             cache: None,
             queue,
+            db,
             rt,
             update_rx,
+            command_rx,
+            format,
+            resampler_quality,
+            offline: false,
+            paused: false,
+            stopped: false,
+            pending_seek: None,
+            volume: Volume::default(),
+            volume_gain: Ramp::new(Volume::default().gain()),
+            replay_gain: ReplayGainSettings::default(),
+            track_gain: Ramp::new(1.0),
+            transitions: TransitionSettings::default(),
+            resume_threshold: DEFAULT_RESUME_THRESHOLD,
+            fade: Ramp::new(1.0),
+            after_fade: None,
+            crossfade: None,
+            sleep: None,
+            sleep_samples: 0,
+            sleep_gain: Ramp::new(1.0),
+            show_speed: None,
+            dsp,
+            frame: vec![0.0; format.channels as usize],
+            frame_position: format.channels as usize,
+            visualizer: None,
+            retired_dsp: None,
             state_tx,
-            samples_to_state_update: 1,
+            // start with an update, so the first frame already comes from the right source
+            samples_to_state_update: 0,
         }
     }
 
@@ -121,22 +766,90 @@ impl QueueSource {
         self.state_tx.subscribe()
     }
 
-    fn start_cache(
-        &self,
-        track: UniqueTrackIdentifier,
-    ) -> oneshot::Receiver<Result<PluginAudioSource, PluginAudioSourceError>> {
+    /// Waits for tracks to load instead of playing silence in the meantime and ends once the queue
+    /// is done, so the output can be rendered as fast as it is pulled.
+    ///
+    /// Loading blocks on the runtime, so this must not be pulled from an async context.
+    fn set_offline(&mut self) {
+        self.offline = true;
+        self.dsp.set_offline();
+    }
+
+    fn start_cache(&self, track: UniqueTrackIdentifier) -> PendingSource {
         let (tx, rx) = oneshot::channel();
 
         info!("Starting cache for track: {:?}", track);
 
         let plugin_system = self.queue.plugin_system.clone();
+        let db = self.db.clone();
+        let rt = self.rt.clone();
+        let format = self.format;
+        let resampler_quality = self.resampler_quality;
+        let resume_threshold = self.resume_threshold;
+        let offline = self.offline;
         self.rt.spawn_blocking(move || {
-            let audio_source =
-                PluginAudioSource::from_track_identifier(&plugin_system, track.clone());
+            let loaded = PluginAudioSource::from_track_identifier(&plugin_system, track.clone())
+                .map(|source| {
+                    let duration = source.duration();
+
+                    // tracks that aren't in the library simply don't get any ReplayGain
+                    let details = rt.block_on(async {
+                        let info = db.get_track_by_identifier(&track).await?;
+                        let measured = db.get_track_loudness(&track).await?;
+                        let tags = info.as_ref().map(|info| &info.tags);
+
+                        let show = tags.and_then(persistence::show);
+                        let speed =
+                            persistence::load_playback_speed(&db, &track, show.as_deref()).await?;
+
+                        let remembers_position =
+                            resume::remembers_position(tags, duration, resume_threshold);
+                        let resume_position = if remembers_position {
+                            resume::load_resume_position(&db, &track).await?
+                        } else {
+                            None
+                        };
+
+                        Ok::<_, DbError>(TrackDetails {
+                            replay_gain: tags
+                                .map(ReplayGainInfo::from_tags)
+                                .unwrap_or_default()
+                                .or_measured(measured),
+                            album_id: info.as_ref().and_then(|info| info.album_id),
+                            show,
+                            speed,
+                            remembers_position,
+                            resume_position,
+                        })
+                    });
+                    let details = details.unwrap_or_else(|e| {
+                        warn!("Failed to look up track {:?}: {}", track, e);
+                        TrackDetails::default()
+                    });
+
+                    let mut source = DecodedSource::spawn(source, format, resampler_quality);
+                    if offline {
+                        source.set_offline();
+                    }
+                    if let Some(position) = details.resume_position.filter(|_| source.is_seekable())
+                    {
+                        info!("Resuming {:?} at {:?}", track, position);
+                        source.seek(position);
+                    }
+
+                    LoadedTrack {
+                        track: Arc::new(track.clone()),
+                        source: StretchedSource::new(source, details.speed),
+                        replay_gain: details.replay_gain,
+                        album_id: details.album_id,
+                        show: details.show,
+                        remembers_position: details.remembers_position,
+                    }
+                });
 
             info!("Finished caching track: {:?}", track);
 
-            let _ = tx.send(audio_source);
+            let _ = tx.send(loaded);
         });
 
         rx
@@ -151,124 +864,591 @@ impl QueueSource {
         self.cache = Some((track.clone(), self.start_cache(track)));
     }
 
-    fn get_cached_source(
-        &mut self,
-        track: &UniqueTrackIdentifier,
-    ) -> Result<PluginAudioSource, PluginAudioSourceError> {
-        let rx = self
-            .cache
+    fn take_cached_source(&mut self, track: &UniqueTrackIdentifier) -> PendingSource {
+        self.cache
             .take()
             .filter(|(cached_id, _)| cached_id == track)
             .map(|(_, rx)| rx)
-            .unwrap_or_else(|| self.start_cache(track.clone()));
+            .unwrap_or_else(|| self.start_cache(track.clone()))
+    }
+
+    fn handle_queue_updates(&mut self) {
+        let mut changed = false;
+        let mut current_changed = false;
+        loop {
+            match self.update_rx.try_recv() {
+                Ok(QueueUpdate::CurrentTrackChanged | QueueUpdate::Replaced)
+                | Err(TryRecvError::Lagged(_)) => {
+                    changed = true;
+                    current_changed = true;
+                }
+                Ok(
+                    QueueUpdate::TrackInserted { .. }
+                    | QueueUpdate::TrackRemoved { .. }
+                    | QueueUpdate::TrackMoved { .. }
+                    | QueueUpdate::Cleared
+                    | QueueUpdate::ShuffleChanged(_)
+                    | QueueUpdate::RepeatChanged(_),
+                ) => changed = true,
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+            }
+        }
+
+        if !changed {
+            return;
+        }
+
+        // our own `forward` also ends up here, so only switch if the queue disagrees with us
+        if current_changed {
+            let current = self.queue.get_current_track();
+            if current.as_ref() != self.current_playing.track() {
+                info!("Current track changed to {:?}", current);
+                self.stopped = false;
+                self.skip_to(current);
+            }
+        }
+
+        match self.queue.get_next_track() {
+            Some(upcoming) => self.cache_track(upcoming),
+            None => {
+                if self.cache.take().is_some() {
+                    debug!("Next track was removed, dropping prefetched source");
+                }
+            }
+        }
+
+        self.cancel_missed_sleep_timer();
+    }
+
+    fn handle_commands(&mut self) {
+        while let Ok(command) = self.command_rx.try_recv() {
+            debug!("Handling player command: {:?}", command);
+
+            match command {
+                PlayerCommand::Play => self.resume(),
+                PlayerCommand::Pause => self.pause(),
+                PlayerCommand::TogglePause
+                    if self.paused
+                        || self.stopped
+                        || matches!(self.after_fade, Some(AfterFade::Pause)) =>
+                {
+                    self.resume()
+                }
+                PlayerCommand::TogglePause => self.pause(),
+                PlayerCommand::Stop => self.stop(),
+                PlayerCommand::Seek(position) => self.seek(|_| position),
+                PlayerCommand::SeekRelative(offset) => self.seek(|current| {
+                    Duration::from_secs_f64((current.as_secs_f64() + offset).max(0.0))
+                }),
+                PlayerCommand::SetVolume(level) => {
+                    self.volume.level = level.clamp(0.0, 1.0);
+                    self.ramp_volume();
+                }
+                PlayerCommand::SetMuted(muted) => {
+                    self.volume.muted = muted;
+                    self.ramp_volume();
+                }
+                PlayerCommand::ToggleMute => {
+                    self.volume.muted = !self.volume.muted;
+                    self.ramp_volume();
+                }
+                PlayerCommand::SetReplayGain(settings) => {
+                    self.replay_gain = settings;
+                    if let QueueCurrentSource::Source(_, loaded) = &self.current_playing {
+                        let gain = loaded.replay_gain.gain(&self.replay_gain);
+                        self.track_gain.ramp_to(gain, self.samples_for(VOLUME_RAMP));
+                    }
+                }
+                PlayerCommand::SetTransitions(settings) => self.transitions = settings,
+                PlayerCommand::SetResamplerQuality(quality) => self.resampler_quality = quality,
+                PlayerCommand::SetDsp(settings) => {
+                    self.dsp.configure(&settings);
+                    if let Some(retired) = &mut self.retired_dsp {
+                        // if the player hasn't collected them yet they're just freed here after all
+                        let _ = retired.push(settings);
+                    }
+                }
+                PlayerCommand::SetResumeThreshold(threshold) => self.resume_threshold = threshold,
+                PlayerCommand::SetSleepTimer(deadline) => self.set_sleep_timer(deadline),
+                PlayerCommand::SetSpeed(speed) => {
+                    if let QueueCurrentSource::Source(_, loaded) = &mut self.current_playing {
+                        loaded.source.set_speed(speed);
+                        if let Some(show) = &loaded.show {
+                            self.show_speed = Some((show.clone(), loaded.source.speed()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Number of samples that make up `duration` in the output format.
+    fn samples_for(&self, duration: Duration) -> usize {
+        let frames = (duration.as_secs_f64() * self.format.sample_rate as f64) as usize;
+        frames * self.format.channels as usize
+    }
+
+    /// Catches up on a speed change for the show of `loaded` since it was prefetched.
+    fn apply_show_speed(&self, loaded: &mut LoadedTrack) {
+        match (&loaded.show, &self.show_speed) {
+            (Some(show), Some((changed, speed))) if show == changed => {
+                loaded.source.set_speed(*speed)
+            }
+            _ => {}
+        }
+    }
+
+    fn ramp_volume(&mut self) {
+        self.volume_gain
+            .ramp_to(self.volume.gain(), self.samples_for(VOLUME_RAMP));
+    }
+
+    fn is_audible(&self) -> bool {
+        !self.paused && matches!(self.current_playing, QueueCurrentSource::Source(..))
+    }
+
+    fn pause(&mut self) {
+        if self.stopped || self.paused {
+            return;
+        }
+
+        if self.transitions.pause_fade.is_zero() || !self.is_audible() {
+            self.paused = true;
+            return;
+        }
+
+        match self.after_fade {
+            None => {
+                self.fade
+                    .ramp_to(0.0, self.samples_for(self.transitions.pause_fade));
+                self.after_fade = Some(AfterFade::Pause);
+            }
+            Some(AfterFade::Pause) => {}
+            // the skip is already fading out, no need to wait for it
+            Some(AfterFade::Switch(_)) => self.paused = true,
+        }
+    }
+
+    fn stop(&mut self) {
+        self.stopped = true;
+        self.paused = false;
+        self.current_playing = QueueCurrentSource::new_silence(self.format);
+        self.crossfade = None;
+        self.after_fade = None;
+        self.fade.jump_to(1.0);
+        self.set_sleep_timer(None);
+    }
+
+    fn resume(&mut self) {
+        if matches!(self.after_fade, Some(AfterFade::Pause)) {
+            // paused again before the fade finished, so just fade back in from where it is
+            self.after_fade = None;
+            self.fade
+                .ramp_to(1.0, self.samples_for(self.transitions.pause_fade));
+            return;
+        }
+
+        if self.paused {
+            self.fade.jump_to(0.0);
+            self.fade
+                .ramp_to(1.0, self.samples_for(self.transitions.pause_fade));
+        }
+        self.paused = false;
+
+        if !std::mem::take(&mut self.stopped) {
+            return;
+        }
+
+        // start the track that was stopped from the beginning, otherwise the queue just continues
+        if let Some(current) = self.queue.get_current_track() {
+            info!("Restarting track: {:?}", current);
+            self.play_track(Some(current));
+        }
+    }
+
+    /// Switches to `track` because the queue changed, fading out first if that's enabled.
+    fn skip_to(&mut self, track: Option<UniqueTrackIdentifier>) {
+        // the pause should still happen, there is just no time left to fade out for it
+        if matches!(self.after_fade, Some(AfterFade::Pause)) {
+            self.finish_fade();
+        }
+
+        if self.transitions.skip_fade.is_zero() || !self.is_audible() {
+            self.after_fade = None;
+            self.play_track(track);
+            return;
+        }
+
+        // skipping again while fading out just changes where the fade goes
+        if self.after_fade.is_none() {
+            self.fade
+                .ramp_to(0.0, self.samples_for(self.transitions.skip_fade));
+        }
+        self.after_fade = Some(AfterFade::Switch(track));
+    }
+
+    /// Does whatever was waiting for the fade out. The fade in happens on resume or once the next
+    /// track is loaded.
+    fn finish_fade(&mut self) {
+        match self.after_fade.take() {
+            Some(AfterFade::Pause) => self.paused = true,
+            Some(AfterFade::Switch(track)) => self.play_track(track),
+            None => {}
+        }
+    }
+
+    /// Starts mixing in the next track once the current one is about to end.
+    fn start_crossfade_if_due(&mut self) {
+        if self.transitions.crossfade.is_zero()
+            || self.crossfade.is_some()
+            || self.after_fade.is_some()
+            || !self.is_audible()
+            || self.sleeps_after_current()
+        {
+            return;
+        }
+
+        let QueueCurrentSource::Source(_, current) = &self.current_playing else {
+            return;
+        };
+        let Some(duration) = current.source.total_duration() else {
+            return;
+        };
+        // what is left of the track takes longer or shorter to play at another speed
+        let remaining = duration
+            .saturating_sub(current.source.playback_position())
+            .div_f32(current.source.speed());
+        if remaining.is_zero() || remaining > self.transitions.crossfade {
+            return;
+        }
+
+        let Some((next_track, pending)) = &mut self.cache else {
+            return;
+        };
+        let mut next = match receive_track(pending, &self.rt, self.offline) {
+            Ok(Ok(next)) => next,
+            Ok(Err(e)) => {
+                // it's loaded again once this track ends, which also takes care of skipping it
+                warn!("Failed to load {:?} for crossfading: {}", next_track, e);
+                self.cache = None;
+                return;
+            }
+            Err(_) => return,
+        };
+        let next_track = next_track.clone();
+
+        if current.album_id.is_some() && current.album_id == next.album_id {
+            trace!("Not crossfading into {:?} from the same album", next_track);
+
+            // `try_recv` consumed the source, it still has to be played once this track ends
+            let (tx, rx) = oneshot::channel();
+            let _ = tx.send(Ok(next));
+            self.cache = Some((next_track, rx));
+            return;
+        }
+
+        self.cache = None;
 
-        match rx.blocking_recv() {
-            Ok(result) => result,
+        let upcoming = self.queue.advance();
+        if upcoming.as_ref() != Some(&next_track) {
+            // the queue changed since the track was cached
+            self.play_track(upcoming);
+            return;
+        }
+
+        info!("Crossfading into {:?} over {:?}", next_track, remaining);
+        self.apply_show_speed(&mut next);
+
+        let length = self.samples_for(remaining);
+        let outgoing_gain = self.track_gain.target();
+        self.track_gain
+            .jump_to(next.replay_gain.gain(&self.replay_gain));
+
+        let previous = std::mem::replace(
+            &mut self.current_playing,
+            QueueCurrentSource::Source(next_track, next),
+        );
+        if let QueueCurrentSource::Source(_, outgoing) = previous {
+            self.crossfade = Some(Crossfade::new(outgoing, outgoing_gain, length));
+        }
+    }
+
+    fn seek(&mut self, target: impl FnOnce(Duration) -> Duration) {
+        let source = match &mut self.current_playing {
+            QueueCurrentSource::Source(_, loaded) => &mut loaded.source,
+            QueueCurrentSource::Loading(..) => {
+                let target = target(self.pending_seek.unwrap_or_default());
+                debug!(
+                    "Track is still loading, seeking to {:?} once it's ready",
+                    target
+                );
+                self.pending_seek = Some(target);
+                return;
+            }
+            QueueCurrentSource::Nothing(_) => {
+                debug!("Nothing is playing, ignoring seek");
+                return;
+            }
+        };
 
-            Err(_) => {
+        if !source.is_seekable() {
+            warn!("Current track can't be seeked");
+            return;
+        }
+
+        let target = target(source.playback_position());
+        source.seek(target);
+        self.crossfade = None;
+    }
+
+    fn play_next(&mut self) {
+        let upcoming = self.queue.advance();
+        if let Some(upcoming) = &upcoming {
+            info!("Playing next track: {:?}", upcoming);
+        }
+
+        self.play_track(upcoming);
+    }
+
+    fn play_track(&mut self, track: Option<UniqueTrackIdentifier>) {
+        self.pending_seek = None;
+        self.crossfade = None;
+        self.current_playing = match track {
+            Some(track) => {
+                let pending = self.take_cached_source(&track);
+                QueueCurrentSource::new_loading(track, pending, self.format)
+            }
+            None => {
+                trace!("No more tracks in the queue, playing silence");
+                QueueCurrentSource::new_silence(self.format)
+            }
+        };
+
+        self.cancel_missed_sleep_timer();
+    }
+
+    /// Switches to the decoded source once the current track has finished loading.
+    fn poll_loading(&mut self) {
+        let QueueCurrentSource::Loading(track, pending, _) = &mut self.current_playing else {
+            return;
+        };
+
+        let result = receive_track(pending, &self.rt, self.offline);
+        let track = track.clone();
+
+        match result {
+            Ok(Ok(mut loaded)) => {
+                debug!("Decoder ready for track: {:?}", track);
+
+                if let Some(position) = self.pending_seek.take() {
+                    loaded.source.seek(position);
+                }
+                self.apply_show_speed(&mut loaded);
+
+                // the new track starts from silence, so there is nothing to ramp from
+                self.track_gain
+                    .jump_to(loaded.replay_gain.gain(&self.replay_gain));
+
+                self.current_playing = QueueCurrentSource::Source(track, loaded);
+
+                // faded out for a skip
+                if self.fade.target() < 1.0 {
+                    self.fade
+                        .ramp_to(1.0, self.samples_for(self.transitions.skip_fade));
+                }
+            }
+            Err(oneshot::error::TryRecvError::Empty) => {}
+            Ok(Err(e)) => {
+                warn!("Failed to get cached source for {:?}: {}", track, e);
+                self.play_next();
+            }
+            Err(oneshot::error::TryRecvError::Closed) => {
                 error!("Failed to receive cached track for {:?}", track);
-                Err(PluginAudioSourceError::NoAudioData)
+                self.play_next();
             }
         }
     }
-}
 
-impl Iterator for QueueSource {
-    type Item = Sample;
+    /// Pulls the next frame and runs it through the DSP chain.
+    #[inline]
+    fn next_frame(&mut self) -> Option<()> {
+        self.frame[0] = self.next_unscaled()?;
+        for channel in 1..self.frame.len() {
+            // state updates only happen at the start of a frame, so the rest is always there
+            self.frame[channel] = self.next_unscaled().unwrap_or(0.0);
+        }
+
+        self.dsp.process(&mut self.frame);
+        if let Some(visualizer) = &mut self.visualizer {
+            visualizer.push(&self.frame);
+        }
+        self.frame_position = 0;
+
+        Some(())
+    }
 
     #[inline]
-    fn next(&mut self) -> Option<Self::Item> {
+    fn next_unscaled(&mut self) -> Option<Sample> {
         loop {
             if self.samples_to_state_update == 0 {
-                if let Ok(update) = self.update_rx.try_recv() {
-                    match update {
-                        QueueUpdate::CurrentTrackChanged | QueueUpdate::TrackAdded(_) => {
-                            if let Some(upcoming) = self.queue.get_next_track() {
-                                self.cache_track(upcoming);
-                            }
-                        }
+                self.handle_queue_updates();
+                self.handle_commands();
+
+                if self.fade.is_done() {
+                    self.finish_fade();
+                }
+                self.start_crossfade_if_due();
+                self.update_sleep_timer();
+
+                // nothing is played while paused, but the track should still be ready to go (and
+                // show up in the state) as soon as possible
+                if self.paused {
+                    self.poll_loading();
+                }
+
+                if self.offline {
+                    // waits for the track, a track that fails to load moves on to the next one
+                    while matches!(self.current_playing, QueueCurrentSource::Loading(..)) {
+                        self.poll_loading();
+                    }
+
+                    if matches!(self.current_playing, QueueCurrentSource::Nothing(_)) {
+                        return None;
                     }
                 }
 
                 match &mut self.current_playing {
                     QueueCurrentSource::Nothing(_) => {
+                        // there is nothing left to put to sleep
+                        self.sleep = None;
+
                         self.state_tx.send_if_modified(|state| {
-                            self.samples_to_state_update = SILENCE_LENGTH;
+                            self.samples_to_state_update =
+                                SILENCE_LENGTH * self.format.channels as usize;
 
-                            let new = !matches!(state, PlaybackState::Stopped);
+                            let new = !matches!(
+                                state,
+                                PlaybackState::Stopped { volume } if *volume == self.volume
+                            );
 
                             if new {
-                                *state = PlaybackState::Stopped;
+                                *state = PlaybackState::Stopped {
+                                    volume: self.volume,
+                                };
                             }
 
                             new
                         });
                     }
-                    QueueCurrentSource::Source(source) => {
-                        let one_sec_in_samples =
-                            source.sample_rate() as f32 * source.channels() as f32;
+                    QueueCurrentSource::Loading(..) => {
+                        self.samples_to_state_update =
+                            SILENCE_LENGTH * self.format.channels as usize;
+                    }
+                    QueueCurrentSource::Source(_, loaded) => {
+                        let source = &loaded.source;
 
-                        self.samples_to_state_update = (one_sec_in_samples / 16.0) as usize;
+                        // whole frames only, so commands are always handled at a frame boundary
+                        self.samples_to_state_update =
+                            (self.format.sample_rate / 16) as usize * self.format.channels as usize;
 
                         self.state_tx.send_replace(PlaybackState::Playing {
+                            track: loaded.track.clone(),
                             duration: source.total_duration(),
-                            position: source.get_pos(),
-                            paused: false,
+                            position: source.playback_position(),
+                            paused: self.paused,
+                            seekable: source.is_seekable(),
+                            underruns: source.underruns(),
+                            volume: self.volume,
+                            speed: source.speed(),
+                            remembers_position: loaded.remembers_position,
+                            sleep: self.sleep_state(),
                         });
                     }
                 }
             }
             self.samples_to_state_update -= 1;
 
-            if let Some(current) = self.current_playing.next() {
-                return Some(current);
+            if self.paused {
+                return Some(0.0);
             }
 
-            let upcoming = self.queue.forward();
+            if let Some(sample) = self.current_playing.next() {
+                let mut sample = sample * self.track_gain.next();
 
-            let source = upcoming
-                .and_then(|upcoming| match self.get_cached_source(&upcoming) {
-                    Ok(source) => {
-                        info!("Playing next track: {:?}", upcoming);
-                        Some(QueueCurrentSource::new_source(source))
+                if let Some(crossfade) = &mut self.crossfade {
+                    sample = crossfade.mix(sample);
+                    if crossfade.is_done() {
+                        self.crossfade = None;
                     }
-                    Err(e) => {
-                        warn!("Failed to get cached source for {:?}: {}", upcoming, e);
-                        None
+                }
+
+                return Some(sample);
+            }
+
+            // sources only ever end after a whole frame, so switching here is safe
+            match &mut self.current_playing {
+                QueueCurrentSource::Loading(.., silence) => {
+                    *silence = QueueCurrentSource::silence(self.format);
+                    self.poll_loading();
+                }
+                QueueCurrentSource::Nothing(silence) if self.stopped => {
+                    *silence = QueueCurrentSource::silence(self.format);
+                }
+                QueueCurrentSource::Source(..) | QueueCurrentSource::Nothing(_) => {
+                    if !self.sleep_after_track() {
+                        self.play_next();
                     }
-                })
-                .unwrap_or_else(|| {
-                    trace!("No more tracks in the queue, playing silence");
-                    QueueCurrentSource::new_silence()
-                });
+                }
+            }
 
-            self.current_playing = source;
+            // the new source starts at a frame boundary, update right away to stay aligned to it
+            self.samples_to_state_update = 0;
         }
     }
 }
 
-impl Source for QueueSource {
-    fn current_span_len(&self) -> Option<usize> {
-        if let Some(span_len) = self.current_playing.current_span_len() {
-            if span_len != 0 {
-                return Some(span_len);
-            }
+/// Like `try_recv`, but with `wait` it blocks until the track has finished loading.
+fn receive_track(pending: &mut PendingSource, rt: &runtime::Handle, wait: bool) -> PendingResult {
+    if !wait {
+        return pending.try_recv();
+    }
+
+    rt.block_on(pending)
+        .map_err(|_| oneshot::error::TryRecvError::Closed)
+}
+
+impl Iterator for QueueSource {
+    type Item = Sample;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.frame_position == self.frame.len() {
+            self.next_frame()?;
         }
 
-        let (lower_bound, _) = self.current_playing.size_hint();
-        if lower_bound > 0 {
-            return Some(lower_bound);
+        let sample = self.frame[self.frame_position];
+        self.frame_position += 1;
+
+        if !self.paused {
+            self.sleep_samples += 1;
         }
 
-        Some(SILENCE_LENGTH)
+        Some(sample * self.volume_gain.next() * self.fade.next() * self.sleep_gain.next())
+    }
+}
+
+impl Source for QueueSource {
+    // every source is converted to the output format, so it never changes
+    fn current_span_len(&self) -> Option<usize> {
+        None
     }
 
     fn sample_rate(&self) -> SampleRate {
-        self.current_playing.sample_rate()
+        self.format.sample_rate
     }
 
     fn channels(&self) -> ChannelCount {
-        self.current_playing.channels()
+        self.format.channels
     }
 
     fn total_duration(&self) -> Option<Duration> {
@@ -285,27 +1465,52 @@ impl std::fmt::Debug for QueueSource {
 }
 
 impl QueueCurrentSource {
-    pub fn new_source(source: PluginAudioSource) -> Self {
-        QueueCurrentSource::Source(source.track_position())
+    pub fn new_silence(format: OutputFormat) -> Self {
+        QueueCurrentSource::Nothing(Self::silence(format))
+    }
+
+    fn new_loading(
+        track: UniqueTrackIdentifier,
+        pending: PendingSource,
+        format: OutputFormat,
+    ) -> Self {
+        QueueCurrentSource::Loading(track, pending, Self::silence(format))
+    }
+
+    fn track(&self) -> Option<&UniqueTrackIdentifier> {
+        match self {
+            QueueCurrentSource::Source(track, _) | QueueCurrentSource::Loading(track, ..) => {
+                Some(track)
+            }
+            QueueCurrentSource::Nothing(_) => None,
+        }
     }
 
-    pub fn new_silence() -> Self {
-        QueueCurrentSource::Nothing(Zero::new_samples(1, 44100, SILENCE_LENGTH))
+    fn silence(format: OutputFormat) -> Zero {
+        Zero::new_samples(
+            format.channels,
+            format.sample_rate,
+            SILENCE_LENGTH * format.channels as usize,
+        )
     }
 
     #[inline]
     pub fn as_source(&self) -> &dyn Source {
         match self {
-            QueueCurrentSource::Source(source) => source,
-            QueueCurrentSource::Nothing(silence) => silence,
+            QueueCurrentSource::Source(_, loaded) => &loaded.source,
+            QueueCurrentSource::Loading(_, _, silence) | QueueCurrentSource::Nothing(silence) => {
+                silence
+            }
         }
     }
 
     #[inline]
     pub fn as_mut_source(&mut self) -> &mut dyn Source {
         match self {
-            QueueCurrentSource::Source(source) => source,
-            QueueCurrentSource::Nothing(silence) => silence,
+            QueueCurrentSource::Source(_, loaded) => &mut loaded.source,
+            QueueCurrentSource::Loading(_, _, silence) | QueueCurrentSource::Nothing(silence) => {
+                silence
+            }
         }
     }
 }
@@ -334,16 +1539,19 @@ impl Source for QueueCurrentSource {
     }
 }
 
+/// Decodes a track through a decoder plugin, one block at a time.
+///
+/// Calls into the plugin can be arbitrarily slow, so this should never be used from the audio
+/// thread directly. [`DecodedSource`] runs it on a worker thread instead.
 pub struct PluginAudioSource {
     playback_id: PlaybackId,
     plugin: PluginHandle,
 
     duration: Option<Duration>,
 
-    current_sample_rate: SampleRate,
-    current_channel_count: ChannelCount,
-    current_block: Vec<Sample>,
-    block_index: usize,
+    sample_rate: SampleRate,
+    channel_count: ChannelCount,
+    initial_block: Option<AudioBlock>,
 }
 
 #[derive(Error, Debug)]
@@ -354,6 +1562,9 @@ pub enum PluginAudioSourceError {
     #[error("Decoding did not return any audio data")]
     NoAudioData,
 
+    #[error("Cannot seek with this plugin")]
+    CannotSeek,
+
     #[error("Plugin error: {0}")]
     PluginError(#[from] PluginError),
 
@@ -388,10 +1599,9 @@ impl PluginAudioSource {
 
             duration: init_result.duration,
 
-            current_sample_rate: initial_block.sample_rate,
-            current_channel_count: initial_block.channel_count,
-            current_block: initial_block.samples,
-            block_index: 0,
+            sample_rate: initial_block.sample_rate,
+            channel_count: initial_block.channel_count,
+            initial_block: Some(initial_block),
         })
     }
 
@@ -399,9 +1609,12 @@ impl PluginAudioSource {
         plugin_system: &PluginSystem,
         track: UniqueTrackIdentifier,
     ) -> Result<PluginAudioSource, PluginAudioSourceError> {
-        let mut file_provider_plugin = plugin_system
-            .get_plugin(track.plugin_id)
-            .ok_or(PluginAudioSourceError::MissingFileProvider(track.plugin_id))?;
+        let mut file_provider_plugin = match plugin_system.get_plugin(track.plugin_id) {
+            Err(PluginError::UnknownPlugin(id)) => {
+                return Err(PluginAudioSourceError::MissingFileProvider(id));
+            }
+            result => result?,
+        };
 
         let file = file_provider_plugin.get_audio_file(&track.plugin_data)?;
 
@@ -410,9 +1623,14 @@ impl PluginAudioSource {
             .values()
             .filter(|pool| pool.capabilities.decode)
             .find_map(|pool| {
-                let decoder_plugin = pool.get_plugin();
-
-                match PluginAudioSource::new(decoder_plugin, file.clone()) {
+                let source = pool
+                    .get_plugin()
+                    .map_err(PluginAudioSourceError::from)
+                    .and_then(|decoder_plugin| {
+                        PluginAudioSource::new(decoder_plugin, file.clone())
+                    });
+
+                match source {
                     Ok(source) => Some(source),
                     Err(e) => {
                         debug!("Plugin '{}' cannot decode audio: {}", pool.metadata.name, e);
@@ -424,50 +1642,38 @@ impl PluginAudioSource {
 
         Ok(audio_source)
     }
-}
-
-impl Iterator for PluginAudioSource {
-    type Item = Sample;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.block_index >= self.current_block.len() {
-            let block = match self.plugin.decode_block(self.playback_id) {
-                Ok(Some(decoded)) => decoded,
-                Ok(None) => return None,
-
-                Err(e) => {
-                    warn!("Error decoding block: {}", e);
-                    return None;
-                }
-            };
-
-            self.current_block = block.samples;
-            self.current_sample_rate = block.sample_rate;
-            self.current_channel_count = block.channel_count;
-            self.block_index = 0;
+    pub fn decode_block(&mut self) -> Result<Option<AudioBlock>, PluginError> {
+        if let Some(block) = self.initial_block.take() {
+            return Ok(Some(block));
         }
 
-        let sample = self.current_block.get(self.block_index).cloned()?;
-        self.block_index += 1;
+        let block = self.plugin.decode_block(self.playback_id)?;
+        if let Some(block) = &block {
+            self.sample_rate = block.sample_rate;
+            self.channel_count = block.channel_count;
+        }
 
-        Some(sample)
+        Ok(block)
     }
-}
 
-impl Source for PluginAudioSource {
-    fn current_span_len(&self) -> Option<usize> {
-        Some(self.current_block.len())
+    pub fn can_seek(&self) -> bool {
+        self.plugin.capabilities().seek
     }
 
-    fn sample_rate(&self) -> SampleRate {
-        self.current_sample_rate
-    }
+    /// Continues decoding from `position`, returns the position that was actually seeked to.
+    pub fn seek(&mut self, position: Duration) -> Result<Duration, PluginAudioSourceError> {
+        if !self.can_seek() {
+            return Err(PluginAudioSourceError::CannotSeek);
+        }
 
-    fn channels(&self) -> ChannelCount {
-        self.current_channel_count
+        let result = self.plugin.seek(self.playback_id, position)?;
+        self.initial_block = None;
+
+        Ok(result.position)
     }
 
-    fn total_duration(&self) -> Option<Duration> {
+    pub fn duration(&self) -> Option<Duration> {
         self.duration
     }
 }
@@ -492,9 +1698,80 @@ impl std::fmt::Debug for PluginAudioSource {
         f.debug_struct("PluginAudioSource")
             .field("playback_id", &self.playback_id)
             .field("duration", &self.duration)
-            .field("current_sample_rate", &self.current_sample_rate)
-            .field("current_channel_count", &self.current_channel_count)
-            .field("current_block_length", &self.current_block.len())
+            .field("sample_rate", &self.sample_rate)
+            .field("channel_count", &self.channel_count)
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::fs;
+
+    async fn empty_player(
+        dir: &TempDir,
+        backend: &OutputBackend,
+    ) -> Result<AudioPlayer, OutputError> {
+        let plugin_dir = dir.join("plugins");
+        fs::create_dir_all(&plugin_dir).unwrap();
+
+        let db = dir.database().await;
+        let plugins = PluginSystem::initialize(plugin_dir, db.clone())
+            .await
+            .unwrap();
+
+        AudioPlayer::new(plugins, db, backend, OutputSettings::default(), None)
+    }
+
+    #[test]
+    fn empty_queue_plays_silence_into_file() {
+        let dir = TempDir::new("file-output");
+        let path = dir.join("output.wav");
+
+        let runtime = runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let player = empty_player(&dir, &OutputBackend::File(path.clone()))
+                .await
+                .unwrap();
+            player.play();
+
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        });
+        // the file is finished once the output is dropped with the player
+        drop(runtime);
+
+        let wav = fs::read(&path).unwrap();
+
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[8..12], b"WAVE");
+        assert_eq!(u16::from_le_bytes([wav[22], wav[23]]), 2);
+        assert_eq!(
+            u32::from_le_bytes(wav[24..28].try_into().unwrap()),
+            output::DEFAULT_SAMPLE_RATE
+        );
+
+        let data_length = u32::from_le_bytes(wav[40..44].try_into().unwrap()) as usize;
+        assert_eq!(data_length, wav.len() - 44);
+        assert!(data_length > 0);
+
+        let samples = wav[44..]
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()));
+        for sample in samples {
+            assert_eq!(sample, 0.0);
+        }
+    }
+
+    #[test]
+    fn unopenable_output_is_an_error() {
+        let dir = TempDir::new("missing-output");
+        let path = dir.join("missing").join("output.wav");
+
+        let runtime = runtime::Runtime::new().unwrap();
+        let result = runtime.block_on(empty_player(&dir, &OutputBackend::File(path)));
+
+        assert!(matches!(result, Err(OutputError::FileError(_))));
+    }
+}
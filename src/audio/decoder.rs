@@ -0,0 +1,517 @@
+use super::PluginAudioSource;
+use super::output::OutputFormat;
+use super::resampler::{FormatConverter, ResamplerQuality};
+use hogehoge_types::{ChannelCount, Sample, SampleRate};
+use rodio::Source;
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+use tracing::*;
+
+// ~3 seconds of 44.1kHz stereo audio
+const RING_BUFFER_LENGTH: usize = 1 << 18;
+const EVENT_BUFFER_LENGTH: usize = 16;
+const WORKER_IDLE_WAIT: Duration = Duration::from_millis(5);
+const OFFLINE_WAIT: Duration = Duration::from_millis(1);
+
+/// Audio decoded ahead of time by a [`DecoderWorker`], already converted to the output format.
+///
+/// This is the consuming half that lives on the audio thread. It never calls into the plugin and
+/// never blocks, if the worker falls behind it plays silence and counts an underrun instead.
+pub struct DecodedSource {
+    samples: Consumer<Sample>,
+    events: Consumer<DecoderEvent>,
+    next_event: Option<DecoderEvent>,
+    shared: Arc<DecoderShared>,
+
+    duration: Option<Duration>,
+    sample_rate: SampleRate,
+    channels: ChannelCount,
+    seekable: bool,
+    // waits for the worker instead of playing silence when it falls behind
+    offline: bool,
+
+    samples_read: u64,
+    // position after the last seek and the sample it happened at
+    seek_position: Duration,
+    seek_sample: u64,
+    // epoch and target of a seek the worker hasn't confirmed yet
+    pending_seek: Option<(u64, Duration)>,
+
+    // remaining samples of a silent frame that is being played during an underrun
+    underrun_silence: u16,
+    in_underrun: bool,
+    underruns: u64,
+}
+
+/// Things that happen in the sample stream at a specific sample index.
+#[derive(Debug, Clone, Copy)]
+enum DecoderEvent {
+    /// Everything before `at` was decoded before the seek with this epoch and has to be dropped.
+    Seeked {
+        at: u64,
+        epoch: u64,
+        /// `None` if seeking failed and decoding just continues.
+        position: Option<Duration>,
+    },
+    EndOfStream {
+        at: u64,
+    },
+}
+
+#[derive(Debug, Default)]
+struct DecoderShared {
+    stop: AtomicBool,
+    // the target has to be stored before the epoch is bumped
+    seek_target_nanos: AtomicU64,
+    seek_epoch: AtomicU64,
+}
+
+struct DecoderWorker {
+    source: PluginAudioSource,
+    samples: Producer<Sample>,
+    events: Producer<DecoderEvent>,
+    shared: Arc<DecoderShared>,
+
+    converter: FormatConverter,
+    channels: usize,
+    block: Vec<Sample>,
+    block_index: usize,
+    samples_written: u64,
+    seek_epoch: u64,
+    // the source has no more blocks, what the converter held back might still be in `block`
+    source_ended: bool,
+    ended: bool,
+}
+
+impl DecoderEvent {
+    fn at(&self) -> u64 {
+        match self {
+            DecoderEvent::Seeked { at, .. } | DecoderEvent::EndOfStream { at } => *at,
+        }
+    }
+}
+
+impl DecodedSource {
+    /// Starts decoding `source` on a new worker thread, converting it to `format`.
+    pub fn spawn(
+        source: PluginAudioSource,
+        format: OutputFormat,
+        quality: ResamplerQuality,
+    ) -> Self {
+        let (samples_tx, samples_rx) = RingBuffer::new(RING_BUFFER_LENGTH);
+        let (events_tx, events_rx) = RingBuffer::new(EVENT_BUFFER_LENGTH);
+        let shared = Arc::new(DecoderShared::default());
+
+        let duration = source.duration();
+        let seekable = source.can_seek();
+
+        let worker = DecoderWorker {
+            source,
+            samples: samples_tx,
+            events: events_tx,
+            shared: shared.clone(),
+
+            converter: FormatConverter::new(format, quality),
+            channels: format.channels.max(1) as usize,
+            block: Vec::new(),
+            block_index: 0,
+            samples_written: 0,
+            seek_epoch: 0,
+            source_ended: false,
+            ended: false,
+        };
+
+        let parent_span = Span::current();
+        thread::Builder::new()
+            .name("decoder".to_string())
+            .spawn(move || {
+                let _span = info_span!(parent: &parent_span, "decoder_worker").entered();
+                worker.run();
+            })
+            .expect("Failed to spawn decoder thread");
+
+        DecodedSource {
+            samples: samples_rx,
+            events: events_rx,
+            next_event: None,
+            shared,
+
+            duration,
+            sample_rate: format.sample_rate,
+            channels: format.channels,
+            seekable,
+            offline: false,
+
+            samples_read: 0,
+            seek_position: Duration::ZERO,
+            seek_sample: 0,
+            pending_seek: None,
+
+            underrun_silence: 0,
+            in_underrun: false,
+            underruns: 0,
+        }
+    }
+
+    /// Position in the track based on the samples that were actually played.
+    pub fn playback_position(&self) -> Duration {
+        if let Some((_, target)) = self.pending_seek {
+            return target;
+        }
+
+        self.seek_position + self.samples_to_duration(self.samples_read - self.seek_sample)
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.underruns
+    }
+
+    pub fn is_seekable(&self) -> bool {
+        self.seekable
+    }
+
+    /// For rendering faster than real time, where waiting for the worker is better than a gap.
+    pub fn set_offline(&mut self) {
+        self.offline = true;
+    }
+
+    /// Asks the worker to continue decoding from `position`.
+    ///
+    /// Until the worker has done so, already buffered samples get dropped and silence is played.
+    /// Must only be called at a frame boundary.
+    pub fn seek(&mut self, position: Duration) {
+        if !self.seekable {
+            return;
+        }
+
+        let position = self
+            .duration
+            .map_or(position, |duration| position.min(duration));
+
+        self.shared
+            .seek_target_nanos
+            .store(position.as_nanos() as u64, Ordering::Relaxed);
+        let epoch = self.shared.seek_epoch.fetch_add(1, Ordering::Release) + 1;
+
+        self.pending_seek = Some((epoch, position));
+    }
+
+    // events have to be polled only after checking for available samples. the worker always
+    // pushes an event before the samples it applies to, so this guarantees we see it in time.
+    fn poll_event(&mut self) -> Option<DecoderEvent> {
+        if self.next_event.is_none() {
+            self.next_event = self.events.pop().ok();
+        }
+
+        self.next_event
+    }
+
+    fn apply_due_events(&mut self) {
+        while let Some(event) = self.poll_event() {
+            if event.at() != self.samples_read {
+                break;
+            }
+
+            // stays around so `next` knows the stream has ended
+            if let DecoderEvent::EndOfStream { .. } = event {
+                break;
+            }
+
+            self.apply_event(event);
+            self.next_event = None;
+        }
+    }
+
+    fn apply_event(&mut self, event: DecoderEvent) {
+        match event {
+            DecoderEvent::Seeked {
+                epoch, position, ..
+            } => {
+                // a failed seek keeps counting from wherever decoding was before
+                let position = position.unwrap_or_else(|| {
+                    self.seek_position
+                        + self.samples_to_duration(self.samples_read - self.seek_sample)
+                });
+
+                if self
+                    .pending_seek
+                    .is_some_and(|(pending, _)| pending == epoch)
+                {
+                    self.pending_seek = None;
+                }
+
+                self.seek_position = position;
+                self.seek_sample = self.samples_read;
+            }
+            DecoderEvent::EndOfStream { .. } => {}
+        }
+    }
+
+    /// Drops everything that was decoded before the pending seek, returns `false` if the worker
+    /// hasn't gotten to it yet.
+    fn finish_seek(&mut self) -> bool {
+        while self.pending_seek.is_some() {
+            let available = self.samples.slots();
+
+            let Some(event) = self.poll_event() else {
+                // no marker yet, so everything that is buffered is from before the seek
+                self.discard(available);
+                return false;
+            };
+
+            self.discard(event.at().saturating_sub(self.samples_read) as usize);
+            // an end of stream from before the seek doesn't matter anymore
+            self.apply_event(event);
+            self.next_event = None;
+        }
+
+        true
+    }
+
+    fn discard(&mut self, count: usize) {
+        if let Ok(chunk) = self.samples.read_chunk(count) {
+            chunk.commit_all();
+            self.samples_read += count as u64;
+        }
+    }
+
+    fn samples_to_duration(&self, samples: u64) -> Duration {
+        let samples_per_sec = self.sample_rate as f64 * self.channels as f64;
+        Duration::from_secs_f64(samples as f64 / samples_per_sec)
+    }
+
+    fn is_finished(&mut self) -> bool {
+        match self.poll_event() {
+            Some(DecoderEvent::EndOfStream { at }) if at == self.samples_read => true,
+            _ => self.samples.is_abandoned() && self.samples.is_empty(),
+        }
+    }
+}
+
+impl Iterator for DecodedSource {
+    type Item = Sample;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.underrun_silence > 0 {
+            self.underrun_silence -= 1;
+            if self.underrun_silence == 0 && !self.samples.is_empty() {
+                self.apply_due_events();
+            }
+            return Some(0.0);
+        }
+
+        if self.pending_seek.is_some() && !self.finish_seek() {
+            self.underrun_silence = self.channels.saturating_sub(1);
+            return Some(0.0);
+        }
+
+        if self.samples.is_empty() && self.offline {
+            while self.samples.is_empty() && !self.is_finished() {
+                thread::sleep(OFFLINE_WAIT);
+            }
+        }
+
+        if self.samples.is_empty() {
+            if self.is_finished() {
+                return None;
+            }
+
+            if !self.in_underrun {
+                self.in_underrun = true;
+                self.underruns += 1;
+            }
+
+            // the worker only ever writes full frames, so we're at a frame boundary here and have
+            // to keep playing full frames to not mix up the channels
+            self.underrun_silence = self.channels.saturating_sub(1);
+            return Some(0.0);
+        }
+        self.in_underrun = false;
+
+        self.apply_due_events();
+
+        let sample = self.samples.pop().ok()?;
+        self.samples_read += 1;
+
+        Some(sample)
+    }
+}
+
+impl Source for DecodedSource {
+    // the format never changes
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.channels
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.duration
+    }
+}
+
+impl Drop for DecodedSource {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl std::fmt::Debug for DecodedSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DecodedSource")
+            .field("duration", &self.duration)
+            .field("sample_rate", &self.sample_rate)
+            .field("channels", &self.channels)
+            .field("buffered_samples", &self.samples.slots())
+            .field("underruns", &self.underruns)
+            .finish()
+    }
+}
+
+impl DecoderWorker {
+    fn run(mut self) {
+        debug!("Decoder worker started for {:?}", self.source);
+
+        while !self.shared.stop.load(Ordering::Relaxed) {
+            self.handle_seek();
+
+            // the source is kept around after it ended, the track might still get seeked back
+            if self.ended {
+                thread::park_timeout(WORKER_IDLE_WAIT);
+                continue;
+            }
+
+            if self.block_index >= self.block.len() && !self.decode_next_block() {
+                self.ended = true;
+                continue;
+            }
+
+            if !self.write_block() {
+                thread::park_timeout(WORKER_IDLE_WAIT);
+            }
+        }
+
+        debug!("Decoder worker stopped for {:?}", self.source);
+    }
+
+    /// Returns `false` once the stream has ended.
+    fn decode_next_block(&mut self) -> bool {
+        if self.source_ended {
+            self.push_event(DecoderEvent::EndOfStream {
+                at: self.samples_written,
+            });
+            return false;
+        }
+
+        self.block.clear();
+        self.block_index = 0;
+
+        match self.source.decode_block() {
+            Ok(Some(block)) => self.converter.process(&block, &mut self.block),
+            Ok(None) => {
+                self.source_ended = true;
+                self.converter.flush(&mut self.block);
+            }
+            Err(e) => {
+                warn!("Error decoding block: {}", e);
+                self.source_ended = true;
+                self.converter.flush(&mut self.block);
+            }
+        }
+
+        true
+    }
+
+    fn handle_seek(&mut self) {
+        let epoch = self.shared.seek_epoch.load(Ordering::Acquire);
+        if epoch == self.seek_epoch {
+            return;
+        }
+        self.seek_epoch = epoch;
+
+        let target = Duration::from_nanos(self.shared.seek_target_nanos.load(Ordering::Relaxed));
+        let position = match self.source.seek(target) {
+            Ok(position) => {
+                debug!("Seeked to {:?} (requested {:?})", position, target);
+                self.block.clear();
+                self.block_index = 0;
+                self.converter.reset();
+                self.source_ended = false;
+                self.ended = false;
+                Some(position)
+            }
+            Err(e) => {
+                warn!("Failed to seek to {:?}: {}", target, e);
+                None
+            }
+        };
+
+        self.push_event(DecoderEvent::Seeked {
+            at: self.samples_written,
+            epoch,
+            position,
+        });
+
+        // the consumer drops the old end of stream together with everything else before the seek
+        if self.ended {
+            self.push_event(DecoderEvent::EndOfStream {
+                at: self.samples_written,
+            });
+        }
+    }
+
+    /// Writes as much of the current block as fits, returns `false` if nothing could be written.
+    fn write_block(&mut self) -> bool {
+        let channels = self.channels;
+
+        let remaining = self.block.len() - self.block_index;
+        let available = remaining.min(self.samples.slots());
+        // only write whole frames so an underrun can never split one
+        let count = if available == remaining {
+            available
+        } else {
+            available / channels * channels
+        };
+        if count == 0 {
+            return remaining == 0;
+        }
+
+        let chunk = self
+            .samples
+            .write_chunk_uninit(count)
+            .expect("Ring buffer to have the slots we just checked");
+        chunk.fill_from_iter(
+            self.block[self.block_index..self.block_index + count]
+                .iter()
+                .copied(),
+        );
+
+        self.block_index += count;
+        self.samples_written += count as u64;
+
+        true
+    }
+
+    fn push_event(&mut self, event: DecoderEvent) {
+        while self.events.push(event).is_err() {
+            if self.shared.stop.load(Ordering::Relaxed) {
+                return;
+            }
+            thread::park_timeout(WORKER_IDLE_WAIT);
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use super::OutputFormat;
+use crate::plugin::PluginSystem;
+use hogehoge_db::{SavedDspProfile, SavedEffect, SavedEqBand, SavedEqPreset};
+use hogehoge_types::{PluginId, Sample};
+
+mod balance;
+use balance::Balance;
+mod effect;
+use effect::EffectChain;
+mod eq;
+use eq::Equalizer;
+mod limiter;
+use limiter::Limiter;
+
+/// Shape of the filter of an EQ band.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EqBandKind {
+    #[default]
+    Peaking,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EqBand {
+    pub kind: EqBandKind,
+    /// Center or corner frequency in Hz.
+    pub frequency: f32,
+    /// Ignored by the pass filters.
+    pub gain_db: f32,
+    pub q: f32,
+}
+
+/// An effect plugin in the [`DspChain`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EffectSettings {
+    pub plugin_id: PluginId,
+    pub bypassed: bool,
+}
+
+/// Everything the [`DspChain`] does to the audio.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DspSettings {
+    pub eq_enabled: bool,
+    /// Applied together with the EQ, usually to make room for boosted bands.
+    pub preamp_db: f32,
+    pub bands: Vec<EqBand>,
+    /// From `-1.0` for only the left channel to `1.0` for only the right one.
+    pub balance: f32,
+    /// Mixes the left and right channel together.
+    pub mono: bool,
+    /// Keeps peaks below full scale, so boosted bands don't clip.
+    pub limiter: bool,
+    /// Run after the EQ and balance, in order.
+    pub effects: Vec<EffectSettings>,
+}
+
+/// A named set of EQ bands.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EqPreset {
+    pub name: String,
+    pub preamp_db: f32,
+    pub bands: Vec<EqBand>,
+    /// Built-in presets can't be overwritten or deleted.
+    pub built_in: bool,
+}
+
+/// One stage of the [`DspChain`], working on single interleaved frames in the output format.
+pub trait Processor: Send {
+    /// Takes over changed settings. The chain is live, so changes have to be smoothed over.
+    fn configure(&mut self, settings: &DspSettings);
+
+    fn process(&mut self, frame: &mut [Sample]);
+
+    /// For rendering faster than real time, where waiting is better than a gap.
+    fn set_offline(&mut self) {}
+}
+
+/// The processors everything that is played goes through, in order.
+pub struct DspChain {
+    processors: Vec<Box<dyn Processor>>,
+}
+
+impl EqBandKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EqBandKind::Peaking => "peaking",
+            EqBandKind::LowShelf => "low_shelf",
+            EqBandKind::HighShelf => "high_shelf",
+            EqBandKind::LowPass => "low_pass",
+            EqBandKind::HighPass => "high_pass",
+        }
+    }
+
+    fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "peaking" => Some(EqBandKind::Peaking),
+            "low_shelf" => Some(EqBandKind::LowShelf),
+            "high_shelf" => Some(EqBandKind::HighShelf),
+            "low_pass" => Some(EqBandKind::LowPass),
+            "high_pass" => Some(EqBandKind::HighPass),
+            _ => None,
+        }
+    }
+
+    /// Whether the gain of the band does anything.
+    pub fn has_gain(&self) -> bool {
+        !matches!(self, EqBandKind::LowPass | EqBandKind::HighPass)
+    }
+}
+
+impl EqBand {
+    pub const fn new(kind: EqBandKind, frequency: f32, gain_db: f32, q: f32) -> Self {
+        EqBand {
+            kind,
+            frequency,
+            gain_db,
+            q,
+        }
+    }
+
+    fn from_saved(saved: SavedEqBand) -> Self {
+        EqBand {
+            kind: EqBandKind::parse(&saved.kind).unwrap_or_default(),
+            frequency: saved.frequency,
+            gain_db: saved.gain_db,
+            q: saved.q,
+        }
+    }
+
+    fn to_saved(self) -> SavedEqBand {
+        SavedEqBand {
+            kind: self.kind.as_str().to_string(),
+            frequency: self.frequency,
+            gain_db: self.gain_db,
+            q: self.q,
+        }
+    }
+}
+
+impl DspSettings {
+    pub fn from_saved(saved: SavedDspProfile) -> Self {
+        DspSettings {
+            eq_enabled: saved.eq_enabled,
+            preamp_db: saved.preamp_db,
+            bands: saved.bands.into_iter().map(EqBand::from_saved).collect(),
+            balance: saved.balance,
+            mono: saved.mono,
+            limiter: saved.limiter,
+            effects: saved
+                .effects
+                .into_iter()
+                .map(|effect| EffectSettings {
+                    plugin_id: effect.plugin_id,
+                    bypassed: effect.bypassed,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_saved(&self) -> SavedDspProfile {
+        SavedDspProfile {
+            eq_enabled: self.eq_enabled,
+            preamp_db: self.preamp_db,
+            bands: self.bands.iter().map(|band| band.to_saved()).collect(),
+            balance: self.balance,
+            mono: self.mono,
+            limiter: self.limiter,
+            effects: self
+                .effects
+                .iter()
+                .map(|effect| SavedEffect {
+                    plugin_id: effect.plugin_id,
+                    bypassed: effect.bypassed,
+                })
+                .collect(),
+        }
+    }
+
+    /// Replaces the EQ with the one of `preset`.
+    pub fn with_preset(&self, preset: &EqPreset) -> Self {
+        DspSettings {
+            eq_enabled: true,
+            preamp_db: preset.preamp_db,
+            bands: preset.bands.clone(),
+            ..self.clone()
+        }
+    }
+}
+
+impl Default for DspSettings {
+    fn default() -> Self {
+        DspSettings {
+            eq_enabled: true,
+            preamp_db: 0.0,
+            bands: FLAT.to_vec(),
+            balance: 0.0,
+            mono: false,
+            limiter: false,
+            effects: Vec::new(),
+        }
+    }
+}
+
+const FLAT: [EqBand; 5] = [
+    EqBand::new(EqBandKind::LowShelf, 80.0, 0.0, 0.7),
+    EqBand::new(EqBandKind::Peaking, 250.0, 0.0, 1.0),
+    EqBand::new(EqBandKind::Peaking, 1000.0, 0.0, 1.0),
+    EqBand::new(EqBandKind::Peaking, 4000.0, 0.0, 1.0),
+    EqBand::new(EqBandKind::HighShelf, 10000.0, 0.0, 0.7),
+];
+
+impl EqPreset {
+    pub fn from_saved(saved: SavedEqPreset) -> Self {
+        EqPreset {
+            name: saved.name,
+            preamp_db: saved.preamp_db,
+            bands: saved.bands.into_iter().map(EqBand::from_saved).collect(),
+            built_in: false,
+        }
+    }
+
+    pub fn to_saved(&self) -> SavedEqPreset {
+        SavedEqPreset {
+            name: self.name.clone(),
+            preamp_db: self.preamp_db,
+            bands: self.bands.iter().map(|band| band.to_saved()).collect(),
+        }
+    }
+
+    pub fn built_in() -> Vec<EqPreset> {
+        let preset = |name: &str, preamp_db: f32, gains: [f32; 5]| EqPreset {
+            name: name.to_string(),
+            preamp_db,
+            bands: FLAT
+                .iter()
+                .zip(gains)
+                .map(|(band, gain_db)| EqBand { gain_db, ..*band })
+                .collect(),
+            built_in: true,
+        };
+
+        vec![
+            preset("Flat", 0.0, [0.0, 0.0, 0.0, 0.0, 0.0]),
+            preset("Bass boost", -6.0, [6.0, 2.0, 0.0, 0.0, 0.0]),
+            preset("Treble boost", -5.0, [0.0, 0.0, 0.0, 2.0, 5.0]),
+            preset("Loudness", -6.0, [6.0, 1.0, -1.0, 1.0, 4.0]),
+            preset("Vocal", -4.0, [-2.0, -1.0, 3.0, 3.0, 0.0]),
+            preset("Bass cut", 0.0, [-8.0, -2.0, 0.0, 0.0, 0.0]),
+        ]
+    }
+}
+
+impl DspChain {
+    pub fn new(format: OutputFormat, plugins: PluginSystem, settings: &DspSettings) -> Self {
+        let mut chain = DspChain {
+            processors: vec![
+                Box::new(Equalizer::new(format)),
+                Box::new(Balance::new(format)),
+                Box::new(EffectChain::new(format, plugins)),
+                // last, so nothing can push the audio back over full scale
+                Box::new(Limiter::new(format)),
+            ],
+        };
+        chain.configure(settings);
+
+        chain
+    }
+
+    pub fn configure(&mut self, settings: &DspSettings) {
+        for processor in &mut self.processors {
+            processor.configure(settings);
+        }
+    }
+
+    pub fn set_offline(&mut self) {
+        for processor in &mut self.processors {
+            processor.set_offline();
+        }
+    }
+
+    #[inline]
+    pub fn process(&mut self, frame: &mut [Sample]) {
+        for processor in &mut self.processors {
+            processor.process(frame);
+        }
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
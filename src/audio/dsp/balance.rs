@@ -0,0 +1,50 @@
+use super::{DspSettings, Processor};
+use crate::audio::{OutputFormat, transition::Ramp};
+use hogehoge_types::Sample;
+
+// in frames, about 20ms
+const BALANCE_RAMP: usize = 1024;
+
+/// Balance between and mono downmix of the front left and right channel.
+pub struct Balance {
+    channels: usize,
+    left: Ramp,
+    right: Ramp,
+    // how much of the other channel is mixed in, up to half of it for mono
+    mono: Ramp,
+}
+
+impl Balance {
+    pub fn new(format: OutputFormat) -> Self {
+        Balance {
+            channels: format.channels as usize,
+            left: Ramp::new(1.0),
+            right: Ramp::new(1.0),
+            mono: Ramp::new(0.0),
+        }
+    }
+}
+
+impl Processor for Balance {
+    fn configure(&mut self, settings: &DspSettings) {
+        let balance = settings.balance.clamp(-1.0, 1.0);
+
+        self.left.ramp_to((1.0 - balance).min(1.0), BALANCE_RAMP);
+        self.right.ramp_to((1.0 + balance).min(1.0), BALANCE_RAMP);
+        self.mono
+            .ramp_to(if settings.mono { 0.5 } else { 0.0 }, BALANCE_RAMP);
+    }
+
+    #[inline]
+    fn process(&mut self, frame: &mut [Sample]) {
+        if self.channels < 2 {
+            return;
+        }
+
+        let mono = self.mono.next();
+        let (left, right) = (frame[0], frame[1]);
+
+        frame[0] = (left * (1.0 - mono) + right * mono) * self.left.next();
+        frame[1] = (right * (1.0 - mono) + left * mono) * self.right.next();
+    }
+}
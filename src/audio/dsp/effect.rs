@@ -0,0 +1,450 @@
+use super::{DspSettings, EffectSettings, Processor};
+use crate::audio::{OutputFormat, transition::Ramp};
+use crate::plugin::{PluginHandle, PluginSystem};
+use hogehoge_types::{AudioBlock, EffectId, PluginId, Sample};
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+use tokio::{
+    runtime,
+    sync::oneshot::{self, error::TryRecvError},
+};
+use tracing::*;
+
+// frames handed to the plugins at once
+const BLOCK_FRAMES: usize = 512;
+// in blocks, leaves the worker about two blocks of time for each one
+const LATENCY_BLOCKS: usize = 3;
+const WORKER_IDLE_WAIT: Duration = Duration::from_millis(1);
+const OFFLINE_WAIT: Duration = Duration::from_millis(1);
+// in frames, about 20ms
+const BYPASS_RAMP: usize = 1024;
+
+/// Runs the effect plugins of the [`DspSettings`] in order.
+///
+/// Like decoding, this never calls into a plugin on the audio thread. A worker processes the audio
+/// in blocks and hands it back [`LATENCY_BLOCKS`] later. The worker only starts with the first
+/// effect and keeps running after the last one is removed, so the latency doesn't jump around.
+pub struct EffectChain {
+    plugins: PluginSystem,
+    rt: runtime::Handle,
+    format: OutputFormat,
+    offline: bool,
+
+    effects: Vec<EffectSettings>,
+    worker: WorkerState,
+}
+
+enum WorkerState {
+    Stopped,
+    Starting(oneshot::Receiver<EffectLink>),
+    Running(EffectLink),
+}
+
+/// The end of an [`EffectWorker`] that lives on the audio thread.
+struct EffectLink {
+    channels: usize,
+    input: Producer<Sample>,
+    output: Consumer<Sample>,
+    effects_tx: mpsc::Sender<Vec<EffectSettings>>,
+    stop: Arc<AtomicBool>,
+    thread: thread::Thread,
+
+    // the input of the last `latency` frames, played instead while the worker is behind
+    dry: Vec<Sample>,
+    dry_position: usize,
+    latency: usize,
+    // frames left until the first processed one is due
+    priming: usize,
+    // processed frames that came too late and get dropped once they arrive
+    missed: usize,
+}
+
+struct EffectWorker {
+    plugins: PluginSystem,
+    format: OutputFormat,
+    input: Consumer<Sample>,
+    output: Producer<Sample>,
+    effects_rx: mpsc::Receiver<Vec<EffectSettings>>,
+    stop: Arc<AtomicBool>,
+
+    effects: Vec<RunningEffect>,
+    block: Vec<Sample>,
+}
+
+struct RunningEffect {
+    plugin_id: PluginId,
+    // `None` if the plugin isn't loaded or failed, the effect then just passes the audio through
+    instance: Option<(PluginHandle, EffectId)>,
+    // crossfades between the unprocessed and processed audio when bypassing
+    wet: Ramp,
+    // fading out, dropped once it's bypassed
+    removed: bool,
+}
+
+impl EffectChain {
+    pub fn new(format: OutputFormat, plugins: PluginSystem) -> Self {
+        EffectChain {
+            plugins,
+            rt: runtime::Handle::current(),
+            format,
+            offline: false,
+            effects: Vec::new(),
+            worker: WorkerState::Stopped,
+        }
+    }
+
+    fn start_worker(&mut self) {
+        let (tx, rx) = oneshot::channel();
+
+        let plugins = self.plugins.clone();
+        let format = self.format;
+        self.rt.spawn_blocking(move || {
+            let _ = tx.send(EffectLink::spawn(plugins, format));
+        });
+
+        self.worker = WorkerState::Starting(rx);
+    }
+}
+
+impl Processor for EffectChain {
+    fn configure(&mut self, settings: &DspSettings) {
+        if settings.effects == self.effects {
+            return;
+        }
+        self.effects = settings.effects.clone();
+
+        match &mut self.worker {
+            WorkerState::Stopped if !self.effects.is_empty() => self.start_worker(),
+            WorkerState::Running(link) => link.set_effects(self.effects.clone()),
+            // a starting worker gets the effects once it's there
+            _ => {}
+        }
+    }
+
+    fn set_offline(&mut self) {
+        self.offline = true;
+    }
+
+    #[inline]
+    fn process(&mut self, frame: &mut [Sample]) {
+        if let WorkerState::Starting(rx) = &mut self.worker {
+            let link = if self.offline {
+                rx.blocking_recv().ok()
+            } else {
+                match rx.try_recv() {
+                    Ok(link) => Some(link),
+                    Err(TryRecvError::Empty) => return,
+                    Err(TryRecvError::Closed) => None,
+                }
+            };
+
+            self.worker = match link {
+                Some(link) => {
+                    link.set_effects(self.effects.clone());
+                    WorkerState::Running(link)
+                }
+                None => WorkerState::Stopped,
+            };
+        }
+
+        if let WorkerState::Running(link) = &mut self.worker {
+            link.process(frame, self.offline);
+        }
+    }
+}
+
+impl EffectLink {
+    fn spawn(plugins: PluginSystem, format: OutputFormat) -> Self {
+        let channels = format.channels.max(1) as usize;
+        let latency = BLOCK_FRAMES * LATENCY_BLOCKS;
+
+        let (input_tx, input_rx) = RingBuffer::new(2 * latency * channels);
+        let (output_tx, output_rx) = RingBuffer::new(2 * latency * channels);
+        let (effects_tx, effects_rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let worker = EffectWorker {
+            plugins,
+            format,
+            input: input_rx,
+            output: output_tx,
+            effects_rx,
+            stop: stop.clone(),
+            effects: Vec::new(),
+            block: Vec::with_capacity(BLOCK_FRAMES * channels),
+        };
+
+        let parent_span = Span::current();
+        let thread = thread::Builder::new()
+            .name("effects".to_string())
+            .spawn(move || {
+                let _span = info_span!(parent: &parent_span, "effect_worker").entered();
+                worker.run();
+            })
+            .expect("Failed to spawn effect thread");
+
+        EffectLink {
+            channels,
+            input: input_tx,
+            output: output_rx,
+            effects_tx,
+            stop,
+            thread: thread.thread().clone(),
+            dry: vec![0.0; latency * channels],
+            dry_position: 0,
+            latency,
+            priming: latency,
+            missed: 0,
+        }
+    }
+
+    fn set_effects(&self, effects: Vec<EffectSettings>) {
+        let _ = self.effects_tx.send(effects);
+    }
+
+    #[inline]
+    fn process(&mut self, frame: &mut [Sample], offline: bool) {
+        let channels = self.channels;
+
+        // if the worker is stuck the frame can't be sent, the dry audio keeps playing then
+        let sent = match self.input.write_chunk_uninit(channels) {
+            Ok(chunk) => {
+                chunk.fill_from_iter(frame.iter().copied());
+                true
+            }
+            Err(_) => false,
+        };
+
+        let start = self.dry_position * channels;
+        for (sample, dry) in frame.iter_mut().zip(&mut self.dry[start..start + channels]) {
+            std::mem::swap(sample, dry);
+        }
+        self.dry_position = (self.dry_position + 1) % self.latency;
+
+        if !sent {
+            return;
+        }
+        if self.priming > 0 {
+            self.priming -= 1;
+            return;
+        }
+
+        while self.missed > 0 && self.output.slots() >= channels {
+            self.discard_frame();
+            self.missed -= 1;
+        }
+
+        if offline {
+            while self.output.slots() < channels && !self.output.is_abandoned() {
+                thread::sleep(OFFLINE_WAIT);
+            }
+        }
+
+        if self.missed > 0 || self.output.slots() < channels {
+            self.missed += 1;
+            return;
+        }
+
+        let chunk = self
+            .output
+            .read_chunk(channels)
+            .expect("Ring buffer to have the frame we just checked");
+        let (first, second) = chunk.as_slices();
+        for (sample, processed) in frame.iter_mut().zip(first.iter().chain(second)) {
+            *sample = *processed;
+        }
+        chunk.commit_all();
+    }
+
+    fn discard_frame(&mut self) {
+        if let Ok(chunk) = self.output.read_chunk(self.channels) {
+            chunk.commit_all();
+        }
+    }
+}
+
+impl Drop for EffectLink {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.unpark();
+    }
+}
+
+impl EffectWorker {
+    fn run(mut self) {
+        debug!("Effect worker started");
+
+        let block_len = BLOCK_FRAMES * self.format.channels.max(1) as usize;
+
+        while !self.stop.load(Ordering::Relaxed) {
+            if let Some(effects) = self.effects_rx.try_iter().last() {
+                self.set_effects(effects);
+            }
+
+            if self.input.slots() < block_len || self.output.slots() < block_len {
+                thread::park_timeout(WORKER_IDLE_WAIT);
+                continue;
+            }
+
+            let chunk = self
+                .input
+                .read_chunk(block_len)
+                .expect("Ring buffer to have the block we just checked");
+            let (first, second) = chunk.as_slices();
+            self.block.clear();
+            self.block.extend_from_slice(first);
+            self.block.extend_from_slice(second);
+            chunk.commit_all();
+
+            for effect in &mut self.effects {
+                effect.process(&mut self.block, self.format);
+            }
+            self.effects.retain(|effect| !effect.is_gone());
+
+            let chunk = self
+                .output
+                .write_chunk_uninit(block_len)
+                .expect("Ring buffer to have the slots we just checked");
+            chunk.fill_from_iter(self.block.iter().copied());
+        }
+
+        debug!("Effect worker stopped");
+    }
+
+    /// Matches running effects up with the new ones by their plugin. Reordering happens right
+    /// away, only adding, removing and bypassing effects is faded.
+    fn set_effects(&mut self, effects: Vec<EffectSettings>) {
+        let mut previous = std::mem::take(&mut self.effects);
+
+        for settings in effects {
+            let existing = previous
+                .iter()
+                .position(|effect| effect.plugin_id == settings.plugin_id && !effect.removed);
+            let mut effect = match existing {
+                Some(index) => previous.remove(index),
+                None => RunningEffect::start(&self.plugins, settings.plugin_id, self.format),
+            };
+
+            let wet = if settings.bypassed { 0.0 } else { 1.0 };
+            effect.wet.ramp_to(wet, BYPASS_RAMP);
+            self.effects.push(effect);
+        }
+
+        for mut effect in previous {
+            effect.removed = true;
+            effect.wet.ramp_to(0.0, BYPASS_RAMP);
+            self.effects.push(effect);
+        }
+    }
+}
+
+impl RunningEffect {
+    fn start(plugins: &PluginSystem, plugin_id: PluginId, format: OutputFormat) -> Self {
+        let instance = match plugins.get_plugin(plugin_id) {
+            Ok(plugin) if !plugin.capabilities().effect => {
+                warn!("Plugin {:?} doesn't provide an effect", plugin_id);
+                None
+            }
+            Ok(mut plugin) => {
+                let effect_id = EffectId::new();
+                match plugin.init_effect(effect_id, format.sample_rate, format.channels) {
+                    Ok(()) => Some((plugin, effect_id)),
+                    Err(e) => {
+                        warn!("Failed to start effect of plugin {:?}: {}", plugin_id, e);
+                        None
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Effect plugin {:?} isn't available: {}", plugin_id, e);
+                None
+            }
+        };
+
+        RunningEffect {
+            plugin_id,
+            instance,
+            wet: Ramp::new(0.0),
+            removed: false,
+        }
+    }
+
+    fn is_gone(&self) -> bool {
+        self.removed && (self.instance.is_none() || self.wet.is_done())
+    }
+
+    fn process(&mut self, block: &mut [Sample], format: OutputFormat) {
+        // fully bypassed effects aren't called at all
+        if self.wet.is_done() && self.wet.target() == 0.0 {
+            return;
+        }
+        let Some((plugin, effect_id)) = &mut self.instance else {
+            return;
+        };
+        // changed settings apply to running effects as well
+        plugin.refresh_settings();
+
+        let processed = plugin.process_block(
+            *effect_id,
+            AudioBlock {
+                samples: block.to_vec(),
+                sample_rate: format.sample_rate,
+                channel_count: format.channels,
+            },
+        );
+
+        let processed = match processed {
+            Ok(processed) if processed.samples.len() == block.len() => processed.samples,
+            Ok(processed) => {
+                warn!(
+                    "Effect of plugin {:?} returned {} samples instead of {}, disabling it",
+                    self.plugin_id,
+                    processed.samples.len(),
+                    block.len()
+                );
+                self.instance = None;
+                return;
+            }
+            Err(e) => {
+                warn!(
+                    "Effect of plugin {:?} failed, disabling it: {}",
+                    self.plugin_id, e
+                );
+                self.instance = None;
+                return;
+            }
+        };
+
+        let channels = format.channels.max(1) as usize;
+        for (frame, processed) in block
+            .chunks_exact_mut(channels)
+            .zip(processed.chunks_exact(channels))
+        {
+            let wet = self.wet.next();
+            for (sample, processed) in frame.iter_mut().zip(processed) {
+                *sample += (processed - *sample) * wet;
+            }
+        }
+    }
+}
+
+impl Drop for RunningEffect {
+    fn drop(&mut self) {
+        if let Some((plugin, effect_id)) = &mut self.instance {
+            if let Err(e) = plugin.finish_effect(*effect_id) {
+                warn!(
+                    "Failed to finish effect of plugin {:?}: {}",
+                    self.plugin_id, e
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use super::{DspSettings, EqBand, EqBandKind, Processor, db_to_gain};
+use crate::audio::{OutputFormat, transition::Ramp};
+use hogehoge_types::Sample;
+use std::f64::consts::PI;
+
+// frames between updates of the filter coefficients while parameters are moving
+const SMOOTHING_INTERVAL: usize = 32;
+// how much of the remaining distance to the target is covered with each update
+const SMOOTHING: f64 = 0.1;
+// in frames, about as long as the bands take to settle
+const PREAMP_RAMP: usize = 4096;
+
+// bands that can be added without allocating on the audio thread, more than that still works
+const PREALLOCATED_BANDS: usize = 32;
+
+const MIN_FREQUENCY: f64 = 10.0;
+const MIN_Q: f64 = 0.1;
+const MAX_Q: f64 = 20.0;
+
+/// Parametric EQ made of a biquad per band.
+pub struct Equalizer {
+    sample_rate: f64,
+    channels: usize,
+
+    enabled: bool,
+    preamp: Ramp,
+    bands: Vec<Band>,
+    // filter state of bands that are gone, reused for new ones
+    spare_states: Vec<Vec<[f64; 2]>>,
+    // some band hasn't reached its target yet
+    settling: bool,
+    frames_to_update: usize,
+}
+
+struct Band {
+    kind: EqBandKind,
+    current: Parameters,
+    target: Parameters,
+    // fading out, dropped once it's neutral
+    removed: bool,
+
+    coefficients: Coefficients,
+    // transposed direct form II state per channel
+    state: Vec<[f64; 2]>,
+}
+
+/// Band parameters, the frequency is logarithmic so it moves evenly across octaves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Parameters {
+    log_frequency: f64,
+    gain_db: f64,
+    q: f64,
+}
+
+/// Normalized biquad coefficients, `a0` is always 1.
+#[derive(Debug, Clone, Copy)]
+struct Coefficients {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Equalizer {
+    pub fn new(format: OutputFormat) -> Self {
+        Equalizer {
+            sample_rate: format.sample_rate as f64,
+            channels: format.channels as usize,
+            enabled: false,
+            preamp: Ramp::new(1.0),
+            bands: Vec::with_capacity(PREALLOCATED_BANDS),
+            spare_states: (0..PREALLOCATED_BANDS)
+                .map(|_| vec![[0.0; 2]; format.channels as usize])
+                .collect(),
+            settling: false,
+            frames_to_update: 0,
+        }
+    }
+
+    fn max_frequency(&self) -> f64 {
+        self.sample_rate * 0.49
+    }
+
+    fn parameters(&self, band: &EqBand) -> Parameters {
+        Parameters {
+            log_frequency: (band.frequency as f64)
+                .clamp(MIN_FREQUENCY, self.max_frequency())
+                .ln(),
+            gain_db: band.gain_db as f64,
+            q: (band.q as f64).clamp(MIN_Q, MAX_Q),
+        }
+    }
+
+    /// Parameters at which a band of `kind` does (close to) nothing.
+    fn neutral(&self, kind: EqBandKind, parameters: Parameters) -> Parameters {
+        match kind {
+            EqBandKind::Peaking | EqBandKind::LowShelf | EqBandKind::HighShelf => Parameters {
+                gain_db: 0.0,
+                ..parameters
+            },
+            EqBandKind::LowPass => Parameters {
+                log_frequency: self.max_frequency().ln(),
+                ..parameters
+            },
+            EqBandKind::HighPass => Parameters {
+                log_frequency: MIN_FREQUENCY.ln(),
+                ..parameters
+            },
+        }
+    }
+
+    fn add_band(&mut self, band: &EqBand) {
+        let target = self.parameters(band);
+        let current = self.neutral(band.kind, target);
+
+        let mut state = self
+            .spare_states
+            .pop()
+            .unwrap_or_else(|| vec![[0.0; 2]; self.channels]);
+        state.fill([0.0; 2]);
+
+        self.bands.push(Band {
+            kind: band.kind,
+            current,
+            target,
+            removed: false,
+            coefficients: Coefficients::new(band.kind, current, self.sample_rate),
+            state,
+        });
+    }
+
+    fn remove_band(&mut self, index: usize) {
+        let band = &self.bands[index];
+        let target = self.neutral(band.kind, band.current);
+
+        let band = &mut self.bands[index];
+        band.target = target;
+        band.removed = true;
+    }
+
+    fn update_bands(&mut self) {
+        self.settling = false;
+
+        for band in &mut self.bands {
+            if band.current == band.target {
+                continue;
+            }
+
+            band.current = band.current.towards(band.target);
+            band.coefficients = Coefficients::new(band.kind, band.current, self.sample_rate);
+            self.settling |= band.current != band.target;
+        }
+
+        let spare_states = &mut self.spare_states;
+        self.bands.retain_mut(|band| {
+            let done = band.removed && band.current == band.target;
+            if done {
+                spare_states.push(std::mem::take(&mut band.state));
+            }
+            !done
+        });
+    }
+}
+
+impl Processor for Equalizer {
+    fn configure(&mut self, settings: &DspSettings) {
+        self.enabled = settings.eq_enabled;
+
+        let preamp = if self.enabled {
+            db_to_gain(settings.preamp_db)
+        } else {
+            1.0
+        };
+        self.preamp.ramp_to(preamp, PREAMP_RAMP);
+
+        let targets: &[EqBand] = if self.enabled { &settings.bands } else { &[] };
+
+        // bands are matched up by position, a band that changes its kind is replaced. added bands
+        // go to the end, so only the existing ones are matched
+        let existing = self.bands.len();
+        let mut position = 0;
+        let mut next_live = |bands: &[Band]| {
+            while position < existing && bands[position].removed {
+                position += 1;
+            }
+            position += 1;
+            (position <= existing).then_some(position - 1)
+        };
+
+        for band in targets {
+            match next_live(&self.bands) {
+                Some(index) if self.bands[index].kind == band.kind => {
+                    self.bands[index].target = self.parameters(band);
+                }
+                Some(index) => {
+                    self.remove_band(index);
+                    self.add_band(band);
+                }
+                None => self.add_band(band),
+            }
+        }
+
+        while let Some(index) = next_live(&self.bands) {
+            self.remove_band(index);
+        }
+
+        self.settling = true;
+        self.frames_to_update = 0;
+    }
+
+    #[inline]
+    fn process(&mut self, frame: &mut [Sample]) {
+        if self.settling {
+            if self.frames_to_update == 0 {
+                self.update_bands();
+                self.frames_to_update = SMOOTHING_INTERVAL;
+            }
+            self.frames_to_update -= 1;
+        }
+
+        let preamp = self.preamp.next();
+        if self.bands.is_empty() && preamp == 1.0 {
+            return;
+        }
+
+        for (channel, sample) in frame.iter_mut().enumerate() {
+            let mut value = (*sample * preamp) as f64;
+            for band in &mut self.bands {
+                value = band.coefficients.process(value, &mut band.state[channel]);
+            }
+
+            *sample = value as Sample;
+        }
+    }
+}
+
+impl Parameters {
+    fn towards(self, target: Parameters) -> Parameters {
+        let step = |current: f64, target: f64, epsilon: f64| {
+            let next = current + (target - current) * SMOOTHING;
+            if (target - next).abs() < epsilon {
+                target
+            } else {
+                next
+            }
+        };
+
+        Parameters {
+            log_frequency: step(self.log_frequency, target.log_frequency, 1e-3),
+            gain_db: step(self.gain_db, target.gain_db, 1e-3),
+            q: step(self.q, target.q, 1e-3),
+        }
+    }
+}
+
+impl Coefficients {
+    /// From the Audio EQ Cookbook by Robert Bristow-Johnson.
+    fn new(kind: EqBandKind, parameters: Parameters, sample_rate: f64) -> Self {
+        let w0 = 2.0 * PI * parameters.log_frequency.exp() / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * parameters.q);
+        let a = 10f64.powf(parameters.gain_db / 40.0);
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let [b0, b1, b2, a0, a1, a2] = match kind {
+            EqBandKind::Peaking => [
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ],
+            EqBandKind::LowShelf => [
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf,
+            ],
+            EqBandKind::HighShelf => [
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ],
+            EqBandKind::LowPass => [
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ],
+            EqBandKind::HighPass => [
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ],
+        };
+
+        Coefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    #[inline]
+    fn process(&self, input: f64, state: &mut [f64; 2]) -> f64 {
+        let output = self.b0 * input + state[0];
+        state[0] = self.b1 * input - self.a1 * output + state[1];
+        state[1] = self.b2 * input - self.a2 * output;
+
+        output
+    }
+}
@@ -0,0 +1,155 @@
+use super::{DspSettings, Processor};
+use crate::audio::{OutputFormat, transition::Ramp};
+use hogehoge_types::Sample;
+use std::{collections::VecDeque, time::Duration};
+
+// about -0.3 dBFS, leaves a little room for the conversion in the device
+const THRESHOLD: Sample = 0.966;
+const LOOKAHEAD: Duration = Duration::from_millis(5);
+const RELEASE: Duration = Duration::from_millis(150);
+// in frames, about 20ms
+const TOGGLE_RAMP: usize = 1024;
+
+/// Lookahead peak limiter. The audio is delayed by [`LOOKAHEAD`], so the gain can already be
+/// turned down when a peak comes up instead of clipping it.
+///
+/// While it's turned off the audio passes straight through without the delay. Turning it on starts
+/// with [`LOOKAHEAD`] of silence and turning it off drops what is still delayed, which is short
+/// enough to not be noticeable.
+pub struct Limiter {
+    channels: usize,
+    // fades the limiting in and out when it's turned on or off
+    amount: Ramp,
+    // off and faded out, nothing is delayed
+    bypassed: bool,
+
+    // in frames
+    lookahead: usize,
+    delay: Vec<Sample>,
+    delay_position: usize,
+
+    // (frame, gain) pairs with increasing gain, the front is the minimum of the lookahead window
+    window: VecDeque<(u64, Sample)>,
+    frame: u64,
+
+    envelope: Sample,
+    release: Sample,
+
+    // averaging the envelope over the lookahead smooths the attack into a ramp that still ends
+    // at or below the gain a peak needs
+    average: Vec<Sample>,
+    average_position: usize,
+    average_sum: f64,
+}
+
+impl Limiter {
+    pub fn new(format: OutputFormat) -> Self {
+        let sample_rate = format.sample_rate as f64;
+        let lookahead = ((LOOKAHEAD.as_secs_f64() * sample_rate) as usize).max(1);
+        let release_frames = RELEASE.as_secs_f64() * sample_rate;
+
+        Limiter {
+            channels: format.channels as usize,
+            amount: Ramp::new(0.0),
+            bypassed: true,
+            lookahead,
+            delay: vec![0.0; lookahead * format.channels as usize],
+            delay_position: 0,
+            window: VecDeque::with_capacity(lookahead + 1),
+            frame: 0,
+            envelope: 1.0,
+            release: (1.0 - (-1.0 / release_frames).exp()) as Sample,
+            average: vec![1.0; lookahead],
+            average_position: 0,
+            average_sum: lookahead as f64,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.delay.fill(0.0);
+        self.delay_position = 0;
+        self.window.clear();
+        self.envelope = 1.0;
+        self.average.fill(1.0);
+        self.average_position = 0;
+        self.average_sum = self.lookahead as f64;
+    }
+
+    /// Gain the frame that is delayed now gets.
+    fn gain(&mut self, peak: Sample) -> Sample {
+        let required = if peak > THRESHOLD {
+            THRESHOLD / peak
+        } else {
+            1.0
+        };
+
+        while self
+            .window
+            .back()
+            .is_some_and(|&(_, gain)| gain >= required)
+        {
+            self.window.pop_back();
+        }
+        self.window.push_back((self.frame, required));
+        while self
+            .window
+            .front()
+            .is_some_and(|&(frame, _)| frame + (self.lookahead as u64) < self.frame)
+        {
+            self.window.pop_front();
+        }
+        self.frame += 1;
+
+        let minimum = self.window.front().map_or(1.0, |&(_, gain)| gain);
+        self.envelope = if minimum < self.envelope {
+            minimum
+        } else {
+            self.envelope + (minimum - self.envelope) * self.release
+        };
+
+        let oldest = std::mem::replace(&mut self.average[self.average_position], self.envelope);
+        self.average_position = (self.average_position + 1) % self.lookahead;
+        self.average_sum += self.envelope as f64 - oldest as f64;
+
+        let gain = (self.average_sum / self.lookahead as f64).min(1.0) as Sample;
+        1.0 + (gain - 1.0) * self.amount.next()
+    }
+}
+
+impl Processor for Limiter {
+    fn configure(&mut self, settings: &DspSettings) {
+        if settings.limiter && self.bypassed {
+            self.reset();
+            self.bypassed = false;
+        }
+
+        let amount = if settings.limiter { 1.0 } else { 0.0 };
+        self.amount.ramp_to(amount, TOGGLE_RAMP);
+    }
+
+    #[inline]
+    fn process(&mut self, frame: &mut [Sample]) {
+        if self.bypassed {
+            return;
+        }
+        if self.amount.is_done() && self.amount.target() == 0.0 {
+            self.bypassed = true;
+            return;
+        }
+
+        let peak = frame
+            .iter()
+            .fold(0.0 as Sample, |peak, sample| peak.max(sample.abs()));
+        let gain = self.gain(peak);
+
+        let start = self.delay_position * self.channels;
+        let delayed = &mut self.delay[start..start + self.channels];
+        for (sample, delayed) in frame.iter_mut().zip(delayed) {
+            let input = *sample;
+            *sample = *delayed * gain;
+            *delayed = input;
+        }
+
+        self.delay_position = (self.delay_position + 1) % self.lookahead;
+    }
+}
@@ -0,0 +1,447 @@
+use super::ResamplerQuality;
+use hogehoge_db::SavedOutput;
+use hogehoge_types::{ChannelCount, Sample, SampleRate};
+use rodio::{
+    OutputStream, OutputStreamBuilder, Source, StreamError,
+    cpal::{
+        self,
+        traits::{DeviceTrait, HostTrait},
+    },
+    mixer::{self, Mixer, MixerSource},
+};
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tracing::*;
+
+mod flac;
+pub use flac::FlacWriter;
+mod wav;
+pub use wav::WavWriter;
+
+// used by outputs without a device that could tell us its format
+pub(super) const DEFAULT_SAMPLE_RATE: SampleRate = 48000;
+const DEFAULT_CHANNELS: ChannelCount = 2;
+// how much the sink thread renders at once
+const SINK_CHUNK: Duration = Duration::from_millis(10);
+// how much is taken from a shared source per lock
+const SHARED_CHUNK_FRAMES: usize = 256;
+
+/// The format everything is converted to before it reaches the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputFormat {
+    pub sample_rate: SampleRate,
+    pub channels: ChannelCount,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OutputSettings {
+    /// Sample rate to open the output with, `None` uses the default rate of the device.
+    pub sample_rate: Option<SampleRate>,
+    pub resampler_quality: ResamplerQuality,
+}
+
+/// Where the audio ends up.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum OutputBackend {
+    /// The selected or default audio device. Falls back to [`OutputBackend::Null`] if there is
+    /// none.
+    #[default]
+    Device,
+    /// Consumes the audio in real time without playing it anywhere.
+    Null,
+    /// Writes the audio to a WAV file in real time.
+    File(PathBuf),
+}
+
+#[derive(Debug, Error)]
+pub enum OutputError {
+    #[error("Failed to create output file: {0}")]
+    FileError(#[from] io::Error),
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown output '{0}', expected 'device', 'null' or 'file:<path>'")]
+pub struct ParseOutputBackendError(String);
+
+/// Called from the audio thread when the device of an output has disappeared.
+pub type DeviceLostCallback = Arc<dyn Fn() + Send + Sync>;
+
+/// An opened output, audio added to its mixer is played until it is dropped.
+pub struct Output {
+    mixer: Mixer,
+    format: OutputFormat,
+    // name of the device that is played on
+    device: Option<String>,
+    // the requested device couldn't be opened, so this is the default device instead
+    fell_back: bool,
+    // only held so the stream or sink thread keeps playing
+    _backend: Backend,
+}
+
+enum Backend {
+    Device { _stream: OutputStream },
+    Sink { _thread: SinkThread },
+}
+
+/// Plays a source that is shared between outputs, so it carries on where it was when the output
+/// is switched.
+pub(super) struct SharedSource<S> {
+    source: Arc<Mutex<S>>,
+    format: OutputFormat,
+    buffer: Vec<Sample>,
+    position: usize,
+}
+
+/// Pulls the audio out of the mixer at the pace a device would.
+struct SinkThread {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl OutputSettings {
+    pub fn from_saved(saved: SavedOutput) -> Self {
+        OutputSettings {
+            sample_rate: saved.sample_rate,
+            resampler_quality: ResamplerQuality::parse(&saved.resampler_quality)
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn to_saved(self) -> SavedOutput {
+        SavedOutput {
+            sample_rate: self.sample_rate,
+            resampler_quality: self.resampler_quality.as_str().to_string(),
+        }
+    }
+}
+
+impl FromStr for OutputBackend {
+    type Err = ParseOutputBackendError;
+
+    fn from_str(backend: &str) -> Result<Self, Self::Err> {
+        match backend {
+            "device" => Ok(OutputBackend::Device),
+            "null" => Ok(OutputBackend::Null),
+            _ => match backend.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(OutputBackend::File(path.into())),
+                _ => Err(ParseOutputBackendError(backend.to_string())),
+            },
+        }
+    }
+}
+
+impl Output {
+    /// Opens the output. Devices are picked by name, `None` or a device that can't be opened uses
+    /// the default device.
+    pub fn open(
+        backend: &OutputBackend,
+        settings: &OutputSettings,
+        device: Option<&str>,
+        on_lost: &DeviceLostCallback,
+    ) -> Result<Output, OutputError> {
+        let output = match backend {
+            OutputBackend::Device => Output::from_device(settings, device, on_lost),
+            OutputBackend::Null => Output::sink(settings, None),
+            OutputBackend::File(path) => {
+                let file = BufWriter::new(File::create(path)?);
+                Output::sink(settings, Some(file))
+            }
+        };
+
+        info!(
+            "Opened {:?} audio output on {:?} with {:?}",
+            backend, output.device, output.format
+        );
+
+        Ok(output)
+    }
+
+    pub fn mixer(&self) -> &Mixer {
+        &self.mixer
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+
+    /// Name of the device that is played on, `None` if the output has no device.
+    pub fn device(&self) -> Option<&str> {
+        self.device.as_deref()
+    }
+
+    /// Whether the requested device couldn't be opened and the default device is used instead.
+    pub fn fell_back(&self) -> bool {
+        self.fell_back
+    }
+
+    fn from_device(
+        settings: &OutputSettings,
+        device: Option<&str>,
+        on_lost: &DeviceLostCallback,
+    ) -> Output {
+        let mut fell_back = false;
+        let opened = match device {
+            Some(name) => open_device(find_device(name), settings, on_lost).or_else(|e| {
+                warn!(
+                    "Failed to open output device '{}', using the default device instead: {}",
+                    name, e
+                );
+                fell_back = true;
+                open_device(
+                    cpal::default_host().default_output_device(),
+                    settings,
+                    on_lost,
+                )
+            }),
+            None => open_device(
+                cpal::default_host().default_output_device(),
+                settings,
+                on_lost,
+            ),
+        };
+
+        match opened {
+            Ok((mut stream, name)) => {
+                // dropping it is how the device is switched, so that's nothing to warn about
+                stream.log_on_drop(false);
+
+                Output {
+                    mixer: stream.mixer().clone(),
+                    format: OutputFormat {
+                        sample_rate: stream.config().sample_rate(),
+                        channels: stream.config().channel_count(),
+                    },
+                    device: Some(name),
+                    fell_back,
+                    _backend: Backend::Device { _stream: stream },
+                }
+            }
+            Err(e) => {
+                warn!(
+                    "No audio device available, playing into a null output: {}",
+                    e
+                );
+                Output {
+                    fell_back: device.is_some(),
+                    ..Output::sink(settings, None)
+                }
+            }
+        }
+    }
+
+    fn sink(settings: &OutputSettings, file: Option<BufWriter<File>>) -> Output {
+        let format = OutputFormat {
+            sample_rate: settings.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE),
+            channels: DEFAULT_CHANNELS,
+        };
+        let (mixer, source) = mixer::mixer(format.channels, format.sample_rate);
+        let writer = file.map(|file| WavWriter::new(file, format));
+
+        Output {
+            mixer,
+            format,
+            device: None,
+            fell_back: false,
+            _backend: Backend::Sink {
+                _thread: SinkThread::spawn(source, format, writer),
+            },
+        }
+    }
+}
+
+/// Names of the output devices that are currently available.
+pub fn output_devices() -> Vec<String> {
+    match cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|device| device.name().ok()).collect(),
+        Err(e) => {
+            error!("Failed to list output devices: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn find_device(name: &str) -> Option<cpal::Device> {
+    cpal::default_host()
+        .output_devices()
+        .ok()?
+        .find(|device| device.name().is_ok_and(|device_name| device_name == name))
+}
+
+/// Opens a stream on `device`, at the configured sample rate if the device supports it.
+fn open_device(
+    device: Option<cpal::Device>,
+    settings: &OutputSettings,
+    on_lost: &DeviceLostCallback,
+) -> Result<(OutputStream, String), StreamError> {
+    let device = device.ok_or(StreamError::NoDevice)?;
+    let name = device
+        .name()
+        .unwrap_or_else(|_| "Unknown device".to_string());
+
+    let on_lost = on_lost.clone();
+    let on_error = move |e: cpal::StreamError| match e {
+        cpal::StreamError::DeviceNotAvailable => on_lost(),
+        e => error!("Audio stream error: {}", e),
+    };
+
+    let builder =
+        OutputStreamBuilder::from_device(device.clone())?.with_error_callback(on_error.clone());
+    let stream = match settings.sample_rate {
+        Some(sample_rate) => builder
+            .with_sample_rate(sample_rate)
+            .open_stream()
+            .or_else(|e| {
+                warn!(
+                    "Failed to open output at {} Hz, using the default rate instead: {}",
+                    sample_rate, e
+                );
+                OutputStreamBuilder::from_device(device)?
+                    .with_error_callback(on_error)
+                    .open_stream_or_fallback()
+            })?,
+        None => builder.open_stream_or_fallback()?,
+    };
+
+    Ok((stream, name))
+}
+
+impl<S> SharedSource<S> {
+    pub fn new(source: Arc<Mutex<S>>, format: OutputFormat) -> Self {
+        SharedSource {
+            source,
+            format,
+            buffer: Vec::with_capacity(SHARED_CHUNK_FRAMES * format.channels as usize),
+            position: 0,
+        }
+    }
+}
+
+impl<S: Iterator<Item = Sample>> Iterator for SharedSource<S> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // locking for every sample would be a bit much, so take a chunk at a time
+        if self.position == self.buffer.len() {
+            let chunk_len = SHARED_CHUNK_FRAMES * self.format.channels as usize;
+            let mut source = self.source.lock().unwrap();
+
+            self.buffer.clear();
+            self.buffer.extend(source.by_ref().take(chunk_len));
+            self.position = 0;
+        }
+
+        let sample = self.buffer.get(self.position).copied()?;
+        self.position += 1;
+
+        Some(sample)
+    }
+}
+
+impl<S: Iterator<Item = Sample>> Source for SharedSource<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.format.channels
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.format.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl SinkThread {
+    fn spawn(
+        source: MixerSource,
+        format: OutputFormat,
+        writer: Option<io::Result<WavWriter<BufWriter<File>>>>,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let parent_span = Span::current();
+        let stop_clone = stop.clone();
+        let handle = thread::Builder::new()
+            .name("audio_sink".to_string())
+            .spawn(move || {
+                let _span = info_span!(parent: &parent_span, "audio_sink").entered();
+                run_sink(source, format, writer, &stop_clone);
+            })
+            .expect("Failed to spawn audio sink thread");
+
+        SinkThread {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for SinkThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run_sink(
+    mut source: MixerSource,
+    format: OutputFormat,
+    writer: Option<io::Result<WavWriter<BufWriter<File>>>>,
+    stop: &AtomicBool,
+) {
+    let mut writer = writer.and_then(|writer| {
+        writer
+            .inspect_err(|e| error!("Failed to start writing output file: {}", e))
+            .ok()
+    });
+
+    let chunk_frames = (format.sample_rate as f64 * SINK_CHUNK.as_secs_f64()) as u64;
+    let chunk_len = chunk_frames as usize * format.channels as usize;
+    let mut chunk: Vec<Sample> = Vec::with_capacity(chunk_len);
+
+    let start = Instant::now();
+    let mut frames_played = 0;
+
+    while !stop.load(Ordering::Relaxed) {
+        // the mixer has nothing to give while no source is added to it, that's just silence
+        chunk.clear();
+        chunk.extend((0..chunk_len).map(|_| source.next().unwrap_or(0.0)));
+
+        if let Some(file) = &mut writer {
+            if let Err(e) = file.write_samples(&chunk) {
+                error!(
+                    "Failed to write to output file, discarding audio from now on: {}",
+                    e
+                );
+                writer = None;
+            }
+        }
+
+        frames_played += chunk_frames;
+        let due = start + Duration::from_secs_f64(frames_played as f64 / format.sample_rate as f64);
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+    }
+
+    if let Some(writer) = writer {
+        if let Err(e) = writer.finish() {
+            error!("Failed to finish output file: {}", e);
+        }
+    }
+}
@@ -0,0 +1,535 @@
+use super::OutputFormat;
+use hogehoge_types::Sample;
+use std::io::{self, Seek, SeekFrom, Write};
+
+const BLOCK_SIZE: usize = 4096;
+const BITS_PER_SAMPLE: u32 = 24;
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_PARTITION_ORDER: u32 = 6;
+// the 4 bit rice parameter uses all ones as an escape code
+const MAX_RICE_PARAMETER: u32 = 14;
+// frame sizes and sample count in the STREAMINFO block, which comes right after the marker and
+// the block header
+const STREAMINFO_FRAME_SIZES_OFFSET: u64 = 12;
+
+/// Writes 24 bit FLAC, using the fixed predictors only. The stream info is only complete after
+/// [`finish`](Self::finish).
+pub struct FlacWriter<W: Write + Seek> {
+    writer: W,
+    format: OutputFormat,
+
+    // interleaved samples that don't fill a whole block yet
+    pending: Vec<i32>,
+    frame_number: u64,
+    samples_per_channel: u64,
+    min_frame_size: u32,
+    max_frame_size: u32,
+
+    channel: Vec<i64>,
+    residuals: Vec<i64>,
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    buffered_bits: u32,
+}
+
+/// How a single channel of a frame gets stored.
+enum Subframe {
+    Constant,
+    Verbatim,
+    Fixed {
+        order: usize,
+        partition_order: u32,
+        parameters: Vec<u32>,
+    },
+}
+
+impl<W: Write + Seek> FlacWriter<W> {
+    pub fn new(mut writer: W, format: OutputFormat) -> io::Result<Self> {
+        writer.write_all(b"fLaC")?;
+
+        let mut header = BitWriter::default();
+        // STREAMINFO is the only and therefore last metadata block
+        header.write(1, 1);
+        header.write(0, 7);
+        header.write(34, 24);
+
+        header.write(BLOCK_SIZE as u64, 16);
+        header.write(BLOCK_SIZE as u64, 16);
+        // frame sizes and the total number of samples are unknown until the end
+        header.write(0, 24);
+        header.write(0, 24);
+        write_stream_format(&mut header, format, 0);
+        // leaving the MD5 sum zeroed marks it as unknown
+        for _ in 0..4 {
+            header.write(0, 32);
+        }
+
+        writer.write_all(&header.finish())?;
+
+        Ok(FlacWriter {
+            writer,
+            format,
+            pending: Vec::with_capacity(BLOCK_SIZE * format.channels as usize),
+            frame_number: 0,
+            samples_per_channel: 0,
+            min_frame_size: u32::MAX,
+            max_frame_size: 0,
+            channel: Vec::with_capacity(BLOCK_SIZE),
+            residuals: Vec::with_capacity(BLOCK_SIZE),
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &[Sample]) -> io::Result<()> {
+        let block_len = BLOCK_SIZE * self.format.channels as usize;
+        let max = (1 << (BITS_PER_SAMPLE - 1)) - 1;
+
+        for &sample in samples {
+            self.pending
+                .push((sample.clamp(-1.0, 1.0) * max as f32).round() as i32);
+
+            if self.pending.len() == block_len {
+                self.write_frame()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the last partial block and fills in the stream info.
+    pub fn finish(mut self) -> io::Result<()> {
+        let channels = self.format.channels as usize;
+        self.pending
+            .truncate(self.pending.len() / channels * channels);
+        if !self.pending.is_empty() {
+            self.write_frame()?;
+        }
+
+        let mut info = BitWriter::default();
+        if self.frame_number == 0 {
+            info.write(0, 24);
+            info.write(0, 24);
+        } else {
+            info.write(self.min_frame_size as u64, 24);
+            info.write(self.max_frame_size as u64, 24);
+        }
+        write_stream_format(&mut info, self.format, self.samples_per_channel);
+
+        self.writer
+            .seek(SeekFrom::Start(STREAMINFO_FRAME_SIZES_OFFSET))?;
+        self.writer.write_all(&info.finish())?;
+
+        self.writer.flush()
+    }
+
+    fn write_frame(&mut self) -> io::Result<()> {
+        let channels = self.format.channels as usize;
+        let block_size = self.pending.len() / channels;
+
+        let mut frame = BitWriter::default();
+        frame.write(0b11111111111110, 14);
+        frame.write(0, 1);
+        // fixed block size, so frames are numbered instead of the position of their first sample
+        frame.write(0, 1);
+        let explicit_block_size = if block_size == BLOCK_SIZE {
+            frame.write(0b1100, 4);
+            false
+        } else {
+            frame.write(0b0111, 4);
+            true
+        };
+        // sample rate from the stream info
+        frame.write(0, 4);
+        // independent channels
+        frame.write(channels as u64 - 1, 4);
+        frame.write(0b110, 3);
+        frame.write(0, 1);
+        frame.write_utf8(self.frame_number);
+        if explicit_block_size {
+            frame.write(block_size as u64 - 1, 16);
+        }
+        let header_crc = crc8(&frame.bytes);
+        frame.write(header_crc as u64, 8);
+
+        for channel in 0..channels {
+            self.channel.clear();
+            self.channel.extend(
+                self.pending[channel..]
+                    .iter()
+                    .step_by(channels)
+                    .map(|&sample| sample as i64),
+            );
+            write_subframe(&mut frame, &self.channel, &mut self.residuals);
+        }
+
+        let mut frame = frame.finish();
+        let frame_crc = crc16(&frame);
+        frame.extend_from_slice(&frame_crc.to_be_bytes());
+
+        self.writer.write_all(&frame)?;
+
+        self.min_frame_size = self.min_frame_size.min(frame.len() as u32);
+        self.max_frame_size = self.max_frame_size.max(frame.len() as u32);
+        self.frame_number += 1;
+        self.samples_per_channel += block_size as u64;
+        self.pending.clear();
+
+        Ok(())
+    }
+}
+
+/// The part of the stream info from the sample rate to the total number of samples.
+fn write_stream_format(bits: &mut BitWriter, format: OutputFormat, samples_per_channel: u64) {
+    bits.write(format.sample_rate as u64, 20);
+    bits.write(format.channels as u64 - 1, 3);
+    bits.write(BITS_PER_SAMPLE as u64 - 1, 5);
+    bits.write(samples_per_channel >> 32, 4);
+    bits.write(samples_per_channel & 0xffff_ffff, 32);
+}
+
+fn write_subframe(bits: &mut BitWriter, samples: &[i64], residuals: &mut Vec<i64>) {
+    let (subframe, order) = choose_subframe(samples, residuals);
+
+    // zero padding bit, then the type
+    bits.write(0, 1);
+    match &subframe {
+        Subframe::Constant => bits.write(0b000000, 6),
+        Subframe::Verbatim => bits.write(0b000001, 6),
+        Subframe::Fixed { order, .. } => bits.write(0b001000 | *order as u64, 6),
+    }
+    // no wasted bits
+    bits.write(0, 1);
+
+    match subframe {
+        Subframe::Constant => bits.write_signed(samples[0], BITS_PER_SAMPLE),
+        Subframe::Verbatim => {
+            for &sample in samples {
+                bits.write_signed(sample, BITS_PER_SAMPLE);
+            }
+        }
+        Subframe::Fixed {
+            partition_order,
+            parameters,
+            ..
+        } => {
+            for &sample in &samples[..order] {
+                bits.write_signed(sample, BITS_PER_SAMPLE);
+            }
+
+            fixed_residuals(samples, order, residuals);
+
+            // rice coding with 4 bit parameters
+            bits.write(0b00, 2);
+            bits.write(partition_order as u64, 4);
+
+            let mut residuals = residuals.iter();
+            for (partition, parameter) in parameters.into_iter().enumerate() {
+                let count = partition_len(samples.len(), partition_order, partition, order);
+
+                bits.write(parameter as u64, 4);
+                for &residual in residuals.by_ref().take(count) {
+                    bits.write_rice(fold(residual), parameter);
+                }
+            }
+        }
+    }
+}
+
+/// Picks the smallest encoding and the order of the predictor it uses.
+fn choose_subframe(samples: &[i64], residuals: &mut Vec<i64>) -> (Subframe, usize) {
+    if samples.iter().all(|&sample| sample == samples[0]) {
+        return (Subframe::Constant, 0);
+    }
+
+    let mut best = (Subframe::Verbatim, 0);
+    let mut best_bits = samples.len() as u64 * BITS_PER_SAMPLE as u64;
+
+    for order in 0..=MAX_FIXED_ORDER.min(samples.len() - 1) {
+        fixed_residuals(samples, order, residuals);
+
+        let warmup_bits = order as u64 * BITS_PER_SAMPLE as u64;
+        let (partition_order, parameters, residual_bits) =
+            choose_partitions(samples.len(), order, residuals);
+
+        if warmup_bits + residual_bits < best_bits {
+            best_bits = warmup_bits + residual_bits;
+            best = (
+                Subframe::Fixed {
+                    order,
+                    partition_order,
+                    parameters,
+                },
+                order,
+            );
+        }
+    }
+
+    best
+}
+
+/// The fixed predictor of `order` is the difference of that order.
+fn fixed_residuals(samples: &[i64], order: usize, residuals: &mut Vec<i64>) {
+    residuals.clear();
+    residuals.extend_from_slice(samples);
+
+    for round in 0..order {
+        for index in (round + 1..residuals.len()).rev() {
+            residuals[index] -= residuals[index - 1];
+        }
+    }
+
+    residuals.drain(..order);
+}
+
+/// Finds the partitioning with the cheapest rice coding, returns the partition order, the
+/// parameter for every partition and the resulting size in bits.
+fn choose_partitions(block_size: usize, order: usize, residuals: &[i64]) -> (u32, Vec<u32>, u64) {
+    let mut best: Option<(u32, Vec<u32>, u64)> = None;
+
+    for partition_order in 0..=MAX_PARTITION_ORDER {
+        let partitions = 1 << partition_order;
+        if block_size % partitions != 0 || block_size / partitions <= order {
+            break;
+        }
+
+        let mut parameters = Vec::with_capacity(partitions);
+        // method, partition order and a parameter for every partition
+        let mut bits = 6 + 4 * partitions as u64;
+        let mut residuals = residuals.iter();
+
+        for partition in 0..partitions {
+            let count = partition_len(block_size, partition_order, partition, order);
+            let folded = residuals
+                .by_ref()
+                .take(count)
+                .map(|&residual| fold(residual))
+                .collect::<Vec<_>>();
+
+            let (parameter, partition_bits) = best_rice_parameter(&folded);
+            parameters.push(parameter);
+            bits += partition_bits;
+        }
+
+        if best
+            .as_ref()
+            .is_none_or(|(_, _, best_bits)| bits < *best_bits)
+        {
+            best = Some((partition_order, parameters, bits));
+        }
+    }
+
+    best.expect("Partition order 0 to always be possible")
+}
+
+/// The first partition is shorter by the warm up samples of the predictor.
+fn partition_len(block_size: usize, partition_order: u32, partition: usize, order: usize) -> usize {
+    let len = block_size >> partition_order;
+    if partition == 0 { len - order } else { len }
+}
+
+fn best_rice_parameter(folded: &[u64]) -> (u32, u64) {
+    let rice_bits = |parameter: u32| {
+        folded
+            .iter()
+            .map(|value| (value >> parameter) + 1 + parameter as u64)
+            .sum::<u64>()
+    };
+
+    // the optimal parameter is close to the log of the mean
+    let mean = folded.iter().sum::<u64>() / folded.len().max(1) as u64;
+    let estimate = mean.checked_ilog2().unwrap_or(0).min(MAX_RICE_PARAMETER);
+
+    (estimate.saturating_sub(1)..=(estimate + 1).min(MAX_RICE_PARAMETER))
+        .map(|parameter| (parameter, rice_bits(parameter)))
+        .min_by_key(|(_, bits)| *bits)
+        .expect("Range to not be empty")
+}
+
+// interleaves positive and negative values, so small magnitudes become small numbers
+fn fold(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+impl BitWriter {
+    /// Writes the lowest `count` bits of `value`, at most 32 at once.
+    fn write(&mut self, value: u64, count: u32) {
+        debug_assert!(count <= 32);
+
+        let mask = (1u64 << count) - 1;
+        self.buffer = (self.buffer << count) | (value & mask);
+        self.buffered_bits += count;
+
+        while self.buffered_bits >= 8 {
+            self.buffered_bits -= 8;
+            self.bytes.push((self.buffer >> self.buffered_bits) as u8);
+        }
+        self.buffer &= (1 << self.buffered_bits) - 1;
+    }
+
+    fn write_signed(&mut self, value: i64, count: u32) {
+        self.write(value as u64, count);
+    }
+
+    fn write_rice(&mut self, value: u64, parameter: u32) {
+        let mut quotient = value >> parameter;
+        while quotient >= 32 {
+            self.write(0, 32);
+            quotient -= 32;
+        }
+        // the quotient in unary, terminated by a one
+        self.write(1, quotient as u32 + 1);
+        self.write(value, parameter);
+    }
+
+    /// Frame numbers are stored like UTF-8 code points, just with a larger range.
+    fn write_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            self.write(value, 8);
+            return;
+        }
+
+        let mut len = 2;
+        while value >= 1 << (5 * len + 1) {
+            len += 1;
+        }
+
+        let prefix = (0xff00 >> len) & 0xff;
+        self.write(prefix | (value >> (6 * (len - 1))), 8);
+        for index in (0..len - 1).rev() {
+            self.write(0x80 | ((value >> (6 * index)) & 0x3f), 8);
+        }
+    }
+
+    /// Pads to a whole byte with zeros.
+    fn finish(mut self) -> Vec<u8> {
+        if self.buffered_bits > 0 {
+            self.write(0, 8 - self.buffered_bits);
+        }
+        self.bytes
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use symphonia::core::{
+        audio::SampleBuffer, codecs::DecoderOptions, errors::Error, formats::FormatOptions,
+        io::MediaSourceStream, meta::MetadataOptions, probe::Hint,
+    };
+
+    fn decode(flac: Vec<u8>) -> (OutputFormat, Vec<i32>) {
+        let source = MediaSourceStream::new(Box::new(Cursor::new(flac)), Default::default());
+        let mut reader = symphonia::default::get_probe()
+            .format(
+                Hint::new().with_extension("flac"),
+                source,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .unwrap()
+            .format;
+
+        let params = reader.default_track().unwrap().codec_params.clone();
+        assert_eq!(params.bits_per_sample, Some(BITS_PER_SAMPLE));
+        let format = OutputFormat {
+            sample_rate: params.sample_rate.unwrap(),
+            channels: params.channels.unwrap().count() as u16,
+        };
+
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&params, &DecoderOptions::default())
+            .unwrap();
+
+        let mut samples = Vec::new();
+        loop {
+            let packet = match reader.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => panic!("Failed to read packet: {e}"),
+            };
+
+            let decoded = decoder.decode(&packet).unwrap();
+            let mut buffer = SampleBuffer::<i32>::new(decoded.capacity() as u64, *decoded.spec());
+            buffer.copy_interleaved_ref(decoded);
+            samples.extend_from_slice(buffer.samples());
+        }
+
+        assert_eq!(
+            params.n_frames,
+            Some((samples.len() / format.channels as usize) as u64)
+        );
+
+        (format, samples)
+    }
+
+    #[test]
+    fn round_trip() {
+        let format = OutputFormat {
+            sample_rate: 44100,
+            channels: 2,
+        };
+
+        // silence, a sine that is loud enough to clip and noise, ending in a partial block
+        let mut rng = fastrand::Rng::with_seed(1);
+        let frames = 3 * BLOCK_SIZE + 1000;
+        let samples = (0..frames)
+            .flat_map(|frame| {
+                let sine = 1.2 * (frame as f32 / 20.0).sin();
+                match frame / BLOCK_SIZE {
+                    0 => [0.0, 0.0],
+                    1 => [sine, -sine],
+                    _ => [rng.f32() * 2.0 - 1.0, sine * 0.5],
+                }
+            })
+            .collect::<Vec<Sample>>();
+
+        let mut flac = Cursor::new(Vec::new());
+        let mut writer = FlacWriter::new(&mut flac, format).unwrap();
+        // in uneven chunks, so blocks are split between calls
+        for chunk in samples.chunks(1234) {
+            writer.write_samples(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let (decoded_format, decoded) = decode(flac.into_inner());
+        assert_eq!(decoded_format, format);
+
+        // the decoder scales everything up to the full 32 bits
+        let max = (1 << (BITS_PER_SAMPLE - 1)) - 1;
+        let expected = samples
+            .iter()
+            .map(|sample| {
+                ((sample.clamp(-1.0, 1.0) * max as f32).round() as i32) << (32 - BITS_PER_SAMPLE)
+            })
+            .collect::<Vec<_>>();
+        assert!(decoded == expected, "decoded samples differ");
+    }
+}
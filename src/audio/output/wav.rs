@@ -0,0 +1,66 @@
+use super::OutputFormat;
+use hogehoge_types::Sample;
+use std::io::{self, Seek, SeekFrom, Write};
+
+const HEADER_LENGTH: u32 = 44;
+const FORMAT_IEEE_FLOAT: u16 = 3;
+
+/// Writes 32 bit float WAV. The sizes in the header are only filled in by
+/// [`finish`](Self::finish).
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    data_length: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, format: OutputFormat) -> io::Result<Self> {
+        let block_align = format.channels * size_of::<Sample>() as u16;
+        let byte_rate = format.sample_rate * block_align as u32;
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&FORMAT_IEEE_FLOAT.to_le_bytes())?;
+        writer.write_all(&format.channels.to_le_bytes())?;
+        writer.write_all(&format.sample_rate.to_le_bytes())?;
+        writer.write_all(&byte_rate.to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&(8 * size_of::<Sample>() as u16).to_le_bytes())?;
+
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            writer,
+            data_length: 0,
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &[Sample]) -> io::Result<()> {
+        for sample in samples {
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+
+        // a longer file can't be described by the header anyway, it just keeps the maximum size
+        let length = (samples.len() * size_of::<Sample>()) as u32;
+        self.data_length = self.data_length.saturating_add(length);
+
+        Ok(())
+    }
+
+    /// Fills in the sizes in the header.
+    pub fn finish(mut self) -> io::Result<()> {
+        let riff_length = self.data_length.saturating_add(HEADER_LENGTH - 8);
+
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&riff_length.to_le_bytes())?;
+        self.writer
+            .seek(SeekFrom::Start(HEADER_LENGTH as u64 - 4))?;
+        self.writer.write_all(&self.data_length.to_le_bytes())?;
+
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,363 @@
+use super::resume::save_track_progress_on_change;
+use super::{
+    AudioPlayer, DspProfile, DspSettings, EqPreset, OutputSettings, PlaybackState,
+    ReplayGainSettings, TransitionSettings, Volume,
+};
+use crate::queue::{Queue, QueueItems};
+use hogehoge_db::{Database, DbError};
+use hogehoge_types::{Tags, UniqueTrackIdentifier};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::{
+        broadcast::error::{RecvError, TryRecvError},
+        watch,
+    },
+    time,
+};
+use tracing::*;
+
+// queue edits tend to come in bursts, so wait for a bit before saving
+const QUEUE_SAVE_DELAY: Duration = Duration::from_secs(1);
+const POSITION_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+impl AudioPlayer {
+    /// Restores the queue, volume and playback settings of the last session, paused at the saved
+    /// position, and keeps saving them from then on. Tracks that remember their position get it
+    /// saved as well.
+    ///
+    /// Returns the tracks that were dropped because their plugin isn't loaded anymore.
+    pub async fn restore_saved_state(&self) -> Vec<UniqueTrackIdentifier> {
+        let dropped = self.load_saved_state().await.unwrap_or_else(|e| {
+            error!("Failed to restore the saved queue: {}", e);
+            Vec::new()
+        });
+
+        tokio::spawn(save_queue_on_change(self.queue.clone(), self.db.clone()));
+        tokio::spawn(save_playback_state_on_change(
+            self.subscribe_state(),
+            self.db.clone(),
+        ));
+        tokio::spawn(save_track_progress_on_change(
+            self.subscribe_state(),
+            self.db.clone(),
+        ));
+        tokio::spawn(load_dsp_profile_on_device_change(self.clone()));
+
+        dropped
+    }
+
+    async fn load_saved_state(&self) -> Result<Vec<UniqueTrackIdentifier>, DbError> {
+        let (level, muted) = self.db.load_volume().await?;
+        self.set_volume(level);
+        self.set_muted(muted);
+
+        let replay_gain = self.db.load_replay_gain().await?;
+        self.apply_replay_gain(ReplayGainSettings::from_saved(replay_gain));
+
+        let transitions = self.db.load_transitions().await?;
+        self.apply_transitions(TransitionSettings::from_saved(transitions));
+
+        let resume_threshold = self.db.load_resume_threshold().await?;
+        self.apply_resume_threshold(resume_threshold);
+
+        let device = self.inner.device_state.borrow().device.clone();
+        self.load_dsp_profile(device).await;
+
+        let mut saved = self.db.load_queue().await?;
+        let position = self.db.load_playback_position().await?;
+
+        let plugins = &self.queue.plugin_system.plugins;
+        let mut dropped = Vec::new();
+        let mut is_loaded = |track: &UniqueTrackIdentifier| {
+            let loaded = plugins.contains_key(&track.plugin_id);
+            if !loaded {
+                dropped.push(track.clone());
+            }
+            loaded
+        };
+
+        saved.past.retain(&mut is_loaded);
+        let current_loaded = saved.current.as_ref().is_some_and(&mut is_loaded);
+        if !current_loaded {
+            saved.current = None;
+        }
+        saved.future.retain(&mut is_loaded);
+        // these are the same tracks as in `future`, so they don't count towards the dropped ones
+        saved
+            .unshuffled
+            .retain(|track| plugins.contains_key(&track.plugin_id));
+
+        if !dropped.is_empty() {
+            warn!(
+                "Dropped {} queued tracks because their plugin isn't loaded",
+                dropped.len()
+            );
+        }
+
+        let items = QueueItems::from_saved(saved);
+        if items.is_empty() {
+            return Ok(dropped);
+        }
+
+        info!(
+            "Restoring queue with {} tracks",
+            items.past.len() + items.future.len() + items.current.iter().len()
+        );
+
+        // has to happen before the queue changes, otherwise the current track would start playing
+        self.pause();
+        self.queue.restore(items);
+        if current_loaded && !position.is_zero() {
+            self.seek(position);
+        }
+
+        Ok(dropped)
+    }
+
+    pub(super) fn save_replay_gain(&self, settings: ReplayGainSettings) {
+        let db = self.db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = db.save_replay_gain(&settings.to_saved()).await {
+                warn!("Failed to save ReplayGain settings: {}", e);
+            }
+        });
+    }
+
+    pub(super) fn save_transitions(&self, settings: TransitionSettings) {
+        let db = self.db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = db.save_transitions(&settings.to_saved()).await {
+                warn!("Failed to save transition settings: {}", e);
+            }
+        });
+    }
+
+    pub(super) fn save_resume_threshold(&self, threshold: Duration) {
+        let db = self.db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = db.save_resume_threshold(threshold).await {
+                warn!("Failed to save resume threshold: {}", e);
+            }
+        });
+    }
+
+    pub(super) fn save_output_settings(&self, settings: OutputSettings) {
+        let db = self.db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = db.save_output(&settings.to_saved()).await {
+                warn!("Failed to save output settings: {}", e);
+            }
+        });
+    }
+
+    /// Applies the DSP profile of `device`, falling back to the shared profile if it has none.
+    pub(super) async fn load_dsp_profile(&self, device: Option<String>) {
+        let own = match &device {
+            Some(device) => self.db.load_dsp_profile(Some(device)).await,
+            None => Ok(None),
+        };
+
+        let profile = match own {
+            Ok(Some(saved)) => Ok(Some((saved, device))),
+            Ok(None) => self
+                .db
+                .load_dsp_profile(None)
+                .await
+                .map(|saved| saved.map(|saved| (saved, None))),
+            Err(e) => Err(e),
+        };
+
+        let profile = match profile {
+            Ok(Some((saved, device))) => DspProfile {
+                settings: DspSettings::from_saved(saved),
+                device,
+            },
+            Ok(None) => DspProfile::default(),
+            Err(e) => {
+                warn!("Failed to load DSP profile: {}", e);
+                return;
+            }
+        };
+
+        if profile != *self.dsp.borrow() {
+            self.apply_dsp(profile);
+        }
+    }
+
+    pub(super) fn save_dsp_profile(&self, device: Option<String>, settings: DspSettings) {
+        let db = self.db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = db
+                .save_dsp_profile(device.as_deref(), &settings.to_saved())
+                .await
+            {
+                warn!("Failed to save DSP profile: {}", e);
+            }
+        });
+    }
+
+    /// The built-in presets followed by the ones saved by the user.
+    pub async fn eq_presets(&self) -> Vec<EqPreset> {
+        let saved = self.db.load_eq_presets().await.unwrap_or_else(|e| {
+            warn!("Failed to load EQ presets: {}", e);
+            Vec::new()
+        });
+
+        EqPreset::built_in()
+            .into_iter()
+            .chain(saved.into_iter().map(EqPreset::from_saved))
+            .collect()
+    }
+
+    /// Saves the current EQ as a preset named `name`, replacing a saved preset with the same name.
+    pub async fn save_eq_preset(&self, name: String) {
+        let settings = self.dsp_profile().settings;
+        let preset = EqPreset {
+            name,
+            preamp_db: settings.preamp_db,
+            bands: settings.bands,
+            built_in: false,
+        };
+
+        if let Err(e) = self.db.save_eq_preset(&preset.to_saved()).await {
+            warn!("Failed to save EQ preset {}: {}", preset.name, e);
+        }
+    }
+
+    pub async fn delete_eq_preset(&self, name: &str) {
+        if let Err(e) = self.db.delete_eq_preset(name).await {
+            warn!("Failed to delete EQ preset {}: {}", name, e);
+        }
+    }
+
+    pub(super) fn save_output_device(&self, device: Option<String>) {
+        let db = self.db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = db.save_output_device(device.as_deref()).await {
+                warn!("Failed to save output device: {}", e);
+            }
+        });
+    }
+
+    /// Saves the speed for the show of `track`, or for `track` if it isn't part of one.
+    pub(super) fn save_playback_speed(&self, track: UniqueTrackIdentifier, speed: f32) {
+        let db = self.db.clone();
+        tokio::spawn(async move {
+            let result = match db.get_track_by_identifier(&track).await {
+                Ok(info) => match info.and_then(|info| show(&info.tags)) {
+                    Some(show) => db.save_show_speed(&show, speed).await,
+                    None => db.save_track_speed(&track, speed).await,
+                },
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = result {
+                warn!("Failed to save playback speed of {:?}: {}", track, e);
+            }
+        });
+    }
+}
+
+pub(super) fn is_podcast(tags: &Tags) -> bool {
+    let flagged = tags
+        .flag_podcast
+        .as_deref()
+        .is_some_and(|flag| !flag.is_empty() && flag != "0");
+
+    flagged || tags.podcast_url.is_some() || tags.podcast_global_unique_id.is_some()
+}
+
+/// The podcast a track is an episode of.
+pub(super) fn show(tags: &Tags) -> Option<String> {
+    if !is_podcast(tags) {
+        return None;
+    }
+
+    tags.show_name.clone().or_else(|| tags.album_title.clone())
+}
+
+/// The speed `track` was last played at, shows remember it for all of their episodes.
+pub(super) async fn load_playback_speed(
+    db: &Database,
+    track: &UniqueTrackIdentifier,
+    show: Option<&str>,
+) -> Result<f32, DbError> {
+    let speed = match show {
+        Some(show) => db.load_show_speed(show).await?,
+        None => db.load_track_speed(track).await?,
+    };
+
+    Ok(speed.unwrap_or(1.0))
+}
+
+async fn save_queue_on_change(queue: Arc<Queue>, db: Database) {
+    let mut update_rx = queue.subscribe_updates();
+
+    loop {
+        if let Err(RecvError::Closed) = update_rx.recv().await {
+            break;
+        }
+
+        time::sleep(QUEUE_SAVE_DELAY).await;
+        while let Ok(_) | Err(TryRecvError::Lagged(_)) = update_rx.try_recv() {}
+
+        if let Err(e) = db.save_queue(&queue.items().to_saved()).await {
+            warn!("Failed to save queue: {}", e);
+        }
+    }
+}
+
+/// Switches to the DSP profile of the device whenever another output device is used.
+async fn load_dsp_profile_on_device_change(player: AudioPlayer) {
+    let mut device_rx = player.subscribe_output_device();
+    device_rx.mark_unchanged();
+
+    while device_rx.changed().await.is_ok() {
+        let device = device_rx.borrow_and_update().device.clone();
+        player.load_dsp_profile(device).await;
+    }
+}
+
+/// Saves the position every few seconds, so at most that much is lost when 2hoge is closed.
+async fn save_playback_state_on_change(mut state_rx: watch::Receiver<PlaybackState>, db: Database) {
+    let mut last_saved: Option<(Duration, bool)> = None;
+    let mut last_saved_volume = Volume::default();
+
+    while state_rx.changed().await.is_ok() {
+        let (position, paused, volume) = match &*state_rx.borrow_and_update() {
+            PlaybackState::Playing {
+                position,
+                paused,
+                volume,
+                ..
+            } => (*position, *paused, *volume),
+            PlaybackState::Stopped { volume } => (Duration::ZERO, true, *volume),
+        };
+
+        if volume != last_saved_volume {
+            match db.save_volume(volume.level, volume.muted).await {
+                Ok(()) => last_saved_volume = volume,
+                Err(e) => warn!("Failed to save volume: {}", e),
+            }
+        }
+
+        let should_save = match last_saved {
+            None => true,
+            // going backwards means a seek or a new track, both should be saved right away
+            Some((last_position, last_paused)) => {
+                paused != last_paused
+                    || position < last_position
+                    || position - last_position >= POSITION_SAVE_INTERVAL
+            }
+        };
+
+        if !should_save {
+            continue;
+        }
+
+        match db.save_playback_position(position).await {
+            Ok(()) => last_saved = Some((position, paused)),
+            Err(e) => warn!("Failed to save playback position: {}", e),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use super::{
+    DspSettings, OutputFormat, OutputSettings, PlayerCommand, QueueSource, ReplayGainSettings,
+    TransitionSettings,
+    output::{DEFAULT_SAMPLE_RATE, FlacWriter, WavWriter},
+};
+use crate::plugin::PluginSystem;
+use crate::queue::Queue;
+use hogehoge_db::{Database, DbError};
+use hogehoge_types::{Sample, UniqueTrackIdentifier};
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::{sync::mpsc, task};
+use tracing::*;
+
+const RENDER_CHANNELS: u16 = 2;
+const RENDER_CHUNK: usize = 1 << 14;
+
+#[derive(Debug, Error)]
+pub enum RenderError {
+    #[error("Unsupported file type, the output has to end in .wav or .flac")]
+    UnsupportedFileType,
+    #[error("Failed to load player settings: {0}")]
+    DatabaseError(#[from] DbError),
+    #[error("Failed to write output file: {0}")]
+    IoError(#[from] io::Error),
+}
+
+/// How much was rendered and how long it took.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderSummary {
+    pub duration: Duration,
+    pub elapsed: Duration,
+}
+
+enum RenderWriter {
+    Wav(WavWriter<BufWriter<File>>),
+    Flac(FlacWriter<BufWriter<File>>),
+}
+
+/// Plays `tracks` into a WAV or FLAC file as fast as they can be decoded.
+///
+/// Everything goes through the same path as regular playback, so ReplayGain, crossfades and the
+/// resampler apply just like they would through the speakers. Only the volume is left out.
+pub async fn render(
+    plugins: PluginSystem,
+    db: Database,
+    tracks: Vec<UniqueTrackIdentifier>,
+    path: &Path,
+    sample_rate: Option<u32>,
+) -> Result<RenderSummary, RenderError> {
+    let output = OutputSettings::from_saved(db.load_output().await?);
+    let replay_gain = ReplayGainSettings::from_saved(db.load_replay_gain().await?);
+    let transitions = TransitionSettings::from_saved(db.load_transitions().await?);
+    // there's no device, so the shared profile is used
+    let dsp = db
+        .load_dsp_profile(None)
+        .await?
+        .map(DspSettings::from_saved)
+        .unwrap_or_default();
+
+    let format = OutputFormat {
+        sample_rate: sample_rate
+            .or(output.sample_rate)
+            .unwrap_or(DEFAULT_SAMPLE_RATE),
+        channels: RENDER_CHANNELS,
+    };
+    let mut writer = RenderWriter::create(path, format)?;
+
+    let queue = Queue::new(plugins, db.clone());
+    let (command_tx, command_rx) = mpsc::unbounded_channel();
+
+    let mut source = QueueSource::new(
+        queue.clone(),
+        db,
+        command_rx,
+        format,
+        output.resampler_quality,
+    );
+    source.set_offline();
+
+    // picked up together with the queue at the first update, before anything is played
+    let _ = command_tx.send(PlayerCommand::SetReplayGain(replay_gain));
+    let _ = command_tx.send(PlayerCommand::SetTransitions(transitions));
+    let _ = command_tx.send(PlayerCommand::SetDsp(dsp));
+    queue.replace(tracks);
+
+    info!("Rendering {:?} with {:?}", path, format);
+    let start = Instant::now();
+
+    let samples = task::spawn_blocking(move || -> Result<u64, io::Error> {
+        let mut chunk: Vec<Sample> = Vec::with_capacity(RENDER_CHUNK);
+        let mut samples = 0;
+
+        loop {
+            chunk.clear();
+            chunk.extend(source.by_ref().take(RENDER_CHUNK));
+            if chunk.is_empty() {
+                break;
+            }
+
+            writer.write_samples(&chunk)?;
+            samples += chunk.len() as u64;
+        }
+
+        writer.finish()?;
+        Ok(samples)
+    })
+    .await
+    .expect("Render thread panicked")?;
+
+    let frames = samples / format.channels as u64;
+    let summary = RenderSummary {
+        duration: Duration::from_secs_f64(frames as f64 / format.sample_rate as f64),
+        elapsed: start.elapsed(),
+    };
+    info!("Finished rendering: {:?}", summary);
+
+    Ok(summary)
+}
+
+impl RenderWriter {
+    /// Picks the format by the extension of `path`.
+    fn create(path: &Path, format: OutputFormat) -> Result<Self, RenderError> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+
+        let writer = match extension.as_deref() {
+            Some("wav") => RenderWriter::Wav(WavWriter::new(open(path)?, format)?),
+            Some("flac") => RenderWriter::Flac(FlacWriter::new(open(path)?, format)?),
+            _ => return Err(RenderError::UnsupportedFileType),
+        };
+
+        Ok(writer)
+    }
+
+    fn write_samples(&mut self, samples: &[Sample]) -> io::Result<()> {
+        match self {
+            RenderWriter::Wav(writer) => writer.write_samples(samples),
+            RenderWriter::Flac(writer) => writer.write_samples(samples),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            RenderWriter::Wav(writer) => writer.finish(),
+            RenderWriter::Flac(writer) => writer.finish(),
+        }
+    }
+}
+
+fn open(path: &Path) -> io::Result<BufWriter<File>> {
+    File::create(path).map(BufWriter::new)
+}
@@ -0,0 +1,157 @@
+use hogehoge_db::{SavedReplayGain, TrackLoudness};
+use hogehoge_types::Tags;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplayGainMode {
+    Off,
+    #[default]
+    Track,
+    /// Keeps the loudness differences between tracks of the same album.
+    Album,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayGainSettings {
+    pub mode: ReplayGainMode,
+    /// Added on top of the gain of every track, in dB.
+    pub preamp_db: f32,
+    /// Used instead of the track gain for tracks without ReplayGain tags, in dB.
+    pub fallback_db: f32,
+    /// Lowers the gain of tracks that would clip, based on their peak.
+    pub prevent_clipping: bool,
+}
+
+/// The ReplayGain values of a track, parsed from its tags.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReplayGainInfo {
+    track_gain_db: Option<f32>,
+    track_peak: Option<f32>,
+    album_gain_db: Option<f32>,
+    album_peak: Option<f32>,
+}
+
+impl ReplayGainMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ReplayGainMode::Off => "off",
+            ReplayGainMode::Track => "track",
+            ReplayGainMode::Album => "album",
+        }
+    }
+
+    fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "off" => Some(ReplayGainMode::Off),
+            "track" => Some(ReplayGainMode::Track),
+            "album" => Some(ReplayGainMode::Album),
+            _ => None,
+        }
+    }
+}
+
+impl Default for ReplayGainSettings {
+    fn default() -> Self {
+        ReplayGainSettings {
+            mode: ReplayGainMode::default(),
+            preamp_db: 0.0,
+            fallback_db: 0.0,
+            prevent_clipping: true,
+        }
+    }
+}
+
+impl ReplayGainSettings {
+    pub fn from_saved(saved: SavedReplayGain) -> Self {
+        ReplayGainSettings {
+            mode: ReplayGainMode::parse(&saved.mode).unwrap_or_default(),
+            preamp_db: saved.preamp_db,
+            fallback_db: saved.fallback_db,
+            prevent_clipping: saved.prevent_clipping,
+        }
+    }
+
+    pub fn to_saved(self) -> SavedReplayGain {
+        SavedReplayGain {
+            mode: self.mode.as_str().to_string(),
+            preamp_db: self.preamp_db,
+            fallback_db: self.fallback_db,
+            prevent_clipping: self.prevent_clipping,
+        }
+    }
+}
+
+impl ReplayGainInfo {
+    pub fn from_tags(tags: &Tags) -> Self {
+        ReplayGainInfo {
+            track_gain_db: tags.replay_gain_track_gain.as_deref().and_then(parse_gain),
+            track_peak: tags.replay_gain_track_peak.as_deref().and_then(parse_peak),
+            album_gain_db: tags.replay_gain_album_gain.as_deref().and_then(parse_gain),
+            album_peak: tags.replay_gain_album_peak.as_deref().and_then(parse_peak),
+        }
+    }
+
+    /// Fills in values that are missing from the tags with the ones measured by the loudness
+    /// analysis.
+    pub fn or_measured(self, measured: TrackLoudness) -> Self {
+        ReplayGainInfo {
+            track_gain_db: self
+                .track_gain_db
+                .or(measured.track.map(|loudness| loudness.replay_gain_db())),
+            track_peak: self
+                .track_peak
+                .or(measured.track.map(|loudness| loudness.true_peak)),
+            album_gain_db: self
+                .album_gain_db
+                .or(measured.album.map(|loudness| loudness.replay_gain_db())),
+            album_peak: self
+                .album_peak
+                .or(measured.album.map(|loudness| loudness.true_peak)),
+        }
+    }
+
+    /// The linear factor samples of this track should be scaled by.
+    pub fn gain(&self, settings: &ReplayGainSettings) -> f32 {
+        // tracks that are missing the requested values fall back to the other ones
+        let (gain_db, peak) = match settings.mode {
+            ReplayGainMode::Off => return 1.0,
+            ReplayGainMode::Track => (
+                self.track_gain_db.or(self.album_gain_db),
+                self.track_peak.or(self.album_peak),
+            ),
+            ReplayGainMode::Album => (
+                self.album_gain_db.or(self.track_gain_db),
+                self.album_peak.or(self.track_peak),
+            ),
+        };
+
+        let gain_db = gain_db.unwrap_or(settings.fallback_db) + settings.preamp_db;
+        let gain = 10f32.powf(gain_db / 20.0);
+
+        match peak {
+            Some(peak) if settings.prevent_clipping && peak > 0.0 => gain.min(1.0 / peak),
+            _ => gain,
+        }
+    }
+}
+
+fn parse_gain(gain: &str) -> Option<f32> {
+    // usually stored like `-6.54 dB`, but the unit is missing or spelled differently sometimes
+    let gain = gain.trim();
+    let gain = gain
+        .strip_suffix("dB")
+        .or_else(|| gain.strip_suffix("db"))
+        .or_else(|| gain.strip_suffix("DB"))
+        .unwrap_or(gain);
+
+    gain.trim()
+        .parse()
+        .ok()
+        .filter(|gain: &f32| gain.is_finite())
+}
+
+fn parse_peak(peak: &str) -> Option<f32> {
+    peak.trim()
+        .parse()
+        .ok()
+        .filter(|peak: &f32| peak.is_finite() && *peak >= 0.0)
+}
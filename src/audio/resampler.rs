@@ -0,0 +1,494 @@
+//! Conversion of decoded audio into the format of the output.
+
+use super::output::OutputFormat;
+use hogehoge_types::{AudioBlock, ChannelCount, Sample, SampleRate};
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResamplerQuality {
+    Fast,
+    Balanced,
+    /// Transparent, at the cost of a bit more CPU time.
+    #[default]
+    High,
+}
+
+/// Brings blocks of any format into the output format, so sources never change the format of the
+/// stream.
+pub struct FormatConverter {
+    output: OutputFormat,
+    quality: ResamplerQuality,
+
+    input: Option<(SampleRate, ChannelCount)>,
+    mapper: ChannelMapper,
+    // `None` while the input already has the output sample rate
+    resampler: Option<Resampler>,
+    mapped: Vec<Sample>,
+}
+
+/// Mixes every output channel from the input channels.
+struct ChannelMapper {
+    inputs: usize,
+    outputs: usize,
+    // `outputs` rows of `inputs` weights, `None` if the channels are passed through as they are
+    matrix: Option<Vec<f32>>,
+}
+
+/// Band limited interpolation with a windowed sinc, works for any ratio of sample rates.
+struct Resampler {
+    channels: usize,
+    // input frames per output frame
+    step: f64,
+    half_taps: usize,
+    phases: usize,
+    // `phases + 1` rows of `2 * half_taps` coefficients, one row per fractional position
+    filter: Vec<f32>,
+
+    // interleaved input that is still needed, starting with `half_taps` frames of silence
+    history: Vec<Sample>,
+    // position of the next output frame in `history`, in input frames
+    position: f64,
+    coefficients: Vec<f32>,
+}
+
+impl ResamplerQuality {
+    pub(super) fn as_str(&self) -> &'static str {
+        match self {
+            ResamplerQuality::Fast => "fast",
+            ResamplerQuality::Balanced => "balanced",
+            ResamplerQuality::High => "high",
+        }
+    }
+
+    pub(super) fn parse(quality: &str) -> Option<Self> {
+        match quality {
+            "fast" => Some(ResamplerQuality::Fast),
+            "balanced" => Some(ResamplerQuality::Balanced),
+            "high" => Some(ResamplerQuality::High),
+            _ => None,
+        }
+    }
+
+    /// Zero crossings of the sinc on each side, interpolated positions between two samples and
+    /// where the passband ends relative to the lower nyquist frequency.
+    fn filter_params(&self) -> (usize, usize, f64) {
+        match self {
+            ResamplerQuality::Fast => (8, 64, 0.85),
+            ResamplerQuality::Balanced => (16, 128, 0.91),
+            ResamplerQuality::High => (48, 256, 0.96),
+        }
+    }
+}
+
+impl FormatConverter {
+    pub fn new(output: OutputFormat, quality: ResamplerQuality) -> Self {
+        FormatConverter {
+            output,
+            quality,
+            input: None,
+            mapper: ChannelMapper::new(output.channels as usize, output.channels as usize),
+            resampler: None,
+            mapped: Vec::new(),
+        }
+    }
+
+    /// Converts `block` and appends the result to `output`. The resampler holds back a few
+    /// samples, those only come out with the next block or [`flush`](Self::flush).
+    pub fn process(&mut self, block: &AudioBlock, output: &mut Vec<Sample>) {
+        if block.sample_rate == 0 || block.channel_count == 0 {
+            return;
+        }
+
+        let format = (block.sample_rate, block.channel_count);
+        if self.input != Some(format) {
+            // whatever the resampler still holds belongs to the old format
+            self.flush(output);
+            self.set_input(format);
+        }
+
+        match &mut self.resampler {
+            Some(resampler) => {
+                self.mapped.clear();
+                self.mapper.process(&block.samples, &mut self.mapped);
+                resampler.process(&self.mapped, output);
+            }
+            None => self.mapper.process(&block.samples, output),
+        }
+    }
+
+    /// Appends the samples the resampler still holds at the end of the stream.
+    pub fn flush(&mut self, output: &mut Vec<Sample>) {
+        if let Some(resampler) = &mut self.resampler {
+            resampler.flush(output);
+        }
+    }
+
+    /// Forgets the previous input, so nothing of it bleeds into the audio after a seek.
+    pub fn reset(&mut self) {
+        if let Some(resampler) = &mut self.resampler {
+            resampler.reset();
+        }
+    }
+
+    fn set_input(&mut self, (sample_rate, channels): (SampleRate, ChannelCount)) {
+        self.input = Some((sample_rate, channels));
+        self.mapper = ChannelMapper::new(channels as usize, self.output.channels as usize);
+        self.resampler = (sample_rate != self.output.sample_rate).then(|| {
+            Resampler::new(
+                sample_rate,
+                self.output.sample_rate,
+                self.output.channels as usize,
+                self.quality,
+            )
+        });
+    }
+}
+
+impl ChannelMapper {
+    fn new(inputs: usize, outputs: usize) -> Self {
+        let matrix = (inputs != outputs).then(|| mix_matrix(inputs, outputs));
+
+        ChannelMapper {
+            inputs,
+            outputs,
+            matrix,
+        }
+    }
+
+    fn process(&self, input: &[Sample], output: &mut Vec<Sample>) {
+        let Some(matrix) = &self.matrix else {
+            output.extend_from_slice(input);
+            return;
+        };
+
+        output.reserve(input.len() / self.inputs * self.outputs);
+        for frame in input.chunks_exact(self.inputs) {
+            output.extend(matrix.chunks_exact(self.inputs).map(|weights| {
+                weights
+                    .iter()
+                    .zip(frame)
+                    .map(|(weight, sample)| weight * sample)
+                    .sum::<Sample>()
+            }));
+        }
+    }
+}
+
+/// Weights for `outputs` channels from `inputs` channels, both in the usual WAVE channel order.
+fn mix_matrix(inputs: usize, outputs: usize) -> Vec<f32> {
+    let mut matrix = vec![0.0; inputs * outputs];
+    let mut set = |output: usize, input: usize, weight: f32| {
+        matrix[output * inputs + input] = weight;
+    };
+
+    match (inputs, outputs) {
+        // mono goes to the front speakers
+        (1, _) => {
+            set(0, 0, 1.0);
+            if outputs > 1 {
+                set(1, 0, 1.0);
+            }
+        }
+        (_, 1) => {
+            for (input, (left, right)) in stereo_downmix(inputs).into_iter().enumerate() {
+                set(0, input, (left + right) / 2.0);
+            }
+        }
+        (_, 2) => {
+            for (input, (left, right)) in stereo_downmix(inputs).into_iter().enumerate() {
+                set(0, input, left);
+                set(1, input, right);
+            }
+        }
+        // between two surround layouts the shared channels stay where they are, if there are
+        // less outputs the rest are folded into the front
+        _ => {
+            for channel in 0..inputs.min(outputs) {
+                set(channel, channel, 1.0);
+            }
+            for (input, (left, right)) in
+                stereo_downmix(inputs).into_iter().enumerate().skip(outputs)
+            {
+                set(0, input, left);
+                set(1, input, right);
+            }
+        }
+    }
+
+    normalize_rows(&mut matrix, inputs);
+    matrix
+}
+
+/// Left and right weight of every input channel when mixing it down to stereo.
+fn stereo_downmix(inputs: usize) -> Vec<(f32, f32)> {
+    const SIDE: f32 = std::f32::consts::FRAC_1_SQRT_2;
+    const LEFT: (f32, f32) = (1.0, 0.0);
+    const RIGHT: (f32, f32) = (0.0, 1.0);
+    const CENTER: (f32, f32) = (SIDE, SIDE);
+    const LEFT_SURROUND: (f32, f32) = (SIDE, 0.0);
+    const RIGHT_SURROUND: (f32, f32) = (0.0, SIDE);
+    // the low frequency channel is left out, like most downmixes do
+    const LFE: (f32, f32) = (0.0, 0.0);
+
+    match inputs {
+        2 => vec![LEFT, RIGHT],
+        3 => vec![LEFT, RIGHT, CENTER],
+        4 => vec![LEFT, RIGHT, LEFT_SURROUND, RIGHT_SURROUND],
+        5 => vec![LEFT, RIGHT, CENTER, LEFT_SURROUND, RIGHT_SURROUND],
+        6 => vec![LEFT, RIGHT, CENTER, LFE, LEFT_SURROUND, RIGHT_SURROUND],
+        7 => vec![
+            LEFT,
+            RIGHT,
+            CENTER,
+            LFE,
+            CENTER,
+            LEFT_SURROUND,
+            RIGHT_SURROUND,
+        ],
+        8 => vec![
+            LEFT,
+            RIGHT,
+            CENTER,
+            LFE,
+            LEFT_SURROUND,
+            RIGHT_SURROUND,
+            LEFT_SURROUND,
+            RIGHT_SURROUND,
+        ],
+        // unknown layout, alternate between the sides
+        _ => (0..inputs)
+            .map(|input| if input % 2 == 0 { LEFT } else { RIGHT })
+            .collect(),
+    }
+}
+
+// a downmix can't be allowed to clip when all inputs are at full scale
+fn normalize_rows(matrix: &mut [f32], inputs: usize) {
+    for row in matrix.chunks_exact_mut(inputs) {
+        let sum = row.iter().sum::<f32>();
+        if sum > 1.0 {
+            row.iter_mut().for_each(|weight| *weight /= sum);
+        }
+    }
+}
+
+impl Resampler {
+    fn new(from: SampleRate, to: SampleRate, channels: usize, quality: ResamplerQuality) -> Self {
+        let (zero_crossings, phases, rolloff) = quality.filter_params();
+
+        // when downsampling the filter has to be stretched to cut below the new nyquist frequency
+        let cutoff = rolloff * (to as f64 / from as f64).min(1.0);
+        let half_taps = (zero_crossings as f64 / cutoff).ceil() as usize;
+
+        let filter = (0..=phases)
+            .flat_map(|phase| {
+                let fraction = phase as f64 / phases as f64;
+                (0..2 * half_taps).map(move |tap| {
+                    // distance of the tap from the interpolated position
+                    let x = tap as f64 - (half_taps - 1) as f64 - fraction;
+                    let window = blackman(x / half_taps as f64);
+
+                    (cutoff * sinc(cutoff * x) * window) as f32
+                })
+            })
+            .collect();
+
+        let mut resampler = Resampler {
+            channels,
+            step: from as f64 / to as f64,
+            half_taps,
+            phases,
+            filter,
+            history: Vec::new(),
+            position: 0.0,
+            coefficients: vec![0.0; 2 * half_taps],
+        };
+        resampler.reset();
+        resampler
+    }
+
+    fn reset(&mut self) {
+        self.history.clear();
+        self.history.resize(self.half_taps * self.channels, 0.0);
+        self.position = self.half_taps as f64;
+    }
+
+    fn process(&mut self, input: &[Sample], output: &mut Vec<Sample>) {
+        self.history.extend_from_slice(input);
+        let frames = self.history.len() / self.channels;
+
+        output.reserve(((input.len() / self.channels) as f64 / self.step) as usize * self.channels);
+
+        loop {
+            let index = self.position as usize;
+            // the last tap has to be available already
+            if index + self.half_taps >= frames {
+                break;
+            }
+
+            self.interpolate_coefficients(self.position - index as f64);
+
+            let start = (index + 1 - self.half_taps) * self.channels;
+            for channel in 0..self.channels {
+                let sample = self
+                    .coefficients
+                    .iter()
+                    .zip(
+                        self.history[start + channel..]
+                            .iter()
+                            .step_by(self.channels),
+                    )
+                    .map(|(coefficient, sample)| coefficient * sample)
+                    .sum::<Sample>();
+                output.push(sample);
+            }
+
+            self.position += self.step;
+        }
+
+        // drop the frames that no future output reaches anymore
+        let consumed = (self.position as usize + 1).saturating_sub(self.half_taps);
+        self.history.drain(..consumed * self.channels);
+        self.position -= consumed as f64;
+    }
+
+    /// Pushes silence through, so the end of the input comes out too.
+    fn flush(&mut self, output: &mut Vec<Sample>) {
+        let silence = vec![0.0; self.half_taps * self.channels];
+        self.process(&silence, output);
+        self.reset();
+    }
+
+    fn interpolate_coefficients(&mut self, fraction: f64) {
+        let phase = fraction * self.phases as f64;
+        let lower = phase as usize;
+        let weight = (phase - lower as f64) as f32;
+
+        let taps = 2 * self.half_taps;
+        let lower_row = &self.filter[lower * taps..(lower + 1) * taps];
+        let upper_row = &self.filter[(lower + 1) * taps..(lower + 2) * taps];
+
+        for ((coefficient, lower), upper) in
+            self.coefficients.iter_mut().zip(lower_row).zip(upper_row)
+        {
+            *coefficient = lower + (upper - lower) * weight;
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Blackman window over `-1.0..=1.0`.
+fn blackman(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        return 0.0;
+    }
+
+    0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(block: &AudioBlock, sample_rate: SampleRate, channels: ChannelCount) -> Vec<Sample> {
+        let output = OutputFormat {
+            sample_rate,
+            channels,
+        };
+        let mut converter = FormatConverter::new(output, ResamplerQuality::default());
+
+        let mut samples = Vec::new();
+        converter.process(block, &mut samples);
+        converter.flush(&mut samples);
+        samples
+    }
+
+    fn sine(frequency: f64, sample_rate: SampleRate, frames: usize) -> AudioBlock {
+        AudioBlock {
+            samples: (0..frames)
+                .map(|n| (2.0 * PI * frequency * n as f64 / sample_rate as f64).sin() as Sample)
+                .collect(),
+            sample_rate,
+            channel_count: 1,
+        }
+    }
+
+    #[test]
+    fn same_format_is_unchanged() {
+        let block = AudioBlock {
+            samples: vec![0.5, -0.25, 1.0, -1.0, 0.0, 0.125],
+            sample_rate: 48000,
+            channel_count: 2,
+        };
+
+        assert_eq!(convert(&block, 48000, 2), block.samples);
+    }
+
+    #[test]
+    fn mono_is_upmixed_to_both_sides() {
+        let block = AudioBlock {
+            samples: vec![0.5, -0.25, 1.0],
+            sample_rate: 48000,
+            channel_count: 1,
+        };
+
+        assert_eq!(
+            convert(&block, 48000, 2),
+            vec![0.5, 0.5, -0.25, -0.25, 1.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn stereo_is_downmixed_to_the_average() {
+        let block = AudioBlock {
+            samples: vec![1.0, 0.0, 0.5, 0.5, -1.0, 1.0],
+            sample_rate: 48000,
+            channel_count: 2,
+        };
+
+        assert_eq!(convert(&block, 48000, 1), vec![0.5, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn surround_downmix_does_not_clip() {
+        let block = AudioBlock {
+            samples: vec![1.0; 6],
+            sample_rate: 48000,
+            channel_count: 6,
+        };
+
+        for sample in convert(&block, 48000, 2) {
+            assert!(sample <= 1.0 + 1e-6, "{sample}");
+        }
+    }
+
+    #[test]
+    fn upsampling_keeps_length_and_frequency() {
+        let output = convert(&sine(1000.0, 44100, 44100), 48000, 1);
+        // the position is accumulated in floating point, so the last frame might go either way
+        assert!(output.len().abs_diff(48000) <= 1, "{} frames", output.len());
+
+        // leave out the edges, where the filter runs into silence
+        let middle = &output[1000..47000];
+
+        // 1kHz crosses zero twice per millisecond
+        let crossings = middle
+            .windows(2)
+            .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+            .count();
+        let expected = 2 * middle.len() / 48;
+        assert!(crossings.abs_diff(expected) <= 2, "{crossings} crossings");
+
+        let peak = middle
+            .iter()
+            .fold(0.0, |peak: Sample, sample| peak.max(sample.abs()));
+        assert!((peak - 1.0).abs() < 0.01, "peak {peak}");
+    }
+}
@@ -0,0 +1,120 @@
+use super::{PlaybackState, persistence};
+use hogehoge_db::{Database, DbError, TrackProgress};
+use hogehoge_types::{Tags, UniqueTrackIdentifier};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::watch;
+use tracing::*;
+
+pub const DEFAULT_RESUME_THRESHOLD: Duration = Duration::from_secs(20 * 60);
+
+// outros and credits don't need to be heard for a track to count as finished
+const FINISHED_BEFORE_END: Duration = Duration::from_secs(30);
+const PROGRESS_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+struct SavedProgress {
+    track: Arc<UniqueTrackIdentifier>,
+    progress: TrackProgress,
+    paused: bool,
+}
+
+/// Podcasts and tracks longer than `threshold` remember where they were left off. A zero
+/// `threshold` only remembers the position of podcasts.
+pub(super) fn remembers_position(
+    tags: Option<&Tags>,
+    duration: Option<Duration>,
+    threshold: Duration,
+) -> bool {
+    let long = !threshold.is_zero() && duration.is_some_and(|duration| duration > threshold);
+
+    long || tags.is_some_and(persistence::is_podcast)
+}
+
+/// Where to continue `track`, `None` if it starts from the beginning.
+pub(super) async fn load_resume_position(
+    db: &Database,
+    track: &UniqueTrackIdentifier,
+) -> Result<Option<Duration>, DbError> {
+    let progress = db.load_track_progress(track).await?;
+
+    Ok(progress
+        .filter(|progress| !progress.finished && !progress.position.is_zero())
+        .map(|progress| progress.position))
+}
+
+fn is_finished(position: Duration, duration: Option<Duration>) -> bool {
+    // short tracks would be finished right from the start otherwise
+    duration.is_some_and(|duration| {
+        duration.saturating_sub(position) <= FINISHED_BEFORE_END.min(duration / 10)
+    })
+}
+
+/// Saves how far tracks that remember their position got, every few seconds while they play and
+/// once more when they end or another track starts.
+pub(super) async fn save_track_progress_on_change(
+    mut state_rx: watch::Receiver<PlaybackState>,
+    db: Database,
+) {
+    let mut saved: Option<SavedProgress> = None;
+    let mut latest: Option<SavedProgress> = None;
+
+    while state_rx.changed().await.is_ok() {
+        let current = match &*state_rx.borrow_and_update() {
+            PlaybackState::Playing {
+                track,
+                remembers_position: true,
+                position,
+                duration,
+                paused,
+                ..
+            } => Some(SavedProgress {
+                track: track.clone(),
+                progress: TrackProgress {
+                    position: *position,
+                    finished: is_finished(*position, *duration),
+                },
+                paused: *paused,
+            }),
+            _ => None,
+        };
+
+        // the track that was left keeps the position it got to since it was last saved
+        let left = latest.take_if(|latest| {
+            current
+                .as_ref()
+                .is_none_or(|current| current.track != latest.track)
+        });
+        if let Some(left) = left {
+            save(&db, left, &mut saved).await;
+        }
+
+        let Some(current) = current else {
+            continue;
+        };
+
+        let should_save = match &saved {
+            Some(saved) if saved.track == current.track => {
+                let (last, new) = (saved.progress, current.progress);
+
+                current.paused != saved.paused
+                    || new.finished != last.finished
+                    || new.position < last.position
+                    || new.position - last.position >= PROGRESS_SAVE_INTERVAL
+            }
+            _ => true,
+        };
+
+        latest = Some(current.clone());
+        if should_save {
+            save(&db, current, &mut saved).await;
+        }
+    }
+}
+
+async fn save(db: &Database, progress: SavedProgress, saved: &mut Option<SavedProgress>) {
+    let result = db.save_track_progress(&progress.track, progress.progress);
+    match result.await {
+        Ok(()) => *saved = Some(progress),
+        Err(e) => warn!("Failed to save progress of {:?}: {}", progress.track, e),
+    }
+}
@@ -0,0 +1,212 @@
+use super::{AudioPlayer, PlayerCommand, QueueCurrentSource, QueueSource, VOLUME_RAMP};
+use hogehoge_types::UniqueTrackIdentifier;
+use rodio::Source;
+use std::time::Duration;
+use tracing::*;
+
+// long enough to not notice it getting quieter when falling asleep
+const SLEEP_FADE: Duration = Duration::from_secs(30);
+
+/// When the sleep timer stops playback. Waiting for a track that is then skipped or taken out of
+/// the queue cancels the timer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SleepTimer {
+    /// Counts down while playing, pausing also pauses the timer.
+    After(Duration),
+    /// Once the current track ends.
+    EndOfTrack,
+    /// Once the last track of the current album that is queued right after it ends.
+    EndOfAlbum,
+}
+
+/// How long the sleep timer still has to go.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SleepState {
+    Remaining(Duration),
+    /// Waiting for a track that hasn't started yet, or that has no known length, to end.
+    AfterTrack,
+}
+
+/// The sleep timer as the audio thread keeps track of it.
+#[derive(Debug, Clone)]
+pub(super) enum SleepDeadline {
+    After(Duration),
+    EndOf(UniqueTrackIdentifier),
+}
+
+impl AudioPlayer {
+    /// Fades out and stops playback once `timer` runs out, replacing any timer that was set
+    /// before.
+    pub fn set_sleep_timer(&self, timer: SleepTimer) {
+        match timer {
+            SleepTimer::After(duration) => {
+                self.send_command(PlayerCommand::SetSleepTimer(Some(SleepDeadline::After(
+                    duration,
+                ))));
+            }
+            SleepTimer::EndOfTrack => {
+                if let Some(track) = self.queue.get_current_track() {
+                    self.send_command(PlayerCommand::SetSleepTimer(Some(SleepDeadline::EndOf(
+                        track,
+                    ))));
+                }
+            }
+            SleepTimer::EndOfAlbum => {
+                let player = self.clone();
+                tokio::spawn(async move {
+                    if let Some(track) = player.last_track_of_album().await {
+                        player.send_command(PlayerCommand::SetSleepTimer(Some(
+                            SleepDeadline::EndOf(track),
+                        )));
+                    }
+                });
+            }
+        }
+    }
+
+    pub fn cancel_sleep_timer(&self) {
+        self.send_command(PlayerCommand::SetSleepTimer(None));
+    }
+
+    /// The last of the tracks from the album of the current track that are queued in a row,
+    /// starting with the current one. Without an album that's just the current track.
+    async fn last_track_of_album(&self) -> Option<UniqueTrackIdentifier> {
+        let items = self.queue.items();
+        let current = items.current?;
+
+        let album_of = |track: UniqueTrackIdentifier| async move {
+            match self.db.get_track_by_identifier(&track).await {
+                Ok(info) => info.and_then(|info| info.album_id),
+                Err(e) => {
+                    warn!("Failed to look up album of {:?}: {}", track, e);
+                    None
+                }
+            }
+        };
+
+        let Some(album) = album_of(current.clone()).await else {
+            return Some(current);
+        };
+
+        let mut last = current;
+        for track in items.future {
+            if album_of(track.clone()).await != Some(album) {
+                break;
+            }
+            last = track;
+        }
+
+        Some(last)
+    }
+}
+
+impl QueueSource {
+    pub(super) fn set_sleep_timer(&mut self, deadline: Option<SleepDeadline>) {
+        info!("Sleep timer set to {:?}", deadline);
+
+        self.sleep = deadline;
+        self.sleep_samples = 0;
+        self.update_sleep_timer();
+    }
+
+    /// Time until the sleep timer stops playback, if it's known yet.
+    fn sleep_remaining(&self) -> Option<Duration> {
+        match self.sleep.as_ref()? {
+            SleepDeadline::After(duration) => {
+                let elapsed = self.sleep_samples as f64
+                    / (self.format.sample_rate as f64 * self.format.channels as f64);
+                Some(duration.saturating_sub(Duration::from_secs_f64(elapsed)))
+            }
+            SleepDeadline::EndOf(track) => match &self.current_playing {
+                QueueCurrentSource::Source(current, loaded) if current == track => {
+                    let source = &loaded.source;
+                    let remaining = source
+                        .total_duration()?
+                        .saturating_sub(source.playback_position());
+
+                    Some(remaining.div_f32(source.speed()))
+                }
+                _ => None,
+            },
+        }
+    }
+
+    pub(super) fn sleep_state(&self) -> Option<SleepState> {
+        if self.sleep.is_none() {
+            return None;
+        }
+
+        Some(match self.sleep_remaining() {
+            Some(remaining) => SleepState::Remaining(remaining),
+            None => SleepState::AfterTrack,
+        })
+    }
+
+    /// Whether the sleep timer ends with the current track, so it shouldn't crossfade into the
+    /// next one.
+    pub(super) fn sleeps_after_current(&self) -> bool {
+        matches!(
+            (&self.sleep, self.current_playing.track()),
+            (Some(SleepDeadline::EndOf(track)), Some(current)) if track == current
+        )
+    }
+
+    /// Fades out towards the end of the sleep timer and stops once it ran out.
+    pub(super) fn update_sleep_timer(&mut self) {
+        let remaining = self.sleep_remaining();
+
+        if matches!(self.sleep, Some(SleepDeadline::After(_)))
+            && remaining.is_some_and(|r| r.is_zero())
+        {
+            self.fall_asleep();
+            return;
+        }
+
+        match remaining {
+            // paused tracks don't get any closer to their end
+            Some(remaining) if remaining <= SLEEP_FADE && !self.paused => {
+                if self.sleep_gain.target() == 1.0 {
+                    self.sleep_gain.ramp_to(0.0, self.samples_for(remaining));
+                }
+            }
+            _ => {
+                if self.sleep_gain.target() < 1.0 {
+                    self.sleep_gain.ramp_to(1.0, self.samples_for(VOLUME_RAMP));
+                }
+            }
+        }
+    }
+
+    /// Cancels a timer that waits for a track that was skipped or taken out of the queue, it
+    /// would never go off otherwise.
+    pub(super) fn cancel_missed_sleep_timer(&mut self) {
+        let Some(SleepDeadline::EndOf(track)) = &self.sleep else {
+            return;
+        };
+
+        if self.current_playing.track() != Some(track) && !self.queue.is_upcoming(track) {
+            info!("Track of the sleep timer won't be played anymore, cancelling it");
+            self.sleep = None;
+        }
+    }
+
+    /// Called when the current track ends, returns whether playback stopped instead of moving on
+    /// to the next track.
+    pub(super) fn sleep_after_track(&mut self) -> bool {
+        let asleep = self.sleeps_after_current();
+        if asleep {
+            self.fall_asleep();
+        }
+
+        asleep
+    }
+
+    /// Stops like [`AudioPlayer::stop`], the queue stays where it is.
+    fn fall_asleep(&mut self) {
+        info!("Sleep timer ran out, stopping");
+
+        // already faded out, so there is no need to fade for stopping as well
+        self.stop();
+        self.sleep_gain.jump_to(1.0);
+    }
+}
@@ -0,0 +1,434 @@
+use super::decoder::DecodedSource;
+use hogehoge_types::{ChannelCount, Sample, SampleRate};
+use rodio::Source;
+use std::time::Duration;
+
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 3.0;
+
+// in seconds, long enough to hold a few periods of a low voice
+const WINDOW: f64 = 0.03;
+// in seconds, how far a segment may be moved to line up with the previous one
+const TOLERANCE: f64 = 0.01;
+
+/// A track played at a different speed without changing its pitch.
+///
+/// Stretching only starts once the speed is first changed, until then the audio is passed
+/// through untouched.
+pub struct StretchedSource {
+    source: DecodedSource,
+    speed: f32,
+    stretcher: Option<Stretcher>,
+}
+
+/// Time stretching by waveform similarity overlap-add (WSOLA).
+///
+/// The output is made of overlapping windowed segments of the input. Each segment is taken from
+/// around where the speed says the input should be, moved by up to [`TOLERANCE`] so it lines up
+/// best with how the previous segment would have continued.
+struct Stretcher {
+    channels: usize,
+    sample_rate: SampleRate,
+    // in frames, half of the window
+    half: usize,
+    tolerance: usize,
+    // rising half of the window, the falling half is `1.0 - rising`
+    rising: Vec<f32>,
+
+    input: Vec<Sample>,
+    // in frames relative to the start of `input`: where the next segment should come from and
+    // where the last one was taken from
+    analysis: f64,
+    previous: usize,
+    // the second half of the previous segment, already windowed
+    overlap: Vec<Sample>,
+    started: bool,
+    ended: bool,
+
+    output: Vec<Sample>,
+    output_position: usize,
+
+    // scratch space for finding the best segment
+    mono: Vec<f32>,
+    natural: Vec<f32>,
+    energy: Vec<f32>,
+}
+
+impl StretchedSource {
+    pub fn new(source: DecodedSource, speed: f32) -> Self {
+        let mut stretched = StretchedSource {
+            source,
+            speed: 1.0,
+            stretcher: None,
+        };
+        stretched.set_speed(speed);
+
+        stretched
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Must only be called at a frame boundary.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+
+        if self.speed != 1.0 && self.stretcher.is_none() {
+            self.stretcher = Some(Stretcher::new(
+                self.source.channels(),
+                self.source.sample_rate(),
+            ));
+        }
+    }
+
+    /// Position in the track, what is buffered for stretching doesn't count as played yet.
+    pub fn playback_position(&self) -> Duration {
+        let position = self.source.playback_position();
+
+        match &self.stretcher {
+            Some(stretcher) => position.saturating_sub(stretcher.buffered()),
+            None => position,
+        }
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.source.underruns()
+    }
+
+    pub fn is_seekable(&self) -> bool {
+        self.source.is_seekable()
+    }
+
+    /// Must only be called at a frame boundary.
+    pub fn seek(&mut self, position: Duration) {
+        self.source.seek(position);
+
+        // what was buffered is from before the seek
+        self.stretcher = None;
+        self.set_speed(self.speed);
+    }
+}
+
+impl Iterator for StretchedSource {
+    type Item = Sample;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.stretcher {
+            Some(stretcher) => stretcher.next(&mut self.source, self.speed),
+            None => self.source.next(),
+        }
+    }
+}
+
+impl Source for StretchedSource {
+    // the format never changes
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.source.sample_rate()
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.source.channels()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+impl std::fmt::Debug for StretchedSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StretchedSource")
+            .field("source", &self.source)
+            .field("speed", &self.speed)
+            .field("stretching", &self.stretcher.is_some())
+            .finish()
+    }
+}
+
+impl Stretcher {
+    fn new(channels: ChannelCount, sample_rate: SampleRate) -> Self {
+        let channels = channels.max(1) as usize;
+        let half = ((WINDOW * sample_rate as f64) as usize / 2).max(1);
+        let tolerance = (TOLERANCE * sample_rate as f64) as usize;
+
+        let rising = (0..half)
+            .map(|i| {
+                let x = (i as f64 + 0.5) / half as f64 * std::f64::consts::FRAC_PI_2;
+                x.sin().powi(2) as f32
+            })
+            .collect();
+
+        Stretcher {
+            channels,
+            sample_rate,
+            half,
+            tolerance,
+            rising,
+            input: Vec::new(),
+            analysis: 0.0,
+            previous: 0,
+            overlap: vec![0.0; half * channels],
+            started: false,
+            ended: false,
+            output: Vec::with_capacity(half * channels),
+            output_position: 0,
+            mono: Vec::new(),
+            natural: Vec::new(),
+            energy: Vec::new(),
+        }
+    }
+
+    /// How much of the input that was already pulled hasn't been played yet.
+    fn buffered(&self) -> Duration {
+        let playing = self.previous * self.channels + self.output_position;
+        let buffered = self.input.len().saturating_sub(playing) / self.channels;
+
+        Duration::from_secs_f64(buffered as f64 / self.sample_rate as f64)
+    }
+
+    #[inline]
+    fn next(&mut self, source: &mut impl Iterator<Item = Sample>, speed: f32) -> Option<Sample> {
+        if self.output_position == self.output.len() {
+            self.output.clear();
+            self.output_position = 0;
+
+            if !self.fill(source, speed) {
+                return None;
+            }
+        }
+
+        let sample = self.output[self.output_position];
+        self.output_position += 1;
+
+        Some(sample)
+    }
+
+    /// Pulls input until there are `frames`, returns `false` if the source ended before that.
+    fn pull(&mut self, source: &mut impl Iterator<Item = Sample>, frames: usize) -> bool {
+        while self.input.len() < frames * self.channels {
+            if self.ended {
+                return false;
+            }
+
+            let Some(first) = source.next() else {
+                self.ended = true;
+                return false;
+            };
+
+            self.input.push(first);
+            // sources only ever end after a whole frame
+            for _ in 1..self.channels {
+                self.input.push(source.next().unwrap_or(0.0));
+            }
+        }
+
+        true
+    }
+
+    /// Produces the next half window of output, returns `false` once everything was played.
+    fn fill(&mut self, source: &mut impl Iterator<Item = Sample>, speed: f32) -> bool {
+        let (half, channels) = (self.half, self.channels);
+
+        if !self.started {
+            self.started = true;
+
+            // the first segment starts out unwindowed, so switching from the unstretched audio
+            // to stretching doesn't leave a dip
+            if self.pull(source, 2 * half) {
+                self.output
+                    .extend_from_slice(&self.input[..half * channels]);
+                self.set_overlap(0);
+                self.previous = 0;
+                self.analysis = half as f64 * speed as f64;
+                return true;
+            }
+
+            self.output.extend_from_slice(&self.input);
+            self.input.clear();
+            return !self.output.is_empty();
+        }
+
+        let natural = self.previous + half;
+        let target = self.analysis.round() as usize;
+        let needed = (natural + half).max(target + self.tolerance + 2 * half);
+
+        if !self.pull(source, needed) {
+            // the rest is played as it is, starting where the previous segment fades out
+            let start = (natural * channels).min(self.input.len());
+            self.output.extend_from_slice(&self.input[start..]);
+            self.input.clear();
+            self.previous = 0;
+            self.analysis = 0.0;
+            return !self.output.is_empty();
+        }
+
+        let chosen = if target == natural {
+            natural
+        } else {
+            self.best_segment(natural, target)
+        };
+
+        for i in 0..half {
+            let rising = self.rising[i];
+            for channel in 0..channels {
+                let sample = self.input[(chosen + i) * channels + channel];
+                self.output
+                    .push(self.overlap[i * channels + channel] + sample * rising);
+            }
+        }
+        self.set_overlap(chosen);
+
+        self.previous = chosen;
+        self.analysis += half as f64 * speed as f64;
+
+        // everything before the next possible segment isn't needed anymore, the natural
+        // continuation always comes after the previous segment
+        let consumed = (self.analysis.floor() as usize)
+            .saturating_sub(self.tolerance)
+            .min(self.previous);
+        if consumed > 0 {
+            self.input.drain(..consumed * channels);
+            self.previous -= consumed;
+            self.analysis -= consumed as f64;
+        }
+
+        true
+    }
+
+    /// Windows the second half of the segment starting at `start` for the next overlap.
+    fn set_overlap(&mut self, start: usize) {
+        let (half, channels) = (self.half, self.channels);
+
+        for i in 0..half {
+            let falling = 1.0 - self.rising[i];
+            for channel in 0..channels {
+                self.overlap[i * channels + channel] =
+                    self.input[(start + half + i) * channels + channel] * falling;
+            }
+        }
+    }
+
+    /// The start of the segment around `target` that best matches the natural continuation of the
+    /// previous segment at `natural`, by normalized cross correlation of the mono downmix.
+    fn best_segment(&mut self, natural: usize, target: usize) -> usize {
+        let (half, channels) = (self.half, self.channels);
+        let low = target.saturating_sub(self.tolerance);
+        let high = target + self.tolerance;
+
+        let downmix = |input: &[Sample], frame: usize| -> f32 {
+            input[frame * channels..(frame + 1) * channels].iter().sum()
+        };
+
+        self.natural.clear();
+        self.natural
+            .extend((0..half).map(|i| downmix(&self.input, natural + i)));
+
+        self.mono.clear();
+        self.mono
+            .extend((low..high + half).map(|frame| downmix(&self.input, frame)));
+
+        // every second frame is enough to line up the waveforms and halves the work. the energy
+        // of each candidate comes from sums of squares running in steps of two.
+        const STEP: usize = 2;
+        let terms = half.div_ceil(STEP);
+
+        self.energy.clear();
+        for (i, sample) in self.mono.iter().enumerate() {
+            let before = if i >= STEP {
+                self.energy[i - STEP]
+            } else {
+                0.0
+            };
+            self.energy.push(before + sample * sample);
+        }
+
+        let mut best = (target, f32::MIN);
+        for offset in 0..=high - low {
+            let last = offset + (terms - 1) * STEP;
+            let energy = self.energy[last]
+                - if offset >= STEP {
+                    self.energy[offset - STEP]
+                } else {
+                    0.0
+                };
+
+            let correlation: f32 = self.mono[offset..]
+                .iter()
+                .step_by(STEP)
+                .zip(self.natural.iter().step_by(STEP))
+                .map(|(a, b)| a * b)
+                .sum();
+
+            let score = correlation / energy.max(1e-9).sqrt();
+            if score > best.1 {
+                best = (low + offset, score);
+            }
+        }
+
+        best.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: SampleRate = 48000;
+
+    fn stereo_tone(seconds: f64) -> Vec<Sample> {
+        let frames = (seconds * SAMPLE_RATE as f64) as usize;
+        (0..frames)
+            .flat_map(|frame| {
+                let t = frame as f32 / SAMPLE_RATE as f32;
+                let left = (2.0 * std::f32::consts::PI * 220.0 * t).sin();
+                let right = (2.0 * std::f32::consts::PI * 330.0 * t).sin();
+                [0.5 * left, 0.5 * right]
+            })
+            .collect()
+    }
+
+    fn stretch(input: &[Sample], speed: f32) -> Vec<Sample> {
+        let mut stretcher = Stretcher::new(2, SAMPLE_RATE);
+        let mut source = input.iter().copied();
+        std::iter::from_fn(|| stretcher.next(&mut source, speed)).collect()
+    }
+
+    #[test]
+    fn unity_speed_is_unchanged() {
+        let input = stereo_tone(1.0);
+        let output = stretch(&input, 1.0);
+
+        assert_eq!(output.len(), input.len());
+        for (index, (output, input)) in output.iter().zip(&input).enumerate() {
+            assert!(
+                (output - input).abs() < 1e-5,
+                "sample {index}: {output} != {input}"
+            );
+        }
+    }
+
+    #[test]
+    fn length_follows_speed() {
+        let input = stereo_tone(4.0);
+        // the end is played as it is, which can make up to a few windows
+        let slack = ((TOLERANCE + 2.0 * WINDOW) * SAMPLE_RATE as f64) as usize;
+
+        for speed in [0.5, 0.8, 1.5, 2.0, MAX_SPEED] {
+            let output = stretch(&input, speed);
+            assert_eq!(output.len() % 2, 0);
+
+            let frames = output.len() / 2;
+            let expected = (input.len() / 2) as f64 / speed as f64;
+            assert!(
+                (frames as f64 - expected).abs() <= slack as f64,
+                "{frames} frames at {speed}x, expected about {expected}"
+            );
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use super::LoadedTrack;
+use hogehoge_db::SavedTransitions;
+use hogehoge_types::Sample;
+use std::{f32::consts::FRAC_PI_2, time::Duration};
+
+/// How tracks blend into each other. A zero duration turns the respective fade off.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TransitionSettings {
+    /// Overlap between a track that ends and the next one. Never applied between consecutive
+    /// tracks of the same album, so gapless albums stay gapless.
+    pub crossfade: Duration,
+    /// Fade out and back in when skipping to another track.
+    pub skip_fade: Duration,
+    /// Fade out when pausing and back in when resuming.
+    pub pause_fade: Duration,
+}
+
+/// A gain that moves linearly towards its target.
+#[derive(Debug, Clone, Copy)]
+pub struct Ramp {
+    value: f32,
+    target: f32,
+    step: f32,
+    remaining: usize,
+}
+
+/// The end of the previous track, mixed into the start of the current one.
+pub struct Crossfade {
+    outgoing: LoadedTrack,
+    outgoing_gain: f32,
+    length: usize,
+    elapsed: usize,
+    outgoing_ended: bool,
+}
+
+impl TransitionSettings {
+    pub fn from_saved(saved: SavedTransitions) -> Self {
+        TransitionSettings {
+            crossfade: saved.crossfade,
+            skip_fade: saved.skip_fade,
+            pause_fade: saved.pause_fade,
+        }
+    }
+
+    pub fn to_saved(self) -> SavedTransitions {
+        SavedTransitions {
+            crossfade: self.crossfade,
+            skip_fade: self.skip_fade,
+            pause_fade: self.pause_fade,
+        }
+    }
+}
+
+impl Ramp {
+    pub fn new(value: f32) -> Self {
+        Ramp {
+            value,
+            target: value,
+            step: 0.0,
+            remaining: 0,
+        }
+    }
+
+    pub fn ramp_to(&mut self, target: f32, samples: usize) {
+        let samples = samples.max(1);
+
+        self.target = target;
+        self.step = (target - self.value) / samples as f32;
+        self.remaining = samples;
+    }
+
+    pub fn jump_to(&mut self, target: f32) {
+        *self = Ramp::new(target);
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.remaining == 0
+    }
+
+    #[inline]
+    pub fn next(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.value = if self.remaining == 0 {
+                self.target
+            } else {
+                self.value + self.step
+            };
+        }
+
+        self.value
+    }
+}
+
+impl Crossfade {
+    /// `outgoing_gain` is the ReplayGain of the outgoing track, `length` is in samples.
+    pub fn new(outgoing: LoadedTrack, outgoing_gain: f32, length: usize) -> Self {
+        Crossfade {
+            outgoing,
+            outgoing_gain,
+            length: length.max(1),
+            elapsed: 0,
+            outgoing_ended: false,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.outgoing_ended || self.elapsed >= self.length
+    }
+
+    /// Mixes the next sample of the outgoing track into `incoming`. Equal power, so the loudness
+    /// stays about the same throughout the fade.
+    #[inline]
+    pub fn mix(&mut self, incoming: Sample) -> Sample {
+        let progress = (self.elapsed as f32 / self.length as f32).min(1.0) * FRAC_PI_2;
+        self.elapsed += 1;
+
+        let outgoing = match self.outgoing.source.next() {
+            Some(sample) => sample * self.outgoing_gain,
+            None => {
+                self.outgoing_ended = true;
+                0.0
+            }
+        };
+
+        incoming * progress.sin() + outgoing * progress.cos()
+    }
+}
@@ -0,0 +1,299 @@
+use super::output::OutputFormat;
+use crate::plugin::PluginSystem;
+use hogehoge_types::Sample;
+pub use hogehoge_types::VisualizerFrame;
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::{
+    f32::consts::PI,
+    sync::{
+        Arc, Weak,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+use tokio::{sync::watch, task};
+use tracing::*;
+
+// frames the spectrum is computed from, about 46ms at 44.1kHz
+const FFT_SIZE: usize = 2048;
+// frames that are averaged into a single point of the waveform
+const WAVEFORM_DECIMATION: usize = 4;
+// about 60 updates per second
+const UPDATE_INTERVAL: Duration = Duration::from_millis(16);
+// in dBFS, quieter bins are reported as this
+const SPECTRUM_FLOOR: f32 = -120.0;
+
+/// The audio thread side of the visualizer. Frames are handed to a worker that does the analysis,
+/// the tap itself never blocks and only does anything while somebody is subscribed.
+pub struct VisualizerTap {
+    samples: Producer<Sample>,
+    listening: Arc<AtomicBool>,
+}
+
+struct VisualizerWorker {
+    samples: Consumer<Sample>,
+    listening: Arc<AtomicBool>,
+    frame_tx: Weak<watch::Sender<VisualizerFrame>>,
+    sample_rate: f32,
+
+    // the last `FFT_SIZE` mono frames, as a ring that starts at `history_position`
+    history: Vec<Sample>,
+    history_position: usize,
+    // how many of the most recent samples in `history` are silent
+    silence: usize,
+    ordered: Vec<Sample>,
+    fft: Fft,
+}
+
+/// Radix-2 FFT of `FFT_SIZE` real samples, which get a Hann window applied first.
+struct Fft {
+    window: Vec<f32>,
+    // scales the magnitudes so a full scale sine ends up at 0dB
+    scale: f32,
+    // e^(-2πik/N) for the first half of k
+    twiddles: Vec<(f32, f32)>,
+    bit_reversed: Vec<usize>,
+    buffer: Vec<(f32, f32)>,
+}
+
+impl VisualizerTap {
+    /// Starts a worker that publishes what the tap is fed to `frame_tx`, it stops once `frame_tx`
+    /// is dropped.
+    pub fn spawn(format: OutputFormat, frame_tx: Weak<watch::Sender<VisualizerFrame>>) -> Self {
+        // a quarter of a second gets the worker through any hiccups
+        let capacity = (format.sample_rate as usize / 4).max(FFT_SIZE);
+        let (samples_tx, samples_rx) = RingBuffer::new(capacity);
+        let listening = Arc::new(AtomicBool::new(false));
+
+        let worker = VisualizerWorker {
+            samples: samples_rx,
+            listening: listening.clone(),
+            frame_tx,
+            sample_rate: format.sample_rate as f32,
+            history: vec![0.0; FFT_SIZE],
+            history_position: 0,
+            silence: FFT_SIZE,
+            ordered: Vec::with_capacity(FFT_SIZE),
+            fft: Fft::new(FFT_SIZE),
+        };
+
+        let parent_span = Span::current();
+        thread::Builder::new()
+            .name("visualizer".to_string())
+            .spawn(move || {
+                let _span = info_span!(parent: &parent_span, "visualizer_worker").entered();
+                worker.run();
+            })
+            .expect("Failed to spawn visualizer thread");
+
+        VisualizerTap {
+            samples: samples_tx,
+            listening,
+        }
+    }
+
+    #[inline]
+    pub fn push(&mut self, frame: &[Sample]) {
+        if !self.listening.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mono = frame.iter().sum::<Sample>() / frame.len() as Sample;
+        // a frame the worker had no room for is simply missing from the visualization
+        let _ = self.samples.push(mono);
+    }
+}
+
+impl VisualizerWorker {
+    fn run(mut self) {
+        loop {
+            thread::sleep(UPDATE_INTERVAL);
+
+            let Some(frame_tx) = self.frame_tx.upgrade() else {
+                break;
+            };
+
+            let listening = frame_tx.receiver_count() > 0;
+            let started = !self.listening.swap(listening, Ordering::Relaxed);
+
+            let changed = self.take_samples();
+            if listening {
+                // nothing new is played while paused, so subscribers aren't woken up for that
+                frame_tx.send_if_modified(|frame| {
+                    if changed || started {
+                        self.analyze(frame);
+                    }
+                    changed || started
+                });
+            }
+        }
+
+        debug!("Visualizer worker stopped");
+    }
+
+    /// Returns whether the history changed, which it doesn't if only more silence came in.
+    fn take_samples(&mut self) -> bool {
+        let available = self.samples.slots();
+        let Ok(chunk) = self.samples.read_chunk(available) else {
+            return false;
+        };
+
+        let was_silent = self.silence == FFT_SIZE;
+        for sample in chunk {
+            self.history[self.history_position] = sample;
+            self.history_position = (self.history_position + 1) % FFT_SIZE;
+            self.silence = if sample == 0.0 {
+                (self.silence + 1).min(FFT_SIZE)
+            } else {
+                0
+            };
+        }
+
+        available > 0 && !(was_silent && self.silence == FFT_SIZE)
+    }
+
+    /// Fills in `frame` without allocating once it has the right size.
+    fn analyze(&mut self, frame: &mut VisualizerFrame) {
+        let (older, newer) = self.history.split_at(self.history_position);
+        self.ordered.clear();
+        self.ordered.extend_from_slice(newer);
+        self.ordered.extend_from_slice(older);
+
+        frame.waveform.clear();
+        frame.waveform.extend(
+            self.ordered
+                .chunks(WAVEFORM_DECIMATION)
+                .map(|chunk| chunk.iter().sum::<Sample>() / chunk.len() as Sample),
+        );
+
+        self.fft.spectrum(&self.ordered, &mut frame.spectrum);
+        frame.bin_width = self.sample_rate / FFT_SIZE as f32;
+    }
+}
+
+/// Hands every frame to the plugins that visualize, until the player is gone. Frames that come in
+/// while the plugins are still busy with the previous one are skipped.
+pub async fn forward_to_plugins(
+    plugins: PluginSystem,
+    mut frame_rx: watch::Receiver<VisualizerFrame>,
+) {
+    while frame_rx.changed().await.is_ok() {
+        let frame = frame_rx.borrow_and_update().clone();
+        let plugins = plugins.clone();
+
+        let forwarded = task::spawn_blocking(move || {
+            for pool in plugins.plugins.values() {
+                if !pool.capabilities.visualize {
+                    continue;
+                }
+
+                let result = pool
+                    .get_plugin()
+                    .and_then(|mut plugin| plugin.visualize(frame.clone()));
+                if let Err(e) = result {
+                    trace!("Plugin '{}' failed to visualize: {}", pool.metadata.name, e);
+                }
+            }
+        })
+        .await;
+
+        if let Err(e) = forwarded {
+            error!("Failed to forward visualizer frame to plugins: {}", e);
+        }
+    }
+}
+
+impl Fft {
+    fn new(size: usize) -> Self {
+        debug_assert!(size.is_power_of_two());
+
+        let window = (0..size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos())
+            .collect::<Vec<_>>();
+        let scale = 2.0 / window.iter().sum::<f32>();
+
+        let twiddles = (0..size / 2)
+            .map(|k| {
+                let angle = -2.0 * PI * k as f32 / size as f32;
+                (angle.cos(), angle.sin())
+            })
+            .collect();
+
+        let bits = size.trailing_zeros();
+        let bit_reversed = (0..size)
+            .map(|i| i.reverse_bits() >> (usize::BITS - bits))
+            .collect();
+
+        Fft {
+            window,
+            scale,
+            twiddles,
+            bit_reversed,
+            buffer: vec![(0.0, 0.0); size],
+        }
+    }
+
+    /// Magnitudes in dBFS of the bins up to half the sample rate.
+    fn spectrum(&mut self, input: &[Sample], magnitudes: &mut Vec<f32>) {
+        let size = self.buffer.len();
+
+        for (i, sample) in input.iter().enumerate() {
+            self.buffer[self.bit_reversed[i]] = (sample * self.window[i], 0.0);
+        }
+
+        let mut length = 2;
+        while length <= size {
+            let half = length / 2;
+            let stride = size / length;
+
+            for start in (0..size).step_by(length) {
+                for k in 0..half {
+                    let (wr, wi) = self.twiddles[k * stride];
+                    let (ar, ai) = self.buffer[start + k];
+                    let (br, bi) = self.buffer[start + k + half];
+
+                    let (tr, ti) = (br * wr - bi * wi, br * wi + bi * wr);
+                    self.buffer[start + k] = (ar + tr, ai + ti);
+                    self.buffer[start + k + half] = (ar - tr, ai - ti);
+                }
+            }
+
+            length *= 2;
+        }
+
+        magnitudes.clear();
+        magnitudes.extend(self.buffer[..size / 2].iter().map(|(re, im)| {
+            let magnitude = (re * re + im * im).sqrt() * self.scale;
+            (20.0 * magnitude.log10()).max(SPECTRUM_FLOOR)
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_scale_sine_is_0_dbfs() {
+        const BIN: usize = 93;
+
+        let mut fft = Fft::new(FFT_SIZE);
+        let input = (0..FFT_SIZE)
+            .map(|i| (2.0 * PI * BIN as f32 * i as f32 / FFT_SIZE as f32).sin())
+            .collect::<Vec<_>>();
+
+        let mut magnitudes = Vec::new();
+        fft.spectrum(&input, &mut magnitudes);
+
+        assert_eq!(magnitudes.len(), FFT_SIZE / 2);
+        assert!(magnitudes[BIN].abs() < 0.1, "{} dBFS", magnitudes[BIN]);
+
+        let loudest = magnitudes
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(bin, _)| bin);
+        assert_eq!(loudest, Some(BIN));
+    }
+}
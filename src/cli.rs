@@ -0,0 +1,214 @@
+use crate::Args;
+use crate::audio::{self, RenderError};
+use crate::plugin::{PluginMountError, PluginSystem, PluginSystemError};
+use clap::{Args as ClapArgs, Subcommand};
+use hogehoge_db::{Database, DbError, PluginMountId};
+use nu_ansi_term::{Color, Style};
+use std::{path::PathBuf, time::Duration};
+use thiserror::Error;
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Manage the host directories that are mounted into plugins
+    #[command(subcommand)]
+    Mounts(MountsCommand),
+    /// Render the queue or an album into a WAV or FLAC file, as fast as possible
+    Render {
+        /// File to write, the format is picked by its extension
+        output: PathBuf,
+        #[command(flatten)]
+        source: RenderSource,
+        /// Sample rate of the file, defaults to the configured output sample rate
+        #[arg(long)]
+        sample_rate: Option<u32>,
+    },
+}
+
+#[derive(Debug, Clone, ClapArgs)]
+#[group(required = true, multiple = false)]
+pub struct RenderSource {
+    /// Render the current track and everything queued after it
+    #[arg(long)]
+    queue: bool,
+    /// Render an album, by title or ID
+    #[arg(long)]
+    album: Option<String>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum MountsCommand {
+    /// List the mounts declared by each plugin and the directories configured for them
+    List,
+    /// Mount a host directory into a plugin
+    Add {
+        /// Name or UUID of the plugin
+        plugin: String,
+        /// Path of the mount as declared by the plugin, e.g. /music
+        internal_path: String,
+        host_path: PathBuf,
+    },
+    /// Remove a configured mount by its ID
+    Remove { id: i64 },
+}
+
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error("Failed to open database: {0}")]
+    DatabaseError(#[from] DbError),
+    #[error("Failed to initialize plugin system: {0}")]
+    PluginSystemError(#[from] PluginSystemError),
+
+    #[error("No plugin named '{0}' is loaded")]
+    UnknownPlugin(String),
+    #[error("No mount with ID {0} exists")]
+    UnknownMount(i64),
+    #[error("{0}")]
+    MountError(#[from] PluginMountError),
+
+    #[error("No album with the title or ID '{0}' exists")]
+    UnknownAlbum(String),
+    #[error("'{0}' matches multiple albums, use one of their IDs instead: {1}")]
+    AmbiguousAlbum(String, String),
+    #[error("Nothing to render")]
+    NothingToRender,
+    #[error("{0}")]
+    RenderError(#[from] RenderError),
+}
+
+pub async fn run(args: &Args, command: Command) -> Result<(), CliError> {
+    let db = Database::connect_headless(&args.db_path).await?;
+    let plugin_system = PluginSystem::initialize(args.plugin_dir.clone(), db.clone()).await?;
+
+    match command {
+        Command::Mounts(command) => run_mounts(&plugin_system, command).await,
+        Command::Render {
+            output,
+            source,
+            sample_rate,
+        } => run_render(plugin_system, db, output, source, sample_rate).await,
+    }
+}
+
+async fn run_render(
+    plugin_system: PluginSystem,
+    db: Database,
+    output: PathBuf,
+    source: RenderSource,
+    sample_rate: Option<u32>,
+) -> Result<(), CliError> {
+    let tracks = match source.album {
+        Some(album) => {
+            let albums = db.find_albums(&album).await?;
+            match albums.as_slice() {
+                [] => return Err(CliError::UnknownAlbum(album)),
+                [(album_id, _)] => db.get_album_tracks(*album_id).await?,
+                _ => {
+                    let candidates = albums
+                        .iter()
+                        .map(|(album_id, title)| format!("{} ({})", album_id.0, title))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    return Err(CliError::AmbiguousAlbum(album, candidates));
+                }
+            }
+        }
+        None => {
+            let queue = db.load_queue().await?;
+            queue.current.into_iter().chain(queue.future).collect()
+        }
+    };
+
+    if tracks.is_empty() {
+        return Err(CliError::NothingToRender);
+    }
+
+    let summary = audio::render(plugin_system, db, tracks, &output, sample_rate).await?;
+
+    let speed = summary.duration.as_secs_f64() / summary.elapsed.as_secs_f64().max(f64::EPSILON);
+    println!(
+        "{}",
+        Color::Green.paint(format!(
+            "Rendered {} of audio to {} in {:.1}s ({:.0}x real time)",
+            format_duration(summary.duration),
+            output.display(),
+            summary.elapsed.as_secs_f64(),
+            speed
+        ))
+    );
+
+    Ok(())
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+async fn run_mounts(plugin_system: &PluginSystem, command: MountsCommand) -> Result<(), CliError> {
+    match command {
+        MountsCommand::List => {
+            let bold = Style::new().bold();
+            let dimmed = Style::new().dimmed();
+
+            for pool in plugin_system.plugins.values() {
+                println!(
+                    "{} {}",
+                    bold.paint(&pool.metadata.name),
+                    dimmed.paint(pool.metadata.uuid.to_string())
+                );
+
+                if pool.metadata.fs_mounts.is_empty() {
+                    println!("  {}", dimmed.paint("no filesystem access"));
+                }
+
+                let mounts = pool.mounts();
+                for declared in &pool.metadata.fs_mounts {
+                    println!(
+                        "  {} {}",
+                        Color::Blue.paint(&declared.internal_path),
+                        dimmed.paint(format!("({})", declared.description))
+                    );
+
+                    for mount in mounts
+                        .iter()
+                        .filter(|mount| mount.internal_path == declared.internal_path)
+                    {
+                        println!("    [{}] {}", mount.id.0, mount.host_path.display());
+                    }
+                }
+            }
+        }
+        MountsCommand::Add {
+            plugin,
+            internal_path,
+            host_path,
+        } => {
+            let (plugin_id, _) = plugin_system
+                .find_plugin(&plugin)
+                .ok_or_else(|| CliError::UnknownPlugin(plugin.clone()))?;
+
+            let mount_id = plugin_system
+                .add_mount(plugin_id, &internal_path, &host_path)
+                .await?;
+
+            println!(
+                "{}",
+                Color::Green.paint(format!("Added mount with ID {}", mount_id.0))
+            );
+        }
+        MountsCommand::Remove { id } => {
+            if !plugin_system.remove_mount(PluginMountId(id)).await? {
+                return Err(CliError::UnknownMount(id));
+            }
+
+            println!("{}", Color::Green.paint(format!("Removed mount {}", id)));
+        }
+    }
+
+    Ok(())
+}
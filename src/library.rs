@@ -7,24 +7,33 @@ use freya::prelude::{Signal, SyncStorage};
 use hogehoge_db::{Database, DbStats};
 use hogehoge_types::{ScanResult, UniqueTrackIdentifier};
 use rayon::{ThreadPool, prelude::*};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 use tracing::*;
 
 use crate::plugin::PluginSystem;
 use crate::ui::notifications::*;
 
+mod loudness;
+
 #[derive(Debug, Clone)]
 pub struct Library {
     thread_pool: Arc<ThreadPool>,
     plugin_system: PluginSystem,
-    import_queue: mpsc::Sender<(UniqueTrackIdentifier, ScanResult)>,
+    import_queue: mpsc::Sender<Import>,
     db: Database,
 }
 
+#[derive(Debug)]
+enum Import {
+    Track(UniqueTrackIdentifier, ScanResult),
+    /// Answered once everything queued before it has been imported.
+    Flush(oneshot::Sender<()>),
+}
+
 // since bulk inserting cannot be done in parallel on a sqlite database, use a separate worker
 #[derive(Debug)]
 struct LibraryImportWorker {
-    import_rx: mpsc::Receiver<(UniqueTrackIdentifier, ScanResult)>,
+    import_rx: mpsc::Receiver<Import>,
     db: Database,
 }
 
@@ -61,7 +70,7 @@ impl Library {
         }
     }
 
-    pub fn stats(&self) -> Signal<DbStats, SyncStorage> {
+    pub fn stats(&self) -> Option<Signal<DbStats, SyncStorage>> {
         self.db.stats()
     }
 
@@ -77,9 +86,11 @@ impl Library {
         info!("Starting music scan...");
         let parent_span = Span::current();
 
+        let library = self.clone();
         let import_queue = self.import_queue.clone();
         let thread_pool = self.thread_pool.clone();
         let plugin_system = self.plugin_system.clone();
+        let runtime = tokio::runtime::Handle::current();
 
         thread_pool.spawn(move || {
             let _span = parent_span.enter();
@@ -94,9 +105,11 @@ impl Library {
                     let _span = info_span!(parent: &parent_span, "prepare_scan").entered();
                     debug!("Preparing scan for plugin '{}'", pool.metadata.name);
 
-                    let mut plugin = pool.get_plugin();
+                    let prepared = pool
+                        .get_plugin()
+                        .and_then(|mut plugin| plugin.prepare_scan());
 
-                    let result = match plugin.prepare_scan() {
+                    let result = match prepared {
                         Ok(prepared_scan) => {
                             debug!("Prepared scan for plugin '{}'", pool.metadata.name);
                             Some((*id, prepared_scan))
@@ -138,9 +151,11 @@ impl Library {
                     prepared_scan.tracks.into_par_iter().for_each(|track| {
                         let _span = parent_span.enter();
 
-                        let mut plugin = plugin_system.get_plugin(id).expect("Plugin not found");
+                        let result = plugin_system
+                            .get_plugin(id)
+                            .and_then(|mut plugin| plugin.scan(&track));
 
-                        match plugin.scan(&track) {
+                        match result {
                             Ok(result) => {
                                 let identifier = UniqueTrackIdentifier {
                                     plugin_id: id,
@@ -148,7 +163,7 @@ impl Library {
                                 };
 
                                 import_queue
-                                    .blocking_send((identifier, result))
+                                    .blocking_send(Import::Track(identifier, result))
                                     .unwrap_or_else(|e| {
                                         error!("Failed to send scan result to import queue: {}", e);
                                     });
@@ -171,9 +186,19 @@ impl Library {
                     });
                 });
 
+            // the analysis needs the scanned tracks to be in the database
+            let (flushed_tx, flushed_rx) = oneshot::channel();
+            if import_queue
+                .blocking_send(Import::Flush(flushed_tx))
+                .is_ok()
+            {
+                flushed_rx.blocking_recv().ok();
+            }
+
             info!("Music scan completed.");
 
-            notification_handle.complete();
+            let _runtime = runtime.enter();
+            library.spawn_loudness_analysis(false, notification_handle);
         });
 
         notification
@@ -183,8 +208,13 @@ impl Library {
 impl LibraryImportWorker {
     #[tracing::instrument(skip(self))]
     pub async fn run(mut self) {
-        while let Some((identifier, scan)) = self.import_rx.recv().await {
-            self.process_scan(scan, identifier).await;
+        while let Some(import) = self.import_rx.recv().await {
+            match import {
+                Import::Track(identifier, scan) => self.process_scan(scan, identifier).await,
+                Import::Flush(flushed) => {
+                    flushed.send(()).ok();
+                }
+            }
         }
     }
 
@@ -0,0 +1,235 @@
+use super::Library;
+use crate::audio::{PluginAudioSource, PluginAudioSourceError};
+use crate::plugin::{PluginError, PluginSystem};
+use crate::ui::notifications::*;
+use hogehoge_db::{Database, Loudness, TrackToAnalyze};
+use hogehoge_types::{AlbumId, TrackId, UniqueTrackIdentifier, WriteReplayGainArgs};
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tracing::*;
+
+mod meter;
+use meter::{LoudnessMeter, Measurement};
+
+#[derive(Debug, Error)]
+enum AnalysisError {
+    #[error("Failed to open track: {0}")]
+    SourceError(#[from] PluginAudioSourceError),
+    #[error("Failed to decode track: {0}")]
+    PluginError(#[from] PluginError),
+}
+
+#[derive(Debug)]
+enum AnalysisResult {
+    Track(TrackId, Loudness),
+    Album(AlbumId, Loudness),
+}
+
+impl Library {
+    /// Measures the loudness of every track without ReplayGain tags, so playback can normalize
+    /// them too. With `write_back`, the results are also written to the tracks through the plugin
+    /// that provides them, if it supports that.
+    pub fn analyze_loudness(&self, write_back: bool) -> Notification {
+        let (notification, notification_handle) = Notification::new_progress("Loudness Analysis");
+        self.spawn_loudness_analysis(write_back, notification_handle);
+
+        notification
+    }
+
+    /// Starts the analysis in the background, reporting its progress through `notification_handle`.
+    #[instrument(skip(self, notification_handle))]
+    pub(super) fn spawn_loudness_analysis(
+        &self,
+        write_back: bool,
+        notification_handle: ProgressNotificationHandle,
+    ) {
+        notification_handle.modify_state(|state| {
+            state.progress = 0.0;
+            state.message = "Looking for tracks to analyze...".into();
+        });
+
+        let parent_span = Span::current();
+
+        let db = self.db.clone();
+        let thread_pool = self.thread_pool.clone();
+        let plugin_system = self.plugin_system.clone();
+
+        let (result_tx, result_rx) = mpsc::channel(128);
+        tokio::spawn(save_results(result_rx, db.clone()));
+
+        tokio::spawn(async move {
+            let tracks = match db.get_tracks_to_analyze().await {
+                Ok(tracks) => tracks,
+                Err(e) => {
+                    error!("Failed to get tracks to analyze: {}", e);
+                    notification_handle.modify_state(|state| {
+                        state.message = format!("Failed to get tracks: {}", e).into();
+                    });
+                    notification_handle.complete();
+                    return;
+                }
+            };
+
+            let tracks_count = tracks.len();
+            info!("Found {} tracks to analyze", tracks_count);
+
+            let albums = group_by_album(tracks);
+            let tracks_analyzed = AtomicUsize::new(0);
+
+            thread_pool.spawn(move || {
+                let _span = parent_span.enter();
+
+                albums.into_par_iter().for_each(|album| {
+                    analyze_album(&plugin_system, album, write_back, &result_tx, || {
+                        let analyzed = tracks_analyzed.fetch_add(1, Ordering::Relaxed) + 1;
+
+                        notification_handle.modify_state(|state| {
+                            state.progress = analyzed as f32 / tracks_count as f32 * 100.0;
+                            state.message =
+                                format!("Analyzing tracks... ({}/{})", analyzed, tracks_count)
+                                    .into();
+                        });
+                    });
+                });
+
+                info!("Loudness analysis completed.");
+
+                notification_handle.complete();
+            });
+        });
+    }
+}
+
+// tracks come sorted by album, tracks without one are analyzed on their own
+fn group_by_album(tracks: Vec<TrackToAnalyze>) -> Vec<Vec<TrackToAnalyze>> {
+    let mut albums: Vec<Vec<TrackToAnalyze>> = Vec::new();
+
+    for track in tracks {
+        match albums.last_mut() {
+            Some(album) if track.album_id.is_some() && album[0].album_id == track.album_id => {
+                album.push(track)
+            }
+            _ => albums.push(vec![track]),
+        }
+    }
+
+    albums
+}
+
+fn analyze_album(
+    plugin_system: &PluginSystem,
+    album: Vec<TrackToAnalyze>,
+    write_back: bool,
+    result_tx: &mpsc::Sender<AnalysisResult>,
+    on_track_done: impl Fn() + Sync,
+) {
+    let measurements = album
+        .par_iter()
+        .map(|track| {
+            let measurement = measure_track(plugin_system, &track.identifier);
+            on_track_done();
+
+            measurement
+                .inspect_err(|e| warn!("Failed to analyze track {:?}: {}", track.identifier, e))
+                .ok()
+        })
+        .collect::<Vec<_>>();
+
+    let send = |result| {
+        result_tx.blocking_send(result).unwrap_or_else(|e| {
+            error!("Failed to send loudness analysis result: {}", e);
+        });
+    };
+
+    // a partial album would give the wrong album loudness
+    let album_loudness = album[0].album_id.and_then(|album_id| {
+        let measurements = measurements
+            .iter()
+            .map(Option::as_ref)
+            .collect::<Option<Vec<_>>>()?;
+        let loudness = Measurement::combine(measurements).loudness()?;
+
+        send(AnalysisResult::Album(album_id, loudness));
+        Some(loudness)
+    });
+
+    for (track, measurement) in album.iter().zip(&measurements) {
+        let Some(loudness) = measurement.as_ref().and_then(Measurement::loudness) else {
+            continue;
+        };
+
+        send(AnalysisResult::Track(track.track_id, loudness));
+
+        if write_back && !track.tagged {
+            write_replay_gain(plugin_system, track, loudness, album_loudness);
+        }
+    }
+}
+
+fn measure_track(
+    plugin_system: &PluginSystem,
+    track: &UniqueTrackIdentifier,
+) -> Result<Measurement, AnalysisError> {
+    let mut source = PluginAudioSource::from_track_identifier(plugin_system, track.clone())?;
+    let mut meter = LoudnessMeter::new();
+
+    while let Some(block) = source.decode_block()? {
+        meter.process(&block);
+    }
+
+    Ok(meter.finish())
+}
+
+fn write_replay_gain(
+    plugin_system: &PluginSystem,
+    track: &TrackToAnalyze,
+    loudness: Loudness,
+    album_loudness: Option<Loudness>,
+) {
+    let mut plugin = match plugin_system.get_plugin(track.identifier.plugin_id) {
+        Ok(plugin) => plugin,
+        Err(e) => {
+            warn!("Can't write ReplayGain for {:?}: {}", track.identifier, e);
+            return;
+        }
+    };
+
+    if !plugin.capabilities().write_replay_gain {
+        trace!("Plugin for {:?} can't write ReplayGain", track.identifier);
+        return;
+    }
+
+    let args = WriteReplayGainArgs {
+        track: track.identifier.plugin_data.clone(),
+        track_gain_db: loudness.replay_gain_db(),
+        track_peak: loudness.true_peak,
+        album_gain_db: album_loudness.map(|loudness| loudness.replay_gain_db()),
+        album_peak: album_loudness.map(|loudness| loudness.true_peak),
+    };
+
+    if let Err(e) = plugin.write_replay_gain(args) {
+        warn!(
+            "Failed to write ReplayGain for {:?}: {}",
+            track.identifier, e
+        );
+    }
+}
+
+async fn save_results(mut result_rx: mpsc::Receiver<AnalysisResult>, db: Database) {
+    while let Some(result) = result_rx.recv().await {
+        let saved = match result {
+            AnalysisResult::Track(track_id, loudness) => {
+                db.save_track_loudness(track_id, loudness).await
+            }
+            AnalysisResult::Album(album_id, loudness) => {
+                db.save_album_loudness(album_id, loudness).await
+            }
+        };
+
+        if let Err(e) = saved {
+            warn!("Failed to save loudness analysis result: {}", e);
+        }
+    }
+}
@@ -0,0 +1,327 @@
+//! Loudness measurement as specified by ITU-R BS.1770 / EBU R128.
+
+use hogehoge_db::Loudness;
+use hogehoge_types::AudioBlock;
+use std::f64::consts::PI;
+
+// gating blocks are 400ms long and start every 100ms
+const SUBBLOCKS_PER_BLOCK: usize = 4;
+const SUBBLOCKS_PER_SECOND: u32 = 10;
+
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+/// Measures a single track. Blocks can be fed in any format, even changing mid-track.
+pub struct LoudnessMeter {
+    channels: Vec<ChannelState>,
+    sample_rate: u32,
+    interpolation: Vec<[f64; TAPS_PER_PHASE]>,
+
+    subblock_len: usize,
+    subblock_remaining: usize,
+    subblock_energy: f64,
+    recent_subblocks: [f64; SUBBLOCKS_PER_BLOCK],
+    subblock_count: usize,
+
+    measurement: Measurement,
+}
+
+/// Gating blocks and peak of one or more tracks.
+#[derive(Debug, Clone, Default)]
+pub struct Measurement {
+    /// Mean square of each gating block, weighted across channels.
+    block_energies: Vec<f64>,
+    true_peak: f64,
+}
+
+struct ChannelState {
+    weight: f64,
+    shelf: Biquad,
+    highpass: Biquad,
+    history: [f64; TAPS_PER_PHASE],
+}
+
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    state: [f64; 2],
+}
+
+impl LoudnessMeter {
+    pub fn new() -> Self {
+        LoudnessMeter {
+            channels: Vec::new(),
+            sample_rate: 0,
+            interpolation: interpolation_filter(),
+            subblock_len: 0,
+            subblock_remaining: 0,
+            subblock_energy: 0.0,
+            recent_subblocks: [0.0; SUBBLOCKS_PER_BLOCK],
+            subblock_count: 0,
+            measurement: Measurement::default(),
+        }
+    }
+
+    pub fn process(&mut self, block: &AudioBlock) {
+        if block.sample_rate == 0 || block.channel_count == 0 {
+            return;
+        }
+
+        if block.sample_rate != self.sample_rate
+            || block.channel_count as usize != self.channels.len()
+        {
+            self.reset(block.sample_rate, block.channel_count as usize);
+        }
+
+        for frame in block.samples.chunks_exact(self.channels.len()) {
+            let mut energy = 0.0;
+            for (channel, &sample) in self.channels.iter_mut().zip(frame) {
+                let sample = sample as f64;
+
+                let weighted = channel.highpass.process(channel.shelf.process(sample));
+                energy += channel.weight * weighted * weighted;
+
+                channel.history.copy_within(..TAPS_PER_PHASE - 1, 1);
+                channel.history[0] = sample;
+                for phase in &self.interpolation {
+                    let interpolated = phase
+                        .iter()
+                        .zip(&channel.history)
+                        .map(|(tap, sample)| tap * sample)
+                        .sum::<f64>();
+                    self.measurement.true_peak = self.measurement.true_peak.max(interpolated.abs());
+                }
+            }
+
+            self.subblock_energy += energy;
+            self.subblock_remaining -= 1;
+            if self.subblock_remaining == 0 {
+                self.finish_subblock();
+            }
+        }
+    }
+
+    pub fn finish(self) -> Measurement {
+        self.measurement
+    }
+
+    fn reset(&mut self, sample_rate: u32, channel_count: usize) {
+        let (shelf, highpass) = k_weighting(sample_rate as f64);
+
+        self.channels = (0..channel_count)
+            .map(|index| ChannelState {
+                weight: channel_weight(index, channel_count),
+                shelf,
+                highpass,
+                history: [0.0; TAPS_PER_PHASE],
+            })
+            .collect();
+
+        self.sample_rate = sample_rate;
+        self.subblock_len = (sample_rate / SUBBLOCKS_PER_SECOND).max(1) as usize;
+        self.subblock_remaining = self.subblock_len;
+        self.subblock_energy = 0.0;
+        // blocks can't span a format change
+        self.subblock_count = 0;
+    }
+
+    fn finish_subblock(&mut self) {
+        self.recent_subblocks[self.subblock_count % SUBBLOCKS_PER_BLOCK] =
+            self.subblock_energy / self.subblock_len as f64;
+        self.subblock_count += 1;
+        self.subblock_energy = 0.0;
+        self.subblock_remaining = self.subblock_len;
+
+        if self.subblock_count >= SUBBLOCKS_PER_BLOCK {
+            let block_energy =
+                self.recent_subblocks.iter().sum::<f64>() / SUBBLOCKS_PER_BLOCK as f64;
+            self.measurement.block_energies.push(block_energy);
+        }
+    }
+}
+
+impl Measurement {
+    /// Combines the measurements of several tracks as if they were played back to back.
+    pub fn combine<'a>(measurements: impl IntoIterator<Item = &'a Measurement>) -> Measurement {
+        let mut combined = Measurement::default();
+        for measurement in measurements {
+            combined
+                .block_energies
+                .extend_from_slice(&measurement.block_energies);
+            combined.true_peak = combined.true_peak.max(measurement.true_peak);
+        }
+
+        combined
+    }
+
+    /// `None` if the audio was too short or too quiet to be measured.
+    pub fn loudness(&self) -> Option<Loudness> {
+        let above_absolute = self
+            .block_energies
+            .iter()
+            .copied()
+            .filter(|energy| energy_to_lufs(*energy) > ABSOLUTE_GATE_LUFS)
+            .collect::<Vec<_>>();
+        if above_absolute.is_empty() {
+            return None;
+        }
+
+        let relative_gate = energy_to_lufs(mean(&above_absolute)) + RELATIVE_GATE_LU;
+        let above_relative = above_absolute
+            .into_iter()
+            .filter(|energy| energy_to_lufs(*energy) > relative_gate)
+            .collect::<Vec<_>>();
+
+        Some(Loudness {
+            integrated_lufs: energy_to_lufs(mean(&above_relative)) as f32,
+            true_peak: self.true_peak as f32,
+        })
+    }
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Biquad {
+            b,
+            a,
+            state: [0.0; 2],
+        }
+    }
+
+    // transposed direct form II
+    #[inline]
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.state[0];
+        self.state[0] = self.b[1] * input - self.a[0] * output + self.state[1];
+        self.state[1] = self.b[2] * input - self.a[1] * output;
+        output
+    }
+}
+
+/// The two filter stages of the K-weighting curve, for any sample rate.
+fn k_weighting(sample_rate: f64) -> (Biquad, Biquad) {
+    // high shelf that models the acoustic effect of the head
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+
+    let k = (PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+
+    let shelf = Biquad::new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    // the RLB high pass
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+
+    let k = (PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+
+    let highpass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    (shelf, highpass)
+}
+
+/// Surround channels count a bit more, the LFE channel is ignored.
+fn channel_weight(index: usize, channel_count: usize) -> f64 {
+    match (channel_count, index) {
+        (6, 3) => 0.0,
+        (5, 3..) | (6, 4..) => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// Windowed sinc low pass, split into one set of taps per interpolated phase.
+fn interpolation_filter() -> Vec<[f64; TAPS_PER_PHASE]> {
+    let length = OVERSAMPLING * TAPS_PER_PHASE;
+    let center = (length - 1) as f64 / 2.0;
+
+    (0..OVERSAMPLING)
+        .map(|phase| {
+            std::array::from_fn(|tap| {
+                let n = (tap * OVERSAMPLING + phase) as f64;
+                let x = (n - center) / OVERSAMPLING as f64;
+
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                let window = 0.5 - 0.5 * (2.0 * PI * n / (length - 1) as f64).cos();
+
+                sinc * window
+            })
+        })
+        .collect()
+}
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, amplitude_db: f64, seconds: u32, sample_rate: u32) -> AudioBlock {
+        let amplitude = 10f64.powf(amplitude_db / 20.0);
+        let samples = (0..sample_rate * seconds)
+            .flat_map(|n| {
+                let sample =
+                    amplitude * (2.0 * PI * frequency * n as f64 / sample_rate as f64).sin();
+                [sample as f32; 2]
+            })
+            .collect();
+
+        AudioBlock {
+            samples,
+            sample_rate,
+            channel_count: 2,
+        }
+    }
+
+    // EBU Tech 3341, test case 1
+    #[test]
+    fn reference_sine() {
+        for sample_rate in [44100, 48000] {
+            let mut meter = LoudnessMeter::new();
+            meter.process(&sine(1000.0, -23.0, 20, sample_rate));
+            let loudness = meter.finish().loudness().unwrap();
+
+            assert!(
+                (loudness.integrated_lufs + 23.0).abs() <= 0.1,
+                "{} LUFS at {} Hz",
+                loudness.integrated_lufs,
+                sample_rate
+            );
+            assert!((20.0 * loudness.true_peak.log10() + 23.0).abs() <= 0.1);
+        }
+    }
+
+    #[test]
+    fn silence_is_not_measured() {
+        let mut meter = LoudnessMeter::new();
+        meter.process(&sine(1000.0, -90.0, 5, 48000));
+        assert!(meter.finish().loudness().is_none());
+    }
+}
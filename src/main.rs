@@ -1,23 +1,28 @@
 use clap::Parser;
 use hogehoge_db::{Database, DbStats};
 use std::path::PathBuf;
-use tokio::task;
+use tokio::{sync::broadcast, task};
 
 mod library;
 use library::Library;
 
 mod audio;
 mod queue;
-use audio::AudioPlayer;
+use audio::{AudioPlayer, OutputBackend, OutputSettings};
 
 mod plugin;
 use plugin::PluginSystem;
 
 mod logging;
 
+mod cli;
+
 mod ui;
 use ui::*;
 
+#[cfg(test)]
+mod testing;
+
 #[derive(Debug, Clone, Parser)]
 struct Args {
     #[clap(long, short, default_value = "2hoge.db")]
@@ -26,6 +31,12 @@ struct Args {
     plugin_dir: PathBuf,
     #[arg(long, short, default_value = "./themes")]
     theme_dir: PathBuf,
+    /// Where to play audio: 'device', 'null' or 'file:<path>' to record into a WAV file
+    #[arg(long, default_value = "device")]
+    output: OutputBackend,
+
+    #[command(subcommand)]
+    command: Option<cli::Command>,
 }
 
 fn main() {
@@ -40,6 +51,14 @@ fn main() {
         .unwrap();
     let _guard = rt.enter();
 
+    if let Some(command) = args.command.clone() {
+        if let Err(e) = rt.block_on(cli::run(&args, command)) {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     launch_cfg(
         app,
         LaunchConfig::new().with_title("2hoge").with_state(args),
@@ -51,7 +70,7 @@ fn app() -> Element {
         StatusBar {},
         PlayerBar {},
         rect {
-            height: "calc(100% - 16 - 32)", // status + player bar height
+            height: "calc(100% - 16 - 40)", // status + player bar height
             width: "100%",
             direction: "horizontal",
             SideBar {},
@@ -96,6 +115,9 @@ fn AppWrapper(children: Element) -> Element {
 fn ContextProvider(children: Element) -> Element {
     let args = use_context::<Args>();
 
+    use_context_provider(|| Signal::new(MainView::default()));
+    let notifications = use_notification_provider();
+
     let db_stats = use_signal_sync(DbStats::default);
     let db = use_resource_provider("Database", move || {
         let db_path = args.db_path.clone();
@@ -112,13 +134,40 @@ fn ContextProvider(children: Element) -> Element {
     .suspend()?;
 
     let db_clone = db.clone();
+    let notifications_clone = notifications.clone();
     let plugin_system = use_resource_provider("Plugin System", move || {
         let plugin_dir = args.plugin_dir.clone();
         let db_clone = db_clone.peek().clone();
+        let notifications = notifications_clone.clone();
         async move {
-            PluginSystem::initialize(plugin_dir, db_clone)
+            let plugin_system = PluginSystem::initialize(plugin_dir, db_clone)
                 .await
-                .expect("Failed to initialize plugin system")
+                .expect("Failed to initialize plugin system");
+
+            for failed in plugin_system.failed.iter() {
+                notifications.add(Notification::new(
+                    "Plugin not loaded",
+                    format!("{}: {}", failed.file, failed.error),
+                ));
+            }
+
+            let mut plugin_notifications = plugin_system.subscribe_notifications();
+            spawn(async move {
+                loop {
+                    match plugin_notifications.recv().await {
+                        Ok(notification) => notifications.add(Notification::new(
+                            format!("{}: {}", notification.plugin, notification.title),
+                            notification.message,
+                        )),
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            tracing::warn!("Dropped {} plugin notifications", missed);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            });
+
+            plugin_system
         }
     })
     .suspend()?;
@@ -131,17 +180,100 @@ fn ContextProvider(children: Element) -> Element {
         async move { Library::new(db, plugin_system).await }
     });
 
+    let db_clone = db.clone();
     let plugin_system_clone = plugin_system.clone();
     use_resource_provider("Player", move || {
+        let db = db_clone.peek().clone();
         let plugin_system = plugin_system_clone.peek().clone();
-        async {
-            task::spawn_blocking(|| AudioPlayer::new(plugin_system))
+        let backend = args.output.clone();
+        async move {
+            // the output is opened with these, so they can't be restored later like the rest
+            let output = db
+                .load_output()
                 .await
-                .unwrap()
+                .map(OutputSettings::from_saved)
+                .unwrap_or_else(|e| {
+                    tracing::error!("Failed to load output settings: {}", e);
+                    OutputSettings::default()
+                });
+            let device = db.load_output_device().await.unwrap_or_else(|e| {
+                tracing::error!("Failed to load output device: {}", e);
+                None
+            });
+
+            let (player, output_error) = task::spawn_blocking(move || {
+                match AudioPlayer::new(
+                    plugin_system.clone(),
+                    db.clone(),
+                    &backend,
+                    output,
+                    device.clone(),
+                ) {
+                    Ok(player) => (player, None),
+                    Err(e) => {
+                        tracing::error!(
+                            "Failed to open {:?} audio output, playing into a null output: {}",
+                            backend,
+                            e
+                        );
+                        // only outputs that write to a file can fail to open
+                        let player = AudioPlayer::new(
+                            plugin_system,
+                            db,
+                            &OutputBackend::Null,
+                            output,
+                            device,
+                        )
+                        .expect("Null output can't fail to open");
+                        (player, Some(e))
+                    }
+                }
+            })
+            .await
+            .unwrap();
+
+            if let Some(e) = output_error {
+                notifications.add(Notification::new(
+                    "Audio output unavailable",
+                    format!("{}, nothing will be played", e),
+                ));
+            }
+
+            let mut device_state = player.subscribe_output_device();
+            let device_notifications = notifications.clone();
+            spawn(async move {
+                loop {
+                    let missing = device_state.borrow_and_update().missing.clone();
+                    if let Some(missing) = missing {
+                        device_notifications.add(Notification::new(
+                            "Output device unavailable",
+                            format!(
+                                "{} can't be used, playing on the default device instead",
+                                missing
+                            ),
+                        ));
+                    }
+
+                    if device_state.changed().await.is_err() {
+                        break;
+                    }
+                }
+            });
+
+            let dropped = player.restore_saved_state().await;
+            if !dropped.is_empty() {
+                notifications.add(Notification::new(
+                    "Queue restored",
+                    format!(
+                        "{} tracks were removed from the queue because their plugin is no longer loaded",
+                        dropped.len()
+                    ),
+                ));
+            }
+
+            player
         }
     });
 
-    use_notification_provider();
-
     children
 }
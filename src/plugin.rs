@@ -1,22 +1,54 @@
-use extism::{Manifest, Plugin as LoadedPlugin, PluginBuilder};
-use hogehoge_db::Database;
+use extism::{Manifest, Plugin as LoadedPlugin, PluginBuilder, Wasm};
+use hogehoge_db::{Database, DbError, PluginMount, PluginMountId, SavedPluginLimits};
 use hogehoge_types::{
-    audio::{AudioBlock, AudioFile, PlaybackId},
+    audio::{
+        AudioBlock, AudioFile, ChannelCount, EffectId, PlaybackId, SampleRate, VisualizerFrame,
+    },
+    host::HostNotification,
     plugin::*,
 };
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     ffi::OsStr,
     path::{Path, PathBuf},
-    sync::{Arc, Condvar, Mutex},
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
 };
 use thiserror::Error;
-use tokio::time;
+use tokio::{
+    sync::{broadcast, mpsc, watch},
+    time,
+};
 use tracing::*;
 
+mod host;
+pub use host::PluginNotification;
+use host::{HostContext, HostServices};
+
+// faults in a row after which a plugin is quarantined
+const QUARANTINE_AFTER: usize = 3;
+// settings are changed with sliders, so wait for a bit before saving
+const SETTINGS_SAVE_DELAY: Duration = Duration::from_secs(1);
+
 #[derive(Debug, Clone)]
 pub struct PluginSystem {
     pub plugins: Arc<HashMap<PluginId, Arc<PluginPool>>>,
+    /// Plugins found in the plugin directory that couldn't be loaded.
+    pub failed: Arc<Vec<FailedPlugin>>,
+    db: Database,
+    host: HostServices,
+    // keys of settings that changed since they were last saved
+    settings_save_tx: mpsc::UnboundedSender<(PluginId, String)>,
+}
+
+/// A plugin that was skipped when initializing the plugin system.
+#[derive(Debug, Clone)]
+pub struct FailedPlugin {
+    pub file: String,
+    pub error: String,
 }
 
 #[derive(Debug, Error)]
@@ -25,40 +57,160 @@ pub enum PluginSystemError {
     InvalidDirectory(PathBuf),
 }
 
+#[derive(Debug, Error)]
+pub enum PluginMountError {
+    #[error("No plugin with ID {0:?} is loaded")]
+    UnknownPlugin(PluginId),
+    #[error("Plugin does not declare a mount at '{0}'")]
+    UndeclaredMount(String),
+    #[error("Host path is not a directory: {0}")]
+    InvalidHostPath(PathBuf),
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] DbError),
+}
+
+#[derive(Debug, Error)]
+pub enum PluginLimitsError {
+    #[error("No plugin with ID {0:?} is loaded")]
+    UnknownPlugin(PluginId),
+    #[error("Plugins need at least some memory and time to run")]
+    InvalidLimits,
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] DbError),
+}
+
+#[derive(Debug, Error)]
+pub enum PluginSettingError {
+    #[error("No plugin with ID {0:?} is loaded")]
+    UnknownPlugin(PluginId),
+    #[error("Plugin does not declare a setting '{0}'")]
+    UndeclaredSetting(String),
+    #[error("Invalid value for setting '{0}': {1}")]
+    InvalidValue(String, String),
+}
+
 #[derive(Debug)]
 pub struct PluginPool {
     pub metadata: PluginMetadata,
     pub capabilities: PluginCapabilities,
 
     plugin_path: PathBuf,
-    plugins: Mutex<VecDeque<Plugin>>,
+    host: HostContext,
+    mounts: Mutex<Vec<PluginMount>>,
+    limits: Mutex<PluginLimits>,
+    settings: Mutex<PluginSettings>,
+    // idle instances, with the settings version they were configured with
+    plugins: Mutex<VecDeque<(Plugin, usize)>>,
+    faults: Arc<FaultTracker>,
+    // bumped whenever the mounts or limits change so instances built with the old manifest get
+    // discarded
+    generation: AtomicUsize,
+    // bumped whenever the settings change so handles that are held on to can reconfigure
+    settings_version: AtomicUsize,
     wait_condvar: Condvar,
 }
 
 #[derive(Debug)]
-pub struct Plugin(LoadedPlugin);
+pub struct Plugin {
+    inner: LoadedPlugin,
+    faults: Arc<FaultTracker>,
+    // calls that fail after this long ran into the timeout
+    timeout: Duration,
+    // the state of an instance that trapped can't be trusted anymore, so it isn't reused
+    trapped: bool,
+}
+
+/// How a plugin has been behaving. Faults are traps, timeouts and instances that failed to load,
+/// errors the plugin returns itself don't count.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PluginHealth {
+    pub faults: usize,
+    /// Faults since the last call that went through.
+    pub consecutive_faults: usize,
+    pub last_fault: Option<String>,
+    /// Quarantined plugins aren't called anymore until they are released.
+    pub quarantined: bool,
+}
+
+/// What a single instance of a plugin may use before it is stopped, which counts as a fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PluginLimits {
+    /// In 64KiB pages.
+    pub memory_pages: u32,
+    /// For a single call, plugins that take longer are assumed to be stuck.
+    pub timeout: Duration,
+}
+
+impl Default for PluginLimits {
+    fn default() -> Self {
+        PluginLimits {
+            // 1GiB, generous since whole audio files are passed to plugins
+            memory_pages: 16384,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl PluginLimits {
+    pub fn from_saved(saved: SavedPluginLimits) -> Self {
+        PluginLimits {
+            memory_pages: saved.memory_pages,
+            timeout: saved.timeout,
+        }
+    }
+
+    pub fn to_saved(self) -> SavedPluginLimits {
+        SavedPluginLimits {
+            memory_pages: self.memory_pages,
+            timeout: self.timeout,
+        }
+    }
+}
+
+/// Keeps track of the faults of all instances of a plugin.
+#[derive(Debug)]
+struct FaultTracker {
+    host: HostContext,
+    consecutive: AtomicUsize,
+    quarantined: AtomicBool,
+    health: watch::Sender<PluginHealth>,
+}
 
 #[derive(Debug)]
 pub struct PluginHandle {
     pool: Arc<PluginPool>,
     plugin: Option<Plugin>,
+    generation: usize,
+    settings_version: usize,
 }
 
 #[derive(Debug, Clone)]
 pub struct PluginCapabilities {
     pub provide_tracks: bool,
     pub decode: bool,
+    pub seek: bool,
+    pub write_replay_gain: bool,
+    pub effect: bool,
+    pub visualize: bool,
 }
 
 impl PluginCapabilities {
     pub fn from_plugin(plugin: &Plugin) -> Self {
         PluginCapabilities {
-            provide_tracks: plugin.has_fn("prepare_scan")
-                && plugin.has_fn("scan")
-                && plugin.has_fn("get_audio_file"),
-            decode: plugin.has_fn("init_decoding")
-                && plugin.has_fn("decode_block")
-                && plugin.has_fn("finish_decoding"),
+            provide_tracks: plugin.has_fn(exports::PREPARE_SCAN)
+                && plugin.has_fn(exports::SCAN)
+                && plugin.has_fn(exports::GET_AUDIO_FILE),
+            decode: plugin.has_fn(exports::INIT_DECODING)
+                && plugin.has_fn(exports::DECODE_BLOCK)
+                && plugin.has_fn(exports::FINISH_DECODING),
+            seek: plugin.has_fn(exports::SEEK),
+            write_replay_gain: plugin.has_fn(exports::WRITE_REPLAY_GAIN),
+            effect: plugin.has_fn(exports::INIT_EFFECT)
+                && plugin.has_fn(exports::PROCESS_BLOCK)
+                && plugin.has_fn(exports::FINISH_EFFECT),
+            visualize: plugin.has_fn(exports::VISUALIZE),
         }
     }
 }
@@ -70,32 +222,136 @@ pub enum PluginError {
 
     #[error("Plugin does not implement required function '{0}'")]
     MissingRequiredFunction(&'static str),
+    #[error("Plugin was built before the plugin API was versioned and needs to be rebuilt")]
+    UnversionedPlugin,
+    #[error(
+        "Plugin targets plugin API version {0}, but only version {} is supported",
+        PLUGIN_API_VERSION
+    )]
+    IncompatibleApiVersion(u32),
     #[error("Failed to call function '{0}': {1}")]
     FunctionCallError(String, extism::Error),
+
+    #[error("No plugin with ID {0:?} is loaded")]
+    UnknownPlugin(PluginId),
+    #[error("Plugin is quarantined after faulting repeatedly")]
+    Quarantined,
+}
+
+impl FaultTracker {
+    fn new(host: HostContext) -> Self {
+        FaultTracker {
+            host,
+            consecutive: AtomicUsize::new(0),
+            quarantined: AtomicBool::new(false),
+            health: watch::Sender::new(PluginHealth::default()),
+        }
+    }
+
+    fn is_quarantined(&self) -> bool {
+        self.quarantined.load(Ordering::SeqCst)
+    }
+
+    fn record_success(&self) {
+        // called for every block of audio, so the health is only touched after a fault
+        if self.consecutive.swap(0, Ordering::SeqCst) != 0 {
+            self.health
+                .send_modify(|health| health.consecutive_faults = 0);
+        }
+    }
+
+    fn record_fault(&self, fault: &str) {
+        let consecutive = self.consecutive.fetch_add(1, Ordering::SeqCst) + 1;
+        let quarantine =
+            consecutive >= QUARANTINE_AFTER && !self.quarantined.swap(true, Ordering::SeqCst);
+
+        warn!("Plugin '{}' faulted: {}", self.host.plugin_name(), fault);
+
+        self.health.send_modify(|health| {
+            health.faults += 1;
+            health.consecutive_faults = consecutive;
+            health.last_fault = Some(fault.to_string());
+            health.quarantined = self.is_quarantined();
+        });
+
+        if quarantine {
+            error!(
+                "Quarantining plugin '{}' after {} faults in a row",
+                self.host.plugin_name(),
+                consecutive
+            );
+            self.host.notify(HostNotification {
+                title: "Plugin quarantined".to_string(),
+                message: format!(
+                    "Disabled after {} faults in a row, the last one was: {}",
+                    consecutive, fault
+                ),
+            });
+        }
+    }
+
+    /// Records a failed call as a fault if it broke the instance, returns whether it did.
+    /// Errors the plugin returned itself don't count.
+    fn record_failed_call(&self, function: &str, e: &extism::Error, timed_out: bool) -> bool {
+        let fault = if timed_out {
+            format!("Timed out in '{}'", function)
+        } else if is_trap(e) {
+            format!("Trapped in '{}': {}", function, e)
+        } else {
+            return false;
+        };
+
+        self.record_fault(&fault);
+        true
+    }
+
+    fn release(&self) {
+        self.consecutive.store(0, Ordering::SeqCst);
+        self.quarantined.store(false, Ordering::SeqCst);
+        self.health.send_modify(|health| {
+            health.consecutive_faults = 0;
+            health.quarantined = false;
+        });
+    }
+}
+
+/// Plugins running out of memory trap as well, since allocations that fail abort.
+fn is_trap(e: &extism::Error) -> bool {
+    e.chain().any(|cause| cause.is::<wasmtime::Trap>())
 }
 
 impl Plugin {
     pub fn has_fn(&self, function: &str) -> bool {
-        self.0.function_exists(function)
+        self.inner.function_exists(function)
+    }
+
+    /// The plugin API version the plugin targets, plugins from before versioning don't report any.
+    pub fn get_api_version(&mut self) -> Result<Option<u32>, PluginError> {
+        if !self.has_fn(exports::GET_API_VERSION) {
+            return Ok(None);
+        }
+
+        let ApiVersion(version) = self.call(exports::GET_API_VERSION, ())?;
+        Ok(Some(version))
     }
 
     pub fn get_metadata(&mut self) -> Result<PluginMetadata, PluginError> {
-        self.call("get_metadata", ())
+        self.call(exports::GET_METADATA, ())
     }
 
     pub fn prepare_scan(&mut self) -> Result<PreparedScan, PluginError> {
-        self.call("prepare_scan", ())
+        self.call(exports::PREPARE_SCAN, ())
     }
 
     pub fn scan(&mut self, ident: &PluginTrackIdentifier) -> Result<ScanResult, PluginError> {
-        self.call("scan", ident)
+        self.call(exports::SCAN, ident)
     }
 
     pub fn get_audio_file(
         &mut self,
         ident: &PluginTrackIdentifier,
     ) -> Result<AudioFile, PluginError> {
-        self.call("get_audio_file", ident)
+        self.call(exports::GET_AUDIO_FILE, ident)
     }
 
     pub fn init_decoding(
@@ -105,7 +361,7 @@ impl Plugin {
         gapless: bool,
     ) -> Result<InitDecodingResult, PluginError> {
         self.call(
-            "init_decoding",
+            exports::INIT_DECODING,
             InitDecodingArgs {
                 playback_id,
                 file,
@@ -118,56 +374,202 @@ impl Plugin {
         &mut self,
         playback_id: PlaybackId,
     ) -> Result<Option<AudioBlock>, PluginError> {
-        self.call("decode_block", playback_id)
+        self.call(exports::DECODE_BLOCK, playback_id)
     }
 
     pub fn finish_decoding(&mut self, playback_id: PlaybackId) -> Result<(), PluginError> {
-        self.call("finish_decoding", playback_id)
+        self.call(exports::FINISH_DECODING, playback_id)
     }
 
-    #[instrument]
-    fn try_load(path: &Path) -> Result<Self, PluginError> {
-        let manifest = Manifest::new([path.to_path_buf()])
-            .with_allowed_path("/home/sakanaa/nas/Audio/Music/".to_string(), "music");
+    pub fn seek(
+        &mut self,
+        playback_id: PlaybackId,
+        position: Duration,
+    ) -> Result<SeekResult, PluginError> {
+        self.call(
+            exports::SEEK,
+            SeekArgs {
+                playback_id,
+                position,
+            },
+        )
+    }
 
-        let plugin = PluginBuilder::new(manifest)
-            .with_wasi(true)
-            .build()
-            .map_err(PluginError::InitializationError)?;
+    pub fn write_replay_gain(&mut self, args: WriteReplayGainArgs) -> Result<(), PluginError> {
+        self.call(exports::WRITE_REPLAY_GAIN, args)
+    }
+
+    pub fn init_effect(
+        &mut self,
+        effect_id: EffectId,
+        sample_rate: SampleRate,
+        channel_count: ChannelCount,
+    ) -> Result<(), PluginError> {
+        self.call(
+            exports::INIT_EFFECT,
+            InitEffectArgs {
+                effect_id,
+                sample_rate,
+                channel_count,
+            },
+        )
+    }
+
+    pub fn process_block(
+        &mut self,
+        effect_id: EffectId,
+        block: AudioBlock,
+    ) -> Result<AudioBlock, PluginError> {
+        self.call(
+            exports::PROCESS_BLOCK,
+            ProcessBlockArgs { effect_id, block },
+        )
+    }
+
+    pub fn finish_effect(&mut self, effect_id: EffectId) -> Result<(), PluginError> {
+        self.call(exports::FINISH_EFFECT, effect_id)
+    }
+
+    pub fn visualize(&mut self, frame: VisualizerFrame) -> Result<(), PluginError> {
+        self.call(exports::VISUALIZE, frame)
+    }
+
+    /// Hands the settings to the plugin, plugins that don't declare any settings can skip the
+    /// `configure` export.
+    pub fn configure(&mut self, settings: &PluginSettings) -> Result<(), PluginError> {
+        if !self.has_fn(exports::CONFIGURE) {
+            return Ok(());
+        }
+
+        self.call(exports::CONFIGURE, settings.clone())
+    }
 
-        if !plugin.function_exists("get_metadata") {
-            return Err(PluginError::MissingRequiredFunction("get_metadata"));
+    /// Loads a new instance, `settings` are passed to `configure` before it is handed out.
+    #[instrument(skip(mounts, settings, faults))]
+    fn try_load(
+        path: &Path,
+        mounts: &[PluginMount],
+        limits: PluginLimits,
+        settings: Option<&PluginSettings>,
+        faults: &Arc<FaultTracker>,
+    ) -> Result<Self, PluginError> {
+        let mut plugin = Plugin::build(Wasm::file(path), mounts, limits, faults)?;
+        if !plugin.has_fn(exports::GET_METADATA) {
+            return Err(PluginError::MissingRequiredFunction(exports::GET_METADATA));
         }
 
-        let mut plugin = Plugin(plugin);
+        // checked before anything else is decoded, which would fail with far less helpful errors
+        match plugin.get_api_version()? {
+            Some(PLUGIN_API_VERSION) => {}
+            Some(version) => return Err(PluginError::IncompatibleApiVersion(version)),
+            None => return Err(PluginError::UnversionedPlugin),
+        }
 
         let metadata = plugin.get_metadata()?;
-        plugin.0.id = metadata.uuid;
+        plugin.inner.id = metadata.uuid;
+
+        if let Some(settings) = settings {
+            // the plugin is still usable with its defaults
+            if let Err(e) = plugin.configure(settings) {
+                warn!("Failed to configure plugin '{}': {}", metadata.name, e);
+            }
+        }
 
         Ok(plugin)
     }
 
+    /// Instantiates `wasm` with the host functions, without calling anything in it yet.
+    fn build(
+        wasm: Wasm,
+        mounts: &[PluginMount],
+        limits: PluginLimits,
+        faults: &Arc<FaultTracker>,
+    ) -> Result<Self, PluginError> {
+        let mut manifest = Manifest::new([wasm])
+            .with_memory_max(limits.memory_pages)
+            .with_timeout(limits.timeout);
+
+        let mut mount_roots: HashMap<&str, Vec<String>> = HashMap::new();
+        for mount in mounts {
+            let guest_path = mount.guest_path();
+
+            manifest = manifest.with_allowed_path(
+                mount.host_path.to_string_lossy().to_string(),
+                guest_path.clone(),
+            );
+
+            mount_roots
+                .entry(mount.internal_path.as_str())
+                .or_default()
+                .push(guest_path);
+        }
+
+        for (internal_path, roots) in mount_roots {
+            manifest =
+                manifest.with_config_key(FsMount::config_key(internal_path), roots.join("\n"));
+        }
+
+        let plugin = faults
+            .host
+            .register(PluginBuilder::new(manifest).with_wasi(true))
+            .build()
+            .map_err(PluginError::InitializationError)?;
+
+        Ok(Plugin {
+            inner: plugin,
+            faults: faults.clone(),
+            timeout: limits.timeout,
+            trapped: false,
+        })
+    }
+
     fn call<'a, 'b, T, R>(&'b mut self, function: &str, args: T) -> Result<R, PluginError>
     where
         T: extism::ToBytes<'a>,
         R: extism::FromBytes<'b>,
     {
-        self.0
-            .call(function, args)
-            .map_err(move |e| PluginError::FunctionCallError(function.to_string(), e))
+        if self.faults.is_quarantined() {
+            return Err(PluginError::Quarantined);
+        }
+
+        let started = Instant::now();
+        match self.inner.call(function, args) {
+            Ok(result) => {
+                self.faults.record_success();
+                Ok(result)
+            }
+            Err(e) => {
+                let timed_out = started.elapsed() >= self.timeout;
+                if self.faults.record_failed_call(function, &e, timed_out) {
+                    self.trapped = true;
+                }
+
+                Err(PluginError::FunctionCallError(function.to_string(), e))
+            }
+        }
     }
 }
 
 impl PluginPool {
-    pub fn try_new(path: &Path) -> Result<Arc<Self>, PluginError> {
-        let mut plugin = Plugin::try_load(path)?;
+    pub fn try_new(path: &Path, host: HostContext) -> Result<Arc<Self>, PluginError> {
+        let faults = Arc::new(FaultTracker::new(host.clone()));
+        let limits = PluginLimits::default();
+        let mut plugin = Plugin::try_load(path, &[], limits, None, &faults)?;
         let metadata = plugin.get_metadata()?;
+        let settings = settings_from_saved(&metadata, HashMap::new());
 
         let pool = Arc::new(PluginPool {
             metadata,
             capabilities: PluginCapabilities::from_plugin(&plugin),
             plugin_path: path.to_path_buf(),
+            host,
+            mounts: Mutex::new(Vec::new()),
+            limits: Mutex::new(limits),
+            settings: Mutex::new(settings),
             plugins: Mutex::new(VecDeque::new()),
+            faults,
+            generation: AtomicUsize::new(0),
+            settings_version: AtomicUsize::new(0),
             wait_condvar: Condvar::new(),
         });
 
@@ -199,33 +601,206 @@ impl PluginPool {
         Ok(pool)
     }
 
-    pub fn get_plugin(self: &Arc<Self>) -> PluginHandle {
+    pub fn get_plugin(self: &Arc<Self>) -> Result<PluginHandle, PluginError> {
+        if self.faults.is_quarantined() {
+            return Err(PluginError::Quarantined);
+        }
+
         let mut plugins = self.plugins.lock().unwrap();
-        if let Some(plugin) = plugins.pop_front() {
-            PluginHandle::new(self.clone(), plugin)
-        } else {
-            info!(
-                "Creating new instance for plugin: {} ({})",
-                self.metadata.name, self.metadata.uuid
+        if let Some((plugin, settings_version)) = plugins.pop_front() {
+            drop(plugins);
+
+            let mut handle = PluginHandle::new(self.clone(), plugin, settings_version);
+            handle.refresh_settings();
+            return Ok(handle);
+        }
+
+        info!(
+            "Creating new instance for plugin: {} ({})",
+            self.metadata.name, self.metadata.uuid
+        );
+
+        // read before the settings, so a change in between gets picked up by the next refresh
+        let settings_version = self.settings_version.load(Ordering::SeqCst);
+        let plugin = Plugin::try_load(
+            &self.plugin_path,
+            &self.mounts.lock().unwrap(),
+            self.limits(),
+            Some(&self.settings()),
+            &self.faults,
+        )
+        .inspect_err(|e| {
+            self.faults
+                .record_fault(&format!("Failed to load instance: {}", e));
+        })?;
+
+        Ok(PluginHandle::new(self.clone(), plugin, settings_version))
+    }
+
+    pub fn health(&self) -> PluginHealth {
+        self.faults.health.borrow().clone()
+    }
+
+    pub fn subscribe_health(&self) -> watch::Receiver<PluginHealth> {
+        self.faults.health.subscribe()
+    }
+
+    /// Lifts the quarantine, so the plugin gets called again.
+    pub fn release(&self) {
+        info!("Releasing plugin '{}' from quarantine", self.metadata.name);
+        self.faults.release();
+    }
+
+    pub fn mounts(&self) -> Vec<PluginMount> {
+        self.mounts.lock().unwrap().clone()
+    }
+
+    /// Replaces the host directories mounted into this plugin. Mounts for paths the plugin
+    /// doesn't declare in its metadata are ignored.
+    pub fn set_mounts(&self, mounts: Vec<PluginMount>) {
+        let (mounts, undeclared): (Vec<_>, Vec<_>) = mounts.into_iter().partition(|mount| {
+            self.metadata
+                .fs_mounts
+                .iter()
+                .any(|declared| declared.internal_path == mount.internal_path)
+        });
+
+        for mount in undeclared {
+            warn!(
+                "Ignoring mount {:?} for plugin '{}' since it does not declare '{}'",
+                mount.host_path, self.metadata.name, mount.internal_path
             );
+        }
 
-            let plugin = Plugin::try_load(&self.plugin_path).expect("Plugin loading to never fail");
-            PluginHandle::new(self.clone(), plugin)
+        *self.mounts.lock().unwrap() = mounts;
+
+        // existing instances still have the old directories preopened
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.plugins.lock().unwrap().clear();
+    }
+
+    pub fn limits(&self) -> PluginLimits {
+        *self.limits.lock().unwrap()
+    }
+
+    /// Replaces the limits instances of this plugin are built with.
+    pub fn set_limits(&self, limits: PluginLimits) {
+        *self.limits.lock().unwrap() = limits;
+
+        // the limits are part of the manifest existing instances were built with
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.plugins.lock().unwrap().clear();
+    }
+
+    pub fn settings(&self) -> PluginSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    /// Replaces the settings of this plugin. New instances are configured with them right away,
+    /// idle ones when they are taken from the pool and handles that are held on to pick them up
+    /// through [`PluginHandle::refresh_settings`].
+    pub fn set_settings(&self, settings: PluginSettings) {
+        *self.settings.lock().unwrap() = settings;
+        self.settings_version.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Saves the settings that changed, one at a time and with their latest values, so that values
+/// from earlier changes never overwrite later ones.
+async fn save_settings_on_change(
+    plugins: Arc<HashMap<PluginId, Arc<PluginPool>>>,
+    db: Database,
+    mut changed_rx: mpsc::UnboundedReceiver<(PluginId, String)>,
+) {
+    while let Some(first) = changed_rx.recv().await {
+        time::sleep(SETTINGS_SAVE_DELAY).await;
+
+        let mut changed = HashSet::from([first]);
+        while let Ok(key) = changed_rx.try_recv() {
+            changed.insert(key);
+        }
+
+        for (plugin_id, key) in changed {
+            let Some(pool) = plugins.get(&plugin_id) else {
+                continue;
+            };
+            let Some(value) = pool.settings().values.get(&key).map(ToString::to_string) else {
+                continue;
+            };
+
+            match db.set_plugin_setting(plugin_id, &key, &value).await {
+                Ok(()) => info!("Saved setting '{}' of plugin '{}'", key, pool.metadata.name),
+                Err(e) => warn!(
+                    "Failed to save setting '{}' of plugin '{}': {}",
+                    key, pool.metadata.name, e
+                ),
+            }
         }
     }
 }
 
+/// The settings declared by the plugin, with the saved values where they are still valid and the
+/// defaults everywhere else.
+fn settings_from_saved(
+    metadata: &PluginMetadata,
+    saved: HashMap<String, String>,
+) -> PluginSettings {
+    let values = metadata
+        .settings
+        .iter()
+        .map(|declared| {
+            let value = match saved.get(&declared.key) {
+                Some(text) => declared.kind.parse(text).unwrap_or_else(|| {
+                    warn!(
+                        "Ignoring invalid saved value '{}' for setting '{}' of plugin '{}'",
+                        text, declared.key, metadata.name
+                    );
+                    declared.kind.default_value()
+                }),
+                None => declared.kind.default_value(),
+            };
+
+            (declared.key.clone(), value)
+        })
+        .collect();
+
+    PluginSettings { values }
+}
+
 impl PluginHandle {
-    pub fn new(pool: Arc<PluginPool>, plugin: Plugin) -> PluginHandle {
+    /// `settings_version` is the version of the settings `plugin` was last configured with.
+    pub fn new(pool: Arc<PluginPool>, plugin: Plugin, settings_version: usize) -> PluginHandle {
+        let generation = pool.generation.load(Ordering::SeqCst);
+
         PluginHandle {
             pool,
             plugin: Some(plugin),
+            generation,
+            settings_version,
         }
     }
 
     pub fn capabilities(&self) -> &PluginCapabilities {
         &self.pool.capabilities
     }
+
+    /// Calls `configure` again if the settings changed since the instance was last configured.
+    /// Only needed for handles that are held on to, like the ones of running effects.
+    pub fn refresh_settings(&mut self) {
+        let settings_version = self.pool.settings_version.load(Ordering::SeqCst);
+        if settings_version == self.settings_version {
+            return;
+        }
+        self.settings_version = settings_version;
+
+        let settings = self.pool.settings();
+        if let Err(e) = self.configure(&settings) {
+            warn!(
+                "Failed to reconfigure plugin '{}': {}",
+                self.pool.metadata.name, e
+            );
+        }
+    }
 }
 
 impl std::ops::Deref for PluginHandle {
@@ -249,7 +824,9 @@ impl Drop for PluginHandle {
     fn drop(&mut self) {
         let mut plugins = self.pool.plugins.lock().unwrap();
         if let Some(plugin) = self.plugin.take() {
-            plugins.push_back(plugin);
+            if self.generation == self.pool.generation.load(Ordering::SeqCst) && !plugin.trapped {
+                plugins.push_back((plugin, self.settings_version));
+            }
         }
         self.pool.wait_condvar.notify_one();
     }
@@ -263,7 +840,9 @@ impl PluginSystem {
             plugin_dir
         );
 
+        let host = HostServices::new(db.clone());
         let mut plugins = HashMap::new();
+        let mut failed = Vec::new();
 
         for entry in std::fs::read_dir(&plugin_dir)
             .map_err(|_| PluginSystemError::InvalidDirectory(plugin_dir.clone()))?
@@ -278,10 +857,14 @@ impl PluginSystem {
                 continue;
             }
 
-            let pool = match PluginPool::try_new(&entry.path()) {
+            let pool = match PluginPool::try_new(&entry.path(), host.context()) {
                 Ok(plugin) => plugin,
                 Err(e) => {
                     warn!(error = %e, "Failed to load plugin {:?}: {}", entry.file_name(), e);
+                    failed.push(FailedPlugin {
+                        file: entry.file_name().to_string_lossy().to_string(),
+                        error: e.to_string(),
+                    });
                     continue;
                 }
             };
@@ -296,20 +879,298 @@ impl PluginSystem {
                 }
                 Err(e) => {
                     warn!(error = %e, "Failed to register plugin {:?} in database: {}", entry.file_name(), e);
+                    failed.push(FailedPlugin {
+                        file: entry.file_name().to_string_lossy().to_string(),
+                        error: e.to_string(),
+                    });
                     continue;
                 }
             };
+            pool.host.set_plugin(id, &pool.metadata.name);
+
+            match db.get_plugin_mounts(id).await {
+                Ok(mounts) => pool.set_mounts(mounts),
+                Err(e) => {
+                    warn!(error = %e, "Failed to load mounts for plugin '{}': {}", pool.metadata.name, e);
+                }
+            }
+
+            match db.get_plugin_limits(id).await {
+                Ok(Some(saved)) => pool.set_limits(PluginLimits::from_saved(saved)),
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(error = %e, "Failed to load limits for plugin '{}': {}", pool.metadata.name, e);
+                }
+            }
+
+            match db.get_plugin_settings(id).await {
+                Ok(saved) => pool.set_settings(settings_from_saved(&pool.metadata, saved)),
+                Err(e) => {
+                    warn!(error = %e, "Failed to load settings for plugin '{}': {}", pool.metadata.name, e);
+                }
+            }
+
             plugins.insert(id, pool);
         }
 
         info!("Loaded {} plugins", plugins.len());
 
+        let plugins = Arc::new(plugins);
+        let (settings_save_tx, settings_save_rx) = mpsc::unbounded_channel();
+        tokio::spawn(save_settings_on_change(
+            plugins.clone(),
+            db.clone(),
+            settings_save_rx,
+        ));
+
         Ok(PluginSystem {
-            plugins: Arc::new(plugins),
+            plugins,
+            failed: Arc::new(failed),
+            db,
+            host,
+            settings_save_tx,
         })
     }
 
-    pub fn get_plugin(&self, id: PluginId) -> Option<PluginHandle> {
-        self.plugins.get(&id).map(|pool| pool.get_plugin())
+    /// Notifications plugins send through the host functions.
+    pub fn subscribe_notifications(&self) -> broadcast::Receiver<PluginNotification> {
+        self.host.subscribe_notifications()
+    }
+
+    pub fn get_plugin(&self, id: PluginId) -> Result<PluginHandle, PluginError> {
+        self.plugins
+            .get(&id)
+            .ok_or(PluginError::UnknownPlugin(id))?
+            .get_plugin()
+    }
+
+    /// Looks up a plugin by its name (case insensitive) or UUID.
+    pub fn find_plugin(&self, query: &str) -> Option<(PluginId, &Arc<PluginPool>)> {
+        self.plugins
+            .iter()
+            .find(|(_, pool)| {
+                pool.metadata.name.eq_ignore_ascii_case(query)
+                    || pool.metadata.uuid.to_string() == query
+            })
+            .map(|(id, pool)| (*id, pool))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn add_mount(
+        &self,
+        plugin_id: PluginId,
+        internal_path: &str,
+        host_path: &Path,
+    ) -> Result<PluginMountId, PluginMountError> {
+        let pool = self
+            .plugins
+            .get(&plugin_id)
+            .ok_or(PluginMountError::UnknownPlugin(plugin_id))?;
+
+        if !pool
+            .metadata
+            .fs_mounts
+            .iter()
+            .any(|mount| mount.internal_path == internal_path)
+        {
+            return Err(PluginMountError::UndeclaredMount(internal_path.to_string()));
+        }
+
+        let host_path = host_path
+            .canonicalize()
+            .ok()
+            .filter(|path| path.is_dir())
+            .ok_or_else(|| PluginMountError::InvalidHostPath(host_path.to_path_buf()))?;
+
+        let mount_id = self
+            .db
+            .add_plugin_mount(plugin_id, internal_path, &host_path)
+            .await?;
+
+        self.reload_mounts(plugin_id).await?;
+
+        info!(
+            "Mounted {:?} at '{}' for plugin '{}'",
+            host_path, internal_path, pool.metadata.name
+        );
+
+        Ok(mount_id)
+    }
+
+    /// Saves the limits of a plugin, instances that are in use keep the old ones until they are
+    /// returned.
+    #[instrument(skip(self))]
+    pub async fn set_limits(
+        &self,
+        plugin_id: PluginId,
+        limits: PluginLimits,
+    ) -> Result<(), PluginLimitsError> {
+        let pool = self
+            .plugins
+            .get(&plugin_id)
+            .ok_or(PluginLimitsError::UnknownPlugin(plugin_id))?;
+
+        if limits.memory_pages == 0 || limits.timeout.is_zero() {
+            return Err(PluginLimitsError::InvalidLimits);
+        }
+
+        self.db
+            .set_plugin_limits(plugin_id, &limits.to_saved())
+            .await?;
+        pool.set_limits(limits);
+
+        info!(
+            "Limited plugin '{}' to {} pages of memory and {:?} per call",
+            pool.metadata.name, limits.memory_pages, limits.timeout
+        );
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn remove_mount(&self, mount_id: PluginMountId) -> Result<bool, PluginMountError> {
+        match self.db.remove_plugin_mount(mount_id).await? {
+            Some(plugin_id) => {
+                if self.plugins.contains_key(&plugin_id) {
+                    self.reload_mounts(plugin_id).await?;
+                }
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn reload_mounts(&self, plugin_id: PluginId) -> Result<(), PluginMountError> {
+        let pool = self
+            .plugins
+            .get(&plugin_id)
+            .ok_or(PluginMountError::UnknownPlugin(plugin_id))?;
+
+        pool.set_mounts(self.db.get_plugin_mounts(plugin_id).await?);
+
+        Ok(())
+    }
+
+    /// Validates `value` against the declaration of the setting and hands it to the plugin. It is
+    /// saved shortly after, once it stopped changing.
+    #[instrument(skip(self))]
+    pub fn set_setting(
+        &self,
+        plugin_id: PluginId,
+        key: &str,
+        value: SettingValue,
+    ) -> Result<(), PluginSettingError> {
+        let pool = self
+            .plugins
+            .get(&plugin_id)
+            .ok_or(PluginSettingError::UnknownPlugin(plugin_id))?;
+
+        let declared = pool
+            .metadata
+            .settings
+            .iter()
+            .find(|declared| declared.key == key)
+            .ok_or_else(|| PluginSettingError::UndeclaredSetting(key.to_string()))?;
+
+        if !declared.kind.accepts(&value) {
+            return Err(PluginSettingError::InvalidValue(
+                key.to_string(),
+                value.to_string(),
+            ));
+        }
+
+        let mut settings = pool.settings();
+        settings.values.insert(key.to_string(), value);
+        pool.set_settings(settings);
+
+        // the task only ends once the plugin system is gone
+        let _ = self.settings_save_tx.send((plugin_id, key.to_string()));
+
+        debug!("Set setting '{}' of plugin '{}'", key, pool.metadata.name);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use tokio::runtime;
+
+    // exports `spin`, which loops forever, and `grow`, which grows its memory until that fails and
+    // then aborts like an allocation failing in a Rust plugin does
+    const RUNAWAY_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f, // type: () -> i32
+        0x03, 0x03, 0x02, 0x00, 0x00, // two functions of that type
+        0x05, 0x03, 0x01, 0x00, 0x01, // a memory of one page
+        0x07, 0x0f, 0x02, // exports
+        0x04, b's', b'p', b'i', b'n', 0x00, 0x00, //
+        0x04, b'g', b'r', b'o', b'w', 0x00, 0x01, //
+        0x0a, 0x20, 0x02, // code
+        // loop br 0 end, i32.const 0
+        0x09, 0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x41, 0x00, 0x0b,
+        // loop (memory.grow 1) == -1 if unreachable end br 0 end, i32.const 0
+        0x14, 0x00, 0x03, 0x40, 0x41, 0x01, 0x40, 0x00, 0x41, 0x7f, 0x46, 0x04, 0x40, 0x00, 0x0b,
+        0x0c, 0x00, 0x0b, 0x41, 0x00, 0x0b,
+    ];
+
+    /// The runtime has to be kept around for as long as the tracker is used.
+    fn fault_tracker(dir: &TempDir) -> (runtime::Runtime, Arc<FaultTracker>) {
+        let runtime = runtime::Runtime::new().unwrap();
+        let host = runtime.block_on(async { HostServices::new(dir.database().await).context() });
+
+        (runtime, Arc::new(FaultTracker::new(host)))
+    }
+
+    #[test]
+    fn plugin_errors_are_not_faults() {
+        let dir = TempDir::new("plugin-errors");
+        let (_runtime, faults) = fault_tracker(&dir);
+
+        for _ in 0..QUARANTINE_AFTER {
+            let e = extism::Error::msg("Track not found");
+            assert!(!faults.record_failed_call(exports::SCAN, &e, false));
+        }
+
+        assert_eq!(faults.consecutive.load(Ordering::SeqCst), 0);
+        assert!(!faults.is_quarantined());
+        assert_eq!(*faults.health.borrow(), PluginHealth::default());
+    }
+
+    #[test]
+    fn running_into_limits_is_a_fault() {
+        let dir = TempDir::new("plugin-limits");
+        let (_runtime, faults) = fault_tracker(&dir);
+
+        let limits = PluginLimits {
+            memory_pages: 64,
+            timeout: Duration::from_millis(100),
+        };
+        let instance = || Plugin::build(Wasm::data(RUNAWAY_WASM), &[], limits, &faults).unwrap();
+
+        let mut plugin = instance();
+        assert!(plugin.call::<_, ()>("spin", ()).is_err());
+        assert!(plugin.trapped);
+        assert_eq!(faults.consecutive.load(Ordering::SeqCst), 1);
+
+        let mut plugin = instance();
+        assert!(plugin.call::<_, ()>("grow", ()).is_err());
+        assert!(plugin.trapped);
+        assert_eq!(faults.consecutive.load(Ordering::SeqCst), 2);
+        assert!(!faults.is_quarantined());
+
+        let mut plugin = instance();
+        assert!(plugin.call::<_, ()>("spin", ()).is_err());
+        assert!(faults.is_quarantined());
+        assert_eq!(faults.health.borrow().consecutive_faults, QUARANTINE_AFTER);
+
+        // quarantined plugins aren't called at all
+        let mut plugin = instance();
+        assert!(matches!(
+            plugin.call::<_, ()>("spin", ()),
+            Err(PluginError::Quarantined)
+        ));
     }
 }
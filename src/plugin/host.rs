@@ -0,0 +1,265 @@
+use extism::{PTR, PluginBuilder, UserData, convert::Msgpack, host_fn};
+use hogehoge_db::Database;
+use hogehoge_types::{
+    AlbumId, PluginId, PluginTrackIdentifier, Tags, UniqueTrackIdentifier, host::*,
+};
+use std::{
+    future::Future,
+    sync::{Arc, OnceLock},
+    time::SystemTime,
+};
+use tokio::{runtime, sync::broadcast, task};
+use tracing::*;
+
+// notifications nobody picked up in time are dropped
+const NOTIFICATION_CAPACITY: usize = 16;
+
+/// A notification sent by a plugin through the host functions.
+#[derive(Debug, Clone)]
+pub struct PluginNotification {
+    pub plugin: String,
+    pub title: String,
+    pub message: String,
+}
+
+/// What the host functions give access to, shared by all plugins.
+#[derive(Debug, Clone)]
+pub struct HostServices {
+    db: Database,
+    rt: runtime::Handle,
+    notifications: broadcast::Sender<PluginNotification>,
+}
+
+/// The host functions as the instances of a single plugin see them.
+#[derive(Debug, Clone)]
+pub struct HostContext {
+    services: HostServices,
+    // only known once the metadata was read and the plugin was registered, until then the
+    // functions that need it fail
+    plugin: Arc<OnceLock<(PluginId, String)>>,
+}
+
+impl HostServices {
+    /// Must be called from within the runtime.
+    pub fn new(db: Database) -> Self {
+        HostServices {
+            db,
+            rt: runtime::Handle::current(),
+            notifications: broadcast::channel(NOTIFICATION_CAPACITY).0,
+        }
+    }
+
+    pub fn subscribe_notifications(&self) -> broadcast::Receiver<PluginNotification> {
+        self.notifications.subscribe()
+    }
+
+    /// A context for a plugin that isn't registered yet.
+    pub fn context(&self) -> HostContext {
+        HostContext {
+            services: self.clone(),
+            plugin: Arc::new(OnceLock::new()),
+        }
+    }
+}
+
+impl HostContext {
+    pub fn set_plugin(&self, plugin_id: PluginId, name: &str) {
+        let _ = self.plugin.set((plugin_id, name.to_string()));
+    }
+
+    /// Registers all host functions with the instance being built.
+    pub fn register<'a>(&self, builder: PluginBuilder<'a>) -> PluginBuilder<'a> {
+        let user_data = || UserData::new(self.clone());
+
+        builder
+            .with_function(functions::LOG, [PTR], [], user_data(), hogehoge_log)
+            .with_function(
+                functions::STORAGE_GET,
+                [PTR],
+                [PTR],
+                user_data(),
+                hogehoge_storage_get,
+            )
+            .with_function(
+                functions::STORAGE_SET,
+                [PTR],
+                [],
+                user_data(),
+                hogehoge_storage_set,
+            )
+            .with_function(functions::NOW, [], [PTR], user_data(), hogehoge_now)
+            .with_function(functions::NOTIFY, [PTR], [], user_data(), hogehoge_notify)
+            .with_function(
+                functions::FIND_ALBUMS,
+                [PTR],
+                [PTR],
+                user_data(),
+                hogehoge_find_albums,
+            )
+            .with_function(
+                functions::ALBUM_TRACKS,
+                [PTR],
+                [PTR],
+                user_data(),
+                hogehoge_album_tracks,
+            )
+            .with_function(
+                functions::TRACK_TAGS,
+                [PTR],
+                [PTR],
+                user_data(),
+                hogehoge_track_tags,
+            )
+    }
+
+    pub fn plugin_name(&self) -> &str {
+        self.plugin
+            .get()
+            .map(|(_, name)| name.as_str())
+            .unwrap_or("<loading>")
+    }
+
+    fn plugin_id(&self) -> Result<PluginId, extism::Error> {
+        self.plugin
+            .get()
+            .map(|(plugin_id, _)| *plugin_id)
+            .ok_or_else(|| extism::Error::msg("Plugin is still being loaded"))
+    }
+
+    /// Plugins are called from worker threads as well as from within the runtime, the database
+    /// is only reachable through the runtime.
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        match runtime::Handle::try_current() {
+            Ok(_) => task::block_in_place(|| self.services.rt.block_on(future)),
+            Err(_) => self.services.rt.block_on(future),
+        }
+    }
+
+    fn log(&self, record: LogRecord) {
+        let plugin = self.plugin_name();
+        let LogRecord {
+            level,
+            message,
+            fields,
+        } = record;
+
+        match level {
+            LogLevel::Trace => trace!(plugin, ?fields, "{}", message),
+            LogLevel::Debug => debug!(plugin, ?fields, "{}", message),
+            LogLevel::Info => info!(plugin, ?fields, "{}", message),
+            LogLevel::Warn => warn!(plugin, ?fields, "{}", message),
+            LogLevel::Error => error!(plugin, ?fields, "{}", message),
+        }
+    }
+
+    fn storage_get(&self, key: &str) -> Result<Option<Vec<u8>>, extism::Error> {
+        let plugin_id = self.plugin_id()?;
+        Ok(self.block_on(self.services.db.get_plugin_value(plugin_id, key))?)
+    }
+
+    fn storage_set(&self, key: &str, value: Option<&[u8]>) -> Result<(), extism::Error> {
+        let plugin_id = self.plugin_id()?;
+        Ok(self.block_on(self.services.db.set_plugin_value(plugin_id, key, value))?)
+    }
+
+    pub fn notify(&self, notification: HostNotification) {
+        // without anyone listening the notification is simply not shown
+        let _ = self.services.notifications.send(PluginNotification {
+            plugin: self.plugin_name().to_string(),
+            title: notification.title,
+            message: notification.message,
+        });
+    }
+
+    fn find_albums(&self, query: &str) -> Result<Vec<LibraryAlbum>, extism::Error> {
+        let albums = self.block_on(self.services.db.find_albums(query))?;
+
+        Ok(albums
+            .into_iter()
+            .map(|(id, title)| LibraryAlbum { id, title })
+            .collect())
+    }
+
+    fn album_tracks(&self, album_id: AlbumId) -> Result<Vec<Tags>, extism::Error> {
+        let db = &self.services.db;
+
+        self.block_on(async {
+            let mut tags = Vec::new();
+            for track in db.get_album_tracks(album_id).await? {
+                if let Some(track) = db.get_track_by_identifier(&track).await? {
+                    tags.push(track.tags);
+                }
+            }
+
+            Ok::<_, extism::Error>(tags)
+        })
+    }
+
+    fn track_tags(&self, track: PluginTrackIdentifier) -> Result<Option<Tags>, extism::Error> {
+        // plugins can only look up their own tracks by identifier, the identifiers of other
+        // plugins mean nothing to them
+        let track = UniqueTrackIdentifier {
+            plugin_id: self.plugin_id()?,
+            plugin_data: track,
+        };
+
+        let track = self.block_on(self.services.db.get_track_by_identifier(&track))?;
+        Ok(track.map(|track| track.tags))
+    }
+}
+
+fn context(user_data: &UserData<HostContext>) -> Result<HostContext, extism::Error> {
+    let context = user_data.get()?;
+    let context = context
+        .lock()
+        .map_err(|_| extism::Error::msg("Host context is poisoned"))?;
+
+    Ok(context.clone())
+}
+
+host_fn!(hogehoge_log(user_data: HostContext; record: LogRecord) {
+    context(&user_data)?.log(record);
+    Ok(())
+});
+
+host_fn!(hogehoge_storage_get(user_data: HostContext; key: String) -> StoredValue {
+    Ok(StoredValue(context(&user_data)?.storage_get(&key)?))
+});
+
+host_fn!(hogehoge_storage_set(user_data: HostContext; args: StorageSetArgs) {
+    context(&user_data)?.storage_set(&args.key, args.value.as_deref())
+});
+
+host_fn!(hogehoge_now(_user_data: HostContext;) -> Msgpack<u64> {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+
+    Ok(Msgpack(now.as_millis() as u64))
+});
+
+host_fn!(hogehoge_notify(user_data: HostContext; notification: HostNotification) {
+    context(&user_data)?.notify(notification);
+    Ok(())
+});
+
+host_fn!(hogehoge_find_albums(
+    user_data: HostContext;
+    query: String
+) -> Msgpack<Vec<LibraryAlbum>> {
+    Ok(Msgpack(context(&user_data)?.find_albums(&query)?))
+});
+
+host_fn!(hogehoge_album_tracks(
+    user_data: HostContext;
+    album_id: AlbumId
+) -> Msgpack<Vec<Tags>> {
+    Ok(Msgpack(context(&user_data)?.album_tracks(album_id)?))
+});
+
+host_fn!(hogehoge_track_tags(
+    user_data: HostContext;
+    track: PluginTrackIdentifier
+) -> Msgpack<Option<Tags>> {
+    Ok(Msgpack(context(&user_data)?.track_tags(track)?))
+});
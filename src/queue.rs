@@ -1,35 +1,157 @@
 use crate::plugin::PluginSystem;
+use hogehoge_db::{Database, SavedQueue, TrackGrouping};
 use hogehoge_types::UniqueTrackIdentifier;
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     sync::{Arc, Mutex},
 };
 use tokio::sync::broadcast;
+use tracing::*;
+
+mod shuffle;
 
 #[derive(Debug)]
 pub struct Queue {
     items: Mutex<QueueItems>,
     update_tx: broadcast::Sender<QueueUpdate>,
     pub plugin_system: PluginSystem,
+    db: Database,
 }
 
+/// Indices passed to and returned from the editing methods always refer to `future`, so `0` is
+/// the track that plays next.
 #[derive(Debug, Clone, Default)]
 pub struct QueueItems {
     pub past: Vec<UniqueTrackIdentifier>,
     pub current: Option<UniqueTrackIdentifier>,
     pub future: VecDeque<UniqueTrackIdentifier>,
+
+    pub repeat: RepeatMode,
+    pub shuffle: ShuffleMode,
+    // order of `future` from before it was shuffled
+    unshuffled: Vec<UniqueTrackIdentifier>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    /// Plays the current track again once it ends. Skipping still moves on.
+    One,
+    /// Starts over from the beginning of `past` once the end of the queue is reached.
+    All,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShuffleMode {
+    #[default]
+    Off,
+    Random,
+    /// Shuffles the order of albums but keeps their tracks together.
+    Album,
+    /// Shuffles while trying not to play the same artist again too soon.
+    WeightedArtist,
 }
 
 pub type QueueUpdateRx = broadcast::Receiver<QueueUpdate>;
 
 #[derive(Debug, Clone)]
 pub enum QueueUpdate {
+    /// The current track changed, tracks might have moved between `past` and `future` with it.
     CurrentTrackChanged,
-    #[allow(dead_code)]
-    TrackAdded(UniqueTrackIdentifier),
+    TrackInserted {
+        index: usize,
+        track: UniqueTrackIdentifier,
+    },
+    TrackRemoved {
+        index: usize,
+        track: UniqueTrackIdentifier,
+    },
+    TrackMoved {
+        from: usize,
+        to: usize,
+    },
+    /// Everything except the current track was removed.
+    Cleared,
+    /// The whole queue was replaced, including the current track.
+    Replaced,
+    /// `future` was reordered by (un)shuffling it.
+    ShuffleChanged(ShuffleMode),
+    RepeatChanged(RepeatMode),
+}
+
+impl RepeatMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RepeatMode::Off => "off",
+            RepeatMode::One => "one",
+            RepeatMode::All => "all",
+        }
+    }
+
+    fn parse(mode: &str) -> Self {
+        match mode {
+            "one" => RepeatMode::One,
+            "all" => RepeatMode::All,
+            _ => RepeatMode::Off,
+        }
+    }
+}
+
+impl ShuffleMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ShuffleMode::Off => "off",
+            ShuffleMode::Random => "random",
+            ShuffleMode::Album => "album",
+            ShuffleMode::WeightedArtist => "weighted_artist",
+        }
+    }
+
+    fn parse(mode: &str) -> Self {
+        match mode {
+            "random" => ShuffleMode::Random,
+            "album" => ShuffleMode::Album,
+            "weighted_artist" => ShuffleMode::WeightedArtist,
+            _ => ShuffleMode::Off,
+        }
+    }
 }
 
 impl QueueItems {
+    pub fn from_saved(saved: SavedQueue) -> Self {
+        let shuffle = ShuffleMode::parse(&saved.shuffle_mode);
+
+        QueueItems {
+            past: saved.past,
+            current: saved.current,
+            future: saved.future.into(),
+
+            repeat: RepeatMode::parse(&saved.repeat_mode),
+            shuffle,
+            unshuffled: match shuffle {
+                ShuffleMode::Off => Vec::new(),
+                _ => saved.unshuffled,
+            },
+        }
+    }
+
+    pub fn to_saved(&self) -> SavedQueue {
+        SavedQueue {
+            past: self.past.clone(),
+            current: self.current.clone(),
+            future: self.future.iter().cloned().collect(),
+            unshuffled: self.unshuffled.clone(),
+
+            repeat_mode: self.repeat.as_str().to_string(),
+            shuffle_mode: self.shuffle.as_str().to_string(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.past.is_empty() && self.current.is_none() && self.future.is_empty()
+    }
+
     pub fn get_at_offset(&self, offset: isize) -> Option<UniqueTrackIdentifier> {
         match offset {
             0 => self.current.clone(),
@@ -44,11 +166,61 @@ impl QueueItems {
         }
     }
 
-    pub fn push(&mut self, track: UniqueTrackIdentifier) {
+    pub fn push(&mut self, track: UniqueTrackIdentifier) -> usize {
         self.future.push_back(track);
+        self.future.len() - 1
+    }
+
+    /// Inserts `track` at `index`, or at the end if `index` is past it. Returns the actual index.
+    pub fn insert(&mut self, index: usize, track: UniqueTrackIdentifier) -> usize {
+        let index = index.min(self.future.len());
+        self.future.insert(index, track);
+        index
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<UniqueTrackIdentifier> {
+        self.future.remove(index)
+    }
+
+    /// Moves the track at `from` to `to`, clamping `to` to the end. Returns the actual new index.
+    pub fn move_track(&mut self, from: usize, to: usize) -> Option<usize> {
+        let track = self.future.remove(from)?;
+        let to = to.min(self.future.len());
+        self.future.insert(to, track);
+        Some(to)
+    }
+
+    pub fn clear(&mut self) {
+        self.past.clear();
+        self.future.clear();
+        self.unshuffled.clear();
+    }
+
+    /// The track that plays once the current one ends, taking the repeat mode into account.
+    pub fn next_track(&self) -> Option<UniqueTrackIdentifier> {
+        match self.repeat {
+            RepeatMode::One if self.current.is_some() => self.current.clone(),
+            RepeatMode::All if self.future.is_empty() => {
+                self.past.first().or(self.current.as_ref()).cloned()
+            }
+            _ => self.future.front().cloned(),
+        }
+    }
+
+    /// Moves on once the current track has ended, unlike [`QueueItems::forward`] this also
+    /// repeats a single track.
+    pub fn advance(&mut self) -> Option<UniqueTrackIdentifier> {
+        match self.repeat {
+            RepeatMode::One if self.current.is_some() => self.current.clone(),
+            _ => self.forward(),
+        }
     }
 
     pub fn forward(&mut self) -> Option<UniqueTrackIdentifier> {
+        if self.repeat == RepeatMode::All && self.future.is_empty() {
+            self.future = self.past.drain(..).chain(self.current.take()).collect();
+        }
+
         let next_track = self.future.pop_front();
 
         let prev_track = std::mem::replace(&mut self.current, next_track);
@@ -59,10 +231,77 @@ impl QueueItems {
 
         self.current.clone()
     }
+
+    /// Goes back to the last track in `past`, the current one becomes the next one.
+    pub fn back(&mut self) -> Option<UniqueTrackIdentifier> {
+        let prev_track = self.past.pop()?;
+
+        if let Some(track) = self.current.replace(prev_track) {
+            self.future.push_front(track);
+        }
+
+        self.current.clone()
+    }
+
+    /// Makes the track at `index` the current one, everything before it moves to `past`.
+    pub fn jump(&mut self, index: usize) -> Option<UniqueTrackIdentifier> {
+        if index >= self.future.len() {
+            return None;
+        }
+
+        let skipped = self.future.drain(..index).collect::<Vec<_>>();
+        self.past.extend(self.current.take());
+        self.past.extend(skipped);
+
+        self.forward()
+    }
+
+    /// Replaces everything, the first track of `tracks` becomes the current one.
+    ///
+    /// Shuffle is turned off, the new tracks are played in the order they were given in.
+    pub fn replace(&mut self, tracks: impl IntoIterator<Item = UniqueTrackIdentifier>) {
+        let mut tracks = tracks.into_iter();
+
+        self.shuffle = ShuffleMode::Off;
+        self.past.clear();
+        self.current = tracks.next();
+        self.future = tracks.collect();
+        self.unshuffled.clear();
+    }
+
+    /// Reorders `future` according to `mode`, `groupings` should contain all tracks of the queue.
+    pub fn set_shuffle(
+        &mut self,
+        mode: ShuffleMode,
+        groupings: &HashMap<UniqueTrackIdentifier, TrackGrouping>,
+    ) {
+        if self.shuffle != ShuffleMode::Off {
+            shuffle::restore(&mut self.future, &self.unshuffled);
+            self.unshuffled.clear();
+        }
+
+        self.shuffle = mode;
+        if mode == ShuffleMode::Off {
+            return;
+        }
+
+        self.unshuffled = self.future.iter().cloned().collect();
+
+        match mode {
+            ShuffleMode::Off => {}
+            ShuffleMode::Random => shuffle::random(&mut self.future),
+            ShuffleMode::Album => shuffle::by_album(&mut self.future, groupings),
+            ShuffleMode::WeightedArtist => shuffle::weighted_artist(
+                &mut self.future,
+                groupings,
+                self.past.iter().chain(self.current.as_ref()),
+            ),
+        }
+    }
 }
 
 impl Queue {
-    pub fn new(plugins: PluginSystem) -> Arc<Queue> {
+    pub fn new(plugins: PluginSystem, db: Database) -> Arc<Queue> {
         let update_tx = broadcast::Sender::new(16);
 
         Arc::new(Queue {
@@ -70,6 +309,7 @@ impl Queue {
 
             update_tx,
             plugin_system: plugins,
+            db,
         })
     }
 
@@ -81,19 +321,333 @@ impl Queue {
         let _ = self.update_tx.send(update);
     }
 
+    /// A copy of the whole queue as it is right now.
+    pub fn items(&self) -> QueueItems {
+        self.items.lock().unwrap().clone()
+    }
+
+    pub fn get_current_track(&self) -> Option<UniqueTrackIdentifier> {
+        self.items.lock().unwrap().get_at_offset(0)
+    }
+
     pub fn get_next_track(&self) -> Option<UniqueTrackIdentifier> {
-        self.items.lock().unwrap().get_at_offset(1)
+        self.items.lock().unwrap().next_track()
+    }
+
+    /// Whether `track` is still queued after the current track.
+    pub fn is_upcoming(&self, track: &UniqueTrackIdentifier) -> bool {
+        self.items.lock().unwrap().future.contains(track)
+    }
+
+    /// Called once the current track has ended.
+    pub fn advance(&self) -> Option<UniqueTrackIdentifier> {
+        let (previous, new) = {
+            let mut items = self.items.lock().unwrap();
+            let previous = items.current.clone();
+            (previous, items.advance())
+        };
+
+        if previous.is_some() || new.is_some() {
+            self.notify_update(QueueUpdate::CurrentTrackChanged);
+        }
+
+        new
     }
 
     pub fn forward(&self) -> Option<UniqueTrackIdentifier> {
-        let new = self.items.lock().unwrap().forward();
-        self.notify_update(QueueUpdate::CurrentTrackChanged);
+        let (previous, new) = {
+            let mut items = self.items.lock().unwrap();
+            let previous = items.current.clone();
+            (previous, items.forward())
+        };
+
+        // forwarding an empty queue happens all the time while nothing is playing
+        if previous.is_some() || new.is_some() {
+            self.notify_update(QueueUpdate::CurrentTrackChanged);
+        }
+
         new
     }
 
+    pub fn back(&self) -> Option<UniqueTrackIdentifier> {
+        let previous = self.items.lock().unwrap().back()?;
+        self.notify_update(QueueUpdate::CurrentTrackChanged);
+        Some(previous)
+    }
+
+    pub fn jump(&self, index: usize) -> Option<UniqueTrackIdentifier> {
+        let new = self.items.lock().unwrap().jump(index)?;
+        self.notify_update(QueueUpdate::CurrentTrackChanged);
+        Some(new)
+    }
+
     pub fn push(&self, track: UniqueTrackIdentifier) {
-        self.items.lock().unwrap().push(track.clone());
+        let index = self.items.lock().unwrap().push(track.clone());
+        self.notify_update(QueueUpdate::TrackInserted { index, track });
+    }
+
+    /// Queues `track` to play right after the current one.
+    pub fn insert_next(&self, track: UniqueTrackIdentifier) {
+        self.insert(0, track);
+    }
+
+    pub fn insert(&self, index: usize, track: UniqueTrackIdentifier) {
+        let index = self.items.lock().unwrap().insert(index, track.clone());
+        self.notify_update(QueueUpdate::TrackInserted { index, track });
+    }
+
+    pub fn remove(&self, index: usize) -> Option<UniqueTrackIdentifier> {
+        let track = self.items.lock().unwrap().remove(index)?;
+        self.notify_update(QueueUpdate::TrackRemoved {
+            index,
+            track: track.clone(),
+        });
+        Some(track)
+    }
+
+    pub fn move_track(&self, from: usize, to: usize) {
+        let Some(to) = self.items.lock().unwrap().move_track(from, to) else {
+            return;
+        };
+
+        if from != to {
+            self.notify_update(QueueUpdate::TrackMoved { from, to });
+        }
+    }
+
+    pub fn clear(&self) {
+        self.items.lock().unwrap().clear();
+        self.notify_update(QueueUpdate::Cleared);
+    }
+
+    pub fn replace(&self, tracks: impl IntoIterator<Item = UniqueTrackIdentifier>) {
+        let was_shuffled = {
+            let mut items = self.items.lock().unwrap();
+            let was_shuffled = items.shuffle != ShuffleMode::Off;
+            items.replace(tracks);
+            was_shuffled
+        };
+
+        self.notify_update(QueueUpdate::Replaced);
+        if was_shuffled {
+            self.notify_update(QueueUpdate::ShuffleChanged(ShuffleMode::Off));
+        }
+    }
+
+    /// Replaces everything including the repeat and shuffle state, e.g. with a saved queue.
+    pub fn restore(&self, items: QueueItems) {
+        let (repeat, shuffle) = (items.repeat, items.shuffle);
+        *self.items.lock().unwrap() = items;
+
+        self.notify_update(QueueUpdate::Replaced);
+        self.notify_update(QueueUpdate::RepeatChanged(repeat));
+        self.notify_update(QueueUpdate::ShuffleChanged(shuffle));
+    }
+
+    pub fn set_repeat(&self, mode: RepeatMode) {
+        self.items.lock().unwrap().repeat = mode;
+        self.notify_update(QueueUpdate::RepeatChanged(mode));
+    }
+
+    pub async fn set_shuffle(&self, mode: ShuffleMode) {
+        let groupings = match mode {
+            ShuffleMode::Album | ShuffleMode::WeightedArtist => {
+                let tracks = {
+                    let items = self.items.lock().unwrap();
+                    items
+                        .past
+                        .iter()
+                        .chain(items.current.as_ref())
+                        .chain(items.future.iter())
+                        .cloned()
+                        .collect::<Vec<_>>()
+                };
+
+                // tracks that were edited in the meantime are just treated as ungrouped
+                self.db
+                    .get_track_groupings(&tracks)
+                    .await
+                    .unwrap_or_else(|e| {
+                        warn!(
+                            "Failed to get track groupings, shuffling without them: {}",
+                            e
+                        );
+                        HashMap::new()
+                    })
+            }
+            ShuffleMode::Off | ShuffleMode::Random => HashMap::new(),
+        };
+
+        self.items.lock().unwrap().set_shuffle(mode, &groupings);
+        self.notify_update(QueueUpdate::ShuffleChanged(mode));
+    }
+}
+
+/// Tracks for tests of the queue and its shuffling, named after their number.
+#[cfg(test)]
+mod test_tracks {
+    use hogehoge_types::{PluginId, PluginTrackIdentifier, UniqueTrackIdentifier};
+
+    pub fn track(n: usize) -> UniqueTrackIdentifier {
+        UniqueTrackIdentifier {
+            plugin_id: PluginId(1),
+            plugin_data: PluginTrackIdentifier(n.to_string()),
+        }
+    }
+
+    pub fn tracks(range: std::ops::Range<usize>) -> Vec<UniqueTrackIdentifier> {
+        range.map(track).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_tracks::{track, tracks};
+    use super::*;
+
+    /// Track 0 is current, the rest is in `future`.
+    fn queue(len: usize) -> QueueItems {
+        let mut items = QueueItems::default();
+        items.replace(tracks(0..len));
+        items
+    }
+
+    fn future(items: &QueueItems) -> Vec<UniqueTrackIdentifier> {
+        items.future.iter().cloned().collect()
+    }
+
+    #[test]
+    fn insert_clamps_to_end() {
+        let mut items = queue(3);
+
+        assert_eq!(items.insert(0, track(10)), 0);
+        assert_eq!(items.insert(100, track(11)), 3);
+        assert_eq!(items.push(track(12)), 4);
+
+        assert_eq!(
+            future(&items),
+            [track(10), track(1), track(2), track(11), track(12)]
+        );
+    }
+
+    #[test]
+    fn remove_and_move() {
+        let mut items = queue(5);
+
+        assert_eq!(items.remove(1), Some(track(2)));
+        assert_eq!(items.remove(10), None);
+        assert_eq!(future(&items), [track(1), track(3), track(4)]);
+
+        assert_eq!(items.move_track(0, 2), Some(2));
+        assert_eq!(future(&items), [track(3), track(4), track(1)]);
+
+        assert_eq!(items.move_track(2, 100), Some(2));
+        assert_eq!(items.move_track(5, 0), None);
+        assert_eq!(future(&items), [track(3), track(4), track(1)]);
+    }
+
+    #[test]
+    fn forward_and_back() {
+        let mut items = queue(3);
+
+        assert_eq!(items.forward(), Some(track(1)));
+        assert_eq!(items.forward(), Some(track(2)));
+        assert_eq!(items.past, [track(0), track(1)]);
+
+        assert_eq!(items.back(), Some(track(1)));
+        assert_eq!(items.past, [track(0)]);
+        assert_eq!(future(&items), [track(2)]);
+
+        assert_eq!(items.forward(), Some(track(2)));
+        assert_eq!(items.forward(), None);
+        assert_eq!(items.past, tracks(0..3));
+        assert_eq!(items.back(), Some(track(2)));
+    }
+
+    #[test]
+    fn jump_moves_skipped_tracks_to_past() {
+        let mut items = queue(5);
+
+        assert_eq!(items.jump(2), Some(track(3)));
+        assert_eq!(items.past, tracks(0..3));
+        assert_eq!(future(&items), [track(4)]);
+
+        assert_eq!(items.jump(1), None);
+        assert_eq!(items.current, Some(track(3)));
+    }
+
+    #[test]
+    fn clear_keeps_current() {
+        let mut items = queue(4);
+        items.forward();
+
+        items.clear();
+        assert!(items.past.is_empty());
+        assert!(items.future.is_empty());
+        assert_eq!(items.current, Some(track(1)));
+    }
+
+    #[test]
+    fn unshuffle_restores_order() {
+        fastrand::seed(1);
+
+        let mut items = queue(20);
+        items.set_shuffle(ShuffleMode::Random, &HashMap::new());
+        assert_ne!(future(&items), tracks(1..20));
+
+        // edits made while shuffled are kept, new tracks end up at the end
+        items.remove(items.future.iter().position(|t| *t == track(5)).unwrap());
+        items.push(track(20));
+
+        items.set_shuffle(ShuffleMode::Off, &HashMap::new());
+        let mut expected = tracks(1..21);
+        expected.retain(|t| *t != track(5));
+        assert_eq!(future(&items), expected);
+    }
+
+    #[test]
+    fn reshuffle_starts_from_original_order() {
+        let mut items = queue(10);
+        items.set_shuffle(ShuffleMode::Random, &HashMap::new());
+        items.set_shuffle(ShuffleMode::Random, &HashMap::new());
+        items.set_shuffle(ShuffleMode::Off, &HashMap::new());
+
+        assert_eq!(future(&items), tracks(1..10));
+    }
+
+    #[test]
+    fn repeat_one() {
+        let mut items = queue(3);
+        items.repeat = RepeatMode::One;
+
+        assert_eq!(items.next_track(), Some(track(0)));
+        assert_eq!(items.advance(), Some(track(0)));
+        // skipping still moves on
+        assert_eq!(items.forward(), Some(track(1)));
+    }
+
+    #[test]
+    fn repeat_all_wraps_around() {
+        let mut items = queue(3);
+        items.repeat = RepeatMode::All;
+        items.forward();
+        items.forward();
+
+        assert_eq!(items.next_track(), Some(track(0)));
+        assert_eq!(items.advance(), Some(track(0)));
+        assert!(items.past.is_empty());
+        assert_eq!(future(&items), [track(1), track(2)]);
+    }
+
+    #[test]
+    fn offsets() {
+        let mut items = queue(4);
+        items.forward();
 
-        self.notify_update(QueueUpdate::TrackAdded(track));
+        assert_eq!(items.get_at_offset(-1), Some(track(0)));
+        assert_eq!(items.get_at_offset(-2), None);
+        assert_eq!(items.get_at_offset(0), Some(track(1)));
+        assert_eq!(items.get_at_offset(2), Some(track(3)));
+        assert_eq!(items.get_at_offset(3), None);
     }
 }
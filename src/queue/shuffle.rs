@@ -0,0 +1,268 @@
+use hogehoge_db::TrackGrouping;
+use hogehoge_types::{AlbumId, ArtistId, UniqueTrackIdentifier};
+use std::collections::{HashMap, VecDeque};
+
+// how many of the last played artists are avoided by the weighted shuffle
+const RECENT_ARTIST_WINDOW: usize = 8;
+
+pub fn random(tracks: &mut VecDeque<UniqueTrackIdentifier>) {
+    fastrand::shuffle(tracks.make_contiguous());
+}
+
+/// Shuffles the order of albums, tracks of the same album stay together in their current order.
+pub fn by_album(
+    tracks: &mut VecDeque<UniqueTrackIdentifier>,
+    groupings: &HashMap<UniqueTrackIdentifier, TrackGrouping>,
+) {
+    let mut albums: Vec<Vec<UniqueTrackIdentifier>> = Vec::new();
+    let mut album_indices: HashMap<AlbumId, usize> = HashMap::new();
+
+    for track in tracks.drain(..) {
+        let album_id = groupings.get(&track).and_then(|g| g.album_id);
+
+        // tracks without an album are an album of their own
+        match album_id.and_then(|id| album_indices.get(&id)) {
+            Some(&index) => albums[index].push(track),
+            None => {
+                if let Some(album_id) = album_id {
+                    album_indices.insert(album_id, albums.len());
+                }
+                albums.push(vec![track]);
+            }
+        }
+    }
+
+    fastrand::shuffle(&mut albums);
+    tracks.extend(albums.into_iter().flatten());
+}
+
+/// Random order that makes it unlikely to hear the same artist again soon.
+///
+/// `recent` are the tracks that were played before, oldest first.
+pub fn weighted_artist<'a>(
+    tracks: &mut VecDeque<UniqueTrackIdentifier>,
+    groupings: &HashMap<UniqueTrackIdentifier, TrackGrouping>,
+    recent: impl DoubleEndedIterator<Item = &'a UniqueTrackIdentifier>,
+) {
+    let artist_of = |track: &UniqueTrackIdentifier| groupings.get(track).and_then(|g| g.artist_id);
+
+    // tracks without a known artist are never considered recent, so they each get their own group
+    let mut artists: Vec<(Option<ArtistId>, Vec<UniqueTrackIdentifier>)> = Vec::new();
+    let mut artist_indices: HashMap<ArtistId, usize> = HashMap::new();
+    for track in tracks.drain(..) {
+        let artist_id = artist_of(&track);
+
+        match artist_id.and_then(|id| artist_indices.get(&id)) {
+            Some(&index) => artists[index].1.push(track),
+            None => {
+                if let Some(artist_id) = artist_id {
+                    artist_indices.insert(artist_id, artists.len());
+                }
+                artists.push((artist_id, vec![track]));
+            }
+        }
+    }
+
+    for (_, tracks) in &mut artists {
+        fastrand::shuffle(tracks);
+    }
+
+    // with only a handful of artists, avoiding all of them would just bunch them up at the end
+    let window = RECENT_ARTIST_WINDOW.min(artist_indices.len().saturating_sub(1));
+
+    let mut recent_artists: VecDeque<ArtistId> = recent
+        .rev()
+        .filter_map(artist_of)
+        .take(window)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect();
+
+    while !artists.is_empty() {
+        let weights = artists
+            .iter()
+            .map(|(artist_id, tracks)| {
+                let recency = artist_id
+                    .and_then(|id| recent_artists.iter().rposition(|recent| *recent == id))
+                    // 1 for the artist that was just played
+                    .map(|index| recent_artists.len() - index);
+
+                let penalty = match recency {
+                    Some(distance) => (distance as f64 / (window + 1) as f64).powi(2),
+                    None => 1.0,
+                };
+
+                tracks.len() as f64 * penalty
+            })
+            .collect::<Vec<_>>();
+
+        let mut pick = fastrand::f64() * weights.iter().sum::<f64>();
+        let index = weights
+            .iter()
+            .position(|weight| {
+                pick -= weight;
+                pick < 0.0
+            })
+            .unwrap_or(artists.len() - 1);
+
+        let (artist_id, artist_tracks) = &mut artists[index];
+        let artist_id = *artist_id;
+        if let Some(track) = artist_tracks.pop() {
+            tracks.push_back(track);
+        }
+        if artist_tracks.is_empty() {
+            artists.swap_remove(index);
+        }
+
+        if let Some(artist_id) = artist_id {
+            recent_artists.push_back(artist_id);
+            while recent_artists.len() > window {
+                recent_artists.pop_front();
+            }
+        }
+    }
+}
+
+/// Puts `tracks` back into the order they had in `original`. Tracks that weren't part of it keep
+/// their relative order at the end.
+pub fn restore(tracks: &mut VecDeque<UniqueTrackIdentifier>, original: &[UniqueTrackIdentifier]) {
+    let mut original_indices: HashMap<&UniqueTrackIdentifier, VecDeque<usize>> = HashMap::new();
+    for (index, track) in original.iter().enumerate() {
+        original_indices.entry(track).or_default().push_back(index);
+    }
+
+    let mut keyed = tracks
+        .drain(..)
+        .map(|track| {
+            let index = original_indices
+                .get_mut(&track)
+                .and_then(|indices| indices.pop_front())
+                .unwrap_or(usize::MAX);
+            (index, track)
+        })
+        .collect::<Vec<_>>();
+
+    keyed.sort_by_key(|(index, _)| *index);
+    tracks.extend(keyed.into_iter().map(|(_, track)| track));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::test_tracks::track;
+
+    fn sorted(tracks: impl IntoIterator<Item = UniqueTrackIdentifier>) -> Vec<String> {
+        let mut tracks = tracks
+            .into_iter()
+            .map(|track| track.plugin_data.0)
+            .collect::<Vec<_>>();
+        tracks.sort();
+        tracks
+    }
+
+    #[test]
+    fn restore_undoes_random() {
+        fastrand::seed(1);
+
+        let original = (0..20).map(track).collect::<Vec<_>>();
+        let mut tracks = original.iter().cloned().collect::<VecDeque<_>>();
+
+        random(&mut tracks);
+        assert_ne!(tracks, original);
+
+        restore(&mut tracks, &original);
+        assert_eq!(tracks, original);
+    }
+
+    #[test]
+    fn restore_keeps_new_tracks_at_the_end() {
+        let original = [track(0), track(1), track(1), track(2)];
+        let mut tracks = VecDeque::from([track(9), track(2), track(1), track(8), track(0)]);
+
+        restore(&mut tracks, &original);
+        assert_eq!(tracks, [track(0), track(1), track(2), track(9), track(8)]);
+    }
+
+    #[test]
+    fn album_shuffle_keeps_albums_together() {
+        fastrand::seed(2);
+
+        // 4 albums of 5 tracks each, interleaved, plus 3 tracks without an album
+        let groupings = (0..23)
+            .map(|n| {
+                let grouping = TrackGrouping {
+                    album_id: (n < 20).then_some(AlbumId((n % 4) as i64)),
+                    artist_id: None,
+                };
+                (track(n), grouping)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let original = (0..23).map(track).collect::<Vec<_>>();
+        let mut tracks = original.iter().cloned().collect::<VecDeque<_>>();
+        by_album(&mut tracks, &groupings);
+
+        assert_eq!(sorted(tracks.clone()), sorted(original));
+
+        let album_of = |track: &UniqueTrackIdentifier| groupings[track].album_id;
+        for album in 0..4 {
+            let positions = tracks
+                .iter()
+                .enumerate()
+                .filter(|(_, track)| album_of(track) == Some(AlbumId(album)))
+                .map(|(position, _)| position)
+                .collect::<Vec<_>>();
+
+            assert_eq!(positions.len(), 5);
+            assert_eq!(
+                positions[4] - positions[0],
+                4,
+                "album {} is split up",
+                album
+            );
+
+            // tracks keep their order within the album
+            let numbers = positions
+                .iter()
+                .map(|&position| tracks[position].plugin_data.0.parse::<i64>().unwrap())
+                .collect::<Vec<_>>();
+            assert!(numbers.is_sorted());
+        }
+    }
+
+    #[test]
+    fn weighted_shuffle_avoids_recent_artists() {
+        fastrand::seed(3);
+
+        // one track for each of 5 artists, the first of them was just played
+        let groupings = (0..6)
+            .map(|n| {
+                let grouping = TrackGrouping {
+                    album_id: None,
+                    artist_id: Some(ArtistId((n % 5) as i64)),
+                };
+                (track(n), grouping)
+            })
+            .collect::<HashMap<_, _>>();
+        let played = [track(5)];
+
+        let mut first_is_recent = 0;
+        for _ in 0..200 {
+            let mut tracks = (0..5).map(track).collect::<VecDeque<_>>();
+            weighted_artist(&mut tracks, &groupings, played.iter());
+
+            assert_eq!(sorted(tracks.clone()), sorted((0..5).map(track)));
+            if tracks[0] == track(0) {
+                first_is_recent += 1;
+            }
+        }
+
+        // about 40 of 200 without the weighting
+        assert!(
+            first_is_recent < 15,
+            "recently played artist came first {} times",
+            first_is_recent
+        );
+    }
+}
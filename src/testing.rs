@@ -0,0 +1,36 @@
+//! Fixtures shared by the tests of multiple modules.
+
+use hogehoge_db::Database;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A directory in the system temp directory that is removed again when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("hogehoge-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+
+        TempDir(path)
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.0.join(path)
+    }
+
+    /// A database in this directory, not connected to the UI.
+    pub async fn database(&self) -> Database {
+        Database::connect_headless(self.join("library.db"))
+            .await
+            .unwrap()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
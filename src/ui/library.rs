@@ -12,8 +12,11 @@ use crate::ui::*;
 pub fn LibraryStats() -> Element {
     let library = use_context_resource::<Library>()?;
 
-    let stats = library.read().stats();
-    let stats = stats.read();
+    let stats = library
+        .read()
+        .stats()
+        .map(|stats| stats.cloned())
+        .unwrap_or_default();
     rsx!(label {
         "Tracks: {stats.num_tracks}, Track Groups: {stats.num_track_groups}, Artists: {stats.num_artists}, Albums: {stats.num_albums}",
     })
@@ -42,7 +45,13 @@ pub fn LibraryView() -> Element {
     let mut track_list = use_signal(Vec::new);
     let db_clone = db.clone();
 
-    let memoized_stats = use_memo(move || library.read().stats().cloned());
+    let memoized_stats = use_memo(move || {
+        library
+            .read()
+            .stats()
+            .map(|stats| stats.cloned())
+            .unwrap_or_default()
+    });
 
     let mut get_track_list = use_future(move || {
         let db = db_clone.clone();
@@ -241,10 +250,13 @@ pub fn LibraryItem(index: usize, track: ReadOnlySignal<Track>) -> Element {
 
         onmouseenter: move |_| state.set(State::Hovered),
         onmouseleave: move |_| state.set(State::Idle),
-        onmousedown: move |_| {
-            player.read().queue.push(
-                track.read().identifier.clone()
-            );
+        onmousedown: move |e: MouseEvent| {
+            let identifier = track.read().identifier.clone();
+            // right clicking queues the track to play next instead of at the end
+            match e.get_trigger_button() {
+                Some(MouseButton::Right) => player.read().queue.insert_next(identifier),
+                _ => player.read().queue.push(identifier),
+            }
 
             state.set(State::Pressed);
         },
@@ -1,13 +1,35 @@
 use crate::ui::*;
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MainView {
+    #[default]
+    Library,
+    Queue,
+    Settings,
+}
+
 #[component]
 pub fn MainContent() -> Element {
-    rsx!(rect {
-        height: "fill",
-        width: "fill",
-        LibraryView {}
-        BottomBar {}
-    })
+    let view = use_context::<Signal<MainView>>();
+
+    match *view.read() {
+        MainView::Library => rsx!(rect {
+            height: "fill",
+            width: "fill",
+            LibraryView {}
+            BottomBar {}
+        }),
+        MainView::Queue => rsx!(rect {
+            height: "fill",
+            width: "fill",
+            QueueView {}
+        }),
+        MainView::Settings => rsx!(rect {
+            height: "fill",
+            width: "fill",
+            SettingsView {}
+        }),
+    }
 }
 
 
@@ -13,9 +13,13 @@ pub use side_bar::SideBar;
 mod player;
 pub use player::PlayerBar;
 mod main_content;
-pub use main_content::MainContent;
+pub use main_content::{MainContent, MainView};
 mod library;
 pub use library::{LibraryStats, LibraryView};
+mod queue;
+pub use queue::QueueView;
+mod settings;
+pub use settings::SettingsView;
 
 use std::sync::LazyLock;
 pub static DEFAULT_THEME: LazyLock<Theme> = LazyLock::new(|| {
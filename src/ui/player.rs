@@ -1,62 +1,459 @@
-use crate::audio::{AudioPlayer, PlaybackState};
+use crate::audio::{AudioPlayer, PlaybackState, SleepState, SleepTimer, VisualizerFrame};
+use crate::queue::{RepeatMode, ShuffleMode};
 use crate::ui::*;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+// the speed button steps through these
+const SPEEDS: [f32; 9] = [0.5, 0.75, 1.0, 1.25, 1.5, 1.75, 2.0, 2.5, 3.0];
+
+// how far the skip buttons next to the time jump
+const SEEK_STEP_SECS: f64 = 10.0;
+
+// log spaced bands the spectrum is grouped into
+const SPECTRUM_BARS: usize = 24;
+const SPECTRUM_MIN_FREQUENCY: f32 = 40.0;
+const SPECTRUM_MAX_FREQUENCY: f32 = 16000.0;
+// in dB below full scale, quieter bands don't show up at all
+const SPECTRUM_RANGE_DB: f32 = 80.0;
+
+// the sleep timer button steps through these, and then turns it off again
+const SLEEP_TIMERS: [SleepTimer; 5] = [
+    SleepTimer::After(Duration::from_secs(15 * 60)),
+    SleepTimer::After(Duration::from_secs(30 * 60)),
+    SleepTimer::After(Duration::from_secs(60 * 60)),
+    SleepTimer::EndOfTrack,
+    SleepTimer::EndOfAlbum,
+];
 
 #[component]
 pub fn PlayerBar() -> Element {
-    let player = use_context_resource::<AudioPlayer>()?;
+    let theme = use_context::<Theme>();
+    let player = use_context_resource::<AudioPlayer>()?.read().clone();
 
     let mut playback_state = use_signal(PlaybackState::default);
-    use_future(move || {
-        let mut state_rx = player.read().subscribe_state();
+    use_future({
+        let player = player.clone();
+        move || {
+            let mut state_rx = player.subscribe_state();
 
-        async move {
-            loop {
-                *playback_state.write() = state_rx.borrow_and_update().clone();
+            async move {
+                loop {
+                    *playback_state.write() = state_rx.borrow_and_update().clone();
+
+                    // dbg!(playback_state.read());
 
-                // dbg!(playback_state.read());
+                    let _ = state_rx.changed().await;
+                }
+            }
+        }
+    });
+
+    let mut modes = use_signal(|| (RepeatMode::default(), ShuffleMode::default()));
+    use_future({
+        let player = player.clone();
+        move || {
+            let queue = player.queue.clone();
+            let mut update_rx = queue.subscribe_updates();
+
+            async move {
+                loop {
+                    let items = queue.items();
+                    modes.set((items.repeat, items.shuffle));
 
-                let _ = state_rx.changed().await;
+                    if let Err(RecvError::Closed) = update_rx.recv().await {
+                        break;
+                    }
+                }
             }
         }
     });
+    let (repeat, shuffle) = *modes.read();
+
+    let volume = playback_state.read().volume();
+
+    // which of `SLEEP_TIMERS` was picked last
+    let mut sleep_choice = use_signal(|| None::<usize>);
+
+    let (paused, speed, sleep, time) = match &*playback_state.read() {
+        PlaybackState::Stopped { .. } => (true, None, None, None),
+        PlaybackState::Playing {
+            position,
+            duration,
+            paused,
+            speed,
+            sleep,
+            ..
+        } => (
+            *paused,
+            Some(*speed),
+            *sleep,
+            Some(match duration {
+                Some(duration) => format!(
+                    "{} / {}",
+                    format_duration(*position),
+                    format_duration(*duration)
+                ),
+                None => format_duration(*position),
+            }),
+        ),
+    };
 
     rsx!(rect {
         width: "100%",
-        height: "32",
-        background: "white",
+        height: "40",
+        background: theme.colors.container,
 
         rect {
-            height: "calc(100% - 2px)",
-        }
+            width: "100%",
+            height: "calc(100% - 4)",
+            direction: "horizontal",
+            cross_align: "center",
+            padding: "0 4",
+            spacing: "4",
 
+            IconButton {
+                icon: theme.icons.previous.clone(),
+                shadow: "none",
+                onclick: {
+                    let player = player.clone();
+                    move |_| {
+                        player.queue.back();
+                    }
+                },
+            },
+            IconButton {
+                icon: if paused { theme.icons.play.clone() } else { theme.icons.pause.clone() },
+                shadow: "none",
+                onclick: {
+                    let player = player.clone();
+                    move |_| player.toggle_pause()
+                },
+            },
+            IconButton {
+                icon: theme.icons.stop.clone(),
+                shadow: "none",
+                onclick: {
+                    let player = player.clone();
+                    move |_| player.stop()
+                },
+            },
+            IconButton {
+                icon: theme.icons.next.clone(),
+                shadow: "none",
+                onclick: {
+                    let player = player.clone();
+                    move |_| {
+                        player.queue.forward();
+                    }
+                },
+            },
+            IconButton {
+                icon: match repeat {
+                    RepeatMode::One => theme.icons.repeat_one.clone(),
+                    RepeatMode::Off | RepeatMode::All => theme.icons.repeat.clone(),
+                },
+                shadow: "none",
+                onclick: {
+                    let player = player.clone();
+                    move |_| {
+                        player.queue.set_repeat(match repeat {
+                            RepeatMode::Off => RepeatMode::All,
+                            RepeatMode::All => RepeatMode::One,
+                            RepeatMode::One => RepeatMode::Off,
+                        })
+                    }
+                },
+            },
+            IconButton {
+                icon: theme.icons.shuffle.clone(),
+                shadow: "none",
+                onclick: {
+                    let player = player.clone();
+                    move |_| {
+                        let queue = player.queue.clone();
+                        spawn(async move {
+                            queue
+                                .set_shuffle(match shuffle {
+                                    ShuffleMode::Off => ShuffleMode::Random,
+                                    ShuffleMode::Random => ShuffleMode::Album,
+                                    ShuffleMode::Album => ShuffleMode::WeightedArtist,
+                                    ShuffleMode::WeightedArtist => ShuffleMode::Off,
+                                })
+                                .await
+                        });
+                    }
+                },
+            },
+            label {
+                "{repeat_label(repeat)}, {shuffle_label(shuffle)}"
+            }
+            if let Some(time) = time {
+                Button {
+                    shadow: "none",
+                    onclick: {
+                        let player = player.clone();
+                        move |_| player.seek_relative(-SEEK_STEP_SECS)
+                    },
+                    label {
+                        "-{SEEK_STEP_SECS}s"
+                    }
+                }
+                label {
+                    "{time}"
+                }
+                Button {
+                    shadow: "none",
+                    onclick: {
+                        let player = player.clone();
+                        move |_| player.seek_relative(SEEK_STEP_SECS)
+                    },
+                    label {
+                        "+{SEEK_STEP_SECS}s"
+                    }
+                }
+            }
+            if let Some(speed) = speed {
+                Button {
+                    shadow: "none",
+                    onclick: {
+                        let player = player.clone();
+                        move |_| player.set_speed(next_speed(speed))
+                    },
+                    label {
+                        "{speed}x"
+                    }
+                }
+                Button {
+                    shadow: "none",
+                    onclick: {
+                        let player = player.clone();
+                        move |_| {
+                            let next = match (sleep, *sleep_choice.read()) {
+                                (Some(_), Some(choice)) => choice + 1,
+                                _ => 0,
+                            };
+
+                            match SLEEP_TIMERS.get(next) {
+                                Some(timer) => {
+                                    sleep_choice.set(Some(next));
+                                    player.set_sleep_timer(*timer);
+                                }
+                                None => {
+                                    sleep_choice.set(None);
+                                    player.cancel_sleep_timer();
+                                }
+                            }
+                        }
+                    },
+                    label {
+                        "{sleep_label(sleep, *sleep_choice.read())}"
+                    }
+                }
+                if sleep.is_some() {
+                    Button {
+                        shadow: "none",
+                        onclick: {
+                            let player = player.clone();
+                            move |_| {
+                                sleep_choice.set(None);
+                                player.cancel_sleep_timer();
+                            }
+                        },
+                        label {
+                            "Cancel"
+                        }
+                    }
+                }
+            }
+            Spectrum {}
+            IconButton {
+                icon: if volume.muted {
+                    theme.icons.volume_mute.clone()
+                } else {
+                    theme.icons.volume_up.clone()
+                },
+                shadow: "none",
+                onclick: {
+                    let player = player.clone();
+                    move |_| player.toggle_mute()
+                },
+            },
+            Slider {
+                size: "100",
+                value: volume.level as f64 * 100.0,
+                onmoved: {
+                    let player = player.clone();
+                    move |value: f64| player.set_volume(value as f32 / 100.0)
+                },
+            },
+        }
 
         ProgressBar {
-            playback_state
+            playback_state,
+            onseek: move |position| player.seek(position),
         },
     })
 }
 
 #[component]
-fn ProgressBar(playback_state: Signal<PlaybackState>) -> Element {
+fn Spectrum() -> Element {
+    let theme = use_context::<Theme>();
+    let player = use_context_resource::<AudioPlayer>()?.read().clone();
+
+    let mut bars = use_signal(|| vec![0.0; SPECTRUM_BARS]);
+    use_future(move || {
+        let mut frame_rx = player.subscribe_visualizer();
+
+        async move {
+            loop {
+                bars.set(spectrum_bars(&frame_rx.borrow_and_update()));
+
+                if frame_rx.changed().await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    rsx!(rect {
+        height: "24",
+        direction: "horizontal",
+        cross_align: "end",
+        spacing: "1",
+
+        for (i, level) in bars.read().iter().enumerate() {
+            rect {
+                key: "{i}",
+                width: "3",
+                height: "{level * 100.0}%",
+                background: theme.colors.foreground,
+            }
+        }
+    })
+}
+
+#[component]
+fn ProgressBar(playback_state: Signal<PlaybackState>, onseek: Callback<Duration>) -> Element {
     let playback_state = playback_state.read();
     let theme = use_context::<Theme>();
+    let (reference, layout) = use_node_signal();
 
-    let progress = match &*playback_state {
-        PlaybackState::Stopped => 0.0,
+    let (progress, seek_duration) = match &*playback_state {
+        PlaybackState::Stopped { .. } => (0.0, None),
         PlaybackState::Playing {
-            position, duration, ..
-        } => duration.map_or(1.0, |d| {
-            if d.as_secs() == 0 {
-                1.0
-            } else {
-                position.as_secs_f64() / d.as_secs_f64()
-            }
-        }),
+            position,
+            duration,
+            seekable,
+            ..
+        } => (
+            duration.map_or(1.0, |d| {
+                if d.as_secs() == 0 {
+                    1.0
+                } else {
+                    position.as_secs_f64() / d.as_secs_f64()
+                }
+            }),
+            duration.filter(|_| *seekable),
+        ),
     };
 
     rsx!(rect {
-        width: format!("{}%", progress * 100.0),
-        height: "2",
-        background: theme.colors.foreground,
+        reference,
+        width: "100%",
+        height: "4",
+
+        onclick: move |e: MouseEvent| {
+            let Some(duration) = seek_duration else {
+                return;
+            };
+
+            let width = layout.read().area.width() as f64;
+            if width <= 0.0 {
+                return;
+            }
+
+            let fraction = (e.get_element_coordinates().x / width).clamp(0.0, 1.0);
+            onseek.call(duration.mul_f64(fraction));
+        },
+
+        rect {
+            width: format!("{}%", progress * 100.0),
+            height: "100%",
+            background: theme.colors.foreground,
+        }
     })
 }
+
+/// Levels between `0.0` and `1.0` of the loudest bin in each band.
+fn spectrum_bars(frame: &VisualizerFrame) -> Vec<f32> {
+    // nothing was analyzed yet
+    if frame.spectrum.is_empty() {
+        return vec![0.0; SPECTRUM_BARS];
+    }
+
+    let ratio = (SPECTRUM_MAX_FREQUENCY / SPECTRUM_MIN_FREQUENCY).powf(1.0 / SPECTRUM_BARS as f32);
+
+    (0..SPECTRUM_BARS)
+        .map(|bar| {
+            let low = SPECTRUM_MIN_FREQUENCY * ratio.powi(bar as i32);
+            let first = (low / frame.bin_width) as usize;
+            // low bands are narrower than a bin, they get the one they fall into
+            let last = ((low * ratio / frame.bin_width) as usize).max(first + 1);
+
+            let loudest = frame
+                .spectrum
+                .get(first..last.min(frame.spectrum.len()))
+                .and_then(|bins| bins.iter().copied().reduce(f32::max));
+
+            loudest.map_or(0.0, |db| (1.0 + db / SPECTRUM_RANGE_DB).clamp(0.0, 1.0))
+        })
+        .collect()
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// The preset after `speed`, wrapping around to the slowest one.
+fn next_speed(speed: f32) -> f32 {
+    SPEEDS
+        .into_iter()
+        .find(|&preset| preset > speed + 0.01)
+        .unwrap_or(SPEEDS[0])
+}
+
+fn sleep_label(sleep: Option<SleepState>, choice: Option<usize>) -> String {
+    match sleep {
+        None => "Sleep timer".to_string(),
+        Some(SleepState::Remaining(remaining)) => {
+            format!("Sleep in {}", format_duration(remaining))
+        }
+        Some(SleepState::AfterTrack) => match choice.map(|choice| SLEEP_TIMERS[choice]) {
+            Some(SleepTimer::EndOfAlbum) => "Sleep after album".to_string(),
+            _ => "Sleep after track".to_string(),
+        },
+    }
+}
+
+fn repeat_label(mode: RepeatMode) -> &'static str {
+    match mode {
+        RepeatMode::Off => "No repeat",
+        RepeatMode::One => "Repeat track",
+        RepeatMode::All => "Repeat queue",
+    }
+}
+
+fn shuffle_label(mode: ShuffleMode) -> &'static str {
+    match mode {
+        ShuffleMode::Off => "in order",
+        ShuffleMode::Random => "shuffled",
+        ShuffleMode::Album => "shuffled by album",
+        ShuffleMode::WeightedArtist => "shuffled by artist",
+    }
+}
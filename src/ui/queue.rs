@@ -0,0 +1,179 @@
+use hogehoge_db::Database;
+use hogehoge_types::{TagKind, UniqueTrackIdentifier};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::audio::AudioPlayer;
+use crate::queue::QueueItems;
+use crate::ui::*;
+
+#[component]
+pub fn QueueView() -> Element {
+    let theme = use_context::<Theme>();
+    let player = use_context_resource::<AudioPlayer>()?.read().clone();
+
+    let mut items = use_signal(QueueItems::default);
+    use_future({
+        let player = player.clone();
+        move || {
+            let queue = player.queue.clone();
+            let mut update_rx = queue.subscribe_updates();
+
+            async move {
+                loop {
+                    items.set(queue.items());
+
+                    if let Err(RecvError::Closed) = update_rx.recv().await {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    let current = items.read().current.clone();
+    let future = items.read().future.iter().cloned().collect::<Vec<_>>();
+    let last = future.len().saturating_sub(1);
+
+    rsx!(rect {
+        width: "fill",
+        height: "fill",
+        background: theme.colors.container,
+        corner_radius: "4",
+        padding: "8",
+        spacing: "4",
+
+        rect {
+            width: "fill",
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            label {
+                font_size: "16",
+                "Queue"
+            }
+            Button {
+                onclick: {
+                    let player = player.clone();
+                    move |_| player.queue.clear()
+                },
+                label {
+                    "Clear"
+                }
+            }
+        }
+
+        if let Some(track) = current {
+            rect {
+                width: "fill",
+                height: "32",
+                direction: "horizontal",
+                cross_align: "center",
+                padding: "0 8",
+                background: theme.colors.table_row_hover,
+                QueueTrackTitle { track }
+            }
+        }
+
+        ScrollView {
+            spacing: "4",
+
+            for (index, track) in future.into_iter().enumerate() {
+                rect {
+                    key: "{index}",
+                    width: "fill",
+                    height: "32",
+                    direction: "horizontal",
+                    cross_align: "center",
+                    padding: "0 8",
+                    spacing: "4",
+                    content: "flex",
+                    background: if index % 2 == 0 {
+                        Some(theme.colors.table_row_alt)
+                    } else {
+                        None
+                    },
+
+                    IconButton {
+                        icon: theme.icons.play.clone(),
+                        shadow: "none",
+                        onclick: {
+                            let player = player.clone();
+                            move |_| {
+                                player.queue.jump(index);
+                            }
+                        },
+                    }
+                    rect {
+                        width: "flex(1)",
+                        QueueTrackTitle { track }
+                    }
+                    if index > 0 {
+                        Button {
+                            shadow: "none",
+                            onclick: {
+                                let player = player.clone();
+                                move |_| player.queue.move_track(index, index - 1)
+                            },
+                            label {
+                                "Up"
+                            }
+                        }
+                    }
+                    if index < last {
+                        Button {
+                            shadow: "none",
+                            onclick: {
+                                let player = player.clone();
+                                move |_| player.queue.move_track(index, index + 1)
+                            },
+                            label {
+                                "Down"
+                            }
+                        }
+                    }
+                    Button {
+                        shadow: "none",
+                        onclick: {
+                            let player = player.clone();
+                            move |_| {
+                                player.queue.remove(index);
+                            }
+                        },
+                        label {
+                            "Remove"
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[component]
+fn QueueTrackTitle(track: ReadOnlySignal<UniqueTrackIdentifier>) -> Element {
+    let db = use_context_resource::<Database>()?;
+
+    let title = use_resource(move || {
+        let db = db.read().clone();
+        let track = track.read().clone();
+
+        async move {
+            match db.get_track_by_identifier(&track).await {
+                Ok(Some(track)) => track.tags.get(TagKind::TrackTitle).to_string(),
+                Ok(None) => "Unknown track".to_string(),
+                Err(e) => {
+                    tracing::error!("Failed to fetch queued track: {e}");
+                    "Loading this track failed".to_string()
+                }
+            }
+        }
+    });
+
+    rsx!(label {
+        width: "fill",
+        max_lines: "1",
+        text_overflow: "ellipsis",
+        "{title.read().clone().unwrap_or_default()}"
+    })
+}
@@ -0,0 +1,1562 @@
+use crate::Library;
+use crate::audio::{
+    AudioPlayer, DspProfile, DspSettings, EffectSettings, EqBand, EqBandKind, OutputDeviceState,
+    OutputSettings, ReplayGainMode, ReplayGainSettings, ResamplerQuality, TransitionSettings,
+};
+use crate::plugin::{PluginLimits, PluginSystem};
+use crate::ui::*;
+use hogehoge_db::PluginMount;
+use hogehoge_types::{PluginId, SettingDeclaration, SettingKind, SettingValue};
+use std::{path::PathBuf, time::Duration};
+
+#[component]
+pub fn SettingsView() -> Element {
+    let theme = use_context::<Theme>();
+
+    rsx!(rect {
+        width: "fill",
+        height: "fill",
+        background: theme.colors.container,
+        corner_radius: "4",
+
+        ScrollView {
+            SettingsSection {
+                title: "ReplayGain",
+                ReplayGainEditor {},
+                LoudnessAnalysis {},
+            }
+            SettingsSection {
+                title: "Transitions",
+                TransitionEditor {},
+            }
+            SettingsSection {
+                title: "Resume",
+                ResumeEditor {},
+            }
+            SettingsSection {
+                title: "Output",
+                OutputDeviceEditor {},
+                OutputEditor {},
+            }
+            SettingsSection {
+                title: "Equalizer",
+                EqualizerEditor {},
+                EqPresets {},
+            }
+            SettingsSection {
+                title: "Effects",
+                EffectsEditor {},
+            }
+            SettingsSection {
+                title: "Plugins",
+                PluginList {},
+            }
+            SettingsSection {
+                title: "Plugin Mounts",
+                PluginMountSettings {},
+            }
+            SettingsSection {
+                title: "Plugin Settings",
+                PluginSettingsEditor {},
+            }
+        }
+    })
+}
+
+#[component]
+fn SettingsSection(title: String, children: Element) -> Element {
+    rsx!(rect {
+        width: "fill",
+        padding: "12",
+        spacing: "8",
+
+        label {
+            font_size: "18",
+            font_weight: "bold",
+            "{title}",
+        },
+        {children}
+    })
+}
+
+// range of the pre-amp and fallback sliders, in both directions
+const REPLAY_GAIN_RANGE_DB: f32 = 15.0;
+
+// longest resume threshold the slider goes up to, in minutes
+const MAX_RESUME_THRESHOLD: u64 = 120;
+
+const OUTPUT_SAMPLE_RATES: [u32; 5] = [44100, 48000, 88200, 96000, 192000];
+
+// range of the EQ gain and pre-amp sliders, in both directions
+const EQ_GAIN_RANGE_DB: f32 = 15.0;
+const EQ_MIN_FREQUENCY: f32 = 20.0;
+const EQ_MAX_FREQUENCY: f32 = 20000.0;
+const EQ_MIN_Q: f32 = 0.1;
+const EQ_MAX_Q: f32 = 10.0;
+
+#[component]
+fn ReplayGainEditor() -> Element {
+    let player = use_context_resource::<AudioPlayer>()?.read().clone();
+    let mut settings = use_signal(|| player.replay_gain());
+
+    let update = use_callback(move |new: ReplayGainSettings| {
+        settings.set(new);
+        player.set_replay_gain(new);
+    });
+
+    let current = *settings.read();
+    let to_slider = |db: f32| ((db / REPLAY_GAIN_RANGE_DB + 1.0) * 50.0) as f64;
+    // in steps of half a dB
+    let from_slider =
+        |value: f64| ((value as f32 / 50.0 - 1.0) * REPLAY_GAIN_RANGE_DB * 2.0).round() / 2.0;
+
+    rsx!(
+        rect {
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            for (mode, name) in [
+                (ReplayGainMode::Off, "Off"),
+                (ReplayGainMode::Track, "Track"),
+                (ReplayGainMode::Album, "Album"),
+            ] {
+                Button {
+                    key: "{name}",
+                    onclick: move |_| update.call(ReplayGainSettings { mode, ..current }),
+                    label {
+                        font_weight: if current.mode == mode { "bold" } else { "normal" },
+                        "{name}"
+                    }
+                }
+            }
+        }
+        rect {
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            label { width: "160", "Pre-amp: {current.preamp_db:+.1} dB" }
+            Slider {
+                size: "200",
+                value: to_slider(current.preamp_db),
+                onmoved: move |value| update.call(ReplayGainSettings {
+                    preamp_db: from_slider(value),
+                    ..current
+                }),
+            }
+        }
+        rect {
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            label { width: "160", "Untagged tracks: {current.fallback_db:+.1} dB" }
+            Slider {
+                size: "200",
+                value: to_slider(current.fallback_db),
+                onmoved: move |value| update.call(ReplayGainSettings {
+                    fallback_db: from_slider(value),
+                    ..current
+                }),
+            }
+        }
+        rect {
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            Switch {
+                enabled: current.prevent_clipping,
+                ontoggled: move |_| update.call(ReplayGainSettings {
+                    prevent_clipping: !current.prevent_clipping,
+                    ..current
+                }),
+            }
+            label { "Prevent clipping" }
+        }
+    )
+}
+
+#[component]
+fn TransitionEditor() -> Element {
+    let player = use_context_resource::<AudioPlayer>()?.read().clone();
+    let mut settings = use_signal(|| player.transitions());
+
+    let update = use_callback(move |new: TransitionSettings| {
+        settings.set(new);
+        player.set_transitions(new);
+    });
+
+    let current = *settings.read();
+
+    rsx!(
+        DurationSetting {
+            name: "Crossfade",
+            value: current.crossfade,
+            max: Duration::from_secs(12),
+            onchange: move |crossfade| update.call(TransitionSettings { crossfade, ..current }),
+        }
+        DurationSetting {
+            name: "Fade on skip",
+            value: current.skip_fade,
+            max: Duration::from_secs(2),
+            onchange: move |skip_fade| update.call(TransitionSettings { skip_fade, ..current }),
+        }
+        DurationSetting {
+            name: "Fade on pause",
+            value: current.pause_fade,
+            max: Duration::from_secs(2),
+            onchange: move |pause_fade| update.call(TransitionSettings { pause_fade, ..current }),
+        }
+    )
+}
+
+#[component]
+fn DurationSetting(
+    name: String,
+    value: Duration,
+    max: Duration,
+    onchange: Callback<Duration>,
+) -> Element {
+    let text = if value.is_zero() {
+        "Off".to_string()
+    } else {
+        format!("{:.1} s", value.as_secs_f32())
+    };
+
+    rsx!(rect {
+        direction: "horizontal",
+        cross_align: "center",
+        spacing: "8",
+
+        label { width: "160", "{name}: {text}" }
+        Slider {
+            size: "200",
+            value: value.as_secs_f64() / max.as_secs_f64() * 100.0,
+            // in steps of a tenth of a second
+            onmoved: move |value: f64| {
+                let secs = (max.as_secs_f64() * value / 100.0 * 10.0).round() / 10.0;
+                onchange.call(Duration::from_secs_f64(secs));
+            },
+        }
+    })
+}
+
+#[component]
+fn ResumeEditor() -> Element {
+    let player = use_context_resource::<AudioPlayer>()?.read().clone();
+    let mut threshold = use_signal(|| player.resume_threshold());
+
+    let minutes = threshold.read().as_secs() / 60;
+    let text = if minutes == 0 {
+        "Podcasts only".to_string()
+    } else {
+        format!("Podcasts and tracks over {} min", minutes)
+    };
+
+    rsx!(rect {
+        direction: "horizontal",
+        cross_align: "center",
+        spacing: "8",
+
+        label { width: "260", "{text}" }
+        Slider {
+            size: "200",
+            value: minutes as f64 / MAX_RESUME_THRESHOLD as f64 * 100.0,
+            // in steps of five minutes
+            onmoved: move |value: f64| {
+                let steps = MAX_RESUME_THRESHOLD as f64 / 5.0 * value / 100.0;
+                let minutes = steps.round() as u64 * 5;
+                let new = Duration::from_secs(minutes * 60);
+
+                threshold.set(new);
+                player.set_resume_threshold(new);
+            },
+        }
+    })
+}
+
+#[component]
+fn OutputDeviceEditor() -> Element {
+    let player = use_context_resource::<AudioPlayer>()?.read().clone();
+    let mut selected = use_signal(|| player.output_device());
+
+    let mut device_state = use_signal(OutputDeviceState::default);
+    use_future({
+        let player = player.clone();
+        move || {
+            let mut state_rx = player.subscribe_output_device();
+
+            async move {
+                loop {
+                    *device_state.write() = state_rx.borrow_and_update().clone();
+
+                    if state_rx.changed().await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    let mut devices = use_resource({
+        let player = player.clone();
+        move || {
+            let player = player.clone();
+            async move { player.output_devices().await }
+        }
+    });
+
+    let select = use_callback(move |device: Option<String>| {
+        selected.set(device.clone());
+        player.set_output_device(device);
+    });
+
+    let current = selected.read().clone();
+    let mut listed = devices.read().clone().unwrap_or_default();
+    // keep showing the selection while it's unplugged, so it can be told apart from the default
+    if let Some(device) = current.as_ref().filter(|device| !listed.contains(device)) {
+        listed.push(device.clone());
+    }
+
+    let state = device_state.read();
+    let mut playing_on = match &state.device {
+        Some(device) => format!("Playing on {device}."),
+        None => "No output device available, audio isn't played anywhere.".to_string(),
+    };
+    if let Some(missing) = &state.missing {
+        playing_on.push_str(&format!(" {missing} is unavailable."));
+    }
+
+    rsx!(
+        label { "{playing_on}" }
+        rect {
+            direction: "horizontal",
+            spacing: "8",
+
+            label { width: "160", "Device" }
+            rect {
+                spacing: "4",
+
+                Button {
+                    onclick: move |_| select.call(None),
+                    label {
+                        font_weight: if current.is_none() { "bold" } else { "normal" },
+                        "System default"
+                    }
+                }
+                for device in listed {
+                    Button {
+                        key: "{device}",
+                        onclick: {
+                            let device = device.clone();
+                            move |_| select.call(Some(device.clone()))
+                        },
+                        label {
+                            font_weight: if current.as_ref() == Some(&device) { "bold" } else { "normal" },
+                            "{device}"
+                        }
+                    }
+                }
+            }
+            Button {
+                onclick: move |_| devices.restart(),
+                label { "Refresh" }
+            }
+        }
+    )
+}
+
+#[component]
+fn OutputEditor() -> Element {
+    let player = use_context_resource::<AudioPlayer>()?.read().clone();
+    let mut settings = use_signal(|| player.output_settings());
+
+    let format = player.output_format();
+    let update = use_callback(move |new: OutputSettings| {
+        settings.set(new);
+        player.set_output_settings(new);
+    });
+
+    let current = *settings.read();
+
+    rsx!(
+        label {
+            "Playing at {format.sample_rate} Hz with {format.channels} channels. Changes to the sample rate apply after a restart.",
+        }
+        rect {
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            label { width: "160", "Sample rate" }
+            Button {
+                onclick: move |_| update.call(OutputSettings { sample_rate: None, ..current }),
+                label {
+                    font_weight: if current.sample_rate.is_none() { "bold" } else { "normal" },
+                    "Device default"
+                }
+            }
+            for sample_rate in OUTPUT_SAMPLE_RATES {
+                Button {
+                    key: "{sample_rate}",
+                    onclick: move |_| update.call(OutputSettings {
+                        sample_rate: Some(sample_rate),
+                        ..current
+                    }),
+                    label {
+                        font_weight: if current.sample_rate == Some(sample_rate) { "bold" } else { "normal" },
+                        "{sample_rate / 1000}.{sample_rate % 1000 / 100} kHz"
+                    }
+                }
+            }
+        }
+        rect {
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            label { width: "160", "Resampling quality" }
+            for (resampler_quality, name) in [
+                (ResamplerQuality::Fast, "Fast"),
+                (ResamplerQuality::Balanced, "Balanced"),
+                (ResamplerQuality::High, "High"),
+            ] {
+                Button {
+                    key: "{name}",
+                    onclick: move |_| update.call(OutputSettings { resampler_quality, ..current }),
+                    label {
+                        font_weight: if current.resampler_quality == resampler_quality { "bold" } else { "normal" },
+                        "{name}"
+                    }
+                }
+            }
+        }
+    )
+}
+
+#[component]
+fn LoudnessAnalysis() -> Element {
+    let library = use_context_resource::<Library>()?.read().clone();
+    let notifications = use_context::<NotificationManager>();
+
+    let mut write_back = use_signal(|| false);
+
+    rsx!(
+        label {
+            "Tracks without ReplayGain tags can be analyzed to normalize them as well.",
+        }
+        rect {
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            Button {
+                onclick: move |_| {
+                    notifications.add(library.analyze_loudness(*write_back.read()));
+                },
+                label { "Analyze tracks" }
+            }
+            Switch {
+                enabled: *write_back.read(),
+                ontoggled: move |_| write_back.toggle(),
+            }
+            label { "Write results to the files" }
+        }
+    )
+}
+
+#[component]
+fn PluginList() -> Element {
+    let theme = use_context::<Theme>();
+    let plugin_system = use_context_resource::<PluginSystem>()?;
+    let plugin_system = plugin_system.read().clone();
+
+    let mut plugins = plugin_system
+        .plugins
+        .iter()
+        .map(|(id, pool)| {
+            (
+                *id,
+                pool.metadata.name.clone(),
+                pool.metadata.version.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+    plugins.sort_by(|(_, a, _), (_, b, _)| a.cmp(b));
+
+    rsx!(
+        if plugins.is_empty() && plugin_system.failed.is_empty() {
+            label {
+                "No plugins found in the plugin directory.",
+            }
+        }
+        for (plugin_id, name, version) in plugins {
+            rect {
+                key: "{plugin_id.0}",
+                width: "fill",
+                spacing: "4",
+
+                rect {
+                    width: "fill",
+                    direction: "horizontal",
+                    spacing: "8",
+
+                    label { "{name} {version}" }
+                    PluginHealthStatus { plugin_id }
+                }
+                PluginLimitsEditor { plugin_id }
+            }
+        }
+        for failed in plugin_system.failed.iter() {
+            label {
+                key: "{failed.file}",
+                color: theme.colors.warning,
+                "{failed.file} was not loaded: {failed.error}",
+            }
+        }
+    )
+}
+
+#[component]
+fn PluginHealthStatus(plugin_id: PluginId) -> Element {
+    let theme = use_context::<Theme>();
+    let plugin_system = use_context_resource::<PluginSystem>()?;
+    let plugin_system = plugin_system.read().clone();
+
+    let Some(pool) = plugin_system.plugins.get(&plugin_id).cloned() else {
+        return rsx!(label { "Plugin is no longer loaded." });
+    };
+
+    let mut health = use_signal(|| pool.health());
+    use_future({
+        let pool = pool.clone();
+        move || {
+            let mut health_rx = pool.subscribe_health();
+
+            async move {
+                loop {
+                    *health.write() = health_rx.borrow_and_update().clone();
+
+                    if health_rx.changed().await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    let health = health.read().clone();
+    let last_fault = health.last_fault.unwrap_or_default();
+
+    rsx!(
+        if health.quarantined {
+            label {
+                color: theme.colors.warning,
+                "Quarantined after {health.consecutive_faults} faults in a row: {last_fault}",
+            }
+            Button {
+                onclick: move |_| pool.release(),
+                label { "Release" }
+            }
+        } else if health.faults > 0 {
+            label {
+                color: theme.colors.warning,
+                "{health.faults} faults, the last one was: {last_fault}",
+            }
+        }
+    )
+}
+
+// wasm memory is allocated in pages of 64KiB
+const PAGES_PER_MIB: u32 = 16;
+
+/// Memory and time a single instance of the plugin may use, running into either counts as a fault.
+#[component]
+fn PluginLimitsEditor(plugin_id: PluginId) -> Element {
+    let notifications = use_context::<NotificationManager>();
+    let plugin_system = use_context_resource::<PluginSystem>()?;
+    let plugin_system = plugin_system.read().clone();
+
+    let Some(pool) = plugin_system.plugins.get(&plugin_id).cloned() else {
+        return rsx!(label { "Plugin is no longer loaded." });
+    };
+
+    // typed text that isn't applied yet is kept here
+    let mut memory = use_signal(|| (pool.limits().memory_pages / PAGES_PER_MIB).to_string());
+    let mut timeout = use_signal(|| pool.limits().timeout.as_secs_f32().to_string());
+
+    let apply = move |_| {
+        let limits = memory
+            .read()
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .and_then(|mib| mib.checked_mul(PAGES_PER_MIB))
+            .zip(
+                timeout
+                    .read()
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok()),
+            )
+            .map(|(memory_pages, timeout)| PluginLimits {
+                memory_pages,
+                timeout,
+            });
+
+        let Some(limits) = limits else {
+            notifications.add(Notification::new(
+                "Failed to change limits",
+                "Memory has to be a whole number of MiB and the timeout a number of seconds",
+            ));
+            return;
+        };
+
+        let plugin_system = plugin_system.clone();
+        let notifications = notifications.clone();
+        spawn(async move {
+            if let Err(e) = plugin_system.set_limits(plugin_id, limits).await {
+                notifications.add(Notification::new("Failed to change limits", e.to_string()));
+            }
+        });
+    };
+
+    rsx!(rect {
+        direction: "horizontal",
+        cross_align: "center",
+        spacing: "8",
+
+        label { "Memory (MiB)" }
+        Input {
+            value: memory.read().clone(),
+            width: "80",
+            onchange: move |value| memory.set(value),
+        },
+        label { "Timeout (s)" }
+        Input {
+            value: timeout.read().clone(),
+            width: "80",
+            onchange: move |value| timeout.set(value),
+        },
+        Button {
+            onclick: apply,
+            label { "Apply" }
+        }
+    })
+}
+
+#[component]
+fn PluginMountSettings() -> Element {
+    let plugin_system = use_context_resource::<PluginSystem>()?;
+
+    let mut plugins = plugin_system
+        .read()
+        .plugins
+        .iter()
+        .filter(|(_, pool)| !pool.metadata.fs_mounts.is_empty())
+        .map(|(id, pool)| (*id, pool.metadata.name.clone()))
+        .collect::<Vec<_>>();
+    plugins.sort_by(|(_, a), (_, b)| a.cmp(b));
+
+    rsx!(
+        if plugins.is_empty() {
+            label {
+                "No loaded plugin requests filesystem access.",
+            }
+        }
+        for (plugin_id, name) in plugins {
+            rect {
+                key: "{plugin_id.0}",
+                width: "fill",
+                spacing: "4",
+
+                label {
+                    font_weight: "bold",
+                    "{name}",
+                },
+                PluginMounts { plugin_id }
+            }
+        }
+    )
+}
+
+#[component]
+fn PluginMounts(plugin_id: PluginId) -> Element {
+    let plugin_system = use_context_resource::<PluginSystem>()?;
+    let plugin_system = plugin_system.read().clone();
+
+    let Some(pool) = plugin_system.plugins.get(&plugin_id).cloned() else {
+        return rsx!(label { "Plugin is no longer loaded." });
+    };
+
+    let declared = pool.metadata.fs_mounts.clone();
+
+    // bumped after every change so the configured mounts get re-read from the pool
+    let revision = use_signal(|| 0usize);
+    let mounts = use_memo(move || {
+        revision.read();
+        pool.mounts()
+    });
+
+    rsx!(
+        for declared in declared {
+            MountEditor {
+                key: "{declared.internal_path}",
+                plugin_id,
+                internal_path: declared.internal_path.clone(),
+                description: declared.description.clone(),
+                mounts: mounts
+                    .read()
+                    .iter()
+                    .filter(|mount| mount.internal_path == declared.internal_path)
+                    .cloned()
+                    .collect::<Vec<_>>(),
+                revision,
+            }
+        }
+    )
+}
+
+#[component]
+fn MountEditor(
+    plugin_id: PluginId,
+    internal_path: String,
+    description: String,
+    mounts: Vec<PluginMount>,
+    mut revision: Signal<usize>,
+) -> Element {
+    let theme = use_context::<Theme>();
+    let notifications = use_context::<NotificationManager>();
+    let plugin_system = use_context_resource::<PluginSystem>()?;
+
+    let mut new_path = use_signal(String::new);
+
+    let add_mount = {
+        let plugin_system = plugin_system.read().clone();
+        let notifications = notifications.clone();
+        let internal_path = internal_path.clone();
+
+        move |_| {
+            let plugin_system = plugin_system.clone();
+            let notifications = notifications.clone();
+            let internal_path = internal_path.clone();
+            let host_path = PathBuf::from(new_path.read().trim());
+
+            spawn(async move {
+                match plugin_system
+                    .add_mount(plugin_id, &internal_path, &host_path)
+                    .await
+                {
+                    Ok(_) => {
+                        new_path.set(String::new());
+                        revision += 1;
+                    }
+                    Err(e) => {
+                        notifications.add(Notification::new("Failed to add mount", e.to_string()))
+                    }
+                }
+            });
+        }
+    };
+
+    rsx!(rect {
+        width: "fill",
+        padding: "0 0 0 8",
+        spacing: "4",
+
+        label {
+            "{internal_path} ({description})",
+        },
+
+        if mounts.is_empty() {
+            label {
+                color: theme.colors.warning,
+                "No host directory configured, the plugin can't access any files here.",
+            }
+        }
+
+        for mount in mounts {
+            rect {
+                key: "{mount.id.0}",
+                width: "fill",
+                direction: "horizontal",
+                cross_align: "center",
+                spacing: "8",
+
+                label {
+                    width: "calc(100% - 80)",
+                    max_lines: "1",
+                    text_overflow: "ellipsis",
+                    "{mount.host_path.display()}",
+                },
+                Button {
+                    onclick: {
+                        let plugin_system = plugin_system.read().clone();
+                        let notifications = notifications.clone();
+
+                        move |_| {
+                            let plugin_system = plugin_system.clone();
+                            let notifications = notifications.clone();
+
+                            spawn(async move {
+                                match plugin_system.remove_mount(mount.id).await {
+                                    Ok(_) => revision += 1,
+                                    Err(e) => notifications.add(Notification::new(
+                                        "Failed to remove mount",
+                                        e.to_string(),
+                                    )),
+                                }
+                            });
+                        }
+                    },
+                    label { "Remove" }
+                }
+            }
+        }
+
+        rect {
+            width: "fill",
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            Input {
+                value: new_path.read().clone(),
+                placeholder: "Host directory",
+                width: "calc(100% - 80)",
+                onchange: move |value| new_path.set(value),
+            },
+            Button {
+                onclick: add_mount,
+                label { "Add" }
+            }
+        }
+    })
+}
+
+#[component]
+fn PluginSettingsEditor() -> Element {
+    let plugin_system = use_context_resource::<PluginSystem>()?;
+
+    let mut plugins = plugin_system
+        .read()
+        .plugins
+        .iter()
+        .filter(|(_, pool)| !pool.metadata.settings.is_empty())
+        .map(|(id, pool)| (*id, pool.metadata.name.clone()))
+        .collect::<Vec<_>>();
+    plugins.sort_by(|(_, a), (_, b)| a.cmp(b));
+
+    rsx!(
+        if plugins.is_empty() {
+            label {
+                "No loaded plugin has any settings.",
+            }
+        }
+        for (plugin_id, name) in plugins {
+            rect {
+                key: "{plugin_id.0}",
+                width: "fill",
+                spacing: "4",
+
+                label {
+                    font_weight: "bold",
+                    "{name}",
+                },
+                PluginSettingsForm { plugin_id }
+            }
+        }
+    )
+}
+
+/// One editor for every setting the plugin declares, generated from the declared kinds.
+#[component]
+fn PluginSettingsForm(plugin_id: PluginId) -> Element {
+    let notifications = use_context::<NotificationManager>();
+    let plugin_system = use_context_resource::<PluginSystem>()?;
+    let plugin_system = plugin_system.read().clone();
+
+    let Some(pool) = plugin_system.plugins.get(&plugin_id).cloned() else {
+        return rsx!(label { "Plugin is no longer loaded." });
+    };
+
+    let declared = pool.metadata.settings.clone();
+
+    // bumped after every change so the values get re-read from the pool
+    let mut revision = use_signal(|| 0usize);
+    let settings = use_memo(move || {
+        revision.read();
+        pool.settings()
+    });
+
+    // applied right away, saving waits until the value stopped changing
+    let set = use_callback(move |(key, value): (String, SettingValue)| {
+        match plugin_system.set_setting(plugin_id, &key, value) {
+            Ok(()) => revision += 1,
+            Err(e) => {
+                notifications.add(Notification::new("Failed to change setting", e.to_string()))
+            }
+        }
+    });
+
+    rsx!(
+        for declaration in declared {
+            SettingEditor {
+                key: "{declaration.key}",
+                value: settings
+                    .read()
+                    .get(&declaration.key)
+                    .cloned()
+                    .unwrap_or_else(|| declaration.kind.default_value()),
+                onchange: {
+                    let key = declaration.key.clone();
+                    move |value| set.call((key.clone(), value))
+                },
+                declaration,
+            }
+        }
+    )
+}
+
+#[component]
+fn SettingEditor(
+    declaration: SettingDeclaration,
+    value: SettingValue,
+    onchange: Callback<SettingValue>,
+) -> Element {
+    // typed text that isn't a valid value yet is kept here instead of being saved
+    let mut text = use_signal(|| value.to_string());
+
+    let editor = match (&declaration.kind, &value) {
+        (SettingKind::Bool { .. }, SettingValue::Bool(enabled)) => {
+            let enabled = *enabled;
+            rsx!(Switch {
+                enabled,
+                ontoggled: move |_| onchange.call(SettingValue::Bool(!enabled)),
+            })
+        }
+        (
+            SettingKind::Number {
+                min: Some(min),
+                max: Some(max),
+                ..
+            },
+            SettingValue::Number(number),
+        ) if max > min => {
+            let (min, max) = (*min, *max);
+            // about a hundredth of the range, rounded to a power of ten
+            let step = 10f64.powf(((max - min) / 100.0).log10().floor());
+            let shown = format!("{:.*}", (-step.log10()).max(0.0) as usize, number);
+
+            rsx!(
+                Slider {
+                    size: "200",
+                    value: (number - min) / (max - min) * 100.0,
+                    onmoved: move |value: f64| {
+                        let number = min + (max - min) * value / 100.0;
+                        let number = ((number / step).round() * step).clamp(min, max);
+                        onchange.call(SettingValue::Number(number));
+                    },
+                }
+                label { "{shown}" }
+            )
+        }
+        (SettingKind::Enum { options, .. }, SettingValue::Enum(selected)) => rsx!(
+            for option in options.clone() {
+                Button {
+                    key: "{option}",
+                    onclick: {
+                        let option = option.clone();
+                        move |_| onchange.call(SettingValue::Enum(option.clone()))
+                    },
+                    label {
+                        font_weight: if option == *selected { "bold" } else { "normal" },
+                        "{option}"
+                    }
+                }
+            }
+        ),
+        // strings, paths and numbers without a range are typed in
+        (kind, _) => {
+            let kind = kind.clone();
+            let placeholder = match &kind {
+                SettingKind::Path { .. } => "Host path",
+                SettingKind::Number { .. } => "Number",
+                _ => "",
+            };
+
+            rsx!(Input {
+                value: text.read().clone(),
+                placeholder,
+                width: "300",
+                onchange: move |value: String| {
+                    if let Some(parsed) = kind.parse(value.trim()) {
+                        onchange.call(parsed);
+                    }
+                    text.set(value);
+                },
+            })
+        }
+    };
+
+    rsx!(
+        rect {
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            label { width: "160", "{declaration.name}" }
+            {editor}
+        }
+        if let Some(description) = &declaration.description {
+            label { "{description}" }
+        }
+    )
+}
+
+#[component]
+fn EqualizerEditor() -> Element {
+    let player = use_context_resource::<AudioPlayer>()?.read().clone();
+    let mut profile = use_dsp_profile(&player);
+
+    let mut device = use_signal(|| None::<String>);
+    use_future({
+        let player = player.clone();
+        move || {
+            let mut state_rx = player.subscribe_output_device();
+
+            async move {
+                loop {
+                    *device.write() = state_rx.borrow_and_update().device.clone();
+
+                    if state_rx.changed().await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    let update = use_callback({
+        let player = player.clone();
+        move |new: DspSettings| {
+            profile.write().settings = new.clone();
+            player.set_dsp_settings(new);
+        }
+    });
+
+    let current = profile.read().clone();
+    let settings = current.settings;
+
+    let balance = match (settings.balance * 100.0).round() as i32 {
+        0 => "Center".to_string(),
+        percent if percent < 0 => format!("{}% left", -percent),
+        percent => format!("{percent}% right"),
+    };
+
+    rsx!(
+        if let Some(device) = device.read().clone() {
+            rect {
+                direction: "horizontal",
+                cross_align: "center",
+                spacing: "8",
+
+                Switch {
+                    enabled: current.device.is_some(),
+                    ontoggled: {
+                        let player = player.clone();
+                        let enabled = current.device.is_none();
+                        move |_| player.set_device_profile(enabled)
+                    },
+                }
+                label { "Separate settings for {device}" }
+            }
+        }
+        rect {
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            Switch {
+                enabled: settings.eq_enabled,
+                ontoggled: {
+                    let settings = settings.clone();
+                    move |_| update.call(DspSettings {
+                        eq_enabled: !settings.eq_enabled,
+                        ..settings.clone()
+                    })
+                },
+            }
+            label { "Equalizer" }
+        }
+        rect {
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            label { width: "160", "Pre-amp: {settings.preamp_db:+.1} dB" }
+            Slider {
+                size: "200",
+                value: gain_to_slider(settings.preamp_db),
+                onmoved: {
+                    let settings = settings.clone();
+                    move |value| update.call(DspSettings {
+                        preamp_db: gain_from_slider(value),
+                        ..settings.clone()
+                    })
+                },
+            }
+        }
+        for (index, band) in settings.bands.iter().copied().enumerate() {
+            EqBandEditor {
+                key: "{index}",
+                band,
+                onchange: {
+                    let settings = settings.clone();
+                    move |band| {
+                        let mut settings = settings.clone();
+                        settings.bands[index] = band;
+                        update.call(settings);
+                    }
+                },
+                onremove: {
+                    let settings = settings.clone();
+                    move |_| {
+                        let mut settings = settings.clone();
+                        settings.bands.remove(index);
+                        update.call(settings);
+                    }
+                },
+            }
+        }
+        Button {
+            onclick: {
+                let settings = settings.clone();
+                move |_| {
+                    let mut settings = settings.clone();
+                    settings.bands.push(EqBand::new(EqBandKind::Peaking, 1000.0, 0.0, 1.0));
+                    update.call(settings);
+                }
+            },
+            label { "Add band" }
+        }
+        rect {
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            label { width: "160", "Balance: {balance}" }
+            Slider {
+                size: "200",
+                value: ((settings.balance + 1.0) * 50.0) as f64,
+                // in steps of a percent
+                onmoved: {
+                    let settings = settings.clone();
+                    move |value: f64| update.call(DspSettings {
+                        balance: ((value as f32 / 50.0 - 1.0) * 100.0).round() / 100.0,
+                        ..settings.clone()
+                    })
+                },
+            }
+        }
+        rect {
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            Switch {
+                enabled: settings.mono,
+                ontoggled: {
+                    let settings = settings.clone();
+                    move |_| update.call(DspSettings {
+                        mono: !settings.mono,
+                        ..settings.clone()
+                    })
+                },
+            }
+            label { "Mono" }
+        }
+        rect {
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            Switch {
+                enabled: settings.limiter,
+                ontoggled: {
+                    let settings = settings.clone();
+                    move |_| update.call(DspSettings {
+                        limiter: !settings.limiter,
+                        ..settings.clone()
+                    })
+                },
+            }
+            label { "Limiter" }
+        }
+    )
+}
+
+#[component]
+fn EqBandEditor(band: EqBand, onchange: Callback<EqBand>, onremove: Callback<()>) -> Element {
+    let frequency = if band.frequency >= 1000.0 {
+        format!("{:.1} kHz", band.frequency / 1000.0)
+    } else {
+        format!("{:.0} Hz", band.frequency)
+    };
+
+    // frequency and Q are spread logarithmically across the sliders
+    let frequency_range = (EQ_MAX_FREQUENCY / EQ_MIN_FREQUENCY).ln();
+    let q_range = (EQ_MAX_Q / EQ_MIN_Q).ln();
+
+    rsx!(rect {
+        width: "fill",
+        padding: "0 0 0 8",
+        spacing: "4",
+
+        rect {
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            for (kind, name) in [
+                (EqBandKind::LowShelf, "Low shelf"),
+                (EqBandKind::Peaking, "Peak"),
+                (EqBandKind::HighShelf, "High shelf"),
+                (EqBandKind::LowPass, "Low pass"),
+                (EqBandKind::HighPass, "High pass"),
+            ] {
+                Button {
+                    key: "{name}",
+                    onclick: move |_| onchange.call(EqBand { kind, ..band }),
+                    label {
+                        font_weight: if band.kind == kind { "bold" } else { "normal" },
+                        "{name}"
+                    }
+                }
+            }
+            Button {
+                onclick: move |_| onremove.call(()),
+                label { "Remove" }
+            }
+        }
+        rect {
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            label { width: "160", "Frequency: {frequency}" }
+            Slider {
+                size: "200",
+                value: ((band.frequency / EQ_MIN_FREQUENCY).ln() / frequency_range * 100.0) as f64,
+                onmoved: move |value: f64| {
+                    let frequency = EQ_MIN_FREQUENCY * (frequency_range * value as f32 / 100.0).exp();
+                    onchange.call(EqBand { frequency: frequency.round(), ..band });
+                },
+            }
+        }
+        if band.kind.has_gain() {
+            rect {
+                direction: "horizontal",
+                cross_align: "center",
+                spacing: "8",
+
+                label { width: "160", "Gain: {band.gain_db:+.1} dB" }
+                Slider {
+                    size: "200",
+                    value: gain_to_slider(band.gain_db),
+                    onmoved: move |value| onchange.call(EqBand {
+                        gain_db: gain_from_slider(value),
+                        ..band
+                    }),
+                }
+            }
+        }
+        rect {
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            label { width: "160", "Q: {band.q:.2}" }
+            Slider {
+                size: "200",
+                value: ((band.q / EQ_MIN_Q).ln() / q_range * 100.0) as f64,
+                onmoved: move |value: f64| {
+                    let q = EQ_MIN_Q * (q_range * value as f32 / 100.0).exp();
+                    onchange.call(EqBand { q: (q * 100.0).round() / 100.0, ..band });
+                },
+            }
+        }
+    })
+}
+
+/// Follows the DSP profile in use, which can also change with the output device.
+fn use_dsp_profile(player: &AudioPlayer) -> Signal<DspProfile> {
+    let mut profile = use_signal(DspProfile::default);
+    use_future({
+        let player = player.clone();
+        move || {
+            let mut profile_rx = player.subscribe_dsp_profile();
+
+            async move {
+                loop {
+                    *profile.write() = profile_rx.borrow_and_update().clone();
+
+                    if profile_rx.changed().await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    profile
+}
+
+fn gain_to_slider(db: f32) -> f64 {
+    ((db / EQ_GAIN_RANGE_DB + 1.0) * 50.0) as f64
+}
+
+/// In steps of half a dB.
+fn gain_from_slider(value: f64) -> f32 {
+    ((value as f32 / 50.0 - 1.0) * EQ_GAIN_RANGE_DB * 2.0).round() / 2.0
+}
+
+#[component]
+fn EqPresets() -> Element {
+    let player = use_context_resource::<AudioPlayer>()?.read().clone();
+
+    let mut presets = use_resource({
+        let player = player.clone();
+        move || {
+            let player = player.clone();
+            async move { player.eq_presets().await }
+        }
+    });
+
+    let mut new_name = use_signal(String::new);
+
+    let save_preset = {
+        let player = player.clone();
+        move |_| {
+            let player = player.clone();
+            let name = new_name.read().trim().to_string();
+            if name.is_empty() {
+                return;
+            }
+
+            spawn(async move {
+                player.save_eq_preset(name).await;
+                new_name.set(String::new());
+                presets.restart();
+            });
+        }
+    };
+
+    let listed = presets.read().clone().unwrap_or_default();
+    let name_taken = listed
+        .iter()
+        .any(|preset| preset.built_in && preset.name == new_name.read().trim());
+
+    rsx!(
+        rect {
+            direction: "horizontal",
+            spacing: "8",
+
+            label { width: "160", "Presets" }
+            rect {
+                spacing: "4",
+
+                for preset in listed {
+                    rect {
+                        key: "{preset.name}",
+                        direction: "horizontal",
+                        cross_align: "center",
+                        spacing: "8",
+
+                        Button {
+                            onclick: {
+                                let player = player.clone();
+                                let preset = preset.clone();
+                                move |_| {
+                                    let settings = player.dsp_profile().settings.with_preset(&preset);
+                                    player.set_dsp_settings(settings);
+                                }
+                            },
+                            label { "{preset.name}" }
+                        }
+                        if !preset.built_in {
+                            Button {
+                                onclick: {
+                                    let player = player.clone();
+                                    let name = preset.name.clone();
+                                    move |_| {
+                                        let player = player.clone();
+                                        let name = name.clone();
+                                        spawn(async move {
+                                            player.delete_eq_preset(&name).await;
+                                            presets.restart();
+                                        });
+                                    }
+                                },
+                                label { "Delete" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        rect {
+            width: "fill",
+            direction: "horizontal",
+            cross_align: "center",
+            spacing: "8",
+
+            Input {
+                value: new_name.read().clone(),
+                placeholder: "Preset name",
+                onchange: move |value| new_name.set(value),
+            },
+            if name_taken {
+                label { "Built-in presets can't be replaced." }
+            } else {
+                Button {
+                    onclick: save_preset,
+                    label { "Save as preset" }
+                }
+            }
+        }
+    )
+}
+
+#[component]
+fn EffectsEditor() -> Element {
+    let player = use_context_resource::<AudioPlayer>()?.read().clone();
+    let plugin_system = use_context_resource::<PluginSystem>()?.read().clone();
+    let mut profile = use_dsp_profile(&player);
+
+    let update = use_callback(move |effects: Vec<EffectSettings>| {
+        let settings = DspSettings {
+            effects,
+            ..profile.read().settings.clone()
+        };
+        profile.write().settings = settings.clone();
+        player.set_dsp_settings(settings);
+    });
+
+    let effects = profile.read().settings.effects.clone();
+    let listed = effects
+        .iter()
+        .map(|effect| {
+            let name = plugin_system
+                .plugins
+                .get(&effect.plugin_id)
+                .map(|pool| pool.metadata.name.clone())
+                // stays in the chain, the plugin might come back
+                .unwrap_or_else(|| format!("Plugin {} isn't loaded", effect.plugin_id.0));
+            (*effect, name)
+        })
+        .collect::<Vec<_>>();
+
+    let mut available = plugin_system
+        .plugins
+        .iter()
+        .filter(|(id, pool)| {
+            pool.capabilities.effect && !effects.iter().any(|effect| effect.plugin_id == **id)
+        })
+        .map(|(id, pool)| (*id, pool.metadata.name.clone()))
+        .collect::<Vec<_>>();
+    available.sort_by(|(_, a), (_, b)| a.cmp(b));
+
+    rsx!(
+        if effects.is_empty() {
+            label { "No effects are used. Effects are run after the equalizer, in order." }
+        }
+        for (index, (effect, name)) in listed.into_iter().enumerate() {
+            rect {
+                key: "{effect.plugin_id.0}",
+                direction: "horizontal",
+                cross_align: "center",
+                spacing: "8",
+
+                Switch {
+                    enabled: !effect.bypassed,
+                    ontoggled: {
+                        let effects = effects.clone();
+                        move |_| {
+                            let mut effects = effects.clone();
+                            effects[index].bypassed = !effect.bypassed;
+                            update.call(effects);
+                        }
+                    },
+                }
+                label { width: "200", "{name}" }
+                Button {
+                    onclick: {
+                        let effects = effects.clone();
+                        move |_| {
+                            let mut effects = effects.clone();
+                            if index > 0 {
+                                effects.swap(index, index - 1);
+                                update.call(effects);
+                            }
+                        }
+                    },
+                    label { "Up" }
+                }
+                Button {
+                    onclick: {
+                        let effects = effects.clone();
+                        move |_| {
+                            let mut effects = effects.clone();
+                            if index + 1 < effects.len() {
+                                effects.swap(index, index + 1);
+                                update.call(effects);
+                            }
+                        }
+                    },
+                    label { "Down" }
+                }
+                Button {
+                    onclick: {
+                        let effects = effects.clone();
+                        move |_| {
+                            let mut effects = effects.clone();
+                            effects.remove(index);
+                            update.call(effects);
+                        }
+                    },
+                    label { "Remove" }
+                }
+            }
+        }
+        if !available.is_empty() {
+            rect {
+                direction: "horizontal",
+                cross_align: "center",
+                spacing: "8",
+
+                label { width: "160", "Add effect" }
+                for (plugin_id, name) in available {
+                    Button {
+                        key: "{plugin_id.0}",
+                        onclick: {
+                            let effects = effects.clone();
+                            move |_| {
+                                let mut effects = effects.clone();
+                                effects.push(EffectSettings {
+                                    plugin_id,
+                                    bypassed: false,
+                                });
+                                update.call(effects);
+                            }
+                        },
+                        label { "{name}" }
+                    }
+                }
+            }
+        }
+    )
+}
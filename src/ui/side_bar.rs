@@ -2,9 +2,29 @@ use crate::ui::*;
 
 #[component]
 pub fn SideBar() -> Element {
+    let theme = use_context::<Theme>();
+    let mut view = use_context::<Signal<MainView>>();
 
     rsx!(rect {
-        width: "32",
+        width: "40",
         height: "100%",
+        padding: "4 2",
+        spacing: "4",
+
+        IconButton {
+            icon: theme.icons.library,
+            shadow: "none",
+            onclick: move |_| view.set(MainView::Library),
+        },
+        IconButton {
+            icon: theme.icons.queue,
+            shadow: "none",
+            onclick: move |_| view.set(MainView::Queue),
+        },
+        IconButton {
+            icon: theme.icons.settings,
+            shadow: "none",
+            onclick: move |_| view.set(MainView::Settings),
+        },
     })
-}
\ No newline at end of file
+}